@@ -0,0 +1,96 @@
+"""Generate the CityJSONSeq sample that is used by the tiling fixture tests.
+
+The sample is a small, deterministic set of box-shaped buildings in EPSG:7415. Most
+buildings are clustered in the south-west corner, so that the quadtree is unbalanced
+and has leaves on several levels. The output is written to
+resources/data/tiling_sample.city.jsonl, the first line is the CityJSON metadata and
+the rest are the CityJSONFeatures.
+
+Copyright 2023 Balázs Dukai, Ravi Peters
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+"""
+from pathlib import Path
+import json
+
+TRANSLATE = [85000.0, 446300.0, 0.0]
+SCALE = 0.001
+EXTENT = 400.0
+
+
+def box(x, y, dx, dy, h):
+    """Vertices (quantized) and boundaries of an axis-aligned box."""
+    coords = [
+        (x, y, 0.0), (x + dx, y, 0.0), (x + dx, y + dy, 0.0), (x, y + dy, 0.0),
+        (x, y, h), (x + dx, y, h), (x + dx, y + dy, h), (x, y + dy, h),
+    ]
+    vertices = [[round(c / SCALE) for c in v] for v in coords]
+    boundaries = [[
+        [[0, 3, 2, 1]], [[4, 5, 6, 7]], [[0, 1, 5, 4]],
+        [[1, 2, 6, 5]], [[2, 3, 7, 6]], [[3, 0, 4, 7]],
+    ]]
+    return vertices, boundaries
+
+
+def buildings():
+    # A dense cluster in the south-west...
+    for i in range(6):
+        for j in range(4):
+            yield 10.0 + i * 15.0, 10.0 + j * 20.0, 10.0, 12.0, 6.0 + (i + j) % 4 * 3.0
+    # ...a few buildings along the east edge...
+    for j in range(4):
+        yield 320.0, 40.0 + j * 90.0, 30.0, 25.0, 12.0
+    # ...and some scattered ones in the north.
+    for i, (x, y) in enumerate([(60.0, 250.0), (150.0, 330.0), (230.0, 260.0), (180.0, 210.0)]):
+        yield x, y, 20.0, 20.0, 9.0 + i * 4.0
+    # A long, narrow structure across the middle, eg. a dike, that spans many cells.
+    yield 20.0, 190.0, 300.0, 4.0, 5.0
+
+
+def main():
+    out = Path(__file__).resolve().parents[1] / "data" / "tiling_sample.city.jsonl"
+    metadata = {
+        "type": "CityJSON",
+        "version": "1.1",
+        "CityObjects": {},
+        "vertices": [],
+        "transform": {"scale": [SCALE, SCALE, SCALE], "translate": TRANSLATE},
+        "metadata": {
+            "geographicalExtent": [
+                TRANSLATE[0], TRANSLATE[1], 0.0,
+                TRANSLATE[0] + EXTENT, TRANSLATE[1] + EXTENT, 30.0,
+            ],
+            "referenceSystem": "https://www.opengis.net/def/crs/EPSG/0/7415",
+        },
+    }
+    lines = [json.dumps(metadata, separators=(",", ":"))]
+    for n, (x, y, dx, dy, h) in enumerate(buildings()):
+        vertices, boundaries = box(x, y, dx, dy, h)
+        coid = f"b{n:03d}"
+        feature = {
+            "type": "CityJSONFeature",
+            "id": coid,
+            "CityObjects": {
+                coid: {
+                    "type": "Building",
+                    "geometry": [{"type": "Solid", "lod": "1.2", "boundaries": boundaries}],
+                }
+            },
+            "vertices": vertices,
+        }
+        lines.append(json.dumps(feature, separators=(",", ":")))
+    out.write_text("\n".join(lines) + "\n")
+
+
+if __name__ == "__main__":
+    main()
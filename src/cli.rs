@@ -13,10 +13,15 @@
 // limitations under the License.
 use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(
+    author,
+    version,
+    about,
+    after_help = "Run 'tyler <COMMAND> --help' for the commands that work on the output of a previous run: extract, thin, inspect"
+)]
 pub struct Cli {
     /// Main CityJSON file (.city.json), containing the coordinate reference system and
     /// transformation properties.
@@ -29,14 +34,37 @@ pub struct Cli {
     /// Directory for the output.
     #[arg(short, long)]
     pub output: PathBuf,
-    // /// Output format.
-    // #[arg(long, value_enum)]
-    // pub format: crate::Formats,
+    /// Directory for the per-tile input files of the conversion, instead of the 'inputs'
+    /// directory in the output. Use an in-memory filesystem, eg. /dev/shm, when the output is
+    /// on a network filesystem, where creating and deleting the many small input files
+    /// dominates the run time. The files are written into a 'tyler-<process ID>'
+    /// subdirectory, which is removed after the run, unless the log level is debug.
+    #[arg(long)]
+    pub inputs_dir: Option<PathBuf>,
+    /// Output formats, separated by commas, eg. '3dtiles,cityjson'. All formats are exported
+    /// from the same quadtree, so the features are indexed only once. The CityJSON tiles are
+    /// written into the 'cityjson' subdirectory of the output.
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "3dtiles")]
+    pub format: Vec<crate::Formats>,
+    /// Reproject the CityJSON tiles into this CRS, eg. 'EPSG:28992'. The vertices are
+    /// quantized with the scale of the input, so the CRS should be a projected CRS with the
+    /// same unit as the input CRS.
+    #[arg(long, value_parser = epsg_code)]
+    pub output_crs: Option<u16>,
     /// The CityObject type to use for the 3D Tiles
     /// (https://www.cityjson.org/specs/1.1.3/#the-different-city-objects).
+    /// The types of the CityJSON Extensions are given with their '+' prefix, eg. +NoiseBuilding.
     /// You can specify it multiple times.
-    #[arg(long, value_enum)]
+    #[arg(long)]
     pub object_type: Option<Vec<crate::parser::CityObjectType>>,
+    /// Only tile the features that contain one of the CityObject IDs in this file.
+    /// The file contains one CityObject ID per line.
+    #[arg(long, value_parser = existing_path)]
+    pub include_ids: Option<PathBuf>,
+    /// Do not tile the features that contain one of the CityObject IDs in this file.
+    /// The file contains one CityObject ID per line.
+    #[arg(long, value_parser = existing_path)]
+    pub exclude_ids: Option<PathBuf>,
     /// The CityObject attribute name and value type to include as feature attribute when the
     /// output is 3D Tiles. Format: <attribute_name>:<attribute_type> eg: 'name1:string'.
     /// Possible value types are, 'bool', 'int', 'float', 'string'.
@@ -52,25 +80,163 @@ pub struct Cli {
     /// By default, explicit tiling is created for the 3D Tiles output.
     #[arg(long = "3dtiles-implicit")]
     pub cesium3dtiles_implicit: bool,
+    /// The 3D Tiles version of the tileset. With '1.0' the glTF tile content is declared with the
+    /// 3DTILES_content_gltf extension, and the tile metadata is left out, because it is a core
+    /// feature of 1.1 only. Version 1.0 cannot be combined with --3dtiles-implicit,
+    /// --priority-attribute or --group-attribute.
+    #[arg(long, value_enum, default_value = "1.1")]
+    pub tileset_version: crate::formats::cesium3dtiles::TilesetVersion,
+    /// The type of the bounding volumes of the tiles and their content. The 'region' and
+    /// 'sphere' volumes enclose the box of the tile, thus they are less tight. A 'sphere' is
+    /// not allowed for implicit tiling.
+    #[arg(long, value_enum, default_value = "box")]
+    pub bounding_volume: crate::formats::cesium3dtiles::BoundingVolumeType,
+    /// Add the number of features, the number of CityObjects per type and the height range of
+    /// the tile content to the tile metadata, so that the tiles can be styled and inspected
+    /// without loading their content. Not available for implicit tiling.
+    #[arg(long, conflicts_with = "cesium3dtiles_implicit")]
+    pub tile_metadata_stats: bool,
     /// Generate and write the Tileset only, without exporting the glTF tiles, when the output format is 3D Tiles (https://docs.ogc.org/cs/22-025r4/22-025r4.html#toc31).
     #[arg(long = "3dtiles-tileset-only")]
     pub cesium3dtiles_tileset_only: bool,
+    /// Also write a gzip-compressed copy of the tileset JSON files next to them, eg.
+    /// tileset.json.gz, for the hosting that serves pre-compressed files. The tileset JSON is
+    /// written without whitespace in any case.
+    #[arg(long = "3dtiles-gzip")]
+    pub cesium3dtiles_gzip: bool,
+    /// The base URI of the tile content, which is prepended to the content URIs in the
+    /// tileset, eg. 'https://cdn.example.com/tiles/' to serve the content from another location
+    /// than the tileset. The URIs of the external tilesets and the subtrees are not changed.
+    #[arg(long = "3dtiles-content-base-uri")]
+    pub cesium3dtiles_content_base_uri: Option<String>,
+    /// Generate preview content, which is the wireframe of the bounding box of each feature,
+    /// instead of converting the features with geof. It is much faster than the conversion,
+    /// and it is meant for checking the spatial coverage of the tileset before the full run.
+    #[arg(long)]
+    pub content_preview: bool,
+    /// The program that writes the glTF content of the tiles. 'native' triangulates the
+    /// surfaces of the features directly, without geof. It writes the --object-attribute
+    /// attributes of the features into a property table, but it does not write the textures
+    /// and the materials of the features, and it ignores the geof options, such as the colors.
+    #[arg(long, value_enum, default_value = "geof", conflicts_with_all = ["content_preview", "obj_export"])]
+    pub content_writer: crate::formats::gltf::ContentWriter,
+    /// Write the content as Batched 3D Model (.b3dm) for the clients that only read the 3D
+    /// Tiles 1.0 content formats. The batch table has the ID of each feature and the values of
+    /// the --object-attribute attributes. It needs '--content-writer native'.
+    #[arg(long, conflicts_with = "cesium3dtiles_implicit")]
+    pub content_b3dm: bool,
+    /// Write the geometry templates of the GeometryInstances once per tile, and draw them at
+    /// each instance with EXT_mesh_gpu_instancing, instead of copying the template into the
+    /// mesh at each instance. This is meant for the trees, lampposts and the other
+    /// SolitaryVegetationObjects and CityFurniture that are modelled with templates. It needs
+    /// '--content-writer native'. Together with --content-b3dm, the content is a Composite
+    /// (.cmpt) of the b3dm and an Instanced 3D Model (.i3dm) for each template.
+    #[arg(long)]
+    pub content_instancing: bool,
+    /// The compression of the content of the native content writer. 'meshopt' compresses the
+    /// buffers in-process with EXT_meshopt_compression, so gltfpack is not needed. The content
+    /// that geof writes is always compressed with meshopt by geof. 'draco' is not built in, and
+    /// it is rejected with a pointer to --post-process.
+    #[arg(long, value_enum, default_value = "meshopt")]
+    pub compression: crate::formats::gltf::Compression,
+    /// Write the vertex positions of the preview content in the axes of ECEF (EPSG:4978),
+    /// relative to the center of the content. The rotation to the y-up of glTF and the
+    /// translation to the center are in the matrix of the glTF node, so the vertices can be
+    /// read as geocentric coordinates without undoing the axis swap.
+    #[arg(long, requires = "content_preview")]
+    pub content_ecef: bool,
+    /// Write a feature table next to the content of each tile (<content>.features.json),
+    /// that maps the position of each feature in the tile to the IDs of its CityObjects and
+    /// the path of its CityJSONFeature file. The features are numbered in the order in which
+    /// they are passed to the conversion.
+    #[arg(long)]
+    pub feature_table: bool,
+    /// Also write the triangulated content of each tile as Wavefront OBJ in the input CRS, into
+    /// tiles_obj/<content>.obj. The vertices are relative to the offset of the tile, which is
+    /// written next to it (<content>.offset.json), to keep the precision of the coordinates.
+    #[arg(long, conflicts_with = "content_preview")]
+    pub obj_export: bool,
+    /// Write an OGC API - 3D GeoVolumes collection description (collections.json) next to the
+    /// tileset.json, so that the output can be registered in an OGC API catalogue.
+    /// The collection ID is the name of the output directory.
+    #[arg(long)]
+    pub ogc_geovolumes: bool,
+    /// Write a CesiumJS viewer page (viewer.html) next to the tileset.json, which loads the
+    /// tileset and zooms to it. Serve the output directory over HTTP to open the page.
+    #[arg(long)]
+    pub viewer_html: bool,
+    /// Write a STAC Collection (collection.json) and Item (item.json) for the tileset into the
+    /// output directory, so that the output can be indexed into a STAC catalogue.
+    #[arg(long)]
+    pub stac: bool,
+    /// Measure the maximal coordinate displacement of each tile in a round trip from the input
+    /// CRS to the output frame and back, and record it in the run report (report.json).
+    /// The displacement includes the reprojection and the single precision quantization error.
+    #[arg(long)]
+    pub precision_diagnostics: bool,
     /// Use the tile boundingVolume as the content boundingVolume, instead of calculating the content boundingVolume from the data.
     #[arg(long = "3dtiles-content-bv-from-tile")]
     pub cesium3dtiles_content_bv_from_tile: bool,
     /// Add the boundingVolume of the content for the the tiles that have content.
     #[arg(long = "3dtiles-content-add-bv")]
     pub cesium3dtiles_content_add_bv: bool,
+    /// Compute the tile bounding volumes from the features in the tiles, instead of the grid.
+    /// With 'z' only the height is fitted to the features, with 'xyz' the horizontal extent
+    /// too. Not available for implicit tiling, where the tiles subdivide the root tile.
+    #[arg(long, value_enum, conflicts_with_all = ["cesium3dtiles_implicit", "tile_index", "flat_grid", "flat_grid_auto", "partition_scheme"])]
+    pub tight_bounds: Option<crate::formats::cesium3dtiles::TightBounds>,
     /// Set the geometric error (see 3D Tiles specification) on the parent nodes of leafs. This controls at what
     /// camera distance leaf nodes become visible. Higher values make content visible earlier when zooming in.
     #[arg(long, short = 'e', default_value = "12")]
     pub geometric_error_above_leaf: Option<f64>,
+    /// How the geometric error of the interior tiles is computed from
+    /// --geometric-error-above-leaf. With 'relative', --geometric-error-above-leaf is the
+    /// fraction of the tile edge length, eg. 0.1, which suits indoor models with small cell
+    /// sizes.
+    #[arg(long, value_enum, default_value = "grid")]
+    pub geometric_error_scheme: crate::formats::cesium3dtiles::GeometricErrorScheme,
+    /// Limit the geometric error of the tileset and the tiles to this value. The automatic
+    /// geometric error of the root grows with the extent of the data, and a large value makes
+    /// the viewers refine into the deep levels already at a small scale.
+    #[arg(long)]
+    pub root_geometric_error_max: Option<f64>,
+    /// Enlarge the bounding volume of the root tile by this many meters on each side, eg. so
+    /// that the viewers keep the tileset loaded when the camera is just outside of the data.
+    #[arg(long, conflicts_with = "cesium3dtiles_implicit")]
+    pub root_padding: Option<f64>,
+    /// Add content to the root tile too, as a coarse overview of the whole tileset with the
+    /// --lod-interior LoD. The root content contains all the features, so it is only practical
+    /// for small and medium sized datasets.
+    #[arg(
+        long,
+        requires = "lod_interior",
+        conflicts_with = "cesium3dtiles_implicit"
+    )]
+    pub root_content: bool,
     /// Set the 2D cell size for the grid that is used for constructing the quadtree.
     /// In input units (eg. meters). Note that the cell size will be adjusted so that it is
     /// possible to construct a tightly fit square, containing 4^n cells. The final cell size will
-    /// larger than this value.
-    #[arg(long, default_value = "250")]
-    pub grid_cellsize: Option<u32>,
+    /// larger than this value. Cell sizes smaller than a unit are supported for indoor models,
+    /// eg. 0.5, and they are rounded to thousandths.
+    #[arg(long, default_value = "250", value_parser = positive_f64)]
+    pub grid_cellsize: Option<f64>,
+    /// Halve the --grid-cellsize and index the features again, while there are grid cells whose
+    /// content exceeds the --qtree-capacity, because the quadtree cannot split a cell. The grid
+    /// is refined at most 4 times. Without this option, the oversized cells are only reported.
+    #[arg(long)]
+    pub grid_auto_refine: bool,
+    /// Only store the grid cells that cover the extent, instead of the square of 4^n cells
+    /// around it. It saves memory and the empty cells in the exports for elongated datasets,
+    /// eg. coastlines and rail corridors. The quadtree and the tiles are the same.
+    #[arg(long)]
+    pub grid_rectangular: bool,
+    /// Pin the grid to 'min_x,min_y,max_x,max_y' in the input CRS, instead of deriving it from
+    /// the extent of the features. The lower-left corner is the origin of the grid cells, and
+    /// --grid-cellsize is used as it is. Thus the tiles stay the same when the input grows
+    /// within this extent, eg. '0,300000,300000,630000' with a cell size of 1000 aligns the
+    /// cells to the Dutch RD kilometre grid. The parts of the features outside are left out.
+    #[arg(long, value_parser = bbox_2d, allow_hyphen_values = true, conflicts_with = "grid_rectangular")]
+    pub grid_extent: Option<[f64; 4]>,
     /// Generate the quadtree directly from a grid.tsv file, skipping the extent computation and feature indexing. A grid.tsv file is created with the --grid-export option. Used for debugging.
     #[arg(long)]
     pub grid_file: Option<String>,
@@ -84,6 +250,32 @@ pub struct Cli {
     /// coordinates. In input units (eg. meters).
     #[arg(long)]
     pub grid_maxz: Option<i32>,
+    /// Force the local origin, as 'x,y,z' in the input CRS. The grid cells are aligned to the
+    /// origin and the grid cell size is used as it is, so that the tiles of separately generated
+    /// tilesets (eg. buildings, terrain) align when they are generated with the same origin
+    /// and --grid-cellsize. The offsets of the tile content are rounded relative to the origin.
+    #[arg(long, value_parser = coordinate_3d, allow_hyphen_values = true)]
+    pub origin: Option<[f64; 3]>,
+    /// Override the geographicalExtent of the CityJSON metadata, as
+    /// 'min_x,min_y,min_z,max_x,max_y,max_z' in the input CRS. The grid is constructed from this
+    /// extent instead of the extent that is computed from the features, and the features are
+    /// checked against it instead of the declared extent.
+    #[arg(long, value_parser = bbox_3d, allow_hyphen_values = true)]
+    pub extent: Option<[f64; 6]>,
+    /// Leave out the features whose bounding box center is outside of the --extent.
+    /// The features that are partially outside of the grid are always clipped to the grid.
+    #[arg(long, requires = "extent")]
+    pub clip_to_extent: bool,
+    /// Fail the run if any feature is outside of the geographicalExtent that is declared in the
+    /// CityJSON metadata. Without this option, the features outside of the declared extent are
+    /// only reported in the features_outside_extent.tsv file in the output directory.
+    #[arg(long)]
+    pub strict_extent: bool,
+    /// Fail the run if any CityJSONFeature declares a different referenceSystem than the
+    /// CityJSON metadata. Without this option, these features are left out of the tileset and
+    /// they are listed in the features_crs_mismatch.tsv file in the output directory.
+    #[arg(long)]
+    pub strict_crs: bool,
     /// Export the grid into .tsv files in the working
     /// directory. Used for debugging.
     #[arg(long)]
@@ -92,25 +284,217 @@ pub struct Cli {
     /// directory. Used for debugging.
     #[arg(long)]
     pub grid_export_features: bool,
+    /// Export debug data in the format: 'geojson' writes the leaves and the internal nodes of
+    /// the quadtree into quadtree_wgs84.geojson in the output directory, as polygons in WGS84
+    /// with their level, number of items and tile ID, which can be put on a web map.
+    #[arg(long, value_enum)]
+    pub debug_export: Option<crate::DebugExport>,
+    /// Export the density of the grid cells into density.png with a world file (density.pgw)
+    /// in the output directory, with a pixel for each cell. The cells are colored by their
+    /// content relative to the --qtree-capacity, and the cells that exceed it are red.
+    #[arg(long)]
+    pub density_export: bool,
+    /// Log the statistics of the grid cells, such as the number of features and vertices per
+    /// cell and the fraction of the empty cells, and write them to grid_stats.json in the output
+    /// directory. Then exit without building the quadtree and converting the tiles. Helps to
+    /// choose the --qtree-capacity and the --grid-cellsize before a full run. There is no run
+    /// report, so it cannot be combined with --post-run-cmd.
+    #[arg(long, conflicts_with = "post_run_cmd")]
+    pub grid_stats: bool,
     /// Load instances from this directory.
     /// In debug mode, tyler writes the generated world, quadtree etc. instances to .bincode files, which later can be used for debugging.
     /// When this argument is specified, tyler will load the instances from the .bincode files that are available in the directory.
     #[arg(long, value_parser = existing_canonical_path)]
     pub debug_load_data: Option<PathBuf>,
-    /// The maximum number of vertices in a leaf of the quadtree.
+    /// Write the feature index (the grid and the quadtree) into index_cache.bincode in the output
+    /// directory, and load it instead of indexing the features again in the next run, if the
+    /// input files and the indexing options are unchanged. The input files are compared by
+    /// their size and modification time.
+    #[arg(long, conflicts_with = "debug_load_data")]
+    pub reuse_index: bool,
+    /// The maximum number of vertices in a leaf of the quadtree, or the maximum of the items of
+    /// the --qtree-criteria, or the maximum sum of the feature weights if --feature-weights is
+    /// set.
     #[arg(long, default_value = "42000")]
     pub qtree_capacity: Option<usize>,
+    /// The items to count for the quadtree leaf capacity: the features (objects), their
+    /// vertices, their triangles, the estimated content size in bytes, or a weighted
+    /// combination of these (weighted). Ignored if --feature-weights is set.
+    #[arg(long, value_enum, default_value = "vertices")]
+    pub qtree_criteria: crate::spatial_structs::QuadTreeCriteria,
+    /// The weights of the criteria for --qtree-criteria weighted, as eg.
+    /// 'objects=1000,vertices=1,triangles=0.5,bytes=0'. The missing criteria have a weight of 0.
+    #[arg(long, required_if_eq("qtree_criteria", "weighted"))]
+    pub qtree_criteria_weights: Option<crate::spatial_structs::WeightedCriteria>,
+    /// The deepest level of the quadtree, where the root is level 0. The nodes at this level
+    /// are leaves even if they exceed the --qtree-capacity, for a predictable number of tiles.
+    #[arg(long, conflicts_with_all = ["tile_index", "flat_grid", "partition_scheme", "split_oversized_content"])]
+    pub qtree_max_depth: Option<u16>,
+    /// The shallowest level of the leaves of the quadtree, where the root is level 0. The nodes
+    /// above this level are split even if they fit in the --qtree-capacity, for at least this
+    /// many levels to stream. The grid cells are not split, so the leaves are not deeper than
+    /// the cells.
+    #[arg(long, conflicts_with_all = ["tile_index", "flat_grid", "partition_scheme"])]
+    pub qtree_min_depth: Option<u16>,
+    /// Collapse the sibling leaves of the quadtree into their parent, if they have at most this
+    /// many features together, even if they exceed the --qtree-capacity. Avoids many tiny
+    /// tiles in the sparse areas. The merge is repeated upwards, but not above the
+    /// --qtree-min-depth.
+    #[arg(long, conflicts_with_all = ["tile_index", "flat_grid", "partition_scheme"])]
+    pub qtree_merge_features: Option<usize>,
+    /// The maximum estimated content size of a quadtree leaf, eg. '50MB'. Possible units are
+    /// 'B', 'KB', 'MB', 'GB' (powers of 1024). The leaves that exceed it are split further,
+    /// regardless of the --qtree-criteria, until they fit or they are a single grid cell. The
+    /// size is estimated from the vertices and triangles of the features, like for
+    /// --qtree-criteria bytes.
+    #[arg(long, value_parser = byte_size, conflicts_with_all = ["tile_index", "flat_grid", "partition_scheme"])]
+    pub max_tile_bytes: Option<u64>,
+    /// Seed for the choices that would otherwise be arbitrary: the grid cell of a feature when
+    /// several cells have the same number of its vertices, and the --content-debug-colors.
+    /// Two runs with the same input and seed produce the same tiles. The seed is recorded in
+    /// the run report and the STAC Item.
+    #[arg(long, default_value = "0")]
+    pub seed: u64,
+    /// Order the tiles of a flat tileset and the conversion of the tiles along the Hilbert
+    /// curve of the grid, instead of the column/row order of the cells and the breadth-first
+    /// order of the tileset. The neighbouring tiles get adjacent IDs and are written one after
+    /// the other, which improves the locality of the output on disk and in CDN caches.
+    #[arg(long)]
+    pub hilbert_order: bool,
+    /// Tile layout that replaces the quadtree, as a GeoJSON FeatureCollection of polygons in the
+    /// input CRS. Each feature is assigned to the polygon that contains its centroid, and each
+    /// polygon with features becomes a tile of a flat tileset.
+    #[arg(long, value_parser = existing_path, conflicts_with_all = ["cesium3dtiles_implicit", "lod_interior", "split_oversized_content", "precision_diagnostics"])]
+    pub tile_index: Option<PathBuf>,
+    /// Generate a flat tileset directly from the grid, without the quadtree. The root has a
+    /// leaf for each non-empty grid cell, named by the cell ID '<column>-<row>'. Suits the
+    /// datasets where every cell has about the same content, eg. terrain patches.
+    #[arg(long, conflicts_with_all = ["tile_index", "cesium3dtiles_implicit", "lod_interior", "split_oversized_content", "precision_diagnostics"])]
+    pub flat_grid: bool,
+    /// Generate a flat tileset from the grid like --flat-grid, but only if the quadtree does
+    /// not merge any of the grid cells, because each cell reaches the --qtree-capacity.
+    #[arg(long, conflicts_with_all = ["flat_grid", "tile_index", "cesium3dtiles_implicit", "lod_interior", "split_oversized_content", "precision_diagnostics"])]
+    pub flat_grid_auto: bool,
+    /// Slice the grid cells of --flat-grid into layers of this height too, in the units of the
+    /// input CRS, so that the features that are stacked in a cell, eg. tunnels, utilities and
+    /// towers, are in separate tiles with tighter bounding volumes. A feature is in the layer
+    /// of the middle of its height.
+    #[arg(long, requires = "flat_grid", value_parser = positive_f64)]
+    pub grid_cellsize_z: Option<f64>,
+    /// The tree that partitions the features into the tiles, by default the quadtree.
+    /// The 'octree' also subdivides the height where the data is at least as tall as wide, eg.
+    /// in high-rise districts. The 'kdtree' splits the nodes at the median feature centroid,
+    /// for datasets with a very uneven feature density. Both use the same --qtree-capacity.
+    #[arg(long, value_enum, conflicts_with_all = ["tile_index", "flat_grid", "flat_grid_auto", "cesium3dtiles_implicit", "lod_interior", "split_oversized_content", "precision_diagnostics"])]
+    pub partition_scheme: Option<crate::spatial_structs::PartitionScheme>,
+    /// Split the height of the octree nodes on this level and below, and split the nodes
+    /// above it like a quadtree. Replaces the default rule of the octree, which splits the
+    /// height of the nodes that are at least as tall as wide.
+    #[arg(long, requires = "partition_scheme")]
+    pub octree_z_level: Option<u16>,
+    /// Split the height of the octree nodes that are taller than this, in the units of the
+    /// input CRS, eg. only the clusters of towers. Replaces the default rule of the octree, and
+    /// it can be combined with --octree-z-level.
+    #[arg(long, requires = "partition_scheme", value_parser = positive_f64)]
+    pub octree_z_height: Option<f64>,
+    /// What to do with the features that are larger than the leaf of the quadtree that they
+    /// are in, eg. stadiums and terrain patches. By default they stay in the leaf. With
+    /// 'promote', they are moved to the content of the lowest ancestor tile that is at least as
+    /// large as the feature, which is refined with 'ADD', so that they are rendered early and
+    /// they are not clipped to the leaf.
+    #[arg(long, value_enum, conflicts_with_all = ["tile_index", "flat_grid", "flat_grid_auto", "partition_scheme", "cesium3dtiles_implicit", "lod_interior", "split_oversized_content"])]
+    pub large_feature_policy: Option<crate::spatial_structs::LargeFeaturePolicy>,
+    /// How the content of the quadtree tiles is named, in the content URIs, and in the file
+    /// names of the content, the tile inputs and the CityJSON tiles. 'level-x-y' is the tile ID
+    /// '<level>/<x>/<y>', 'quadkey' is a digit for each level below the root, and 'morton' is
+    /// '<level>-<morton>', with the Morton-code of the tile within its level. The implicit
+    /// tiling always uses 'level-x-y', as required by its URI template.
+    #[arg(long, value_enum, default_value = "level-x-y", conflicts_with_all = ["tile_index", "flat_grid", "flat_grid_auto", "partition_scheme", "cesium3dtiles_implicit"])]
+    pub tile_naming: crate::spatial_structs::TileNaming,
+    /// The property of the --tile-index polygons that contains the tile ID. If the property is
+    /// missing, the GeoJSON Feature 'id' is used.
+    #[arg(long, default_value = "id", requires = "tile_index")]
+    pub tile_index_id: String,
+    /// Assign the features whose bounding box is at least this long, in the units of the input
+    /// CRS, to every tile that their bounding box intersects, instead of only to the tile of
+    /// their centroid or of the most of their vertices. For long features that span many
+    /// tiles, eg. roads and dikes.
+    #[arg(long)]
+    pub large_footprint_size: Option<f64>,
+    /// Insert every feature into all the grid cells that its bounding box intersects, instead of
+    /// only into the cell of the most of its vertices, so that the features do not stick out
+    /// of the bounding volume of their tiles. The features are in the content of all of these
+    /// tiles. The vertices and the weight of a feature are still counted in one cell.
+    #[arg(long, conflicts_with = "large_footprint_size")]
+    pub grid_insert_bbox: bool,
+    /// CSV file with precomputed feature weights, eg. the content size or the rendering cost
+    /// from a previous run. The first column is the CityObject ID and the second column is the
+    /// weight. If set, the quadtree capacity is the sum of the feature weights in a leaf,
+    /// instead of the number of vertices.
+    #[arg(long, value_parser = existing_path)]
+    pub feature_weights: Option<PathBuf>,
     /// Path to the geoflow executable for clipping and exporting the gltf files.
     #[arg(long, value_parser = existing_path)]
     pub exe_geof: Option<PathBuf>,
     #[arg(long)]
     pub verbose_geof: bool,
-    /// Maximum error that is allowed in mesh simplification to reduce the number of vertices. Value should be a float that represents that maximum allowed error in meters. Ignored for building object types.
+    /// A command that post-processes the content of each tile after its conversion, eg.
+    /// 'gltfpack -cc -kn -tc -i {tile} -o {tile}'. The {tile} placeholder is replaced by the path
+    /// of the content file, which must be modified in place. The statistics of the tile are
+    /// available in the {tile_level}, {tile_nr_features}, {tile_nr_vertices} and
+    /// {tile_capacity} placeholders. You can specify it multiple times, and the commands are
+    /// run in the given order. The tile fails if a command fails.
+    #[arg(long)]
+    pub post_process: Vec<String>,
+    /// A command that is run after each tile is finished, eg. to upload the tile right away.
+    /// The placeholders {tile_id}, {path}, {success}, {duration_ms} and {content_size} are
+    /// replaced by the values of the tile, and the statistics placeholders of --post-process
+    /// too. A failing command does not fail the tile.
+    #[arg(long)]
+    pub post_tile_cmd: Option<String>,
+    /// A command that is run before the run starts, eg. to lock a dataset version. The
+    /// placeholders {output} and {report} are replaced by the output directory and the path of
+    /// the run report. The run fails if the command fails.
+    #[arg(long)]
+    pub pre_run_cmd: Option<String>,
+    /// A command that is run after the run is finished, eg. to send a notification or to swap a
+    /// CDN alias. The placeholders {output} and {report} are replaced by the output directory
+    /// and the path of the run report. The run fails if the command fails.
+    #[arg(long)]
+    pub post_run_cmd: Option<String>,
+    /// Write a provisional tileset.json and report.json every this many seconds while the tiles
+    /// are converted, so that the finished areas of a long run can be previewed and monitored.
+    /// The tiles that are not converted yet have no content in the provisional tileset. The
+    /// files are replaced by the final ones at the end of the run.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub snapshot_interval: Option<u64>,
+    /// Write the output of the conversion subprocess (eg. geof, gltfpack) and the timings of
+    /// each tile into a separate log file in the 'logs' directory of the output. The log files
+    /// are referenced from the run report (report.json). The console only receives the
+    /// summary lines.
+    #[arg(long)]
+    pub tile_logs: bool,
+    /// Maximum error that is allowed in mesh simplification to reduce the number of vertices. Value should be a float that represents that maximum allowed error in meters. Ignored for building object types. The error of the tiles that exceed the --qtree-capacity is scaled up with their load, up to 4 times this value.
     #[arg(long, default_value = "1.0")]
     pub simplification_max_error: Option<f64>,
     /// Compute smooth vertex normals.
     #[arg(long)]
     pub smooth_normals: bool,
+    /// Vertical exaggeration factor. The z coordinates of the tile content and the bounding
+    /// volumes are multiplied by this value, eg. 2.0 doubles the heights. It needs
+    /// '--content-writer native' or --content-preview, because geof does not scale the content.
+    #[arg(long, default_value = "1.0", value_parser = positive_f64)]
+    pub z_scale: f64,
+    /// The maximum size of the content of a tile, eg. '10MB'. Possible units are 'B', 'KB', 'MB',
+    /// 'GB' (powers of 1024). Tiles whose content exceeds this size are flagged in the run
+    /// report.
+    #[arg(long, value_parser = byte_size)]
+    pub max_content_size: Option<u64>,
+    /// Split the tiles whose content exceeds the --max-content-size into four children, and
+    /// export the children instead. The splitting is repeated until the content fits in the
+    /// budget, or the tile cannot be split further. Only supported for explicit tiling.
+    #[arg(long, requires = "max_content_size")]
+    pub split_oversized_content: bool,
     /// Wait for the tile conversion process to finish, or terminate it if it is not finished after the provided number of seconds.
     #[arg(long)]
     pub timeout: Option<u64>,
@@ -174,6 +558,59 @@ pub struct Cli {
     /// LoD to use in output for lod_generic_city_object features
     #[arg(long)]
     pub lod_generic_city_object: Option<String>,
+    /// The refinement of the tiles. With 'add', the content of the interior tiles (see
+    /// --lod-interior) stays visible and the children add their content to it, instead of
+    /// replacing it.
+    #[arg(long, value_enum, default_value = "replace")]
+    pub refine: crate::formats::cesium3dtiles::Refinement,
+    /// LoD to use in output for the content of the interior tiles, for all CityObject types.
+    /// When set, the interior tiles also receive content, so that the refinement replaces the
+    /// coarse models of the interior tiles with the detailed models of the leaves (eg. LoD1.2
+    /// blocks in the interior tiles and LoD2.2 models in the leaves).
+    #[arg(long)]
+    pub lod_interior: Option<String>,
+    /// The number of levels above the deepest level of the tileset, that receive interior
+    /// content with the --lod-interior LoD.
+    #[arg(long, default_value = "1", requires = "lod_interior")]
+    pub lod_interior_levels: u16,
+    /// The LoDs of the interior tiles on the levels right above the leaves, from the coarsest
+    /// to the finest, eg. '--lod-interior 1.2 --lod-interior-cascade 1.3 2.2'. The last LoD is
+    /// used one level above the leaves, the one before it two levels above, and so on. The
+    /// levels further up use the --lod-interior LoD. Set --lod-interior-levels to the number
+    /// of levels that receive content.
+    #[arg(long, num_args = 1.., requires = "lod_interior", conflicts_with = "cesium3dtiles_implicit")]
+    pub lod_interior_cascade: Vec<String>,
+    /// Count the --lod-interior-levels from the leaves of each subtree, instead of the deepest
+    /// level of the tileset. Thus each leaf has the same number of ancestors with content, also
+    /// in the sparse areas where the quadtree is shallow.
+    #[arg(long, requires = "lod_interior")]
+    pub lod_interior_adaptive: bool,
+    /// Append the LoD of the models to the name of the tile content files, eg.
+    /// t/3/5/2-lod2.2.glb. The quadtree level and the LoD are always recorded in the tile
+    /// metadata.
+    #[arg(long, conflicts_with = "cesium3dtiles_implicit")]
+    pub content_lod_names: bool,
+    /// CityObject attribute that marks the important features, eg. 'landmark'. The content of
+    /// the tiles that contain a feature with a true value in the attribute is put into a 3D
+    /// Tiles content group with 'priority: true', so that the clients can load it first over
+    /// slow connections. True values are true, non-zero numbers and strings other than
+    /// 'false', 'no' and '0'.
+    #[arg(long, conflicts_with = "cesium3dtiles_implicit")]
+    pub priority_attribute: Option<String>,
+    /// CityObject attribute that assigns the features to groups, eg. 'district' or
+    /// 'delivery_batch'. The content of the tiles is put into a 3D Tiles content group per
+    /// attribute value, so that the clients can show, hide or authorize the groups separately.
+    /// The content of a tile belongs to the group of the most of its features.
+    #[arg(
+        long,
+        conflicts_with_all = ["cesium3dtiles_implicit", "priority_attribute"]
+    )]
+    pub group_attribute: Option<String>,
+    /// Color the content of each tile, or each tileset level, with a distinct color instead of
+    /// the CityObject type colors. Used for visually checking the tile boundaries, refinement
+    /// and LoD switching.
+    #[arg(long, value_enum)]
+    pub content_debug_colors: Option<crate::DebugColors>,
     /// Color for Building features specified as a hex rgb-color value, eg. #FF0000 is red.
     #[arg(long, value_parser = hex_color)]
     pub color_building: Option<String>,
@@ -238,9 +675,6 @@ pub struct Cli {
     // Counted from the leaves.
     // #[arg(long, default_value = "0")]
     // pub qtree_export_levels: Option<u16>,
-    // /// The criteria to check for the quadtree leaf capacity.
-    // #[arg(long, value_enum, default_value = "vertices")]
-    // pub qtree_criteria: Option<crate::spatial_structs::QuadTreeCriteria>,
     // /// Path to the python interpreter (>=3.8) to use for generating CityJSON tiles.
     // /// The interpreter must have a recent cjio (https://github.com/cityjson/cjio)
     // /// installed.
@@ -254,6 +688,74 @@ pub struct Cli {
     pub bag3d_attributes_per_part: bool,
 }
 
+impl Cli {
+    /// The LoD that is set for the CityObject type with the --lod-<cityobject type> arguments.
+    pub fn lod_of(&self, cotype: &crate::parser::CityObjectType) -> Option<&str> {
+        use crate::parser::CityObjectType as T;
+        match cotype {
+            T::Building => &self.lod_building,
+            T::BuildingPart => &self.lod_building_part,
+            T::BuildingInstallation => &self.lod_building_installation,
+            T::TINRelief => &self.lod_tin_relief,
+            T::Road => &self.lod_road,
+            T::Railway => &self.lod_railway,
+            T::TransportSquare => &self.lod_transport_square,
+            T::WaterBody => &self.lod_water_body,
+            T::PlantCover => &self.lod_plant_cover,
+            T::SolitaryVegetationObject => &self.lod_solitary_vegetation_object,
+            T::LandUse => &self.lod_land_use,
+            T::CityFurniture => &self.lod_city_furniture,
+            T::Bridge => &self.lod_bridge,
+            T::BridgePart => &self.lod_bridge_part,
+            T::BridgeInstallation => &self.lod_bridge_installation,
+            T::BridgeConstructiveElement => &self.lod_bridge_construction_element,
+            T::Tunnel => &self.lod_tunnel,
+            T::TunnelPart => &self.lod_tunnel_part,
+            T::TunnelInstallation => &self.lod_tunnel_installation,
+            T::GenericCityObject => &self.lod_generic_city_object,
+            _ => &None,
+        }
+        .as_deref()
+    }
+
+    /// The LoD label of the leaf content, which lists the distinct LoD-s that are set for
+    /// the CityObject types, or 'all' if no LoD is set.
+    pub fn lod_leaf(&self) -> String {
+        let mut lods: Vec<&str> = [
+            &self.lod_building,
+            &self.lod_building_part,
+            &self.lod_building_installation,
+            &self.lod_tin_relief,
+            &self.lod_road,
+            &self.lod_railway,
+            &self.lod_transport_square,
+            &self.lod_water_body,
+            &self.lod_plant_cover,
+            &self.lod_solitary_vegetation_object,
+            &self.lod_land_use,
+            &self.lod_city_furniture,
+            &self.lod_bridge,
+            &self.lod_bridge_part,
+            &self.lod_bridge_installation,
+            &self.lod_bridge_construction_element,
+            &self.lod_tunnel,
+            &self.lod_tunnel_part,
+            &self.lod_tunnel_installation,
+            &self.lod_generic_city_object,
+        ]
+        .into_iter()
+        .filter_map(|lod| lod.as_deref())
+        .collect();
+        lods.sort_unstable();
+        lods.dedup();
+        if lods.is_empty() {
+            String::from("all")
+        } else {
+            lods.join(",")
+        }
+    }
+}
+
 fn existing_canonical_path(s: &str) -> Result<PathBuf, String> {
     if let Ok(c) = Path::new(s).canonicalize() {
         if c.exists() {
@@ -295,6 +797,168 @@ fn hex_color(s: &str) -> Result<String, String> {
     Ok(String::from(s))
 }
 
+/// The commands that work on the output of a previous run, and the service mode, instead of
+/// generating a new tileset.
+#[derive(Parser, Debug)]
+#[command(name = "tyler", author, version, about)]
+pub struct ToolsCli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+impl ToolsCli {
+    /// Returns `true` if `arg` is the name of one of the commands.
+    pub fn is_command(arg: &str) -> bool {
+        use clap::CommandFactory;
+        Self::command()
+            .get_subcommands()
+            .any(|c| c.get_name() == arg)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Copy the tiles that intersect a bounding box from the output of a previous run into a
+    /// new, self-contained tileset.
+    Extract {
+        /// The bounding box in WGS84 longitude, latitude degrees, in the format of
+        /// 'min_lon,min_lat,max_lon,max_lat'.
+        #[arg(long, value_parser = bbox_2d, allow_hyphen_values = true)]
+        bbox: [f64; 4],
+        /// The output directory of a previous run, which contains the tileset.json.
+        #[arg(value_parser = existing_path)]
+        input: PathBuf,
+        /// Directory for the extracted tileset.
+        output: PathBuf,
+    },
+    /// Write a lighter version of the tileset from the output of a previous run, which only
+    /// contains the levels up to a maximum level.
+    Thin {
+        /// The deepest level that is kept. The root is at level 0.
+        #[arg(long)]
+        max_level: u16,
+        /// The output directory of a previous run, which contains the tileset.json.
+        #[arg(value_parser = existing_path)]
+        input: PathBuf,
+        /// Directory for the thinned tileset.
+        output: PathBuf,
+    },
+    /// Print the feature IDs, attribute table, vertex and triangle counts, and the used
+    /// extensions of a GLB tile, and its bounding volume from the tileset.
+    Inspect {
+        /// The GLB file of the tile, eg. t/4/3/2.glb.
+        #[arg(value_parser = existing_path)]
+        tile: PathBuf,
+        /// The tileset.json that references the tile. By default, the tileset.json is searched
+        /// in the parent directories of the tile.
+        #[arg(long, value_parser = existing_path)]
+        tileset: Option<PathBuf>,
+    },
+    /// Run as a service that re-tiles the input when it changes, on a schedule or when the
+    /// 'retile' file is created in the output directory. Each run is written into a new
+    /// version directory, which is published atomically when the run succeeded.
+    Daemon {
+        /// The JSON configuration file of the service.
+        #[arg(long, value_parser = existing_path)]
+        config: PathBuf,
+    },
+}
+
+/// Parses a 2D bounding box in the format of 'min_x,min_y,max_x,max_y'.
+fn bbox_2d(s: &str) -> Result<[f64; 4], String> {
+    let coords = s
+        .split(',')
+        .map(|c| c.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| format!("invalid bounding box {:?}", s))?;
+    let bbox: [f64; 4] = coords.try_into().map_err(|_| {
+        format!(
+            "bounding box must be in the format of 'min_x,min_y,max_x,max_y': {:?}",
+            s
+        )
+    })?;
+    if bbox[0] > bbox[2] || bbox[1] > bbox[3] {
+        return Err(format!("the minimum is larger than the maximum: {:?}", s));
+    }
+    Ok(bbox)
+}
+
+/// Parses a 3D bounding box in the format of 'min_x,min_y,min_z,max_x,max_y,max_z'.
+fn bbox_3d(s: &str) -> Result<[f64; 6], String> {
+    let coords = s
+        .split(',')
+        .map(|c| c.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| format!("invalid bounding box {:?}", s))?;
+    let bbox: [f64; 6] = coords.try_into().map_err(|_| {
+        format!(
+            "bounding box must be in the format of 'min_x,min_y,min_z,max_x,max_y,max_z': {:?}",
+            s
+        )
+    })?;
+    if bbox[0] > bbox[3] || bbox[1] > bbox[4] || bbox[2] > bbox[5] {
+        return Err(format!("the minimum is larger than the maximum: {:?}", s));
+    }
+    Ok(bbox)
+}
+
+/// Parses a 3D coordinate in the format of 'x,y,z'.
+fn coordinate_3d(s: &str) -> Result<[f64; 3], String> {
+    let coords = s
+        .split(',')
+        .map(|c| c.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| format!("invalid coordinate {:?}", s))?;
+    coords
+        .try_into()
+        .map_err(|_| format!("coordinate must be in the format of 'x,y,z': {:?}", s))
+}
+
+/// Parses an EPSG code in the format of 'EPSG:28992' or '28992'.
+fn epsg_code(s: &str) -> Result<u16, String> {
+    let code = s
+        .strip_prefix("EPSG:")
+        .or_else(|| s.strip_prefix("epsg:"))
+        .unwrap_or(s);
+    code.parse::<u16>()
+        .map_err(|_| format!("invalid EPSG code {:?}, expected eg. 'EPSG:28992'", s))
+}
+
+/// Parses a float that must be larger than zero.
+fn positive_f64(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("invalid number {:?}", s))?;
+    if value > 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("value must be larger than zero: {}", value))
+    }
+}
+
+/// Parses a size in bytes with an optional unit, eg. '10MB', '512 KB', '1000'.
+fn byte_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split_at = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split_at);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid size value in {:?}", s))?;
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024_u64.pow(2),
+        "G" | "GB" => 1024_u64.pow(3),
+        _ => {
+            return Err(format!(
+                "invalid size unit in {:?}, possible units are 'B', 'KB', 'MB', 'GB'",
+                s
+            ))
+        }
+    };
+    Ok((value * multiplier as f64) as u64)
+}
+
 #[cfg(test)]
 mod tests {
     use super::Cli;
@@ -319,6 +983,44 @@ mod tests {
         Cli::command().debug_assert()
     }
 
+    #[test]
+    fn verify_post_process() {
+        let args: Vec<&'static str> = vec![
+            "tyler",
+            "-m",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"),
+            "-f",
+            env!("CARGO_MANIFEST_DIR"),
+            "-o",
+            env!("CARGO_MANIFEST_DIR"),
+            "--post-process",
+            "gltfpack -cc -kn -i {tile} -o {tile}",
+            "--post-process",
+            "gltf-transform meshopt {tile} {tile}",
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.post_process.len(), 2);
+        assert_eq!(cli.post_process[1], "gltf-transform meshopt {tile} {tile}");
+    }
+
+    #[test]
+    fn verify_grid_stats() {
+        let args: Vec<&'static str> = vec![
+            "tyler",
+            "-m",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"),
+            "-f",
+            env!("CARGO_MANIFEST_DIR"),
+            "-o",
+            env!("CARGO_MANIFEST_DIR"),
+            "--grid-stats",
+        ];
+        assert!(Cli::try_parse_from(&args).unwrap().grid_stats);
+        let mut args = args;
+        args.extend(["--post-run-cmd", "echo {report}"]);
+        assert!(Cli::try_parse_from(&args).is_err());
+    }
+
     /// Can we pass multiple CityObject types?
     #[test]
     fn verify_object_types() {
@@ -331,4 +1033,115 @@ mod tests {
         assert!(otypes.contains(&crate::parser::CityObjectType::Building));
         assert!(otypes.contains(&crate::parser::CityObjectType::PlantCover));
     }
+
+    #[test]
+    fn verify_byte_size() {
+        assert_eq!(super::byte_size("10MB").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(super::byte_size("1.5 kb").unwrap(), 1536);
+        assert_eq!(super::byte_size("1000").unwrap(), 1000);
+        assert!(super::byte_size("10XB").is_err());
+        assert!(super::byte_size("MB").is_err());
+    }
+
+    #[test]
+    fn verify_coordinate_3d() {
+        assert_eq!(
+            super::coordinate_3d("85000,446000.5,-10").unwrap(),
+            [85000.0, 446000.5, -10.0]
+        );
+        assert!(super::coordinate_3d("1,2").is_err());
+        assert!(super::coordinate_3d("1,a,2").is_err());
+    }
+
+    #[test]
+    fn verify_extract() {
+        let args = vec![
+            "tyler",
+            "extract",
+            "--bbox",
+            "-4.1,52.0,-4.0,52.1",
+            env!("CARGO_MANIFEST_DIR"),
+            "mini",
+        ];
+        assert!(super::ToolsCli::is_command(args[1]));
+        assert!(!super::ToolsCli::is_command("--metadata"));
+        let tools_cli = super::ToolsCli::try_parse_from(args).unwrap();
+        if let super::Command::Extract { bbox, output, .. } = tools_cli.command {
+            assert_eq!(bbox, [-4.1, 52.0, -4.0, 52.1]);
+            assert_eq!(output, std::path::PathBuf::from("mini"));
+        } else {
+            panic!("expected the extract command");
+        }
+        assert!(super::bbox_2d("1,2,0,3").is_err());
+    }
+
+    #[test]
+    fn verify_format() {
+        let mut args: Vec<&'static str> = vec![
+            "tyler",
+            "-m",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"),
+            "-f",
+            env!("CARGO_MANIFEST_DIR"),
+            "-o",
+            env!("CARGO_MANIFEST_DIR"),
+        ];
+        let cli = Cli::try_parse_from(&args).unwrap();
+        assert_eq!(cli.format, vec![crate::Formats::_3DTiles]);
+        args.extend(["--format", "3dtiles,cityjson"]);
+        let cli = Cli::try_parse_from(&args).unwrap();
+        assert_eq!(
+            cli.format,
+            vec![crate::Formats::_3DTiles, crate::Formats::CityJSON]
+        );
+    }
+
+    #[test]
+    fn verify_lod_leaf() {
+        let mut args: Vec<&'static str> = vec![
+            "tyler",
+            "-m",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"),
+            "-f",
+            env!("CARGO_MANIFEST_DIR"),
+            "-o",
+            env!("CARGO_MANIFEST_DIR"),
+        ];
+        let cli = Cli::try_parse_from(&args).unwrap();
+        assert_eq!(cli.lod_leaf(), "all");
+        args.extend([
+            "--lod-building",
+            "2.2",
+            "--lod-building-part",
+            "2.2",
+            "--lod-tin-relief",
+            "1",
+        ]);
+        let cli = Cli::try_parse_from(&args).unwrap();
+        assert_eq!(cli.lod_leaf(), "1,2.2");
+    }
+
+    #[test]
+    fn verify_epsg_code() {
+        assert_eq!(super::epsg_code("EPSG:28992").unwrap(), 28992);
+        assert_eq!(super::epsg_code("7415").unwrap(), 7415);
+        assert!(super::epsg_code("EPSG:abc").is_err());
+    }
+
+    #[test]
+    fn verify_bbox_3d() {
+        assert_eq!(
+            super::bbox_3d("-1,2,-3.5,4,5,6").unwrap(),
+            [-1.0, 2.0, -3.5, 4.0, 5.0, 6.0]
+        );
+        assert!(super::bbox_3d("1,2,3,4").is_err());
+        assert!(super::bbox_3d("1,2,3,4,5,2").is_err());
+    }
+
+    #[test]
+    fn verify_positive_f64() {
+        assert_eq!(super::positive_f64("2.5").unwrap(), 2.5);
+        assert!(super::positive_f64("0").is_err());
+        assert!(super::positive_f64("-1").is_err());
+    }
 }
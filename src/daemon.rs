@@ -0,0 +1,471 @@
+//! Service mode, that keeps the tileset of an input location up to date.
+//! The daemon watches the input, re-tiles it into a new version directory when it changes, on a
+//! schedule or on a trigger, and publishes the version atomically when the run succeeded.
+//! The state of the daemon is served as JSON over HTTP.
+// Copyright 2023 Balázs Dukai, Ravi Peters
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+/// The name of the file in the output directory that triggers a run when it exists.
+const TRIGGER_FILE: &str = "retile";
+/// The name of the file in the output directory that records the published version.
+const CURRENT_FILE: &str = "current.json";
+/// How long the status server waits for the request line of a client.
+const STATUS_READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// The maximum length of the request line that the status server reads.
+const MAX_REQUEST_LINE: u64 = 8192;
+
+/// The configuration of the daemon, read from a JSON file.
+///
+/// ```json
+/// {
+///   "watch": ["/data/features", "/data/metadata.city.json"],
+///   "args": ["--metadata", "/data/metadata.city.json", "--features", "/data/features", "--format", "3dtiles"],
+///   "output": "/srv/tiles",
+///   "poll_interval": "1m",
+///   "schedule": "24h",
+///   "keep_versions": 3,
+///   "status_address": "127.0.0.1:8080"
+/// }
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// The files and directories that are watched for changes, normally the `--metadata` and
+    /// the `--features` of the run.
+    pub watch: Vec<PathBuf>,
+    /// The arguments of the tyler run, without `--output`, which is set by the daemon.
+    pub args: Vec<String>,
+    /// The directory of the versions and the published version.
+    pub output: PathBuf,
+    /// How often the watched paths are checked for changes, eg. `30s`.
+    #[serde(default = "DaemonConfig::default_poll_interval")]
+    pub poll_interval: String,
+    /// Re-tile after this time since the last run, also if the input did not change, eg. `24h`.
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// The number of versions that are kept, including the published version.
+    #[serde(default = "DaemonConfig::default_keep_versions")]
+    pub keep_versions: usize,
+    /// The address of the status endpoint, eg. `127.0.0.1:8080`.
+    #[serde(default)]
+    pub status_address: Option<String>,
+}
+
+impl DaemonConfig {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let config: Self = serde_json::from_reader(BufReader::new(File::open(path.as_ref())?))?;
+        if config.args.iter().any(|a| a == "--output" || a == "-o") {
+            return Err(
+                "the output of the runs is set by the daemon, remove --output from the args".into(),
+            );
+        }
+        if config.keep_versions == 0 {
+            return Err("keep_versions must be at least 1".into());
+        }
+        Ok(config)
+    }
+
+    fn default_poll_interval() -> String {
+        "1m".to_string()
+    }
+
+    fn default_keep_versions() -> usize {
+        3
+    }
+}
+
+/// The state of the daemon, that is served by the status endpoint.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Status {
+    pub running: bool,
+    /// The published version, which is the name of its directory in `versions`.
+    pub current_version: Option<String>,
+    pub last_run_start: Option<String>,
+    pub last_run_duration_s: Option<f64>,
+    pub last_error: Option<String>,
+    pub nr_runs: usize,
+    pub nr_runs_failed: usize,
+}
+
+/// The file count, total size and latest modification time of the watched paths.
+/// The input changed if the fingerprint changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fingerprint {
+    nr_files: usize,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Fingerprint {
+    fn from_paths(paths: &[PathBuf]) -> Self {
+        let mut fingerprint = Self {
+            nr_files: 0,
+            size: 0,
+            modified: None,
+        };
+        for path in paths {
+            for entry in WalkDir::new(path).into_iter().filter_map(Result::ok) {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if !metadata.is_file() {
+                    continue;
+                }
+                fingerprint.nr_files += 1;
+                fingerprint.size += metadata.len();
+                if let Ok(modified) = metadata.modified() {
+                    fingerprint.modified = fingerprint.modified.max(Some(modified));
+                }
+            }
+        }
+        fingerprint
+    }
+}
+
+/// Run the daemon until it is stopped.
+/// The first run starts immediately, then the input is checked every `poll_interval`.
+pub fn run(path_config: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let config = DaemonConfig::from_file(path_config)?;
+    let poll_interval = humantime::parse_duration(&config.poll_interval)?;
+    let schedule = config
+        .schedule
+        .as_deref()
+        .map(humantime::parse_duration)
+        .transpose()?;
+    let exe = std::env::current_exe()?;
+    let path_versions = config.output.join("versions");
+    fs::create_dir_all(&path_versions)?;
+    let path_trigger = config.output.join(TRIGGER_FILE);
+
+    let status = Arc::new(Mutex::new(Status {
+        current_version: read_current(&config.output),
+        ..Default::default()
+    }));
+    if let Some(address) = &config.status_address {
+        let listener = TcpListener::bind(address)?;
+        info!("Serving the status on http://{address}/status");
+        let status = Arc::clone(&status);
+        thread::spawn(move || serve_status(listener, status));
+    }
+
+    let mut last_fingerprint: Option<Fingerprint> = None;
+    let mut last_run: Option<Instant> = None;
+    loop {
+        let fingerprint = Fingerprint::from_paths(&config.watch);
+        let triggered = path_trigger.exists();
+        let reason = if last_fingerprint.as_ref() != Some(&fingerprint) {
+            Some("the input changed")
+        } else if triggered {
+            Some("triggered")
+        } else if schedule.is_some_and(|s| last_run.is_some_and(|t| t.elapsed() >= s)) {
+            Some("scheduled")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            info!("Re-tiling, because {reason}");
+            if triggered {
+                let _ = fs::remove_file(&path_trigger);
+            }
+            last_fingerprint = Some(fingerprint);
+            last_run = Some(Instant::now());
+            retile(&config, &exe, &path_versions, &status);
+        }
+        thread::sleep(poll_interval);
+    }
+}
+
+/// Run tyler into a new version directory and publish the version if the run succeeded.
+fn retile(config: &DaemonConfig, exe: &Path, path_versions: &Path, status: &Mutex<Status>) {
+    let start = Instant::now();
+    let now = SystemTime::now();
+    let (version, path_version) = match create_version_dir(path_versions, now) {
+        Ok(version) => version,
+        Err(e) => {
+            error!("Failed to create the version directory in {path_versions:?}: {e}");
+            let mut status = status.lock().unwrap();
+            status.nr_runs += 1;
+            status.nr_runs_failed += 1;
+            status.last_error = Some(e.to_string());
+            return;
+        }
+    };
+    {
+        let mut status = status.lock().unwrap();
+        status.running = true;
+        status.last_run_start = Some(humantime::format_rfc3339_seconds(now).to_string());
+    }
+    let result = std::process::Command::new(exe)
+        .args(&config.args)
+        .arg("--output")
+        .arg(&path_version)
+        .status()
+        .map_err(|e| e.to_string())
+        .and_then(|exit_status| {
+            if exit_status.success() {
+                Ok(())
+            } else {
+                Err(format!("the run failed with {exit_status}"))
+            }
+        })
+        .and_then(|_| publish(&config.output, &version).map_err(|e| e.to_string()));
+    let mut status = status.lock().unwrap();
+    status.running = false;
+    status.nr_runs += 1;
+    status.last_run_duration_s = Some(start.elapsed().as_secs_f64());
+    match result {
+        Ok(()) => {
+            info!("Published version {version}");
+            status.current_version = Some(version.clone());
+            status.last_error = None;
+            if let Err(e) = remove_old_versions(path_versions, config.keep_versions, &version) {
+                warn!("Failed to remove the old versions: {e}");
+            }
+        }
+        Err(e) => {
+            error!("Version {version} is not published, {e}");
+            status.nr_runs_failed += 1;
+            status.last_error = Some(e);
+            let _ = fs::remove_dir_all(&path_version);
+        }
+    }
+}
+
+/// Create the directory of a new version, named by the time of the run, eg.
+/// `2024-01-02T000000Z`. If there is already a version with that name, because the runs were
+/// in the same second, a number is appended, eg. `2024-01-02T000000Z-1`. The directory is new,
+/// so removing it after a failed run never removes a published version.
+fn create_version_dir(path_versions: &Path, now: SystemTime) -> std::io::Result<(String, PathBuf)> {
+    // Without the ':', which is not allowed in the file names on Windows
+    let name = humantime::format_rfc3339_seconds(now)
+        .to_string()
+        .replace(':', "");
+    let mut i = 0;
+    loop {
+        let version = match i {
+            0 => name.clone(),
+            _ => format!("{name}-{i}"),
+        };
+        let path_version = path_versions.join(&version);
+        match fs::create_dir(&path_version) {
+            Ok(()) => return Ok((version, path_version)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => i += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Publish the version atomically, by renaming a temporary file over the `current.json`, and
+/// on Unix also over the `current` symbolic link, so that the readers never see a partial
+/// state.
+fn publish(output: &Path, version: &str) -> std::io::Result<()> {
+    let path_tmp = output.join(format!("{CURRENT_FILE}.tmp"));
+    let current = serde_json::json!({
+        "version": version,
+        "tileset": format!("versions/{version}/tileset.json"),
+    });
+    let mut file = BufWriter::new(File::create(&path_tmp)?);
+    serde_json::to_writer_pretty(&mut file, &current)?;
+    file.flush()?;
+    drop(file);
+    fs::rename(&path_tmp, output.join(CURRENT_FILE))?;
+    #[cfg(unix)]
+    {
+        let path_link_tmp = output.join("current.tmp");
+        let _ = fs::remove_file(&path_link_tmp);
+        std::os::unix::fs::symlink(Path::new("versions").join(version), &path_link_tmp)?;
+        fs::rename(&path_link_tmp, output.join("current"))?;
+    }
+    Ok(())
+}
+
+/// The published version from the `current.json` of a previous run of the daemon.
+fn read_current(output: &Path) -> Option<String> {
+    let file = File::open(output.join(CURRENT_FILE)).ok()?;
+    let current: serde_json::Value = serde_json::from_reader(BufReader::new(file)).ok()?;
+    current["version"].as_str().map(String::from)
+}
+
+/// Remove the oldest versions, so that `keep` versions remain. The `current` version is never
+/// removed. The version names sort by time.
+fn remove_old_versions(path_versions: &Path, keep: usize, current: &str) -> std::io::Result<()> {
+    let mut versions: Vec<String> = fs::read_dir(path_versions)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    versions.sort();
+    let nr_remove = versions.len().saturating_sub(keep);
+    for version in versions.iter().take(nr_remove) {
+        if version != current {
+            info!("Removing version {version}");
+            fs::remove_dir_all(path_versions.join(version))?;
+        }
+    }
+    Ok(())
+}
+
+/// Serve the status as JSON on `/status` and `/`, one request at a time. A client that does
+/// not send its request line is dropped after [STATUS_READ_TIMEOUT], so that it does not
+/// block the other requests.
+fn serve_status(listener: TcpListener, status: Arc<Mutex<Status>>) {
+    for stream in listener.incoming().filter_map(Result::ok) {
+        let status = status.lock().unwrap().clone();
+        if let Err(e) = respond(stream, &status) {
+            warn!("Failed to respond to a status request: {e}");
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &Status) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(STATUS_READ_TIMEOUT))?;
+    let mut request_line = String::new();
+    // Only the request line is read, and at most MAX_REQUEST_LINE bytes of it
+    BufReader::new((&stream).take(MAX_REQUEST_LINE)).read_line(&mut request_line)?;
+    stream.write_all(&http_response(&request_line, status))
+}
+
+/// The HTTP response to the request with the `request_line`, eg. `GET /status HTTP/1.1`.
+fn http_response(request_line: &str, status: &Status) -> Vec<u8> {
+    let mut parts = request_line.split_whitespace();
+    let (code, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/" | "/status")) => {
+            ("200 OK", serde_json::to_string(status).unwrap_or_default())
+        }
+        (Some("GET"), _) => ("404 Not Found", r#"{"error":"not found"}"#.to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            r#"{"error":"method not allowed"}"#.to_string(),
+        ),
+    };
+    format!(
+        "HTTP/1.1 {code}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_and_remove_old_versions() -> std::io::Result<()> {
+        let output = std::env::temp_dir().join("tyler_test_daemon");
+        let _ = fs::remove_dir_all(&output);
+        let path_versions = output.join("versions");
+        for version in [
+            "2024-01-01T000000Z",
+            "2024-01-02T000000Z",
+            "2024-01-03T000000Z",
+        ] {
+            fs::create_dir_all(path_versions.join(version))?;
+        }
+        publish(&output, "2024-01-02T000000Z")?;
+        assert_eq!(read_current(&output).as_deref(), Some("2024-01-02T000000Z"));
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(output.join("current"))?,
+            Path::new("versions").join("2024-01-02T000000Z")
+        );
+        // The current version is kept, also if it is older than the kept versions
+        remove_old_versions(&path_versions, 1, "2024-01-02T000000Z")?;
+        assert!(!path_versions.join("2024-01-01T000000Z").exists());
+        assert!(path_versions.join("2024-01-02T000000Z").exists());
+        assert!(path_versions.join("2024-01-03T000000Z").exists());
+        fs::remove_dir_all(&output)
+    }
+
+    #[test]
+    fn test_create_version_dir() -> std::io::Result<()> {
+        let path_versions = std::env::temp_dir().join("tyler_test_daemon_version_dir");
+        let _ = fs::remove_dir_all(&path_versions);
+        fs::create_dir_all(&path_versions)?;
+        let now = SystemTime::UNIX_EPOCH;
+        // The runs in the same second get their own directories
+        let (first, _) = create_version_dir(&path_versions, now)?;
+        let (second, path_second) = create_version_dir(&path_versions, now)?;
+        assert_eq!(first, "1970-01-01T000000Z");
+        assert_eq!(second, "1970-01-01T000000Z-1");
+        assert!(path_second.is_dir());
+        fs::remove_dir_all(&path_versions)
+    }
+
+    #[test]
+    fn test_respond_long_request_line() -> std::io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let client = thread::spawn(move || -> std::io::Result<String> {
+            let mut stream = TcpStream::connect(address)?;
+            // A request line without its end, which is not waited for
+            stream.write_all(&vec![b'A'; MAX_REQUEST_LINE as usize])?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        });
+        let (stream, _) = listener.accept()?;
+        respond(stream, &Status::default())?;
+        let response = client.join().unwrap()?;
+        assert!(response.starts_with("HTTP/1.1 405"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fingerprint() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join("tyler_test_daemon_fingerprint");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("a.city.jsonl"), "{}")?;
+        let paths = vec![dir.clone()];
+        let before = Fingerprint::from_paths(&paths);
+        assert_eq!(before.nr_files, 1);
+        assert_eq!(before, Fingerprint::from_paths(&paths));
+        fs::write(dir.join("b.city.jsonl"), "{}")?;
+        assert_ne!(before, Fingerprint::from_paths(&paths));
+        fs::remove_dir_all(&dir)
+    }
+
+    #[test]
+    fn test_http_response() {
+        let status = Status {
+            nr_runs: 2,
+            ..Default::default()
+        };
+        let response =
+            String::from_utf8(http_response("GET /status HTTP/1.1\r\n", &status)).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let json: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(json["nr_runs"], 2);
+        assert_eq!(json["running"], false);
+        let response =
+            String::from_utf8(http_response("GET /tileset.json HTTP/1.1", &status)).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+        let response = String::from_utf8(http_response("POST /status HTTP/1.1", &status)).unwrap();
+        assert!(response.starts_with("HTTP/1.1 405"));
+    }
+}
@@ -0,0 +1,451 @@
+//! Reading of the geometry that the CityObjects reference with `+externalGeometry`, so that
+//! the native content writer can write it into the tile content.
+//! The meshes are read from local glTF (`.gltf`, `.glb`) and OBJ (`.obj`) files. Their vertices
+//! are in the coordinate reference system of the features, with the y-up axes of glTF in the
+//! glTF files (the z of the CRS is the y of glTF), and with the axes of the CRS in the OBJ
+//! files. Only the triangles of the glTF meshes are read, without the materials and the
+//! textures, and the glTF files that require an extension, such as a compression, are not
+//! supported.
+// Copyright 2023 Balázs Dukai, Ravi Peters
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::formats::gltf::Polygon;
+
+/// The magic of the binary glTF.
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+/// The maximum depth of the node hierarchy of a glTF, which guards against the cycles.
+const MAX_NODE_DEPTH: usize = 64;
+const IDENTITY: [f64; 16] = [
+    1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+];
+
+/// Read the surfaces of the external geometry file at `path`. The file is a glTF if it is a
+/// GLB or if its extension is `gltf`, and an OBJ if its extension is `obj`.
+pub fn read(path: &Path) -> Result<Vec<Polygon>, Box<dyn Error>> {
+    let data = fs::read(path)?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase);
+    if data.starts_with(GLB_MAGIC) {
+        // The chunks follow the 12 byte header, the JSON chunk first
+        let mut json: Option<Value> = None;
+        let mut bin: Option<&[u8]> = None;
+        let mut position = 12;
+        while position + 8 <= data.len() {
+            let length = u32::from_le_bytes(data[position..position + 4].try_into()?) as usize;
+            let chunk_type = u32::from_le_bytes(data[position + 4..position + 8].try_into()?);
+            let chunk = data
+                .get(position + 8..position + 8 + length)
+                .ok_or("the GLB is truncated")?;
+            match chunk_type {
+                GLB_CHUNK_JSON => json = Some(serde_json::from_slice(chunk)?),
+                GLB_CHUNK_BIN if bin.is_none() => bin = Some(chunk),
+                _ => {}
+            }
+            position += 8 + length;
+        }
+        gltf_polygons(&json.ok_or("the GLB has no JSON chunk")?, bin, path)
+    } else if extension.as_deref() == Some("gltf") {
+        gltf_polygons(&serde_json::from_slice(&data)?, None, path)
+    } else if extension.as_deref() == Some("obj") {
+        obj_polygons(&String::from_utf8_lossy(&data))
+    } else {
+        Err(format!("{path:?} is not a glTF or an OBJ file").into())
+    }
+}
+
+/// The triangles of the meshes of the default scene of the `gltf`, with the transformations
+/// of their nodes. The `bin` chunk of a GLB is the first buffer, the other buffers are read
+/// from the data URIs or from the files relative to the `path` of the glTF.
+fn gltf_polygons(
+    gltf: &Value,
+    bin: Option<&[u8]>,
+    path: &Path,
+) -> Result<Vec<Polygon>, Box<dyn Error>> {
+    if let Some(required) = gltf["extensionsRequired"].as_array() {
+        if !required.is_empty() {
+            return Err(
+                format!("the required glTF extensions {required:?} are not supported").into(),
+            );
+        }
+    }
+    let mut buffers: Vec<Vec<u8>> = Vec::new();
+    for (i, buffer) in gltf["buffers"].as_array().into_iter().flatten().enumerate() {
+        let data = match buffer["uri"].as_str() {
+            None if i == 0 => bin.ok_or("the glTF buffer has no data")?.to_vec(),
+            None => return Err("the glTF buffer has no data".into()),
+            Some(uri) => match uri.strip_prefix("data:") {
+                Some(data_uri) => {
+                    let (_, encoded) = data_uri
+                        .split_once(";base64,")
+                        .ok_or("the data URI of the glTF buffer is not base64")?;
+                    base64_decode(encoded)?
+                }
+                None => fs::read(path.parent().unwrap_or(Path::new("")).join(uri))?,
+            },
+        };
+        buffers.push(data);
+    }
+    let nodes: Vec<u64> =
+        match gltf["scenes"][gltf["scene"].as_u64().unwrap_or(0) as usize]["nodes"].as_array() {
+            Some(nodes) => nodes.iter().filter_map(Value::as_u64).collect(),
+            // Without a scene, the roots are the nodes that are not the child of another node
+            None => {
+                let nr_nodes = gltf["nodes"].as_array().map_or(0, Vec::len) as u64;
+                let children: Vec<u64> = gltf["nodes"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|node| node["children"].as_array().into_iter().flatten())
+                    .filter_map(Value::as_u64)
+                    .collect();
+                (0..nr_nodes).filter(|i| !children.contains(i)).collect()
+            }
+        };
+    let mut polygons: Vec<Polygon> = Vec::new();
+    for node in nodes {
+        push_node(gltf, &buffers, node as usize, &IDENTITY, 0, &mut polygons)?;
+    }
+    Ok(polygons)
+}
+
+/// Append the triangles of the mesh of the `node` and of its children, transformed with the
+/// `parent` matrix and the matrices of the nodes, and converted from y-up to z-up.
+fn push_node(
+    gltf: &Value,
+    buffers: &[Vec<u8>],
+    node: usize,
+    parent: &[f64; 16],
+    depth: usize,
+    polygons: &mut Vec<Polygon>,
+) -> Result<(), Box<dyn Error>> {
+    if depth > MAX_NODE_DEPTH {
+        return Err("the glTF node hierarchy is too deep".into());
+    }
+    let node = &gltf["nodes"][node];
+    let matrix = multiply(parent, &node_matrix(node));
+    if let Some(mesh) = node["mesh"].as_u64() {
+        let primitives = gltf["meshes"][mesh as usize]["primitives"].as_array();
+        // Only the triangles, the points and the lines have no surface
+        for primitive in primitives
+            .into_iter()
+            .flatten()
+            .filter(|primitive| primitive["mode"].as_u64().unwrap_or(4) == 4)
+        {
+            let position = primitive["attributes"]["POSITION"]
+                .as_u64()
+                .ok_or("the glTF primitive has no POSITION")?;
+            let positions: Vec<[f64; 3]> = read_accessor(gltf, buffers, position as usize)?
+                .chunks_exact(3)
+                .map(|p| {
+                    let [x, y, z] = transform_point(&matrix, [p[0], p[1], p[2]]);
+                    [x, -z, y]
+                })
+                .collect();
+            let indices: Vec<usize> = match primitive["indices"].as_u64() {
+                Some(indices) => read_accessor(gltf, buffers, indices as usize)?
+                    .into_iter()
+                    .map(|i| i as usize)
+                    .collect(),
+                None => (0..positions.len()).collect(),
+            };
+            for triangle in indices.chunks_exact(3) {
+                let ring = triangle
+                    .iter()
+                    .map(|i| positions.get(*i).copied())
+                    .collect::<Option<Vec<[f64; 3]>>>()
+                    .ok_or("a glTF index is out of the positions")?;
+                polygons.push(vec![ring]);
+            }
+        }
+    }
+    for child in node["children"].as_array().into_iter().flatten() {
+        let child = child
+            .as_u64()
+            .ok_or("the glTF node child is not an index")?;
+        push_node(gltf, buffers, child as usize, &matrix, depth + 1, polygons)?;
+    }
+    Ok(())
+}
+
+/// The components of the elements of the accessor.
+fn read_accessor(
+    gltf: &Value,
+    buffers: &[Vec<u8>],
+    index: usize,
+) -> Result<Vec<f64>, Box<dyn Error>> {
+    let accessor = &gltf["accessors"][index];
+    if accessor.get("sparse").is_some() {
+        return Err("the sparse glTF accessors are not supported".into());
+    }
+    let view = &gltf["bufferViews"][accessor["bufferView"]
+        .as_u64()
+        .ok_or("the glTF accessor has no buffer view")?
+        as usize];
+    let buffer = buffers
+        .get(view["buffer"].as_u64().unwrap_or(0) as usize)
+        .ok_or("the glTF buffer view has no buffer")?;
+    let nr_components = match accessor["type"].as_str() {
+        Some("SCALAR") => 1,
+        Some("VEC2") => 2,
+        Some("VEC3") => 3,
+        Some("VEC4") => 4,
+        _ => return Err("the type of the glTF accessor is not supported".into()),
+    };
+    let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+    let component_size = match component_type {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        _ => return Err("the component type of the glTF accessor is not supported".into()),
+    };
+    let count = accessor["count"].as_u64().unwrap_or(0) as usize;
+    let stride = view["byteStride"]
+        .as_u64()
+        .map_or(nr_components * component_size, |stride| stride as usize);
+    let start = view["byteOffset"].as_u64().unwrap_or(0) as usize
+        + accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+    let mut values: Vec<f64> = Vec::with_capacity(count * nr_components);
+    for i in 0..count {
+        for c in 0..nr_components {
+            let offset = start + i * stride + c * component_size;
+            let bytes = buffer
+                .get(offset..offset + component_size)
+                .ok_or("the glTF accessor is out of its buffer")?;
+            values.push(match component_type {
+                5120 => bytes[0] as i8 as f64,
+                5121 => bytes[0] as f64,
+                5122 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                5123 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                5125 => u32::from_le_bytes(bytes.try_into()?) as f64,
+                _ => f32::from_le_bytes(bytes.try_into()?) as f64,
+            });
+        }
+    }
+    Ok(values)
+}
+
+/// The column-major local matrix of the glTF `node`, from its `matrix`, or from its
+/// translation, rotation and scale.
+fn node_matrix(node: &Value) -> [f64; 16] {
+    let values = |key: &str, default: &[f64]| -> Vec<f64> {
+        match node[key].as_array() {
+            Some(values) if values.len() == default.len() => {
+                values.iter().map(|v| v.as_f64().unwrap_or(0.0)).collect()
+            }
+            _ => default.to_vec(),
+        }
+    };
+    if node.get("matrix").is_some() {
+        return values("matrix", &IDENTITY).try_into().unwrap_or(IDENTITY);
+    }
+    let t = values("translation", &[0.0; 3]);
+    let [x, y, z, w]: [f64; 4] = values("rotation", &[0.0, 0.0, 0.0, 1.0])
+        .try_into()
+        .unwrap_or([0.0, 0.0, 0.0, 1.0]);
+    let s = values("scale", &[1.0; 3]);
+    [
+        (1.0 - 2.0 * (y * y + z * z)) * s[0],
+        2.0 * (x * y + z * w) * s[0],
+        2.0 * (x * z - y * w) * s[0],
+        0.0,
+        2.0 * (x * y - z * w) * s[1],
+        (1.0 - 2.0 * (x * x + z * z)) * s[1],
+        2.0 * (y * z + x * w) * s[1],
+        0.0,
+        2.0 * (x * z + y * w) * s[2],
+        2.0 * (y * z - x * w) * s[2],
+        (1.0 - 2.0 * (x * x + y * y)) * s[2],
+        0.0,
+        t[0],
+        t[1],
+        t[2],
+        1.0,
+    ]
+}
+
+/// The product of the column-major matrices `a` and `b`.
+fn multiply(a: &[f64; 16], b: &[f64; 16]) -> [f64; 16] {
+    let mut product = [0.0; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            product[column * 4 + row] = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+        }
+    }
+    product
+}
+
+fn transform_point(m: &[f64; 16], p: [f64; 3]) -> [f64; 3] {
+    [
+        m[0] * p[0] + m[4] * p[1] + m[8] * p[2] + m[12],
+        m[1] * p[0] + m[5] * p[1] + m[9] * p[2] + m[13],
+        m[2] * p[0] + m[6] * p[1] + m[10] * p[2] + m[14],
+    ]
+}
+
+fn base64_decode(encoded: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut decoded: Vec<u8> = Vec::with_capacity(encoded.len() * 3 / 4);
+    let (mut bits, mut nr_bits) = (0_u32, 0);
+    for c in encoded.bytes().filter(|c| *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err("the data URI of the glTF buffer is not base64".into()),
+        };
+        bits = ((bits << 6) | value as u32) & 0xFFFF;
+        nr_bits += 6;
+        if nr_bits >= 8 {
+            nr_bits -= 8;
+            decoded.push((bits >> nr_bits) as u8);
+        }
+    }
+    Ok(decoded)
+}
+
+/// The faces of an OBJ. Only the vertices (`v`) and the faces (`f`) are read, the faces are
+/// triangulated later, like the surfaces of the features.
+fn obj_polygons(obj: &str) -> Result<Vec<Polygon>, Box<dyn Error>> {
+    let mut vertices: Vec<[f64; 3]> = Vec::new();
+    let mut polygons: Vec<Polygon> = Vec::new();
+    for line in obj.lines() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let coordinates: Vec<f64> =
+                    tokens.take(3).map(str::parse).collect::<Result<_, _>>()?;
+                let vertex: [f64; 3] = coordinates
+                    .try_into()
+                    .map_err(|_| "an OBJ vertex has less than 3 coordinates")?;
+                vertices.push(vertex);
+            }
+            Some("f") => {
+                let mut ring: Vec<[f64; 3]> = Vec::new();
+                for token in tokens {
+                    // The vertex index is followed by the texture and the normal indices
+                    let index: i64 = token.split('/').next().unwrap_or_default().parse()?;
+                    // The negative indices count from the last vertex
+                    let index = if index < 0 {
+                        vertices.len() as i64 + index
+                    } else {
+                        index - 1
+                    };
+                    let vertex = usize::try_from(index)
+                        .ok()
+                        .and_then(|index| vertices.get(index))
+                        .ok_or("an OBJ face refers to a missing vertex")?;
+                    ring.push(*vertex);
+                }
+                if ring.len() >= 3 {
+                    polygons.push(vec![ring]);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(polygons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_obj_polygons() {
+        let obj = "# A square and a triangle\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nvn 0 0 1\nf 1//1 2//1 3//1 4//1\nf -5/1 -4/1 -1/1\n";
+        let polygons = obj_polygons(obj).unwrap();
+        assert_eq!(polygons.len(), 2);
+        assert_eq!(polygons[0][0].len(), 4);
+        assert_eq!(
+            polygons[1][0],
+            vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]
+        );
+        assert!(obj_polygons("v 0 0 0\nf 1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn test_gltf_polygons() {
+        // A triangle in a child node, with a data URI buffer of the positions and the indices
+        let positions: Vec<u8> = [0.0_f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, -3.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let mut buffer = positions.clone();
+        buffer.extend([0_u16, 1, 2].iter().flat_map(|i| i.to_le_bytes()));
+        let encoded = "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAAEAAAEDAAAABAAIA";
+        assert_eq!(base64_decode(encoded).unwrap(), buffer);
+        let gltf = serde_json::json!({
+            "scene": 0,
+            "scenes": [{"nodes": [0]}],
+            "nodes": [
+                {"translation": [100.0, 10.0, -200.0], "children": [1]},
+                {"mesh": 0, "scale": [2.0, 2.0, 2.0]}
+            ],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "indices": 1}]}],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"},
+                {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}
+            ],
+            "bufferViews": [
+                {"buffer": 0, "byteOffset": 0, "byteLength": 36},
+                {"buffer": 0, "byteOffset": 36, "byteLength": 6}
+            ],
+            "buffers": [{"byteLength": 42, "uri": format!("data:application/octet-stream;base64,{encoded}")}]
+        });
+        let polygons = gltf_polygons(&gltf, None, Path::new("model.gltf")).unwrap();
+        // Scaled by the child, translated by the parent, then from y-up to z-up
+        assert_eq!(
+            polygons,
+            vec![vec![vec![
+                [100.0, 200.0, 10.0],
+                [102.0, 200.0, 10.0],
+                [100.0, 206.0, 14.0]
+            ]]]
+        );
+        let mut compressed = gltf.clone();
+        compressed["extensionsRequired"] = serde_json::json!(["EXT_meshopt_compression"]);
+        assert!(gltf_polygons(&compressed, None, Path::new("model.gltf")).is_err());
+
+        // The same glTF as a GLB, with the buffer in the binary chunk
+        let mut json = gltf.clone();
+        json["buffers"] = serde_json::json!([{"byteLength": 42}]);
+        let mut json = serde_json::to_vec(&json).unwrap();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        buffer.resize(44, 0);
+        let mut glb: Vec<u8> = b"glTF".to_vec();
+        glb.extend(2_u32.to_le_bytes());
+        glb.extend(((12 + 8 + json.len() + 8 + buffer.len()) as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(GLB_CHUNK_JSON.to_le_bytes());
+        glb.extend(&json);
+        glb.extend((buffer.len() as u32).to_le_bytes());
+        glb.extend(GLB_CHUNK_BIN.to_le_bytes());
+        glb.extend(&buffer);
+        let path = std::env::temp_dir().join("tyler_test_external_geometry.glb");
+        fs::write(&path, &glb).unwrap();
+        assert_eq!(read(&path).unwrap(), polygons);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! Test fixtures that index a committed CityJSONSeq sample and compare the resulting
+//! partitioning against a committed reference.
+// Copyright 2023 Balázs Dukai, Ravi Peters
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// The sample is generated by resources/python/make_tiling_sample.py. When a change to the
+// indexing is intentional, regenerate the reference files by running the tests with
+// TYLER_UPDATE_FIXTURES=1 and review the diff of the reference files.
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::parser::{CityObjectType, World, WorldOptions};
+use crate::spatial_structs::{Bbox, QuadTree, QuadTreeCapacity, QuadTreeDepth};
+
+/// Set this environment variable to overwrite the reference files instead of comparing
+/// against them.
+const UPDATE_ENV: &str = "TYLER_UPDATE_FIXTURES";
+
+pub fn test_data_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("resources")
+        .join("data")
+}
+
+/// A [World] that is indexed from a CityJSONSeq sample in the test data directory.
+pub struct TilingFixture {
+    pub name: String,
+    pub world: World,
+}
+
+impl TilingFixture {
+    /// Load the `<name>.city.jsonl` CityJSONSeq sample and index it with the grid.
+    /// The sample is split into a metadata file and one file per feature in a temporary
+    /// directory, because that is the input layout of [World].
+    pub fn load(name: &str, cellsize: f64) -> Self {
+        let path_seq = test_data_dir().join(format!("{}.city.jsonl", name));
+        // The tests run in parallel, so each load needs its own directory.
+        static NR_LOADED: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "tyler_test_fixture_{}_{}_{}",
+            name,
+            std::process::id(),
+            NR_LOADED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let path_features = dir.join("features");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&path_features).unwrap();
+        let path_metadata = dir.join("metadata.city.json");
+        Self::split_cityjsonseq(&path_seq, &path_metadata, &path_features);
+
+        let mut world = World::new(
+            path_metadata,
+            path_features,
+            cellsize,
+            WorldOptions {
+                cityobject_types: Some(vec![CityObjectType::Building]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        world.index_with_grid(None);
+        Self {
+            name: name.to_string(),
+            world,
+        }
+    }
+
+    fn split_cityjsonseq(path_seq: &Path, path_metadata: &Path, path_features: &Path) {
+        let reader = BufReader::new(fs::File::open(path_seq).unwrap());
+        let mut lines = reader.lines();
+        fs::write(path_metadata, lines.next().unwrap().unwrap()).unwrap();
+        for line in lines {
+            let line = line.unwrap();
+            let feature: Value = serde_json::from_str(&line).unwrap();
+            let id = feature["id"].as_str().unwrap();
+            fs::write(path_features.join(format!("{}.city.jsonl", id)), &line).unwrap();
+        }
+    }
+
+    /// The file stems of the features, which are the CityJSONFeature IDs.
+    fn feature_names(&self, feature_ids: &[usize]) -> Vec<String> {
+        let mut names: Vec<String> = feature_ids
+            .iter()
+            .map(|fid| {
+                let path = &self.world.features[*fid].path_jsonl;
+                let file_name = path.file_name().unwrap().to_string_lossy();
+                file_name.trim_end_matches(".city.jsonl").to_string()
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Compare `actual` to the reference `<name>.<kind>.reference.json`, or overwrite the
+    /// reference if [UPDATE_ENV] is set.
+    pub fn assert_reference<T: Serialize>(&self, kind: &str, actual: &T) {
+        let path = test_data_dir().join(format!("{}.{}.reference.json", self.name, kind));
+        let actual = serde_json::to_string_pretty(actual).unwrap() + "\n";
+        if std::env::var_os(UPDATE_ENV).is_some() {
+            fs::write(&path, &actual).unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+            panic!(
+                "cannot read the reference {}: {}, run the tests with {}=1 to create it",
+                path.display(),
+                e,
+                UPDATE_ENV
+            )
+        });
+        assert_eq!(
+            expected,
+            actual,
+            "the {} of the fixture {} differs from {}, if the change is intended, run the tests with {}=1 to update the reference",
+            kind,
+            self.name,
+            path.display(),
+            UPDATE_ENV
+        );
+    }
+
+    /// The grid cells that have features, with their feature names.
+    pub fn grid_reference(&self) -> GridReference {
+        let cells = self
+            .world
+            .grid
+            .into_iter()
+            .filter(|(_, cell)| !cell.feature_ids.is_empty())
+            .map(|(cellid, cell)| CellReference {
+                cell: [cellid.column, cellid.row],
+                nr_vertices: cell.nr_vertices,
+                features: self.feature_names(&cell.feature_ids),
+            })
+            .collect();
+        GridReference {
+            bbox: round_bbox(&self.world.grid.bbox),
+            length: self.world.grid.length,
+            cells,
+        }
+    }
+
+    /// The quadtree nodes with their bounding boxes, and the cells and features of the
+    /// leaves. The nesting of the nodes is the structure of the tileset.
+    pub fn quadtree_reference(&self, limit: QuadTreeCapacity) -> NodeReference {
+        let quadtree = QuadTree::from_world(&self.world, limit, QuadTreeDepth::default());
+        self.node_reference(&quadtree)
+    }
+
+    fn node_reference(&self, node: &QuadTree) -> NodeReference {
+        let mut cells: Vec<[usize; 2]> = Vec::new();
+        let mut feature_ids: Vec<usize> = Vec::new();
+        if node.children.is_empty() {
+            for cellid in node.cells() {
+                cells.push([cellid.column, cellid.row]);
+                for fid in &self.world.grid.cell(cellid).feature_ids {
+                    if !feature_ids.contains(fid) {
+                        feature_ids.push(*fid);
+                    }
+                }
+            }
+        }
+        NodeReference {
+            id: node.id.to_string(),
+            bbox: round_bbox(&node.bbox(&self.world.grid)),
+            nr_items: node.nr_items,
+            cells,
+            features: self.feature_names(&feature_ids),
+            children: node
+                .children
+                .iter()
+                .map(|child| self.node_reference(child))
+                .collect(),
+        }
+    }
+}
+
+/// Round to millimeters, so that the reference does not depend on the last digits of the
+/// floating point arithmetic.
+fn round_bbox(bbox: &Bbox) -> Bbox {
+    bbox.map(|c| (c * 1000.0).round() / 1000.0)
+}
+
+#[derive(Serialize)]
+pub struct GridReference {
+    bbox: Bbox,
+    length: usize,
+    cells: Vec<CellReference>,
+}
+
+#[derive(Serialize)]
+struct CellReference {
+    /// `[column, row]`
+    cell: [usize; 2],
+    nr_vertices: usize,
+    features: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct NodeReference {
+    id: String,
+    bbox: Bbox,
+    nr_items: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cells: Vec<[usize; 2]>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    features: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<NodeReference>,
+}
@@ -16,8 +16,10 @@
 
 pub mod cesium3dtiles {
     //! Cesium [3D Tiles](https://github.com/CesiumGS/3d-tiles).
-    //! Supported version: 1.1.
+    //! Supported versions: 1.1, and 1.0 with glTF content (see [TilesetVersion]).
     //! Not supported: `extras`.
+    use std::collections::BTreeMap;
+    use std::collections::BTreeSet;
     use std::collections::HashMap;
     use std::collections::VecDeque;
     use std::fmt::{Display, Formatter};
@@ -32,7 +34,160 @@ pub mod cesium3dtiles {
     use serde_repr::{Deserialize_repr, Serialize_repr};
 
     use crate::proj::Proj;
-    use crate::spatial_structs::{Bbox, CellId, QuadTree, QuadTreeNodeId, SquareGrid};
+    use crate::spatial_structs::{
+        bbox_scale_z, Bbox, CellId, PartitionTree, QuadTree, QuadTreeNodeId, SquareGrid, TileNaming,
+    };
+
+    /// How the geometric error of the interior tiles is computed.
+    #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum)]
+    pub enum GeometricErrorScheme {
+        /// The geometric error above the leaves is multiplied by the number of grid cells
+        /// along the tile edge, minus two.
+        #[default]
+        Grid,
+        /// The geometric error above the leaves is a fraction of the tile edge length, so the
+        /// error is independent of the grid cell size. Suited for indoor models with small
+        /// cell sizes.
+        Relative,
+    }
+
+    /// The options of [Tileset::from_quadtree].
+    #[derive(Debug, Clone, Copy)]
+    pub struct TilesetOptions {
+        pub geometric_error_above_leaf: f64,
+        pub geometric_error_scheme: GeometricErrorScheme,
+        /// The cell size of the grid.
+        pub cellsize: f64,
+        /// The minimum and maximum z of the bounding volumes, if set.
+        pub minz: Option<i32>,
+        pub maxz: Option<i32>,
+        /// The content bounding volume is the tile bounding volume.
+        pub content_bv_from_tile: bool,
+        /// Add a bounding volume to the content.
+        pub content_add_bv: bool,
+        pub z_scale: f64,
+        pub tight_bounds: Option<TightBounds>,
+    }
+
+    /// Which extents of the tile bounding volumes are computed from the features in the tile,
+    /// instead of the grid.
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, clap::ValueEnum)]
+    pub enum TightBounds {
+        /// The height of the tile is the height range of its features, the horizontal extent
+        /// is the extent of the grid cells of the tile.
+        Z,
+        /// The tile bounding volume is the bounding box of its features.
+        Xyz,
+    }
+
+    /// The bounding box of the quadtree node, in the input CRS. By default, the horizontal
+    /// extent is the extent of the node's grid cells and the height is the height of the grid.
+    /// With `tight_bounds`, the extents are computed from the features in the node instead.
+    /// A node without features takes them from the `parent_bbox`, so that it is enclosed by
+    /// its parent.
+    fn tile_bbox(
+        quadtree: &QuadTree,
+        world: &crate::parser::World,
+        arg_minz: Option<i32>,
+        arg_maxz: Option<i32>,
+        tight_bounds: Option<TightBounds>,
+        parent_bbox: Option<&Bbox>,
+    ) -> Bbox {
+        let mut bbox = quadtree.bbox(&world.grid);
+        let Some(tight_bounds) = tight_bounds else {
+            return bbox;
+        };
+        let tight_bbox = if quadtree.nr_items > 0 {
+            quadtree.node_content_bbox(world, arg_minz, arg_maxz)
+        } else if let Some(parent_bbox) = parent_bbox {
+            *parent_bbox
+        } else {
+            return bbox;
+        };
+        match tight_bounds {
+            TightBounds::Z => {
+                bbox[2] = tight_bbox[2];
+                bbox[5] = tight_bbox[5];
+            }
+            TightBounds::Xyz => bbox = tight_bbox,
+        }
+        bbox
+    }
+
+    /// The LoD of the content of the interior tiles. The tiles on the levels right above the
+    /// leaves take the LoDs of the `cascade`, from the finest at the last position to the
+    /// coarsest at the first position. The tiles further up take the `lod`. Thus the detail
+    /// of the models decreases from the leaves towards the root.
+    #[derive(Debug, Clone, Default)]
+    pub struct InteriorLod {
+        pub lod: String,
+        pub cascade: Vec<String>,
+        /// The number of levels between the interior tiles and the leaves, see
+        /// [Tileset::interior_levels].
+        pub levels: HashMap<TileId, u16>,
+    }
+
+    impl InteriorLod {
+        /// The LoD of the content of the interior tile `tile_id`.
+        pub fn lod_of(&self, tile_id: &TileId) -> &str {
+            match self
+                .levels
+                .get(tile_id)
+                .map(|levels_up| *levels_up as usize)
+            {
+                Some(levels_up) if levels_up >= 1 && levels_up <= self.cascade.len() => {
+                    &self.cascade[self.cascade.len() - levels_up]
+                }
+                _ => &self.lod,
+            }
+        }
+    }
+
+    /// The statistics of the content of a tile, that are written into the tile metadata, see
+    /// [Tileset::add_tile_stats].
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub struct TileContentStats {
+        pub nr_features: usize,
+        /// The number of CityObjects of each type.
+        pub cityobject_types: BTreeMap<String, usize>,
+        /// The minimum and maximum height of the features, in the units of the input CRS.
+        pub height: Option<[f64; 2]>,
+    }
+
+    /// The version of the 3D Tiles specification that the tileset is written for.
+    #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum)]
+    pub enum TilesetVersion {
+        /// 3D Tiles 1.0, with the glTF content declared by the
+        /// [3DTILES_content_gltf](https://github.com/CesiumGS/3d-tiles/tree/main/extensions/3DTILES_content_gltf)
+        /// extension. Metadata, content groups and implicit tiling are not available.
+        #[value(name = "1.0")]
+        V1_0,
+        /// 3D Tiles 1.1, with glTF as core tile content.
+        #[default]
+        #[value(name = "1.1")]
+        V1_1,
+    }
+
+    impl Display for TilesetVersion {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                TilesetVersion::V1_0 => write!(f, "1.0"),
+                TilesetVersion::V1_1 => write!(f, "1.1"),
+            }
+        }
+    }
+
+    /// The type of the bounding volumes of the tiles and their content.
+    #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum)]
+    pub enum BoundingVolumeType {
+        /// An oriented box in EPSG:4978, aligned to the axes of the input CRS.
+        #[default]
+        Box,
+        /// A region of longitude, latitude and height in EPSG:4979, which encloses the box.
+        Region,
+        /// A sphere in EPSG:4978, which encloses the box. Not available for implicit tiling.
+        Sphere,
+    }
 
     /// [Tileset](https://github.com/CesiumGS/3d-tiles/tree/main/specification#tileset).
     ///
@@ -46,6 +201,10 @@ pub mod cesium3dtiles {
         #[serde(skip_serializing_if = "Option::is_none")]
         properties: Option<Properties>,
         #[serde(skip_serializing_if = "Option::is_none")]
+        schema: Option<Schema>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        groups: Option<Vec<MetadataEntity>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         extensions_used: Option<Vec<ExtensionName>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         extensions_required: Option<Vec<ExtensionName>>,
@@ -57,9 +216,7 @@ pub mod cesium3dtiles {
         /// Write the tileset to a `tileset.json` file
         pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
             let file_out = File::create(path.as_ref())?;
-            let mut ser =
-                serde_json::ser::Serializer::with_formatter(file_out, BoundingVolumeFormatter);
-            self.serialize(&mut ser)?;
+            serde_json::to_writer(file_out, self)?;
             Ok(())
         }
 
@@ -153,12 +310,7 @@ pub mod cesium3dtiles {
         pub fn from_quadtree(
             quadtree: &QuadTree,
             world: &crate::parser::World,
-            geometric_error_above_leaf: f64,
-            arg_cellsize: u32,
-            arg_minz: Option<i32>,
-            arg_maxz: Option<i32>,
-            content_bv_from_tile: bool,
-            content_add_bv: bool,
+            options: &TilesetOptions,
         ) -> Self {
             let crs_from = format!("EPSG:{}", world.crs.to_epsg().unwrap());
             // Because we have a boundingVolume.box. For a boundingVolume.region we need 4979.
@@ -170,32 +322,24 @@ pub mod cesium3dtiles {
             //     1.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0,
             // ]);
 
-            let root = Self::generate_tiles(
-                quadtree,
-                world,
-                &transformer,
-                geometric_error_above_leaf,
-                arg_cellsize,
-                arg_minz,
-                arg_maxz,
-                content_bv_from_tile,
-                content_add_bv,
-            );
+            let root = Self::generate_tiles(quadtree, world, &transformer, options, None);
             // root.transform = Some(y_up_to_z_up);
 
-            // Using gltf tile content
-            let mut extensions: Extensions = HashMap::new();
-            let e1 = Extension::ContentGtlf {
-                extensions_used: None,
-                extensions_required: None,
-            };
-            extensions.insert(ExtensionName::ContentGltf, e1);
-
             Self {
                 asset: Default::default(),
-                geometric_error: geometric_error_above_leaf + root.geometric_error * 1.5,
+                geometric_error: match options.geometric_error_scheme {
+                    GeometricErrorScheme::Grid => {
+                        options.geometric_error_above_leaf + root.geometric_error * 1.5
+                    }
+                    GeometricErrorScheme::Relative => {
+                        let root_bbox = quadtree.bbox(&world.grid);
+                        options.geometric_error_above_leaf * (root_bbox[3] - root_bbox[0]) * 2.0
+                    }
+                },
                 root,
                 properties: None,
+                schema: None,
+                groups: None,
                 extensions_used: None,
                 extensions_required: None,
                 extensions: None,
@@ -210,13 +354,32 @@ pub mod cesium3dtiles {
             quadtree: &QuadTree,
             world: &crate::parser::World,
             transformer: &Proj,
-            geometric_error_above_leaf: f64,
-            arg_cellsize: u32,
-            arg_minz: Option<i32>,
-            arg_maxz: Option<i32>,
-            content_bv_from_tile: bool,
-            content_add_bv: bool,
+            options: &TilesetOptions,
+            parent_bbox: Option<&Bbox>,
         ) -> Tile {
+            let TilesetOptions {
+                geometric_error_above_leaf,
+                geometric_error_scheme,
+                cellsize: arg_cellsize,
+                minz: arg_minz,
+                maxz: arg_maxz,
+                content_bv_from_tile,
+                content_add_bv,
+                z_scale,
+                tight_bounds,
+            } = *options;
+            let node_bbox = tile_bbox(
+                quadtree,
+                world,
+                arg_minz,
+                arg_maxz,
+                tight_bounds,
+                parent_bbox,
+            );
+            // The geometric error follows the size of the node, even if the bounding volume
+            // is fitted to the features
+            let grid_bbox = quadtree.bbox(&world.grid);
+            let node_length = grid_bbox[3] - grid_bbox[0];
             if !quadtree.children.is_empty() {
                 let tile_id = TileId::from(&quadtree.id);
 
@@ -226,7 +389,7 @@ pub mod cesium3dtiles {
                 // Tile bounding volume
                 // Set the bounding volume height from the grid height, which can be set with
                 // an argument, or else calculated from the data (content).
-                let mut tile_bbox = quadtree.bbox(&world.grid);
+                let mut tile_bbox = bbox_scale_z(&node_bbox, z_scale);
                 // But it can happen with faulty data, eg. 3D Basisvoorziening,
                 // that maxz is less than minz.
                 if tile_bbox[5] < tile_bbox[2] {
@@ -238,30 +401,33 @@ pub mod cesium3dtiles {
 
                 // The geometric error of a tile is computed based on the specified error
                 // for the nodes have leafs as children (assuming all leaf nodes are at the same level)
-                let level_multiplier = (tile_bbox[3] - tile_bbox[0]) / (arg_cellsize as f64) - 2.0;
-                let mut d = geometric_error_above_leaf * level_multiplier;
-                let d_string = format!("{d:.2}");
-                if d < 0.0 {
-                    warn!("d is negative in internal tile {tile_id}");
-                } else if d_string == *"0.00" {
-                    // Because, for instance we have a —grid-cellsize 250, then a parent of the deepest level will have an edge length of 2 * 250.
-                    // So for the 'level_multiplier' formula we get:
-                    // 500 / 250 - 2.0 = 0
-                    // Which then results in a 'd' of 0.
-                    d = geometric_error_above_leaf;
-                }
+                let d = match geometric_error_scheme {
+                    GeometricErrorScheme::Grid => {
+                        let level_multiplier = node_length / arg_cellsize - 2.0;
+                        let mut d = geometric_error_above_leaf * level_multiplier;
+                        let d_string = format!("{d:.2}");
+                        if d < 0.0 {
+                            warn!("d is negative in internal tile {tile_id}");
+                        } else if d_string == *"0.00" {
+                            // Because, for instance we have a —grid-cellsize 250, then a parent of the deepest level will have an edge length of 2 * 250.
+                            // So for the 'level_multiplier' formula we get:
+                            // 500 / 250 - 2.0 = 0
+                            // Which then results in a 'd' of 0.
+                            d = geometric_error_above_leaf;
+                        }
+                        d
+                    }
+                    // The error is proportional to the tile size, thus it is never zero
+                    GeometricErrorScheme::Relative => geometric_error_above_leaf * node_length,
+                };
                 let mut tile_children: Vec<Tile> = Vec::new();
                 for child in quadtree.children.iter() {
                     tile_children.push(Self::generate_tiles(
                         child,
                         world,
                         transformer,
-                        geometric_error_above_leaf,
-                        arg_cellsize,
-                        arg_minz,
-                        arg_maxz,
-                        content_bv_from_tile,
-                        content_add_bv,
+                        options,
+                        Some(&node_bbox),
                     ));
                 }
                 Tile {
@@ -273,13 +439,14 @@ pub mod cesium3dtiles {
                     transform: None,
                     content: None,
                     children: Some(tile_children),
+                    metadata: None,
                     implicit_tiling: None,
                 }
             } else {
                 let tile_id = TileId::from(&quadtree.id);
 
                 // Tile bounding volume
-                let mut tile_bbox = quadtree.bbox(&world.grid);
+                let mut tile_bbox = bbox_scale_z(&node_bbox, z_scale);
                 if tile_bbox[5] < tile_bbox[2] {
                     // See explanation above
                     debug!("Leaf tile {tile_id} {:?} (in input CRS) bbox maxz {} is less than minz {}. Replacing maxz with minz + minz * 0.01.", &tile_bbox, tile_bbox[5], tile_bbox[2]);
@@ -290,8 +457,10 @@ pub mod cesium3dtiles {
                 let mut content: Option<Content> = None;
 
                 if quadtree.nr_items > 0 {
-                    let mut tile_content_bbox_rw =
-                        quadtree.node_content_bbox(world, arg_minz, arg_maxz);
+                    let mut tile_content_bbox_rw = bbox_scale_z(
+                        &quadtree.node_content_bbox(world, arg_minz, arg_maxz),
+                        z_scale,
+                    );
                     if content_bv_from_tile {
                         tile_content_bbox_rw = tile_bbox;
                     } else {
@@ -331,6 +500,7 @@ pub mod cesium3dtiles {
                             None
                         },
                         uri: format!("t/{}.glb", quadtree.id),
+                        group: None,
                     });
                 }
 
@@ -343,142 +513,183 @@ pub mod cesium3dtiles {
                     transform: None,
                     content,
                     children: None,
+                    metadata: None,
                     implicit_tiling: None,
                 }
             }
         }
 
-        #[allow(dead_code)]
+        /// Create a flat tileset directly from the grid, with a leaf for each non-empty cell,
+        /// for datasets where the quadtree would not merge any cells. The leaves are the tiles
+        /// of [crate::tile_index::TileIndex::from_grid], in the same order.
         pub fn from_grid(
-            grid: &SquareGrid,
-            citymodel: &crate::parser::CityJSONMetadata,
-            feature_set: &crate::parser::FeatureSet,
+            world: &crate::parser::World,
+            geometric_error_above_leaf: f64,
+            z_scale: f64,
         ) -> Self {
-            let crs_from = format!(
-                "EPSG:{}",
-                citymodel.metadata.reference_system.to_epsg().unwrap()
-            );
-            // Because we have a boundingVolume.box. For a boundingVolume.region we need 4979.
-            let crs_to = "EPSG:4978";
-            let transformer = Proj::new_known_crs(&crs_from, crs_to, None).unwrap();
+            Self::from_tile_index(
+                &crate::tile_index::TileIndex::from_grid(&world.grid),
+                world,
+                geometric_error_above_leaf,
+                z_scale,
+            )
+        }
+
+        /// Create a flat tileset from an external tile index, with a leaf for each tile of the
+        /// index that has features. The leaf content is named by the tile index ID, and the
+        /// leaf `TileId` is `1/<position in the index>/0`.
+        pub fn from_tile_index(
+            tile_index: &crate::tile_index::TileIndex,
+            world: &crate::parser::World,
+            geometric_error_above_leaf: f64,
+            z_scale: f64,
+        ) -> Self {
+            let crs_from = format!("EPSG:{}", world.crs.to_epsg().unwrap());
+            let transformer = Proj::new_known_crs(&crs_from, "EPSG:4978", None).unwrap();
 
-            let mut root_children: Vec<Tile> = Vec::with_capacity(grid.length * grid.length);
-            for (cellid, cell) in grid {
-                if cell.feature_ids.is_empty() {
-                    // Empty cell, don't create tiles for it
+            let mut root_bbox = [f64::MAX, f64::MAX, f64::MAX, f64::MIN, f64::MIN, f64::MIN];
+            let mut root_children: Vec<Tile> = Vec::new();
+            for (i, index_tile) in tile_index.tiles.iter().enumerate() {
+                if index_tile.feature_ids.is_empty() {
                     continue;
                 }
-
-                let mut content_bbox_qc = feature_set[cell.feature_ids[0]].bbox_qc.clone();
-                for fi in cell.feature_ids.iter() {
-                    content_bbox_qc.update_with(&feature_set[*fi].bbox_qc);
+                let tile_bbox = bbox_scale_z(&index_tile.bbox_3d(&world.grid), z_scale);
+                for j in 0..3 {
+                    root_bbox[j] = root_bbox[j].min(tile_bbox[j]);
+                    root_bbox[j + 3] = root_bbox[j + 3].max(tile_bbox[j + 3]);
                 }
-                let content_bbox_rw = content_bbox_qc.to_bbox(&citymodel.transform, None, None);
-                let content_bounding_voume =
-                    BoundingVolume::box_from_bbox(&content_bbox_rw, &transformer).unwrap();
-
-                let mut cell_bbox = grid.cell_bbox(&cellid);
-                // Set the bounding volume height from the content height
-                cell_bbox[2] = content_bbox_rw[2];
-                cell_bbox[5] = content_bbox_rw[5];
-                let bounding_volume =
-                    BoundingVolume::box_from_bbox(&cell_bbox, &transformer).unwrap();
-
-                // We are adding a child for each LoD.
-                // TODO: but we are cheating here now, because we know that the input data has 3 LoDs...
-
-                // The geometric error of a tile is its 'size'.
-                // Since we have square tiles, we compute its size as the length of
-                // its side on the x-axis.
-                let dz = cell_bbox[5] - cell_bbox[2];
-
-                // this is a leaf node, so the geometric_error is 0
-                // LoD2.2
-                let tile_lod22 = Tile {
-                    id: TileId::new(cellid.column, cellid.row, 3),
-                    bounding_volume,
+                root_children.push(Tile {
+                    id: TileId::new(i, 0, 1),
+                    bounding_volume: BoundingVolume::box_from_bbox(&tile_bbox, &transformer)
+                        .unwrap(),
                     geometric_error: 0.0,
                     viewer_request_volume: None,
                     refine: Some(Refinement::Replace),
                     transform: None,
                     content: Some(Content {
-                        bounding_volume: Some(content_bounding_voume),
-                        uri: format!("t/{}-0-0.glb", cellid),
+                        bounding_volume: None,
+                        uri: format!("t/{}.glb", index_tile.file_stem()),
+                        group: None,
                     }),
                     children: None,
-                    implicit_tiling: None,
-                };
-
-                // LoD 1.3
-                let tile_lod13 = Tile {
-                    id: TileId::new(cellid.column, cellid.row, 2),
-                    bounding_volume,
-                    geometric_error: dz * 0.1,
-                    viewer_request_volume: None,
-                    refine: Some(Refinement::Replace),
-                    transform: None,
-                    content: Some(Content {
-                        bounding_volume: Some(content_bounding_voume),
-                        uri: format!("t/{}-0.glb", cellid),
-                    }),
-                    children: Some(vec![tile_lod22]),
-                    implicit_tiling: None,
-                };
-
-                // LoD 1.2
-                root_children.push(Tile {
-                    id: TileId::new(cellid.column, cellid.row, 1),
-                    bounding_volume,
-                    geometric_error: dz * 0.3,
-                    // geometric_error: 10.0,
-                    viewer_request_volume: None,
-                    refine: Some(Refinement::Replace),
-                    transform: None,
-                    content: Some(Content {
-                        bounding_volume: Some(content_bounding_voume),
-                        uri: format!("t/{}.glb", cellid),
-                    }),
-                    children: Some(vec![tile_lod13]),
+                    metadata: None,
                     implicit_tiling: None,
                 });
             }
 
-            let root_volume = BoundingVolume::box_from_bbox(&grid.bbox, &transformer).unwrap();
-            debug!("root bbox: {:?}", &grid.bbox);
-            debug!("root boundingVolume: {:?}", &root_volume);
-            let root_geometric_error = grid.bbox[3] - grid.bbox[0];
-
             let root = Tile {
                 id: TileId::new(0, 0, 0),
-                bounding_volume: root_volume,
-                geometric_error: root_geometric_error,
+                bounding_volume: BoundingVolume::box_from_bbox(&root_bbox, &transformer).unwrap(),
+                geometric_error: geometric_error_above_leaf,
                 viewer_request_volume: None,
                 refine: Some(Refinement::Replace),
                 transform: None,
                 content: None,
                 children: Some(root_children),
+                metadata: None,
                 implicit_tiling: None,
             };
 
-            // Using gltf tile content
-            let mut extensions: Extensions = HashMap::new();
-            let e1 = Extension::ContentGtlf {
+            Self {
+                asset: Default::default(),
+                geometric_error: geometric_error_above_leaf * 1.5,
+                root,
+                properties: None,
+                schema: None,
+                groups: None,
                 extensions_used: None,
                 extensions_required: None,
-            };
-            extensions.insert(ExtensionName::ContentGltf, e1);
+                extensions: None,
+            }
+        }
 
+        /// Create a tileset from a partition tree, eg. the octree. The leaves are the tiles of
+        /// [crate::tile_index::TileIndex::from_partition_tree], in the same order, thus the leaf
+        /// `TileId` is `<level>/<position in the index>/0` and the content is named by the
+        /// node ID. The interior tiles do not have content, their `TileId` is `<level>/<n>/1`.
+        /// The geometric error is `geometric_error_above_leaf` on the tiles above the leaves,
+        /// and it doubles on each level upwards.
+        pub fn from_partition_tree(
+            tree: &dyn PartitionTree,
+            world: &crate::parser::World,
+            geometric_error_above_leaf: f64,
+            z_scale: f64,
+        ) -> Self {
+            let crs_from = format!("EPSG:{}", world.crs.to_epsg().unwrap());
+            let transformer = Proj::new_known_crs(&crs_from, "EPSG:4978", None).unwrap();
+            let mut counters = [0_usize; 2];
+            let (root, _) = Self::generate_partition_tiles(
+                tree,
+                world,
+                &transformer,
+                geometric_error_above_leaf,
+                z_scale,
+                &mut counters,
+            );
             Self {
                 asset: Default::default(),
-                geometric_error: root_geometric_error * 1.5,
+                geometric_error: root.geometric_error.max(geometric_error_above_leaf) * 2.0,
                 root,
                 properties: None,
+                schema: None,
+                groups: None,
                 extensions_used: None,
                 extensions_required: None,
                 extensions: None,
             }
         }
 
+        /// Returns the tile of the tree node and the number of levels to its deepest leaf.
+        /// The `counters` are the number of leaves and interior tiles that are created so far.
+        fn generate_partition_tiles(
+            node: &dyn PartitionTree,
+            world: &crate::parser::World,
+            transformer: &Proj,
+            geometric_error_above_leaf: f64,
+            z_scale: f64,
+            counters: &mut [usize; 2],
+        ) -> (Tile, u16) {
+            let tile_bbox = bbox_scale_z(&node.content_bbox(world), z_scale);
+            let bounding_volume = BoundingVolume::box_from_bbox(&tile_bbox, transformer).unwrap();
+            let mut tile = Tile {
+                bounding_volume,
+                refine: Some(Refinement::Replace),
+                ..Default::default()
+            };
+            let children = node.children();
+            if children.is_empty() {
+                tile.id = TileId::new(counters[0], 0, node.level());
+                if !node.feature_ids().is_empty() {
+                    counters[0] += 1;
+                    tile.content = Some(Content {
+                        bounding_volume: None,
+                        uri: format!("t/{}.glb", node.file_stem()),
+                        group: None,
+                    });
+                }
+                return (tile, 0);
+            }
+            tile.id = TileId::new(counters[1], 1, node.level());
+            counters[1] += 1;
+            let mut height: u16 = 0;
+            let mut child_tiles: Vec<Tile> = Vec::with_capacity(children.len());
+            for child in children {
+                let (child_tile, child_height) = Self::generate_partition_tiles(
+                    child,
+                    world,
+                    transformer,
+                    geometric_error_above_leaf,
+                    z_scale,
+                    counters,
+                );
+                height = height.max(child_height + 1);
+                child_tiles.push(child_tile);
+            }
+            tile.geometric_error = geometric_error_above_leaf * 2_f64.powi(height as i32 - 1);
+            tile.children = Some(child_tiles);
+            (tile, height)
+        }
+
         /// Flatten the tile hierarchy, visiting each tile in the quadtree.
         /// If 'levels_up' is provided, the tiles will be flattened only
         /// 'n levels upwards from the leaves', outputting only the flattened tiles
@@ -488,83 +699,508 @@ pub mod cesium3dtiles {
             self.root.flatten(levels_up)
         }
 
+        #[allow(dead_code)]
         pub fn collect_leaves(&self) -> Vec<&Tile> {
             self.root.collect_leaves()
         }
 
-        #[allow(dead_code)]
-        pub fn add_content(&mut self, levels_up: Option<u16>) {
-            self.root.add_content_from_level(levels_up);
+        /// Add content to the interior tiles that are at most `levels_up` levels above the
+        /// deepest level. If `levels_up` is `None`, all interior tiles receive content.
+        /// If `adaptive` is true, the levels are counted from the nearest leaf in the subtree of
+        /// the tile instead of the deepest level of the tileset, so that the shallow subtrees
+        /// in sparse areas receive content too.
+        pub fn add_content(&mut self, levels_up: Option<u16>, adaptive: bool) {
+            if adaptive {
+                self.root
+                    .add_content_adaptive(levels_up.unwrap_or(u16::MAX));
+            } else {
+                self.root.add_content_from_level(levels_up);
+            }
         }
 
-        /// The number of levels in the quadtree, which is `max_level + 1`.
-        pub fn available_levels(&self) -> u16 {
-            self.root.max_level() + 1
+        /// Set the refinement of every tile. With [Refinement::Add], the content of the
+        /// interior tiles stays visible when the viewer refines into their children.
+        pub fn set_refine(&mut self, refine: Refinement) {
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                tile.refine = Some(refine);
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
         }
 
-        /// Convert to implicit tiling.
-        /// It modifies the tileset and deletes the explicit tiles.
-        /// Expects that explicit tiling is already created.
-        pub fn make_implicit(
-            &mut self,
-            grid: &SquareGrid,
-            qtree: &QuadTree,
-            grid_export: bool,
-            subtrees_dir: Option<&str>,
-            output_dir_debug: Option<&Path>,
-        ) -> (Vec<(Tile, TileId)>, Vec<(TileId, Vec<u8>)>) {
-            let mut subtrees_vec: Vec<(TileId, Vec<u8>)> = Vec::new();
-            let mut flat_tiles_with_content: Vec<(Tile, TileId)> = Vec::new();
-            // https://docs.ogc.org/cs/22-025r4/22-025r4.html#toc37
-            let subtree_sections: usize = 1;
-            let subtree_levels =
-                (self.available_levels() as f32 / subtree_sections as f32).ceil() as u16;
-            let subtrees = match subtrees_dir {
-                None => Subtrees::default(),
-                Some(dirname) => Subtrees::new(dirname),
-            };
-            let implicittiling = ImplicitTiling {
-                subdivision_scheme: SubdivisionScheme::Quadtree,
-                subtree_levels,
-                available_levels: self.available_levels(),
-                subtrees,
-            };
-            debug!("{:?}", &implicittiling);
-            self.root.implicit_tiling = Some(implicittiling);
+        /// Add content to the root tile, as a coarse overview of the whole tileset.
+        /// Returns `false` if the root is a leaf, which has its content already.
+        pub fn add_root_content(&mut self) -> bool {
+            if self.root.children.is_none() {
+                return false;
+            }
+            if self.root.content.is_none() {
+                self.root.add_content();
+            }
+            true
+        }
 
-            // We want to have a sparse implicit tileset, which stores the availability
-            //  of each tile. We do not have a full quadtree, because of the node
-            //  capacity that is set.
-            // In order to create this sparse implicit tileset, we need to have a full
-            //  theoretical quadtree of the same height as the explicit quadtree
-            //  (tileset), and for each node in the theoretical tree, we need to mark
-            //  whether that node (tile) is indeed available and whether it has a
-            //  content in the explicit tileset.
+        /// Name the content of the quadtree tiles with the `naming` scheme, instead of the tile
+        /// ID. `max_level` is the level of the single grid cells, see [TileNaming::file_stem].
+        pub fn rename_content(&mut self, naming: TileNaming, max_level: u16) {
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                if let Some(ref mut content) = tile.content {
+                    let stem = naming.file_stem(&QuadTreeNodeId::from(&tile.id), max_level);
+                    content.uri = format!("t/{stem}.glb");
+                }
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+        }
 
-            let grid_epsg = grid.epsg;
-            let level_subtree_root: u32 = 0; // subtree root level
-            let mut subtree_queue = VecDeque::new();
-            let rootid = &self.root.id;
-            let cellid: CellId = rootid.into();
-            subtree_queue.push_back((level_subtree_root, cellid, &self.root));
+        /// Reference the content with the `extension` of a 3D Tiles 1.0 format, such as the
+        /// Batched 3D Model (`b3dm`) or the Composite (`cmpt`), instead of glTF. These are core
+        /// content formats, thus the `3DTILES_content_gltf` extension is removed.
+        pub fn use_legacy_content(&mut self, extension: &str) {
+            self.extensions = None;
+            self.extensions_used = None;
+            self.extensions_required = None;
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                if let Some(ref mut content) = tile.content {
+                    if let Some(stem) = content.uri.strip_suffix(".glb") {
+                        content.uri = format!("{stem}.{extension}");
+                    }
+                }
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+        }
 
-            while let Some((level_subtree_root, cellid, tile)) = subtree_queue.pop_front() {
-                let subtree_id = TileId::new(cellid.column, cellid.row, level_subtree_root as u16);
-                let mut buffer_vec: Vec<u8> = Vec::new();
-                let mut tile_availability_bitstream: bv::BitVec<u8, bv::Lsb0> = bv::BitVec::new();
-                let mut content_availability_bitstream: bv::BitVec<u8, bv::Lsb0> =
-                    bv::BitVec::new();
+        /// Prepend the `base` URI to the content URIs, eg. to serve the content from another
+        /// host than the tileset. It is applied to the written tileset, after the content
+        /// files are named from the content URIs.
+        pub fn set_content_base_uri(&mut self, base: &str) {
+            let base = base.trim_end_matches('/');
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                if let Some(ref mut content) = tile.content {
+                    content.uri = format!("{base}/{}", content.uri);
+                }
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+        }
 
-                let tileid = &tile.id;
-                let qtree_nodeid: QuadTreeNodeId = tileid.into();
-                let tile_bbox = qtree.node(&qtree_nodeid).unwrap().bbox(grid);
-                let extent_width = tile_bbox[3] - tile_bbox[0];
+        /// Add content to the interior tiles that receive promoted large features (see
+        /// [crate::spatial_structs::LargeFeaturePolicy::Promote]), and remove the content of
+        /// the leaves whose features were all promoted. The promoted content is refined with
+        /// `ADD`, so that it stays visible together with the content of the children, which
+        /// keep their `REPLACE` refinement.
+        /// Returns the number of tiles with promoted content.
+        pub fn add_promoted_content<F, G>(&mut self, is_promoted_to: F, is_emptied: G) -> usize
+        where
+            F: Fn(&Tile) -> bool,
+            G: Fn(&Tile) -> bool,
+        {
+            let mut nr_tiles: usize = 0;
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                match tile.children {
+                    Some(_) if is_promoted_to(tile) => {
+                        tile.add_content();
+                        tile.refine = Some(Refinement::Add);
+                        nr_tiles += 1;
+                    }
+                    None if is_emptied(tile) => tile.content = None,
+                    _ => {}
+                }
+                if let Some(ref mut children) = tile.children {
+                    for child in children.iter_mut() {
+                        child.refine.get_or_insert(Refinement::Replace);
+                    }
+                    q.extend(children.iter_mut());
+                }
+            }
+            nr_tiles
+        }
 
-                let mut tiles_queue = VecDeque::new();
-                tiles_queue.push_back(tile);
-                let mut level_quadtree: u32 = 0;
-                for level_subtree in 0..subtree_levels as u32 {
-                    level_quadtree = level_subtree_root + level_subtree;
+        /// Limit the geometric error of the tileset and of each tile to `max`.
+        /// The geometric error decreases from the root to the leaves, thus the limited tiles
+        /// are the ones at the top of the hierarchy, and the children never have a larger
+        /// geometric error than their parent.
+        pub fn limit_geometric_error(&mut self, max: GeometricError) {
+            self.geometric_error = self.geometric_error.min(max);
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                tile.geometric_error = tile.geometric_error.min(max);
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+        }
+
+        /// Enlarge the bounding volume of the root tile by `padding` meters on each side.
+        pub fn pad_root(&mut self, padding: f64) {
+            self.root.bounding_volume = self.root.bounding_volume.padded(padding);
+        }
+
+        /// Collect the tiles that have content, including the interior tiles.
+        pub fn collect_tiles_with_content(&self) -> Vec<&Tile> {
+            let mut tiles: Vec<&Tile> = Vec::new();
+            let mut q = VecDeque::new();
+            q.push_back(&self.root);
+            while let Some(tile) = q.pop_front() {
+                if tile.content.is_some() {
+                    tiles.push(tile);
+                }
+                if let Some(ref children) = tile.children {
+                    q.extend(children.iter());
+                }
+            }
+            tiles
+        }
+
+        /// The number of levels between each interior tile and the leaves. If `adaptive`, the
+        /// levels are counted to the nearest leaf in the subtree of the tile, else to the
+        /// deepest level of the tileset, like in [Tileset::add_content].
+        pub fn interior_levels(&self, adaptive: bool) -> HashMap<TileId, u16> {
+            let mut levels: HashMap<TileId, u16> = HashMap::new();
+            self.root
+                .interior_levels_recurse(adaptive, self.root.max_level(), &mut levels);
+            levels
+        }
+
+        /// Label the content of the tiles with the quadtree level and the LoD of the models,
+        /// in the tile metadata and optionally in the content file name (see [content_stem]).
+        /// The interior tiles are labeled with their [InteriorLod] and the leaves with
+        /// `lod_leaf`.
+        pub fn add_lod_metadata(
+            &mut self,
+            lod_leaf: &str,
+            lod_interior: Option<&InteriorLod>,
+            lod_names: bool,
+        ) {
+            self.schema
+                .get_or_insert_with(Schema::tyler)
+                .classes
+                .insert("tile".to_string(), Class::tile_lod());
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                let lod = match (&tile.children, lod_interior) {
+                    (Some(_), Some(lod_interior)) => lod_interior.lod_of(&tile.id),
+                    _ => lod_leaf,
+                };
+                if lod_names {
+                    if let Some(stem) = tile.content_file_stem() {
+                        let uri = format!("t/{}.glb", content_stem(stem, Some(lod)));
+                        tile.content.as_mut().unwrap().uri = uri;
+                    }
+                }
+                if tile.content.is_some() {
+                    tile.metadata = Some(MetadataEntity::tile_lod(tile.id.level, lod));
+                }
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+        }
+
+        /// Add the [TileContentStats] of the content of each tile to the properties of the
+        /// `tile` metadata, next to the level and the LoD of [Tileset::add_lod_metadata], so
+        /// that the tiles can be styled and inspected without loading their content.
+        /// `stats_of` returns the statistics of the content of a tile.
+        /// Returns the number of tiles with statistics.
+        pub fn add_tile_stats<F: Fn(&Tile) -> Option<TileContentStats>>(
+            &mut self,
+            stats_of: F,
+        ) -> usize {
+            self.schema
+                .get_or_insert_with(Schema::tyler)
+                .classes
+                .entry("tile".to_string())
+                .or_default()
+                .properties
+                .extend(Class::tile_content_stats().properties);
+            let mut nr_tiles: usize = 0;
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                if let Some(stats) = tile.content.as_ref().and_then(|_| stats_of(tile)) {
+                    tile.metadata
+                        .get_or_insert_with(|| MetadataEntity {
+                            class: "tile".to_string(),
+                            properties: BTreeMap::new(),
+                        })
+                        .properties
+                        .extend(MetadataEntity::tile_content_stats(&stats));
+                    nr_tiles += 1;
+                }
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+            nr_tiles
+        }
+
+        /// Put the content of the tiles that contain priority features into a
+        /// [content group](https://github.com/CesiumGS/3d-tiles/tree/main/specification#metadata-groups)
+        /// with `priority: true`, so that the clients can load them first.
+        /// `has_priority` tells if the content of a tile contains priority features.
+        /// Returns the number of tiles in the group.
+        pub fn add_priority_group<F: Fn(&Tile) -> bool>(&mut self, has_priority: F) -> usize {
+            self.schema
+                .get_or_insert_with(Schema::tyler)
+                .classes
+                .insert("group".to_string(), Class::group_priority());
+            self.groups = Some(vec![MetadataEntity::group_priority()]);
+            let mut nr_tiles: usize = 0;
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                let priority = tile.content.is_some() && has_priority(tile);
+                if let (true, Some(content)) = (priority, tile.content.as_mut()) {
+                    content.group = Some(0);
+                    nr_tiles += 1;
+                }
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+            nr_tiles
+        }
+
+        /// Put the content of the tiles into a
+        /// [content group](https://github.com/CesiumGS/3d-tiles/tree/main/specification#metadata-groups)
+        /// per value of the `attribute`, so that the clients can show, hide or authorize the
+        /// groups separately. `group_of` returns the attribute value of the content of a tile.
+        /// The groups are ordered by their value.
+        /// Returns the number of groups.
+        pub fn add_attribute_groups<F: Fn(&Tile) -> Option<String>>(
+            &mut self,
+            attribute: &str,
+            group_of: F,
+        ) -> usize {
+            // The group of each tile in the breadth-first order
+            let mut tile_groups: Vec<Option<String>> = Vec::new();
+            let mut q = VecDeque::new();
+            q.push_back(&self.root);
+            while let Some(tile) = q.pop_front() {
+                tile_groups.push(tile.content.as_ref().and_then(|_| group_of(tile)));
+                if let Some(ref children) = tile.children {
+                    q.extend(children.iter());
+                }
+            }
+            let values: BTreeSet<&String> = tile_groups.iter().flatten().collect();
+            let values: Vec<String> = values.into_iter().cloned().collect();
+            if values.is_empty() {
+                return 0;
+            }
+            let mut tile_groups = tile_groups.into_iter();
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                if let (Some(group), Some(content)) =
+                    (tile_groups.next().flatten(), tile.content.as_mut())
+                {
+                    content.group = values.binary_search(&group).ok();
+                }
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+            self.schema
+                .get_or_insert_with(Schema::tyler)
+                .classes
+                .insert("group".to_string(), Class::group_attribute(attribute));
+            self.groups = Some(
+                values
+                    .iter()
+                    .map(|value| MetadataEntity::group_attribute(value))
+                    .collect(),
+            );
+            values.len()
+        }
+
+        /// Add the metadata `class` of the features to the schema, with a property for each of
+        /// the CityObject `attributes`, in the `<name>:<type>` format of --object-attribute, so
+        /// that the property tables of the glTF content (`EXT_structural_metadata`) resolve to
+        /// a class of the tileset schema.
+        pub fn add_feature_class(
+            &mut self,
+            class: &str,
+            attributes: &[String],
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let schema = self.schema.get_or_insert_with(Schema::tyler);
+            if schema.classes.contains_key(class) {
+                return Err(format!(
+                    "the metadata class '{class}' is already used by tyler, choose another name"
+                )
+                .into());
+            }
+            schema
+                .classes
+                .insert(class.to_string(), Class::feature_attributes(attributes)?);
+            Ok(())
+        }
+
+        /// Replace the box bounding volumes of the tiles and their content with the
+        /// `bounding_volume_type`, which encloses the box.
+        pub fn convert_bounding_volumes(
+            &mut self,
+            bounding_volume_type: BoundingVolumeType,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let transformer = match bounding_volume_type {
+                BoundingVolumeType::Box => return Ok(()),
+                BoundingVolumeType::Region => {
+                    Some(Proj::new_known_crs("EPSG:4978", "EPSG:4979", None)?)
+                }
+                BoundingVolumeType::Sphere => None,
+            };
+            let convert = |bv: &BoundingVolume| match &transformer {
+                Some(transformer) => bv.to_region(transformer),
+                None => Ok(bv.to_sphere()),
+            };
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                tile.bounding_volume = convert(&tile.bounding_volume)?;
+                if let Some(bv) = tile
+                    .content
+                    .as_mut()
+                    .and_then(|c| c.bounding_volume.as_mut())
+                {
+                    *bv = convert(bv)?;
+                }
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+            Ok(())
+        }
+
+        /// Set the 3D Tiles version of the tileset and declare the extensions that the version
+        /// needs. For 1.0 the glTF content is declared with the `3DTILES_content_gltf` extension,
+        /// together with the glTF extensions of the content, and the tile metadata, schema and
+        /// content groups are removed, because they are core features of 1.1 only. Implicit
+        /// tiling is not converted, therefore a 1.0 tileset must be explicit.
+        /// Set the version before [Tileset::split], so that the external tilesets inherit it.
+        pub fn set_version(&mut self, version: TilesetVersion) {
+            self.asset.version = version.to_string();
+            match version {
+                TilesetVersion::V1_0 => {
+                    let mut extensions: Extensions = HashMap::new();
+                    extensions.insert(
+                        ExtensionName::ContentGltf,
+                        Extension::ContentGtlf {
+                            extensions_used: Some(vec![
+                                ExtensionName::MeshFeatures,
+                                ExtensionName::StructuralMetadata,
+                            ]),
+                            extensions_required: None,
+                        },
+                    );
+                    self.extensions = Some(extensions);
+                    self.extensions_used = Some(vec![ExtensionName::ContentGltf]);
+                    self.extensions_required = Some(vec![ExtensionName::ContentGltf]);
+                    self.schema = None;
+                    self.groups = None;
+                    let mut q = VecDeque::new();
+                    q.push_back(&mut self.root);
+                    while let Some(tile) = q.pop_front() {
+                        tile.metadata = None;
+                        if let Some(content) = tile.content.as_mut() {
+                            content.group = None;
+                        }
+                        if let Some(ref mut children) = tile.children {
+                            q.extend(children.iter_mut());
+                        }
+                    }
+                }
+                TilesetVersion::V1_1 => {
+                    self.extensions = None;
+                    self.extensions_used = None;
+                    self.extensions_required = None;
+                }
+            }
+        }
+
+        /// The number of levels in the quadtree, which is `max_level + 1`.
+        pub fn available_levels(&self) -> u16 {
+            self.root.max_level() + 1
+        }
+
+        /// Convert to implicit tiling.
+        /// It modifies the tileset and deletes the explicit tiles.
+        /// Expects that explicit tiling is already created.
+        pub fn make_implicit(
+            &mut self,
+            grid: &SquareGrid,
+            qtree: &QuadTree,
+            grid_export: bool,
+            subtrees_dir: Option<&str>,
+            output_dir_debug: Option<&Path>,
+        ) -> (Vec<(Tile, TileId)>, Vec<(TileId, Vec<u8>)>) {
+            let mut subtrees_vec: Vec<(TileId, Vec<u8>)> = Vec::new();
+            let mut flat_tiles_with_content: Vec<(Tile, TileId)> = Vec::new();
+            // https://docs.ogc.org/cs/22-025r4/22-025r4.html#toc37
+            let subtree_sections: usize = 1;
+            let subtree_levels =
+                (self.available_levels() as f32 / subtree_sections as f32).ceil() as u16;
+            let subtrees = match subtrees_dir {
+                None => Subtrees::default(),
+                Some(dirname) => Subtrees::new(dirname),
+            };
+            let implicittiling = ImplicitTiling {
+                subdivision_scheme: SubdivisionScheme::Quadtree,
+                subtree_levels,
+                available_levels: self.available_levels(),
+                subtrees,
+            };
+            debug!("{:?}", &implicittiling);
+            self.root.implicit_tiling = Some(implicittiling);
+
+            // We want to have a sparse implicit tileset, which stores the availability
+            //  of each tile. We do not have a full quadtree, because of the node
+            //  capacity that is set.
+            // In order to create this sparse implicit tileset, we need to have a full
+            //  theoretical quadtree of the same height as the explicit quadtree
+            //  (tileset), and for each node in the theoretical tree, we need to mark
+            //  whether that node (tile) is indeed available and whether it has a
+            //  content in the explicit tileset.
+
+            let grid_epsg = grid.epsg;
+            let level_subtree_root: u32 = 0; // subtree root level
+            let mut subtree_queue = VecDeque::new();
+            let rootid = &self.root.id;
+            let cellid: CellId = rootid.into();
+            subtree_queue.push_back((level_subtree_root, cellid, &self.root));
+
+            while let Some((level_subtree_root, cellid, tile)) = subtree_queue.pop_front() {
+                let subtree_id = TileId::new(cellid.column, cellid.row, level_subtree_root as u16);
+                let mut buffer_vec: Vec<u8> = Vec::new();
+                let mut tile_availability_bitstream: bv::BitVec<u8, bv::Lsb0> = bv::BitVec::new();
+                let mut content_availability_bitstream: bv::BitVec<u8, bv::Lsb0> =
+                    bv::BitVec::new();
+
+                let tileid = &tile.id;
+                let qtree_nodeid: QuadTreeNodeId = tileid.into();
+                let tile_bbox = qtree.node(&qtree_nodeid).unwrap().bbox(grid);
+                let extent_width = tile_bbox[3] - tile_bbox[0];
+
+                let mut tiles_queue = VecDeque::new();
+                tiles_queue.push_back(tile);
+                let mut level_quadtree: u32 = 0;
+                for level_subtree in 0..subtree_levels as u32 {
+                    level_quadtree = level_subtree_root + level_subtree;
                     // The number of tiles on the current level of the full quadtree
                     let _nr_tiles = 4_usize.pow(level_quadtree);
                     // The number of tiles on the current level within the subtree. Each subtree
@@ -655,7 +1291,7 @@ pub mod cesium3dtiles {
                     if grid_export {
                         let nr_tiles = 4_usize.pow(level_subtree);
                         // Grid for the current level
-                        let tile_width = (extent_width / (nr_tiles as f64).sqrt()) as u32;
+                        let tile_width = extent_width / (nr_tiles as f64).sqrt();
                         let grid_for_level = SquareGrid::new(&tile_bbox, tile_width, grid_epsg);
                         let outdir = output_dir_debug.unwrap_or(Path::new(""));
                         let filename = outdir.join(format!(
@@ -851,6 +1487,7 @@ pub mod cesium3dtiles {
             self.root.content = Some(Content {
                 bounding_volume: None,
                 uri: "t/{level}/{x}/{y}.glb".to_string(),
+                group: None,
             });
             self.root.children = None;
             (flat_tiles_with_content, subtrees_vec)
@@ -909,7 +1546,7 @@ pub mod cesium3dtiles {
             let qtree_nodeid: QuadTreeNodeId = tileid.into();
             let tile_bbox = qtree.node(&qtree_nodeid).unwrap().bbox(grid);
             let [minx, miny, ..] = tile_bbox;
-            format!("{:.0},{:.0}", minx, miny)
+            format!("{:.3},{:.3}", minx, miny)
         }
 
         /// Build a map of grid-cell-corner-coorinates and cell ID-s.
@@ -926,7 +1563,7 @@ pub mod cesium3dtiles {
             let nr_tiles = 4_usize.pow(level_current);
 
             // Grid for the current level
-            let tile_width = (extent_width / (nr_tiles as f64).sqrt()) as u32;
+            let tile_width = extent_width / (nr_tiles as f64).sqrt();
             let grid_for_level = SquareGrid::new(bbox, tile_width, epsg);
 
             // Map of:
@@ -947,9 +1584,9 @@ pub mod cesium3dtiles {
 
             for (i, (_mc, cellid)) in mortoncodes.iter().enumerate() {
                 let [minx, miny, ..] = grid_for_level.cell_bbox(cellid);
-                // Since the input for grid_cellsize is u16 and expected to be in the range
-                //  of several (hundreds) of meters, we don't care about decimal precision.
-                let corner_coord_string = format!("{:.0},{:.0}", minx, miny);
+                // The grid cellsize can be smaller than a unit (eg. indoor models), but it is
+                //  rounded to thousandths, so we don't care about more decimal precision.
+                let corner_coord_string = format!("{:.3},{:.3}", minx, miny);
                 grid_for_level_corner_coords.insert(corner_coord_string, (*cellid, i));
             }
 
@@ -989,11 +1626,47 @@ pub mod cesium3dtiles {
         }
 
         /// Prune the tileset by removing the tiles in `tiles_to_remove`.
-        /// In addition, it also removes that with `nr_items == 0`.
+        /// In addition, it also removes that with `nr_items == 0`, and the interior tiles
+        /// without content whose children are all removed, so that the tileset does not
+        /// refer to missing content.
         pub fn prune(&mut self, tiles_to_remove: &Vec<Tile>, qtree: &QuadTree) {
             self.root.prune(tiles_to_remove, qtree);
         }
 
+        /// Remove the tiles from the tileset. Unlike [Tileset::prune], it does not need the
+        /// quadtree, thus it also works for the tileset of a tile index.
+        pub fn remove_tiles(&mut self, tiles_to_remove: &[Tile]) {
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                if let Some(ref mut children) = tile.children {
+                    children.retain(|child| !tiles_to_remove.contains(child));
+                    q.extend(children.iter_mut());
+                }
+            }
+        }
+
+        /// Remove the content of the tiles for which `is_incomplete` is true, but keep the
+        /// tiles, so that the content of their descendants is still loaded. It is used for the
+        /// provisional tilesets, that are written while the tiles are converted.
+        /// Returns the number of tiles whose content was removed.
+        pub fn remove_content<F: Fn(&Tile) -> bool>(&mut self, is_incomplete: F) -> usize {
+            let mut nr_tiles: usize = 0;
+            let mut q = VecDeque::new();
+            q.push_back(&mut self.root);
+            while let Some(tile) = q.pop_front() {
+                if tile.content.is_some() && is_incomplete(tile) {
+                    tile.content = None;
+                    tile.metadata = None;
+                    nr_tiles += 1;
+                }
+                if let Some(ref mut children) = tile.children {
+                    q.extend(children.iter_mut());
+                }
+            }
+            nr_tiles
+        }
+
         /// Splits a tileset into several tilesets at the given level, to create
         /// [external tilesets](https://docs.ogc.org/cs/22-025r4/22-025r4.html#core-external-tilesets).
         /// The tile at `level` becomes the root tile of the new tileset.
@@ -1005,6 +1678,12 @@ pub mod cesium3dtiles {
         pub fn split(&mut self, level: u16) -> Vec<(String, Tileset)> {
             let max_nr_tilesets = 4_usize.pow(level as u32);
             let mut child_tilesets: Vec<(String, Tileset)> = Vec::with_capacity(max_nr_tilesets);
+            let schema = self.schema.clone();
+            let groups = self.groups.clone();
+            let asset = self.asset.clone();
+            let extensions_used = self.extensions_used.clone();
+            let extensions_required = self.extensions_required.clone();
+            let extensions = self.extensions.clone();
             let mut q = VecDeque::new();
             q.push_back(&mut self.root);
             while let Some(tile) = q.pop_front() {
@@ -1015,20 +1694,24 @@ pub mod cesium3dtiles {
                     child_tilesets.push((
                         filename.clone(),
                         Tileset {
-                            asset: Default::default(),
+                            asset: asset.clone(),
                             geometric_error: tile.geometric_error,
                             root: tile.clone(),
                             properties: None,
-                            extensions_used: None,
-                            extensions_required: None,
-                            extensions: None,
+                            schema: schema.clone(),
+                            groups: groups.clone(),
+                            extensions_used: extensions_used.clone(),
+                            extensions_required: extensions_required.clone(),
+                            extensions: extensions.clone(),
                         },
                     ));
                     // Update the current tile to point to the new tileset
                     tile.content = Some(Content {
                         bounding_volume: None,
                         uri: filename,
+                        group: None,
                     });
+                    tile.metadata = None;
                     tile.children = None;
                 }
                 if let Some(ref mut children) = tile.children {
@@ -1125,6 +1808,8 @@ pub mod cesium3dtiles {
         #[serde(skip_serializing_if = "Option::is_none")]
         content: Option<Content>,
         #[serde(skip_serializing_if = "Option::is_none")]
+        metadata: Option<MetadataEntity>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         pub children: Option<Vec<Tile>>,
         #[serde(skip_serializing_if = "Option::is_none")]
         implicit_tiling: Option<ImplicitTiling>,
@@ -1199,7 +1884,6 @@ pub mod cesium3dtiles {
             leaves
         }
 
-        #[allow(dead_code)]
         fn add_content_from_level(&mut self, levels_up: Option<u16>) {
             let max_level = self.max_level();
             let mut lower_limit: u16 = 0;
@@ -1211,7 +1895,9 @@ pub mod cesium3dtiles {
             let mut q = VecDeque::new();
             q.push_back(self);
             while let Some(node) = q.pop_front() {
-                if node.id.level >= lower_limit {
+                // The leaves already have their content
+                if node.id.level >= lower_limit && node.children.is_some() && node.content.is_none()
+                {
                     node.add_content();
                 }
                 if let Some(ref mut children) = node.children {
@@ -1222,6 +1908,49 @@ pub mod cesium3dtiles {
             }
         }
 
+        /// Add content to the interior tiles that are at most `levels_up` levels above one of
+        /// the leaves in their subtree. Returns the number of levels to the nearest leaf.
+        fn add_content_adaptive(&mut self, levels_up: u16) -> u16 {
+            let levels_to_leaf = match self.children {
+                None => return 0,
+                Some(ref mut children) => children
+                    .iter_mut()
+                    .map(|child| child.add_content_adaptive(levels_up))
+                    .min()
+                    .unwrap_or(0)
+                    .saturating_add(1),
+            };
+            if levels_to_leaf <= levels_up && self.content.is_none() {
+                self.add_content();
+            }
+            levels_to_leaf
+        }
+
+        /// Returns the number of levels to the nearest leaf.
+        fn interior_levels_recurse(
+            &self,
+            adaptive: bool,
+            max_level: u16,
+            levels: &mut HashMap<TileId, u16>,
+        ) -> u16 {
+            let levels_to_leaf = match self.children {
+                None => return 0,
+                Some(ref children) => children
+                    .iter()
+                    .map(|child| child.interior_levels_recurse(adaptive, max_level, levels))
+                    .min()
+                    .unwrap_or(0)
+                    .saturating_add(1),
+            };
+            let levels_up = if adaptive {
+                levels_to_leaf
+            } else {
+                max_level.saturating_sub(self.id.level)
+            };
+            levels.insert(self.id.clone(), levels_up);
+            levels_to_leaf
+        }
+
         fn max_level(&self) -> u16 {
             let mut max_level: u16 = 0;
             self.max_level_recurse(&mut max_level);
@@ -1240,14 +1969,23 @@ pub mod cesium3dtiles {
 
         // Adds `Content` to the Tile, by generating a content bounding volume from the
         // tile's bounding volume and a filepath from the tile.id.
-        #[allow(dead_code)]
         pub fn add_content(&mut self) {
             self.content = Some(Content {
                 bounding_volume: Some(self.bounding_volume),
                 uri: format!("t/{}.glb", self.id),
+                group: None,
             })
         }
 
+        /// The file stem of the content, relative to the content directory, eg. `3/5/2` for
+        /// `t/3/5/2.glb`.
+        pub fn content_file_stem(&self) -> Option<&str> {
+            self.content
+                .as_ref()
+                .and_then(|c| c.uri.strip_prefix("t/"))
+                .and_then(|uri| uri.strip_suffix(".glb"))
+        }
+
         fn prune(&mut self, tiles_to_remove: &Vec<Tile>, qtree: &QuadTree) {
             if let Some(mut children) = self.children.take() {
                 let mut children_new: Vec<Tile> = Vec::with_capacity(4);
@@ -1258,19 +1996,22 @@ pub mod cesium3dtiles {
                         if let Some(qtree_node) = qtree.node(&qtree_nodeid) {
                             if qtree_node.nr_items > 0 {
                                 child.prune(tiles_to_remove, qtree);
-                                children_new.push(child.clone());
+                                // An empty tile is left if all its children are removed
+                                if child.children.is_some() || child.content.is_some() {
+                                    children_new.push(child.clone());
+                                }
                             }
                         } else {
                             error!("Did not find matching QuadTree node for TileId {}", tileid);
                         }
                     }
                 }
-                self.children = Some(children_new);
+                self.children = (!children_new.is_empty()).then_some(children_new);
             }
         }
     }
 
-    #[derive(Clone, Debug, Default, Eq, PartialEq)]
+    #[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
     pub struct TileId {
         pub(crate) x: usize,
         pub(crate) y: usize,
@@ -1314,29 +2055,27 @@ pub mod cesium3dtiles {
         }
     }
 
-    /// Format the BoundingVolume coordinates to 2 decimal places in the JSON output.
+    /// Round the BoundingVolume coordinates to 2 decimal places in the JSON output.
     /// 2 decimal places, because we have Cartesian ECEF coordinates.
     /// If we had lat/long, we would need 6 decimal places, because that gives 0.11112m precision.
     /// See https://wiki.openstreetmap.org/wiki/Precision_of_coordinates
-    struct BoundingVolumeFormatter;
-
-    impl serde_json::ser::Formatter for BoundingVolumeFormatter {
-        fn write_f64<W>(&mut self, writer: &mut W, value: f64) -> std::io::Result<()>
-        where
-            W: ?Sized + Write,
-        {
-            write!(writer, "{:.2}", value)
-        }
+    fn serialize_rounded<S: serde::Serializer, const N: usize>(
+        values: &[f64; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(|value| (value * 100.0).round() / 100.0))
     }
 
     /// [boundingVolume](https://github.com/CesiumGS/3d-tiles/tree/main/specification#bounding-volumes).
+    /// The box and the sphere are rounded to centimeters. The longitude and latitude of a region
+    /// are in radians, thus the region is written in full precision.
     #[allow(dead_code)]
     #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
     #[serde(rename_all = "lowercase")]
     enum BoundingVolume {
-        Box([f64; 12]),
+        Box(#[serde(serialize_with = "serialize_rounded")] [f64; 12]),
         Region([f64; 6]),
-        Sphere([f64; 4]),
+        Sphere(#[serde(serialize_with = "serialize_rounded")] [f64; 4]),
     }
 
     impl Default for BoundingVolume {
@@ -1346,6 +2085,42 @@ pub mod cesium3dtiles {
     }
 
     impl BoundingVolume {
+        /// The bounding volume enlarged by `padding` meters on each side.
+        /// The padding of a region is converted to radians on the mean radius of the WGS84
+        /// ellipsoid, which is exact enough for a padding.
+        fn padded(self, padding: f64) -> Self {
+            const RADIUS: f64 = 6_371_008.8;
+            match self {
+                Self::Box(mut b) => {
+                    for axis in [3, 6, 9] {
+                        let length =
+                            (b[axis].powi(2) + b[axis + 1].powi(2) + b[axis + 2].powi(2)).sqrt();
+                        if length > 0.0 {
+                            let scale = (length + padding) / length;
+                            for v in &mut b[axis..axis + 3] {
+                                *v *= scale;
+                            }
+                        }
+                    }
+                    Self::Box(b)
+                }
+                Self::Region([west, south, east, north, min_h, max_h]) => {
+                    let d_lat = padding / RADIUS;
+                    let lat = south.abs().max(north.abs());
+                    let d_lon = padding / (RADIUS * lat.cos().max(f64::EPSILON));
+                    Self::Region([
+                        (west - d_lon).max(-std::f64::consts::PI),
+                        (south - d_lat).max(-std::f64::consts::FRAC_PI_2),
+                        (east + d_lon).min(std::f64::consts::PI),
+                        (north + d_lat).min(std::f64::consts::FRAC_PI_2),
+                        min_h - padding,
+                        max_h + padding,
+                    ])
+                }
+                Self::Sphere([x, y, z, r]) => Self::Sphere([x, y, z, r + padding]),
+            }
+        }
+
         /// Compute the boundingVolume.box from a 'regular' bounding box.
         ///
         /// This function does reproject the bounding box coordinates.
@@ -1464,6 +2239,59 @@ pub mod cesium3dtiles {
             ]))
         }
 
+        /// The corners, the face centers and the center of a box, which bound the height of
+        /// the box above the ellipsoid too.
+        fn box_points(b: &[f64; 12]) -> Vec<[f64; 3]> {
+            let mut points: Vec<[f64; 3]> = Vec::with_capacity(27);
+            for sx in [-1.0, 0.0, 1.0] {
+                for sy in [-1.0, 0.0, 1.0] {
+                    for sz in [-1.0, 0.0, 1.0] {
+                        points.push(std::array::from_fn(|i| {
+                            b[i] + sx * b[3 + i] + sy * b[6 + i] + sz * b[9 + i]
+                        }));
+                    }
+                }
+            }
+            points
+        }
+
+        /// The sphere that encloses the box. The other bounding volumes are returned as they
+        /// are.
+        fn to_sphere(self) -> Self {
+            match self {
+                Self::Box(b) => {
+                    let radius = Self::box_points(&b)
+                        .iter()
+                        .map(|p| {
+                            ((p[0] - b[0]).powi(2) + (p[1] - b[1]).powi(2) + (p[2] - b[2]).powi(2))
+                                .sqrt()
+                        })
+                        .fold(0.0, f64::max);
+                    Self::Sphere([b[0], b[1], b[2], radius])
+                }
+                other => other,
+            }
+        }
+
+        /// The region that encloses the box. The `transformer` must convert from `EPSG:4978`
+        /// to `EPSG:4979`. The other bounding volumes are returned as they are.
+        fn to_region(self, transformer: &Proj) -> Result<Self, Box<dyn std::error::Error>> {
+            let Self::Box(b) = self else {
+                return Ok(self);
+            };
+            let mut region = [f64::MAX, f64::MAX, f64::MIN, f64::MIN, f64::MAX, f64::MIN];
+            for [x, y, z] in Self::box_points(&b) {
+                let (lon, lat, h) = transformer.convert((x, y, z))?;
+                region[0] = region[0].min(lon.to_radians());
+                region[1] = region[1].min(lat.to_radians());
+                region[2] = region[2].max(lon.to_radians());
+                region[3] = region[3].max(lat.to_radians());
+                region[4] = region[4].min(h);
+                region[5] = region[5].max(h);
+            }
+            Ok(Self::Region(region))
+        }
+
         #[allow(dead_code)]
         fn region_from_bbox(
             bbox: &Bbox,
@@ -1531,9 +2359,7 @@ pub mod cesium3dtiles {
                     bbox[3].to_degrees(),
                     bbox[5],
                 ],
-                BoundingVolume::Sphere(_) => {
-                    unimplemented!()
-                }
+                BoundingVolume::Sphere([x, y, z, r]) => [x - r, y - r, z - r, x + r, y + r, z + r],
             };
             format!(
                 "POLYGON(({minx} {miny}, {maxx} {miny}, {maxx} {maxy}, {minx} {maxy}, {minx} {miny}))",
@@ -1546,11 +2372,12 @@ pub mod cesium3dtiles {
     }
 
     /// [Tile.refine](https://github.com/CesiumGS/3d-tiles/tree/main/specification#tilerefine).
-    #[allow(dead_code)]
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, clap::ValueEnum)]
     #[serde(rename_all = "UPPERCASE")]
-    enum Refinement {
+    pub enum Refinement {
+        /// The content of the children is rendered together with the content of the parent.
         Add,
+        /// The content of the children replaces the content of the parent.
         Replace,
     }
 
@@ -1570,25 +2397,290 @@ pub mod cesium3dtiles {
         }
     }
 
-    /// [Tile.content](https://github.com/CesiumGS/3d-tiles/tree/main/specification#content).
+    /// The file stem of the tile content, relative to the content directory. The `name` is the
+    /// tile ID, or the tile index ID. If `lod` is provided, it is appended to the name, eg.
+    /// `3/5/2-lod2.2`. The level is already part of the tile ID.
+    pub fn content_stem(name: &str, lod: Option<&str>) -> String {
+        match lod {
+            None => name.to_string(),
+            Some(lod) => format!("{name}-lod{}", lod.replace(',', "_")),
+        }
+    }
+
+    /// [Metadata schema](https://github.com/CesiumGS/3d-tiles/tree/main/specification/Metadata#schema).
+    ///
+    /// Only the `classes` are supported.
     #[derive(Serialize, Deserialize, Default, Debug, Clone)]
     #[serde(rename_all = "camelCase")]
-    struct Content {
-        #[serde(skip_serializing_if = "Option::is_none")]
-        bounding_volume: Option<BoundingVolume>,
-        uri: String,
+    struct Schema {
+        id: String,
+        classes: BTreeMap<String, Class>,
     }
 
-    /// Implicit tiling object.
-    /// https://github.com/CesiumGS/3d-tiles/tree/1.1/specification/ImplicitTiling#implicit-root-tile
-    /// https://github.com/CesiumGS/3d-tiles/blob/1.1/specification/schema/tile.implicitTiling.schema.json
+    impl Schema {
+        /// The schema of the metadata that is written by tyler, without classes.
+        fn tyler() -> Self {
+            Self {
+                id: "tyler".to_string(),
+                classes: BTreeMap::new(),
+            }
+        }
+    }
+
+    /// [Class](https://github.com/CesiumGS/3d-tiles/tree/main/specification/Metadata#class).
     #[derive(Serialize, Deserialize, Default, Debug, Clone)]
     #[serde(rename_all = "camelCase")]
-    struct ImplicitTiling {
-        subdivision_scheme: SubdivisionScheme,
-        subtree_levels: u16,
-        available_levels: u16,
-        subtrees: Subtrees,
+    struct Class {
+        properties: BTreeMap<String, ClassProperty>,
+    }
+
+    impl Class {
+        /// The `tile` class, which records the quadtree level and the LoD of the tile content.
+        fn tile_lod() -> Self {
+            let properties = BTreeMap::from([
+                (
+                    "level".to_string(),
+                    ClassProperty {
+                        property_type: "SCALAR".to_string(),
+                        component_type: Some("UINT32".to_string()),
+                        array: None,
+                        description: Some("Level of the tile in the quadtree".to_string()),
+                    },
+                ),
+                (
+                    "lod".to_string(),
+                    ClassProperty {
+                        property_type: "STRING".to_string(),
+                        component_type: None,
+                        array: None,
+                        description: Some("LoD of the models in the tile content".to_string()),
+                    },
+                ),
+            ]);
+            Self { properties }
+        }
+
+        /// The properties of the `tile` class that describe the content of the tile, see
+        /// [TileContentStats].
+        fn tile_content_stats() -> Self {
+            let property = |property_type: &str,
+                            component_type: Option<&str>,
+                            array: bool,
+                            description: &str| ClassProperty {
+                property_type: property_type.to_string(),
+                component_type: component_type.map(str::to_string),
+                array: array.then_some(true),
+                description: Some(description.to_string()),
+            };
+            let properties = BTreeMap::from([
+                (
+                    "featureCount".to_string(),
+                    property(
+                        "SCALAR",
+                        Some("UINT32"),
+                        false,
+                        "Number of features in the tile content",
+                    ),
+                ),
+                (
+                    "cityObjectTypes".to_string(),
+                    property("STRING", None, true, "CityObject types in the tile content"),
+                ),
+                (
+                    "cityObjectCounts".to_string(),
+                    property(
+                        "SCALAR",
+                        Some("UINT32"),
+                        true,
+                        "Number of CityObjects of each of the cityObjectTypes",
+                    ),
+                ),
+                (
+                    "minimumHeight".to_string(),
+                    property(
+                        "SCALAR",
+                        Some("FLOAT64"),
+                        false,
+                        "Minimum height of the features, in the units of the input CRS",
+                    ),
+                ),
+                (
+                    "maximumHeight".to_string(),
+                    property(
+                        "SCALAR",
+                        Some("FLOAT64"),
+                        false,
+                        "Maximum height of the features, in the units of the input CRS",
+                    ),
+                ),
+            ]);
+            Self { properties }
+        }
+
+        /// The class of the features, with the CityObject `attributes` that are written into
+        /// the glTF content, in the `<name>:<type>` format, where the type is 'bool', 'int',
+        /// 'float' or 'string'.
+        fn feature_attributes(attributes: &[String]) -> Result<Self, String> {
+            let mut properties: BTreeMap<String, ClassProperty> = BTreeMap::new();
+            for attribute in attributes {
+                let (name, attribute_type) = attribute.split_once(':').ok_or_else(|| {
+                    format!("attribute must be in the format of 'name:type': {attribute}")
+                })?;
+                let (property_type, component_type) = match attribute_type {
+                    "bool" => ("BOOLEAN", None),
+                    "int" => ("SCALAR", Some("INT32")),
+                    "float" => ("SCALAR", Some("FLOAT32")),
+                    "string" => ("STRING", None),
+                    _ => {
+                        return Err(format!(
+                            "attribute type must be one of 'bool', 'int', 'float', 'string': {attribute}"
+                        ))
+                    }
+                };
+                properties.insert(
+                    name.to_string(),
+                    ClassProperty {
+                        property_type: property_type.to_string(),
+                        component_type: component_type.map(str::to_string),
+                        array: None,
+                        description: Some(format!("CityObject attribute '{name}'")),
+                    },
+                );
+            }
+            Ok(Self { properties })
+        }
+
+        /// The `group` class of the content groups, which marks the content that contains
+        /// priority features.
+        fn group_priority() -> Self {
+            let properties = BTreeMap::from([(
+                "priority".to_string(),
+                ClassProperty {
+                    property_type: "BOOLEAN".to_string(),
+                    component_type: None,
+                    array: None,
+                    description: Some(
+                        "The content contains features that should be loaded first".to_string(),
+                    ),
+                },
+            )]);
+            Self { properties }
+        }
+
+        /// The `group` class of the content groups, which records the value of the group
+        /// `attribute` of the features in the content.
+        fn group_attribute(attribute: &str) -> Self {
+            let properties = BTreeMap::from([(
+                "value".to_string(),
+                ClassProperty {
+                    property_type: "STRING".to_string(),
+                    component_type: None,
+                    array: None,
+                    description: Some(format!(
+                        "The value of the '{}' attribute of the features in the content",
+                        attribute
+                    )),
+                },
+            )]);
+            Self { properties }
+        }
+    }
+
+    /// [Class property](https://github.com/CesiumGS/3d-tiles/tree/main/specification/Metadata#class-property).
+    #[derive(Serialize, Deserialize, Default, Debug, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct ClassProperty {
+        #[serde(rename = "type")]
+        property_type: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        component_type: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        array: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+    }
+
+    /// [Metadata entity](https://github.com/CesiumGS/3d-tiles/tree/main/specification#metadataentity).
+    #[derive(Serialize, Deserialize, Default, Debug, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct MetadataEntity {
+        class: String,
+        properties: BTreeMap<String, serde_json::Value>,
+    }
+
+    impl MetadataEntity {
+        fn group_priority() -> Self {
+            Self {
+                class: "group".to_string(),
+                properties: BTreeMap::from([(
+                    "priority".to_string(),
+                    serde_json::Value::from(true),
+                )]),
+            }
+        }
+
+        fn group_attribute(value: &str) -> Self {
+            Self {
+                class: "group".to_string(),
+                properties: BTreeMap::from([("value".to_string(), serde_json::Value::from(value))]),
+            }
+        }
+
+        fn tile_content_stats(stats: &TileContentStats) -> BTreeMap<String, serde_json::Value> {
+            let mut properties = BTreeMap::from([
+                (
+                    "featureCount".to_string(),
+                    serde_json::Value::from(stats.nr_features),
+                ),
+                (
+                    "cityObjectTypes".to_string(),
+                    serde_json::Value::from_iter(stats.cityobject_types.keys().cloned()),
+                ),
+                (
+                    "cityObjectCounts".to_string(),
+                    serde_json::Value::from_iter(stats.cityobject_types.values().copied()),
+                ),
+            ]);
+            if let Some([min, max]) = stats.height {
+                properties.insert("minimumHeight".to_string(), serde_json::Value::from(min));
+                properties.insert("maximumHeight".to_string(), serde_json::Value::from(max));
+            }
+            properties
+        }
+
+        fn tile_lod(level: u16, lod: &str) -> Self {
+            Self {
+                class: "tile".to_string(),
+                properties: BTreeMap::from([
+                    ("level".to_string(), serde_json::Value::from(level)),
+                    ("lod".to_string(), serde_json::Value::from(lod)),
+                ]),
+            }
+        }
+    }
+
+    /// [Tile.content](https://github.com/CesiumGS/3d-tiles/tree/main/specification#content).
+    #[derive(Serialize, Deserialize, Default, Debug, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct Content {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bounding_volume: Option<BoundingVolume>,
+        uri: String,
+        /// The index of the content group in [Tileset] `groups`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        group: Option<usize>,
+    }
+
+    /// Implicit tiling object.
+    /// https://github.com/CesiumGS/3d-tiles/tree/1.1/specification/ImplicitTiling#implicit-root-tile
+    /// https://github.com/CesiumGS/3d-tiles/blob/1.1/specification/schema/tile.implicitTiling.schema.json
+    #[derive(Serialize, Deserialize, Default, Debug, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct ImplicitTiling {
+        subdivision_scheme: SubdivisionScheme,
+        subtree_levels: u16,
+        available_levels: u16,
+        subtrees: Subtrees,
     }
 
     /// Implicit tiling subtree subdivision scheme.
@@ -1710,27 +2802,42 @@ pub mod cesium3dtiles {
                 test_data_dir()
                     .join("features_3dbag_5909")
                     .join("3dbag_v21031_7425c21b_5909_subset"),
-                200,
-                Some(vec![
-                    crate::parser::CityObjectType::Building,
-                    crate::parser::CityObjectType::BuildingPart,
-                ]),
-                None,
-                None,
+                200.0,
+                crate::parser::WorldOptions {
+                    cityobject_types: Some(vec![
+                        crate::parser::CityObjectType::Building,
+                        crate::parser::CityObjectType::BuildingPart,
+                    ]),
+                    ..Default::default()
+                },
             )
             .unwrap();
-            world.index_with_grid();
+            world.index_with_grid(None);
 
             world.export_grid(false, None).unwrap();
 
             let quadtree = QuadTree::from_world(
                 &world,
                 crate::spatial_structs::QuadTreeCapacity::Vertices(15000),
+                crate::spatial_structs::QuadTreeDepth::default(),
             );
             quadtree.export(&world, None).unwrap();
 
-            let _tileset =
-                Tileset::from_quadtree(&quadtree, &world, 16_f64, 200, None, None, true, true);
+            let _tileset = Tileset::from_quadtree(
+                &quadtree,
+                &world,
+                &TilesetOptions {
+                    geometric_error_above_leaf: 16_f64,
+                    geometric_error_scheme: GeometricErrorScheme::Grid,
+                    cellsize: 200.0,
+                    minz: None,
+                    maxz: None,
+                    content_bv_from_tile: true,
+                    content_add_bv: true,
+                    z_scale: 1.0,
+                    tight_bounds: None,
+                },
+            );
 
             // tileset.make_implicit(&world.grid, &quadtree, );
 
@@ -1738,12 +2845,749 @@ pub mod cesium3dtiles {
             println!("{}", serde_json::to_string(&i).unwrap());
         }
 
+        #[test]
+        fn test_add_content_interior() {
+            let leaf = |x: usize, y: usize| Tile {
+                id: TileId::new(x, y, 2),
+                content: Some(Content {
+                    bounding_volume: None,
+                    uri: format!("t/2/{x}/{y}.glb"),
+                    group: None,
+                }),
+                ..Default::default()
+            };
+            let interior = Tile {
+                id: TileId::new(0, 0, 1),
+                children: Some(vec![leaf(0, 0), leaf(1, 0)]),
+                ..Default::default()
+            };
+            let mut tileset = Tileset {
+                root: Tile {
+                    id: TileId::new(0, 0, 0),
+                    children: Some(vec![interior]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset.add_content(Some(1), false);
+            let ids: Vec<String> = tileset
+                .collect_tiles_with_content()
+                .iter()
+                .map(|t| t.id.to_string())
+                .collect();
+            assert_eq!(ids, vec!["1/0/0", "2/0/0", "2/1/0"]);
+            // The leaves keep their own content
+            assert!(tileset.collect_leaves()[0]
+                .content
+                .as_ref()
+                .is_some_and(|c| c.bounding_volume.is_none()));
+        }
+
+        #[test]
+        fn test_rename_content() {
+            // A quadtree of a 4x4 grid
+            let leaf = |x: usize, y: usize| Tile {
+                id: TileId::new(x, y, 2),
+                content: Some(Content {
+                    bounding_volume: None,
+                    uri: format!("t/2/{x}/{y}.glb"),
+                    group: None,
+                }),
+                ..Default::default()
+            };
+            let interior = Tile {
+                id: TileId::new(2, 0, 1),
+                children: Some(vec![leaf(2, 0), leaf(3, 1)]),
+                ..Default::default()
+            };
+            let mut tileset = Tileset {
+                root: Tile {
+                    id: TileId::new(0, 0, 0),
+                    children: Some(vec![interior]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset.add_content(None, false);
+            tileset.rename_content(TileNaming::Quadkey, 2);
+            let uris = |tileset: &Tileset| -> Vec<String> {
+                tileset
+                    .collect_tiles_with_content()
+                    .iter()
+                    .map(|t| t.content.as_ref().unwrap().uri.clone())
+                    .collect()
+            };
+            assert_eq!(
+                uris(&tileset),
+                vec!["t/root.glb", "t/1.glb", "t/10.glb", "t/13.glb"]
+            );
+            // The LoD is appended to the renamed content
+            tileset.add_lod_metadata("2.2", None, true);
+            assert_eq!(tileset.root.content_file_stem(), Some("root-lod2.2"));
+            tileset.rename_content(TileNaming::Morton, 2);
+            assert_eq!(
+                uris(&tileset),
+                vec!["t/0-0.glb", "t/1-1.glb", "t/2-4.glb", "t/2-7.glb"]
+            );
+        }
+
+        #[test]
+        fn test_prune() {
+            use crate::spatial_structs::{QuadTreeCapacity, QuadTreeDepth};
+            let world = crate::fixtures::TilingFixture::load("tiling_sample", 20.0).world;
+            let quadtree = QuadTree::from_world(
+                &world,
+                QuadTreeCapacity::Objects(2),
+                QuadTreeDepth::default(),
+            );
+            // The explicit tiles of the quadtree, with content in the leaves
+            fn tile(node: &QuadTree) -> Tile {
+                Tile {
+                    id: TileId::from(&node.id),
+                    content: node.children.is_empty().then(Content::default),
+                    children: (!node.children.is_empty())
+                        .then(|| node.children.iter().map(tile).collect()),
+                    ..Default::default()
+                }
+            }
+            let tileset = Tileset {
+                root: tile(&quadtree),
+                ..Default::default()
+            };
+            let interior = tileset
+                .root
+                .children
+                .as_ref()
+                .unwrap()
+                .iter()
+                .find(|t| t.children.is_some())
+                .expect("the quadtree should have an interior tile below the root")
+                .clone();
+
+            // The empty leaves are removed
+            let mut pruned = tileset.clone();
+            pruned.prune(&vec![], &quadtree);
+            let nr_leaves = quadtree
+                .collect_leaves()
+                .iter()
+                .filter(|leaf| leaf.nr_items > 0)
+                .count();
+            assert_eq!(pruned.collect_leaves().len(), nr_leaves);
+
+            // The interior tile is removed with all of its leaves
+            let failed: Vec<Tile> = interior.collect_leaves().into_iter().cloned().collect();
+            let mut pruned = tileset.clone();
+            pruned.prune(&failed, &quadtree);
+            fn contains(tile: &Tile, id: &TileId) -> bool {
+                tile.id == *id
+                    || tile
+                        .children
+                        .iter()
+                        .flatten()
+                        .any(|child| contains(child, id))
+            }
+            assert!(!contains(&pruned.root, &interior.id));
+            assert!(pruned.collect_leaves().iter().all(|t| t.content.is_some()));
+
+            // Nothing is left if all the leaves fail
+            let failed: Vec<Tile> = tileset.collect_leaves().into_iter().cloned().collect();
+            let mut pruned = tileset.clone();
+            pruned.prune(&failed, &quadtree);
+            assert!(pruned.root.children.is_none());
+        }
+
+        #[test]
+        fn test_root_options() {
+            let leaf = |x: usize| Tile {
+                id: TileId::new(x, 0, 1),
+                geometric_error: 12.0,
+                content: Some(Content::default()),
+                ..Default::default()
+            };
+            let mut tileset = Tileset {
+                geometric_error: 600.0,
+                root: Tile {
+                    id: TileId::new(0, 0, 0),
+                    geometric_error: 400.0,
+                    bounding_volume: BoundingVolume::Box([
+                        0.0, 0.0, 0.0, 3.0, 4.0, 0.0, 0.0, 10.0, 0.0, 0.0, 0.0, 0.0,
+                    ]),
+                    children: Some(vec![leaf(0), leaf(1)]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset.limit_geometric_error(100.0);
+            assert_eq!(tileset.geometric_error, 100.0);
+            assert_eq!(tileset.root.geometric_error, 100.0);
+            assert_eq!(tileset.collect_leaves()[0].geometric_error, 12.0);
+
+            tileset.pad_root(5.0);
+            let BoundingVolume::Box(b) = tileset.root.bounding_volume else {
+                panic!("expected a box")
+            };
+            assert_eq!(&b[3..9], &[6.0, 8.0, 0.0, 0.0, 15.0, 0.0]);
+            // A degenerate axis is not padded, because it has no direction
+            assert_eq!(&b[9..], &[0.0, 0.0, 0.0]);
+
+            assert!(tileset.add_root_content());
+            assert_eq!(tileset.root.content_file_stem(), Some("0/0/0"));
+            let BoundingVolume::Sphere(s) =
+                BoundingVolume::Sphere([1.0, 2.0, 3.0, 4.0]).padded(1.0)
+            else {
+                panic!("expected a sphere")
+            };
+            assert_eq!(s, [1.0, 2.0, 3.0, 5.0]);
+        }
+
+        #[test]
+        fn test_convert_bounding_volumes() {
+            let bv = BoundingVolume::Box([
+                10.0, 20.0, 30.0, 3.0, 0.0, 0.0, 0.0, 4.0, 0.0, 0.0, 0.0, 12.0,
+            ]);
+            let mut tileset = Tileset {
+                root: Tile {
+                    bounding_volume: bv,
+                    content: Some(Content {
+                        bounding_volume: Some(bv),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset
+                .convert_bounding_volumes(BoundingVolumeType::Sphere)
+                .unwrap();
+            let value = serde_json::to_value(&tileset).unwrap();
+            let sphere = serde_json::json!({"sphere": [10.0, 20.0, 30.0, 13.0]});
+            assert_eq!(value["root"]["boundingVolume"], sphere);
+            assert_eq!(value["root"]["content"]["boundingVolume"], sphere);
+            assert_eq!(
+                tileset.root.bounding_volume.as_wkt(),
+                "POLYGON((-3 7, 23 7, 23 33, -3 33, -3 7))"
+            );
+        }
+
+        #[test]
+        fn test_add_content_adaptive() {
+            let leaf = |x: usize, y: usize, level: u16| Tile {
+                id: TileId::new(x, y, level),
+                content: Some(Content::default()),
+                ..Default::default()
+            };
+            // A deep subtree on the left and a shallow leaf on the right
+            let deep = Tile {
+                id: TileId::new(0, 0, 1),
+                children: Some(vec![Tile {
+                    id: TileId::new(0, 0, 2),
+                    children: Some(vec![leaf(0, 0, 3)]),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            };
+            let root = Tile {
+                id: TileId::new(0, 0, 0),
+                children: Some(vec![deep, leaf(1, 0, 1)]),
+                ..Default::default()
+            };
+            let ids = |tileset: &Tileset| -> Vec<String> {
+                tileset
+                    .collect_tiles_with_content()
+                    .iter()
+                    .map(|t| t.id.to_string())
+                    .collect()
+            };
+            let mut tileset = Tileset {
+                root: root.clone(),
+                ..Default::default()
+            };
+            tileset.add_content(Some(1), false);
+            assert_eq!(ids(&tileset), vec!["1/1/0", "2/0/0", "3/0/0"]);
+            // The root is one level above the shallow leaf
+            let mut tileset = Tileset {
+                root,
+                ..Default::default()
+            };
+            tileset.add_content(Some(1), true);
+            assert_eq!(ids(&tileset), vec!["0/0/0", "1/1/0", "2/0/0", "3/0/0"]);
+        }
+
+        #[test]
+        fn test_interior_lod() {
+            let tile = |level: u16, children: Option<Vec<Tile>>| Tile {
+                id: TileId::new(0, 0, level),
+                children,
+                ..Default::default()
+            };
+            // A leaf on level 1 and a subtree down to level 3
+            let tileset = Tileset {
+                root: tile(
+                    0,
+                    Some(vec![
+                        tile(1, None),
+                        Tile {
+                            id: TileId::new(1, 0, 1),
+                            children: Some(vec![tile(2, Some(vec![tile(3, None)]))]),
+                            ..Default::default()
+                        },
+                    ]),
+                ),
+                ..Default::default()
+            };
+            let levels = tileset.interior_levels(false);
+            assert_eq!(levels[&TileId::new(0, 0, 0)], 3);
+            assert_eq!(levels[&TileId::new(1, 0, 1)], 2);
+            assert_eq!(levels[&TileId::new(0, 0, 2)], 1);
+            assert!(!levels.contains_key(&TileId::new(0, 0, 1)));
+            assert_eq!(tileset.interior_levels(true)[&TileId::new(0, 0, 0)], 1);
+
+            let lod_interior = InteriorLod {
+                lod: "1.2".to_string(),
+                cascade: vec!["1.3".to_string(), "2.2".to_string()],
+                levels,
+            };
+            assert_eq!(lod_interior.lod_of(&TileId::new(0, 0, 2)), "2.2");
+            assert_eq!(lod_interior.lod_of(&TileId::new(1, 0, 1)), "1.3");
+            assert_eq!(lod_interior.lod_of(&TileId::new(0, 0, 0)), "1.2");
+        }
+
+        #[test]
+        fn test_add_lod_metadata() {
+            let leaf = |x: usize, y: usize| Tile {
+                id: TileId::new(x, y, 1),
+                content: Some(Content {
+                    bounding_volume: None,
+                    uri: format!("t/1/{x}/{y}.glb"),
+                    group: None,
+                }),
+                ..Default::default()
+            };
+            let mut tileset = Tileset {
+                root: Tile {
+                    id: TileId::new(0, 0, 0),
+                    content: Some(Content {
+                        bounding_volume: None,
+                        uri: "t/0/0/0.glb".to_string(),
+                        group: None,
+                    }),
+                    children: Some(vec![leaf(0, 0), leaf(1, 0)]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let lod_interior = InteriorLod {
+                lod: "1.2".to_string(),
+                ..Default::default()
+            };
+            tileset.add_lod_metadata("1.2,2.2", Some(&lod_interior), true);
+            let uris: Vec<&str> = tileset
+                .collect_tiles_with_content()
+                .iter()
+                .map(|t| t.content.as_ref().unwrap().uri.as_str())
+                .collect();
+            assert_eq!(
+                uris,
+                vec![
+                    "t/0/0/0-lod1.2.glb",
+                    "t/1/0/0-lod1.2_2.2.glb",
+                    "t/1/1/0-lod1.2_2.2.glb"
+                ]
+            );
+            let metadata = serde_json::to_value(&tileset.collect_leaves()[1].metadata).unwrap();
+            assert_eq!(
+                metadata,
+                serde_json::json!({"class": "tile", "properties": {"level": 1, "lod": "1.2,2.2"}})
+            );
+            assert!(serde_json::to_string(&tileset)
+                .unwrap()
+                .contains(r#""schema":{"id":"tyler","classes":{"tile""#));
+
+            assert_eq!(tileset.add_priority_group(|tile| tile.id.x == 1), 1);
+            assert_eq!(
+                tileset.collect_leaves()[1].content.as_ref().unwrap().group,
+                Some(0)
+            );
+            assert_eq!(
+                tileset.collect_leaves()[0].content.as_ref().unwrap().group,
+                None
+            );
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert_eq!(
+                value["groups"],
+                serde_json::json!([{"class": "group", "properties": {"priority": true}}])
+            );
+            // The priority class is added next to the tile class
+            assert_eq!(value["schema"]["classes"].as_object().unwrap().len(), 2);
+        }
+
+        #[test]
+        fn test_set_refine() {
+            let mut tileset = Tileset {
+                root: Tile {
+                    refine: Some(Refinement::Replace),
+                    children: Some(vec![Tile {
+                        id: TileId::new(0, 0, 1),
+                        refine: Some(Refinement::Replace),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset.set_refine(Refinement::Add);
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert_eq!(value["root"]["refine"], "ADD");
+            assert_eq!(value["root"]["children"][0]["refine"], "ADD");
+        }
+
+        #[test]
+        fn test_use_legacy_content() {
+            let mut tileset = Tileset {
+                root: Tile {
+                    children: Some(vec![Tile {
+                        id: TileId::new(0, 0, 1),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset.root.children.as_mut().unwrap()[0].add_content();
+            tileset.set_version(TilesetVersion::V1_0);
+            tileset.use_legacy_content("b3dm");
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert_eq!(
+                value["root"]["children"][0]["content"]["uri"],
+                "t/1/0/0.b3dm"
+            );
+            assert!(value.get("extensionsRequired").is_none());
+        }
+
+        #[test]
+        fn test_set_content_base_uri() {
+            let mut tileset = Tileset {
+                root: Tile {
+                    children: Some(vec![Tile {
+                        id: TileId::new(0, 0, 1),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset.root.children.as_mut().unwrap()[0].add_content();
+            tileset.set_content_base_uri("https://cdn.example.com/tiles/");
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert_eq!(
+                value["root"]["children"][0]["content"]["uri"],
+                "https://cdn.example.com/tiles/t/1/0/0.glb"
+            );
+            assert!(value["root"].get("content").is_none());
+        }
+
+        #[test]
+        fn test_add_promoted_content() {
+            let leaf = |x: usize, y: usize| Tile {
+                id: TileId::new(x, y, 1),
+                refine: Some(Refinement::Replace),
+                content: Some(Content {
+                    bounding_volume: None,
+                    uri: format!("t/1/{x}/{y}.glb"),
+                    group: None,
+                }),
+                ..Default::default()
+            };
+            let mut tileset = Tileset {
+                root: Tile {
+                    id: TileId::new(0, 0, 0),
+                    refine: Some(Refinement::Replace),
+                    children: Some(vec![leaf(0, 0), leaf(1, 0)]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let nr_tiles =
+                tileset.add_promoted_content(|tile| tile.id.level == 0, |tile| tile.id.x == 1);
+            assert_eq!(nr_tiles, 1);
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert_eq!(value["root"]["refine"], "ADD");
+            assert_eq!(value["root"]["content"]["uri"], "t/0/0/0.glb");
+            let children = value["root"]["children"].as_array().unwrap();
+            assert!(children.iter().all(|c| c["refine"] == "REPLACE"));
+            assert_eq!(children[0]["content"]["uri"], "t/1/0/0.glb");
+            assert!(children[1].get("content").is_none());
+        }
+
+        #[test]
+        fn test_add_attribute_groups() {
+            let leaf = |x: usize, y: usize| Tile {
+                id: TileId::new(x, y, 1),
+                content: Some(Content {
+                    bounding_volume: None,
+                    uri: format!("t/1/{x}/{y}.glb"),
+                    group: None,
+                }),
+                ..Default::default()
+            };
+            let mut tileset = Tileset {
+                root: Tile {
+                    id: TileId::new(0, 0, 0),
+                    children: Some(vec![leaf(0, 0), leaf(1, 0), leaf(0, 1)]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let nr_groups = tileset.add_attribute_groups("district", |tile| match tile.id.x {
+                0 if tile.id.y == 0 => Some("west".to_string()),
+                1 => Some("east".to_string()),
+                _ => None,
+            });
+            assert_eq!(nr_groups, 2);
+            let groups: Vec<Option<usize>> = tileset
+                .collect_leaves()
+                .iter()
+                .map(|t| t.content.as_ref().unwrap().group)
+                .collect();
+            assert_eq!(groups, vec![Some(1), Some(0), None]);
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert_eq!(
+                value["groups"],
+                serde_json::json!([
+                    {"class": "group", "properties": {"value": "east"}},
+                    {"class": "group", "properties": {"value": "west"}}
+                ])
+            );
+            assert_eq!(
+                value["schema"]["classes"]["group"]["properties"]["value"]["type"],
+                "STRING"
+            );
+        }
+
+        #[test]
+        fn test_tile_bbox() {
+            let world = crate::fixtures::TilingFixture::load("tiling_sample", 20.0).world;
+            let quadtree = QuadTree::from_world(
+                &world,
+                crate::spatial_structs::QuadTreeCapacity::Objects(2),
+                crate::spatial_structs::QuadTreeDepth::default(),
+            );
+            let grid_bbox = quadtree.bbox(&world.grid);
+            let content_bbox = quadtree.node_content_bbox(&world, None, None);
+            assert_eq!(
+                tile_bbox(&quadtree, &world, None, None, None, None),
+                grid_bbox
+            );
+            let bbox_z = tile_bbox(&quadtree, &world, None, None, Some(TightBounds::Z), None);
+            assert_eq!(bbox_z[0..2], grid_bbox[0..2]);
+            assert_eq!(bbox_z[3..5], grid_bbox[3..5]);
+            assert_eq!([bbox_z[2], bbox_z[5]], [content_bbox[2], content_bbox[5]]);
+            let bbox_xyz = tile_bbox(&quadtree, &world, None, None, Some(TightBounds::Xyz), None);
+            assert_eq!(bbox_xyz, content_bbox);
+
+            // A node without features is enclosed by its parent
+            let leaf = quadtree.collect_leaves()[0];
+            let mut empty = leaf.clone();
+            empty.nr_items = 0;
+            let bbox_empty = tile_bbox(
+                &empty,
+                &world,
+                None,
+                None,
+                Some(TightBounds::Xyz),
+                Some(&bbox_xyz),
+            );
+            assert_eq!(bbox_empty, bbox_xyz);
+        }
+
+        #[test]
+        fn test_add_tile_stats() {
+            let leaf = |x: usize| Tile {
+                id: TileId::new(x, 0, 1),
+                content: Some(Content::default()),
+                ..Default::default()
+            };
+            let mut tileset = Tileset {
+                root: Tile {
+                    id: TileId::new(0, 0, 0),
+                    children: Some(vec![leaf(0), leaf(1)]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset.add_lod_metadata("2.2", None, false);
+            let nr_tiles = tileset.add_tile_stats(|tile| {
+                (tile.id.x == 0).then(|| TileContentStats {
+                    nr_features: 2,
+                    cityobject_types: BTreeMap::from([
+                        ("Building".to_string(), 2),
+                        ("BuildingPart".to_string(), 3),
+                    ]),
+                    height: Some([-1.5, 30.0]),
+                })
+            });
+            assert_eq!(nr_tiles, 1);
+            let value = serde_json::to_value(&tileset).unwrap();
+            let children = value["root"]["children"].as_array().unwrap();
+            assert_eq!(
+                children[0]["metadata"]["properties"],
+                serde_json::json!({
+                    "level": 1,
+                    "lod": "2.2",
+                    "featureCount": 2,
+                    "cityObjectTypes": ["Building", "BuildingPart"],
+                    "cityObjectCounts": [2, 3],
+                    "minimumHeight": -1.5,
+                    "maximumHeight": 30.0
+                })
+            );
+            assert_eq!(children[1]["metadata"]["properties"]["level"], 1);
+            assert!(children[1]["metadata"]["properties"]["featureCount"].is_null());
+            let properties = &value["schema"]["classes"]["tile"]["properties"];
+            assert_eq!(properties["cityObjectCounts"]["array"], true);
+            assert!(properties["lod"].is_object());
+        }
+
+        #[test]
+        fn test_add_feature_class() {
+            let mut tileset = Tileset::default();
+            let attributes = vec!["height:float".to_string(), "name:string".to_string()];
+            tileset.add_feature_class("building", &attributes).unwrap();
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert_eq!(
+                value["schema"]["classes"]["building"]["properties"]["height"],
+                serde_json::json!({
+                    "type": "SCALAR",
+                    "componentType": "FLOAT32",
+                    "description": "CityObject attribute 'height'"
+                })
+            );
+            assert_eq!(
+                value["schema"]["classes"]["building"]["properties"]["name"]["type"],
+                "STRING"
+            );
+            // The classes of tyler are not replaced
+            tileset.add_lod_metadata("2.2", None, false);
+            assert!(tileset.add_feature_class("tile", &attributes).is_err());
+            assert!(tileset
+                .add_feature_class("other", &["height:double".to_string()])
+                .is_err());
+            assert!(tileset
+                .add_feature_class("other", &["height".to_string()])
+                .is_err());
+        }
+
+        #[test]
+        fn test_set_version() {
+            let leaf = |x: usize, y: usize| Tile {
+                id: TileId::new(x, y, 1),
+                content: Some(Content {
+                    bounding_volume: None,
+                    uri: format!("t/1/{x}/{y}.glb"),
+                    group: None,
+                }),
+                ..Default::default()
+            };
+            let mut tileset = Tileset {
+                root: Tile {
+                    id: TileId::new(0, 0, 0),
+                    children: Some(vec![leaf(0, 0), leaf(1, 0)]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset.add_lod_metadata("2.2", None, false);
+            tileset.add_priority_group(|tile| tile.id.x == 1);
+            tileset.set_version(TilesetVersion::V1_0);
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert_eq!(value["asset"]["version"], "1.0");
+            assert_eq!(
+                value["extensionsUsed"],
+                serde_json::json!(["3DTILES_content_gltf"])
+            );
+            assert_eq!(
+                value["extensionsRequired"],
+                serde_json::json!(["3DTILES_content_gltf"])
+            );
+            assert_eq!(
+                value["extensions"],
+                serde_json::json!({"3DTILES_content_gltf": {"extensionsUsed": ["EXT_mesh_features", "EXT_structural_metadata"]}})
+            );
+            // The metadata and the content groups are 1.1 only
+            assert!(value.get("schema").is_none());
+            assert!(value.get("groups").is_none());
+            let children = value["root"]["children"].as_array().unwrap();
+            assert!(children.iter().all(|c| c.get("metadata").is_none()));
+            assert!(children[1]["content"].get("group").is_none());
+            // The external tilesets inherit the version
+            let (_, child) = &tileset.split(1)[0];
+            assert_eq!(child.asset.version, "1.0");
+            assert!(child.extensions.is_some());
+
+            tileset.set_version(TilesetVersion::V1_1);
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert_eq!(value["asset"]["version"], "1.1");
+            assert!(value.get("extensionsUsed").is_none());
+            assert!(value.get("extensions").is_none());
+        }
+
+        #[test]
+        fn test_remove_content() {
+            let leaf = |x: usize, y: usize| Tile {
+                id: TileId::new(x, y, 1),
+                content: Some(Content {
+                    bounding_volume: None,
+                    uri: format!("t/1/{x}/{y}.glb"),
+                    group: None,
+                }),
+                ..Default::default()
+            };
+            let mut tileset = Tileset {
+                root: Tile {
+                    id: TileId::new(0, 0, 0),
+                    content: Some(Content {
+                        bounding_volume: None,
+                        uri: "t/0/0/0.glb".to_string(),
+                        group: None,
+                    }),
+                    children: Some(vec![leaf(0, 0), leaf(1, 0)]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            tileset.add_lod_metadata("2.2", None, false);
+            let converted = [TileId::new(1, 0, 1)];
+            let nr_tiles = tileset.remove_content(|tile| !converted.contains(&tile.id));
+            assert_eq!(nr_tiles, 2);
+            let value = serde_json::to_value(&tileset).unwrap();
+            assert!(value["root"].get("content").is_none());
+            assert!(value["root"].get("metadata").is_none());
+            let children = value["root"]["children"].as_array().unwrap();
+            assert_eq!(children.len(), 2);
+            assert!(children[0].get("content").is_none());
+            assert_eq!(children[1]["content"]["uri"], "t/1/1/0.glb");
+        }
+
         #[test]
         fn test_availability() {
             let a = AvailabilityConstant::Available;
             assert_eq!("1", serde_json::to_string(&a).unwrap());
         }
 
+        #[test]
+        fn test_bounding_volume_rounding() {
+            let region = BoundingVolume::Region([0.0857, 0.9078470812, 0.0862, 0.91, -5.0, 30.126]);
+            assert_eq!(
+                serde_json::to_string(&region).unwrap(),
+                r#"{"region":[0.0857,0.9078470812,0.0862,0.91,-5.0,30.126]}"#
+            );
+            let sphere = BoundingVolume::Sphere([3923223.4964, 299941.408, 5003058.5057, 0.004]);
+            assert_eq!(
+                serde_json::to_string(&sphere).unwrap(),
+                r#"{"sphere":[3923223.5,299941.41,5003058.51,0.0]}"#
+            );
+        }
+
         #[test]
         fn test_refinement() {
             let r = Refinement::Replace;
@@ -1811,6 +3655,8 @@ pub mod cesium3dtiles {
                 asset: Default::default(),
                 geometric_error: 0.0,
                 properties: None,
+                schema: None,
+                groups: None,
                 extensions_used: Some(vec![ExtensionName::ContentGltf]),
                 extensions_required: Some(vec![ExtensionName::ContentGltf]),
                 extensions: Some(extensions),
@@ -1836,6 +3682,8 @@ pub mod cesium3dtiles {
                 asset: Default::default(),
                 geometric_error: 0.0,
                 properties: None,
+                schema: None,
+                groups: None,
                 extensions_used: Some(vec![ExtensionName::ContentGltf]),
                 extensions_required: Some(vec![ExtensionName::ContentGltf]),
                 extensions: Some(extensions),
@@ -1845,3 +3693,3252 @@ pub mod cesium3dtiles {
         }
     }
 }
+
+pub mod geovolumes {
+    //! [OGC API - 3D GeoVolumes](https://docs.ogc.org/per/20-029.html) collection description.
+    //! It describes the extent of the tileset and links to the 3D Tiles content, so that
+    //! the output can be registered in an OGC API catalogue.
+    use std::fs::File;
+    use std::io::BufWriter;
+    use std::path::Path;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::spatial_structs::InputBbox;
+
+    /// The CRS of the collection extent, longitude, latitude and ellipsoidal height.
+    const CRS84H: &str = "http://www.opengis.net/def/crs/OGC/0/CRS84h";
+
+    /// Convert the `extent` from the CRS of the input data to CRS84h, that is
+    /// `[min lon, min lat, min h, max lon, max lat, max h]`.
+    pub fn extent_crs84h(extent: &InputBbox) -> Result<[f64; 6], Box<dyn std::error::Error>> {
+        Ok(extent.to_epsg4979()?.to_array())
+    }
+
+    /// The `collections.json` document.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Collections {
+        pub collections: Vec<Collection>,
+        pub links: Vec<Link>,
+    }
+
+    impl Collections {
+        /// Create a collections document with a single collection that links to the
+        /// 3D Tiles tileset at `tileset_href`.
+        /// The `extent` is in the CRS of the input data, and it is converted to CRS84h.
+        pub fn new(
+            id: &str,
+            extent: &InputBbox,
+            tileset_href: &str,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let tileset_link = Link {
+                href: tileset_href.to_string(),
+                rel: "original".to_string(),
+                media_type: "application/json+3dtiles".to_string(),
+                title: Some(format!("{id} 3D Tiles")),
+            };
+            let collection = Collection {
+                id: id.to_string(),
+                title: id.to_string(),
+                collection_type: "3d-container".to_string(),
+                extent: Extent {
+                    spatial: SpatialExtent {
+                        bbox: vec![extent_crs84h(extent)?],
+                        crs: CRS84H.to_string(),
+                    },
+                },
+                links: vec![tileset_link.clone()],
+                content: vec![tileset_link],
+            };
+            Ok(Self {
+                collections: vec![collection],
+                links: vec![Link {
+                    href: "collections.json".to_string(),
+                    rel: "self".to_string(),
+                    media_type: "application/json".to_string(),
+                    title: None,
+                }],
+            })
+        }
+
+        pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+            let file_out = BufWriter::new(File::create(path.as_ref())?);
+            serde_json::to_writer_pretty(file_out, self)?;
+            Ok(())
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Collection {
+        pub id: String,
+        pub title: String,
+        pub collection_type: String,
+        pub extent: Extent,
+        pub links: Vec<Link>,
+        pub content: Vec<Link>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Extent {
+        pub spatial: SpatialExtent,
+    }
+
+    /// The `bbox` is `[min lon, min lat, min h, max lon, max lat, max h]`.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SpatialExtent {
+        pub bbox: Vec<[f64; 6]>,
+        pub crs: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Link {
+        pub href: String,
+        pub rel: String,
+        #[serde(rename = "type")]
+        pub media_type: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub title: Option<String>,
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_link_serialize() {
+            let link = Link {
+                href: "tileset.json".to_string(),
+                rel: "original".to_string(),
+                media_type: "application/json+3dtiles".to_string(),
+                title: None,
+            };
+            assert_eq!(
+                serde_json::to_string(&link).unwrap(),
+                r#"{"href":"tileset.json","rel":"original","type":"application/json+3dtiles"}"#
+            );
+        }
+    }
+}
+
+pub mod stac {
+    //! [STAC](https://stacspec.org) Collection and Item for the produced tileset, so that the
+    //! output can be indexed into a STAC catalogue.
+    //! The Item links to the tileset and the run report as its assets, and it records the
+    //! provenance with the [processing extension](https://github.com/stac-extensions/processing).
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::io::BufWriter;
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::spatial_structs::InputBbox;
+
+    const STAC_VERSION: &str = "1.0.0";
+    const PROCESSING_EXTENSION: &str =
+        "https://stac-extensions.github.io/processing/v1.1.0/schema.json";
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Collection {
+        #[serde(rename = "type")]
+        pub stac_type: String,
+        pub stac_version: String,
+        pub id: String,
+        pub description: String,
+        pub license: String,
+        pub extent: Value,
+        pub links: Vec<Link>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Item {
+        #[serde(rename = "type")]
+        pub stac_type: String,
+        pub stac_version: String,
+        pub stac_extensions: Vec<String>,
+        pub id: String,
+        pub collection: String,
+        pub bbox: [f64; 6],
+        pub geometry: Value,
+        pub properties: Value,
+        pub links: Vec<Link>,
+        pub assets: BTreeMap<String, Asset>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Link {
+        pub rel: String,
+        pub href: String,
+        #[serde(rename = "type")]
+        pub media_type: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Asset {
+        pub href: String,
+        #[serde(rename = "type")]
+        pub media_type: String,
+        pub title: String,
+        pub roles: Vec<String>,
+    }
+
+    impl Asset {
+        fn new(href: &str, media_type: &str, title: &str, role: &str) -> Self {
+            Self {
+                href: href.to_string(),
+                media_type: media_type.to_string(),
+                title: title.to_string(),
+                roles: vec![role.to_string()],
+            }
+        }
+    }
+
+    /// Create the STAC Collection and Item for the tileset with the `id`.
+    /// The `extent` is in the CRS of the input data (`crs_from`). The `lineage` is a
+    /// description of the input data.
+    /// The `assets` are the `(key, href)` of the additional files that were written, besides the
+    /// `tileset.json`. The `seed` of the run is recorded with the provenance.
+    pub fn collection_and_item(
+        id: &str,
+        extent: &InputBbox,
+        lineage: &str,
+        assets: &[(&str, &str)],
+        seed: u64,
+    ) -> Result<(Collection, Item), Box<dyn std::error::Error>> {
+        let extent_4979 = extent.to_epsg4979()?;
+        let bbox = extent_4979.to_array();
+        let datetime = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+        let json_type = "application/json".to_string();
+        let collection = Collection {
+            stac_type: "Collection".to_string(),
+            stac_version: STAC_VERSION.to_string(),
+            id: id.to_string(),
+            description: format!("3D Tiles of {id}"),
+            license: "other".to_string(),
+            extent: json!({
+                "spatial": { "bbox": [bbox] },
+                "temporal": { "interval": [[datetime, null]] },
+            }),
+            links: vec![
+                Link {
+                    rel: "self".to_string(),
+                    href: "./collection.json".to_string(),
+                    media_type: json_type.clone(),
+                },
+                Link {
+                    rel: "item".to_string(),
+                    href: "./item.json".to_string(),
+                    media_type: "application/geo+json".to_string(),
+                },
+            ],
+        };
+        let [west, south, east, north] = extent_4979.to_lonlat();
+        let mut item_assets: BTreeMap<String, Asset> = BTreeMap::new();
+        item_assets.insert(
+            "tileset".to_string(),
+            Asset::new("./tileset.json", &json_type, "3D Tiles tileset", "data"),
+        );
+        for (key, href) in assets {
+            item_assets.insert(
+                key.to_string(),
+                Asset::new(&format!("./{href}"), &json_type, href, "metadata"),
+            );
+        }
+        let item = Item {
+            stac_type: "Feature".to_string(),
+            stac_version: STAC_VERSION.to_string(),
+            stac_extensions: vec![PROCESSING_EXTENSION.to_string()],
+            id: id.to_string(),
+            collection: id.to_string(),
+            bbox,
+            geometry: json!({
+                "type": "Polygon",
+                "coordinates": [[
+                    [west, south],
+                    [east, south],
+                    [east, north],
+                    [west, north],
+                    [west, south],
+                ]],
+            }),
+            properties: json!({
+                "datetime": datetime,
+                "created": datetime,
+                "processing:lineage": lineage,
+                "processing:software": { "tyler": clap::crate_version!() },
+                "tyler:seed": seed,
+            }),
+            links: vec![
+                Link {
+                    rel: "collection".to_string(),
+                    href: "./collection.json".to_string(),
+                    media_type: json_type.clone(),
+                },
+                Link {
+                    rel: "parent".to_string(),
+                    href: "./collection.json".to_string(),
+                    media_type: json_type,
+                },
+            ],
+            assets: item_assets,
+        };
+        Ok((collection, item))
+    }
+
+    /// Write a STAC object to a JSON file.
+    pub fn to_file<T: Serialize, P: AsRef<Path>>(
+        stac_object: &T,
+        path: P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let file_out = BufWriter::new(File::create(path.as_ref())?);
+        serde_json::to_writer_pretty(file_out, stac_object)?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_asset_serialize() {
+            let asset = Asset::new("./report.json", "application/json", "report", "metadata");
+            let j = serde_json::to_value(&asset).unwrap();
+            assert_eq!(j["type"], "application/json");
+            assert_eq!(j["roles"], json!(["metadata"]));
+        }
+    }
+}
+
+pub mod viewer {
+    //! A minimal [CesiumJS](https://cesium.com/platform/cesiumjs/) page that loads the tileset
+    //! and zooms to it, for checking the output locally.
+    use std::path::Path;
+
+    /// The CesiumJS release that is loaded from the CDN.
+    const CESIUMJS_VERSION: &str = "1.121";
+
+    /// The HTML page of a CesiumJS viewer that loads the tileset at `tileset_uri`, relative to
+    /// the page. The base layer is OpenStreetMap, so that the page does not need a Cesium ion
+    /// access token.
+    pub fn html(tileset_uri: &str) -> String {
+        let cesium = format!(
+            "https://cesium.com/downloads/cesiumjs/releases/{CESIUMJS_VERSION}/Build/Cesium"
+        );
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>tyler {tyler_version} - {tileset_uri}</title>
+  <script src="{cesium}/Cesium.js"></script>
+  <link href="{cesium}/Widgets/widgets.css" rel="stylesheet">
+  <style>html, body, #cesiumContainer {{ width: 100%; height: 100%; margin: 0; padding: 0; overflow: hidden; }}</style>
+</head>
+<body>
+<div id="cesiumContainer"></div>
+<script>
+  const viewer = new Cesium.Viewer("cesiumContainer", {{
+    baseLayer: new Cesium.ImageryLayer(new Cesium.OpenStreetMapImageryProvider({{
+      url: "https://tile.openstreetmap.org/"
+    }})),
+    baseLayerPicker: false,
+    geocoder: false,
+  }});
+  viewer.extend(Cesium.viewerCesium3DTilesInspectorMixin);
+  Cesium.Cesium3DTileset.fromUrl("{tileset_uri}").then((tileset) => {{
+    viewer.scene.primitives.add(tileset);
+    viewer.zoomTo(tileset);
+  }}).catch((error) => {{
+    console.error(error);
+    window.alert(`Failed to load {tileset_uri}: ${{error}}`);
+  }});
+</script>
+</body>
+</html>
+"#,
+            tyler_version = clap::crate_version!(),
+        )
+    }
+
+    /// Write the viewer page for the tileset at `tileset_uri` to `path`.
+    pub fn to_file<P: AsRef<Path>>(
+        tileset_uri: &str,
+        path: P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, html(tileset_uri))?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_html() {
+            let page = html("tileset.json");
+            assert!(page.contains(r#"Cesium.Cesium3DTileset.fromUrl("tileset.json")"#));
+            assert!(page.contains("viewer.zoomTo(tileset)"));
+            assert!(page.contains(&format!(
+                "releases/{CESIUMJS_VERSION}/Build/Cesium/Cesium.js"
+            )));
+        }
+    }
+}
+
+pub mod preview {
+    //! Preview content for quickly checking the spatial coverage of a tileset.
+    //! The content of a tile is the wireframe of the bounding boxes of its features, written
+    //! directly as a binary glTF (GLB), thus it does not need the conversion subprocess.
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+
+    use serde_json::{json, Value};
+
+    use crate::proj::Proj;
+    use crate::spatial_structs::Bbox;
+
+    const GLB_MAGIC: u32 = 0x4654_6C67; // "glTF"
+    const CHUNK_JSON: u32 = 0x4E4F_534A; // "JSON"
+    const CHUNK_BIN: u32 = 0x004E_4942; // "BIN\0"
+
+    /// The edges of a box as pairs of corner indices. The corner `i` is at the maximum x, y, z
+    /// when the bit 0, 1, 2 of `i` is set.
+    const BOX_EDGES: [u32; 24] = [
+        0, 1, 2, 3, 4, 5, 6, 7, // along x
+        0, 2, 1, 3, 4, 6, 5, 7, // along y
+        0, 4, 1, 5, 2, 6, 3, 7, // along z
+    ];
+
+    /// Write the wireframe of the `bboxes` into a GLB file.
+    /// The `transformer` must have `EPSG:4978` as target CRS. The vertices are stored relative
+    /// to the center of the content and they are rotated from z-up to the y-up of glTF.
+    /// With `ecef`, the vertices keep the axes of ECEF and the rotation to y-up is in the
+    /// matrix of the node instead, together with the translation to the center.
+    /// Returns the size of the written file in bytes.
+    pub fn write_bbox_glb<P: AsRef<Path>>(
+        path: P,
+        bboxes: &[Bbox],
+        transformer: &Proj,
+        ecef: bool,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut corners_ecef: Vec<[f64; 3]> = Vec::with_capacity(bboxes.len() * 8);
+        for bbox in bboxes {
+            for i in 0..8 {
+                let x = if i & 1 == 0 { bbox[0] } else { bbox[3] };
+                let y = if i & 2 == 0 { bbox[1] } else { bbox[4] };
+                let z = if i & 4 == 0 { bbox[2] } else { bbox[5] };
+                let (xe, ye, ze) = transformer.convert((x, y, z))?;
+                corners_ecef.push([xe, ye, ze]);
+            }
+        }
+        let glb = glb_bytes(&corners_ecef, ecef);
+        let mut file = File::create(path.as_ref())?;
+        file.write_all(&glb)?;
+        Ok(glb.len() as u64)
+    }
+
+    /// Build the GLB from the box corners, 8 consecutive corners per box.
+    fn glb_bytes(corners: &[[f64; 3]], ecef: bool) -> Vec<u8> {
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        for c in corners {
+            for d in 0..3 {
+                min[d] = min[d].min(c[d]);
+                max[d] = max[d].max(c[d]);
+            }
+        }
+        let center: [f64; 3] = if corners.is_empty() {
+            [0.0; 3]
+        } else {
+            [
+                (min[0] + max[0]) / 2.0,
+                (min[1] + max[1]) / 2.0,
+                (min[2] + max[2]) / 2.0,
+            ]
+        };
+        // z-up to y-up
+        let to_gltf = |c: &[f64; 3]| [c[0], c[2], -c[1]];
+
+        // 12 bytes of position and 12 bytes of indices per corner
+        let mut bin: Vec<u8> = Vec::with_capacity(corners.len() * 24);
+        let mut pos_min = [f32::MAX; 3];
+        let mut pos_max = [f32::MIN; 3];
+        for c in corners {
+            let offset = [c[0] - center[0], c[1] - center[1], c[2] - center[2]];
+            let rel = if ecef { offset } else { to_gltf(&offset) };
+            for d in 0..3 {
+                let v = rel[d] as f32;
+                pos_min[d] = pos_min[d].min(v);
+                pos_max[d] = pos_max[d].max(v);
+                bin.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        let positions_length = bin.len();
+        let nr_boxes = corners.len() / 8;
+        for b in 0..nr_boxes as u32 {
+            for e in BOX_EDGES {
+                bin.extend_from_slice(&(b * 8 + e).to_le_bytes());
+            }
+        }
+        let indices_length = bin.len() - positions_length;
+
+        let translation = to_gltf(&center);
+        let node = if ecef {
+            // The column-major matrix of the z-up to y-up rotation and the translation
+            #[rustfmt::skip]
+            let matrix = [
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, -1.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                translation[0], translation[1], translation[2], 1.0,
+            ];
+            json!({"mesh": 0, "matrix": matrix})
+        } else {
+            json!({"mesh": 0, "translation": translation})
+        };
+        let gltf: Value = json!({
+            "asset": {"version": "2.0", "generator": "tyler"},
+            "scene": 0,
+            "scenes": [{"nodes": [0]}],
+            "nodes": [node],
+            "meshes": [{"primitives": [{
+                "attributes": {"POSITION": 0},
+                "indices": 1,
+                "mode": 1
+            }]}],
+            "accessors": [
+                {
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": corners.len(),
+                    "type": "VEC3",
+                    "min": pos_min,
+                    "max": pos_max
+                },
+                {
+                    "bufferView": 1,
+                    "componentType": 5125,
+                    "count": nr_boxes * BOX_EDGES.len(),
+                    "type": "SCALAR"
+                }
+            ],
+            "bufferViews": [
+                {"buffer": 0, "byteOffset": 0, "byteLength": positions_length, "target": 34962},
+                {"buffer": 0, "byteOffset": positions_length, "byteLength": indices_length, "target": 34963}
+            ],
+            "buffers": [{"byteLength": bin.len()}]
+        });
+        glb_container(&gltf, bin)
+    }
+
+    /// Pack the glTF JSON and the binary buffer into a GLB.
+    pub(crate) fn glb_container(gltf: &Value, mut bin: Vec<u8>) -> Vec<u8> {
+        let mut json_chunk = gltf.to_string().into_bytes();
+        // The chunks must be aligned to 4 bytes, the JSON chunk is padded with spaces
+        json_chunk.resize(json_chunk.len().next_multiple_of(4), b' ');
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        let length = 12 + 8 + json_chunk.len() + 8 + bin.len();
+        let mut glb: Vec<u8> = Vec::with_capacity(length);
+        glb.extend_from_slice(&GLB_MAGIC.to_le_bytes());
+        glb.extend_from_slice(&2_u32.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+        glb.extend_from_slice(&(json_chunk.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+        glb.extend_from_slice(&json_chunk);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+        glb.extend_from_slice(&bin);
+        glb
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_glb_bytes() {
+            let mut corners: Vec<[f64; 3]> = Vec::new();
+            for i in 0..8 {
+                corners.push([
+                    (i & 1) as f64 + 1000.0,
+                    ((i & 2) >> 1) as f64,
+                    ((i & 4) >> 2) as f64,
+                ]);
+            }
+            let glb = glb_bytes(&corners, false);
+            assert_eq!(&glb[0..4], b"glTF");
+            assert_eq!(glb.len() % 4, 0);
+            let length = u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize;
+            assert_eq!(length, glb.len());
+            let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+            let gltf: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+            assert_eq!(gltf["accessors"][0]["count"], 8);
+            assert_eq!(gltf["accessors"][1]["count"], 24);
+            assert_eq!(gltf["nodes"][0]["translation"], json!([1000.5, 0.5, -0.5]));
+            let bin_length =
+                u32::from_le_bytes(glb[20 + json_length..24 + json_length].try_into().unwrap());
+            assert_eq!(bin_length, 8 * 12 + 24 * 4);
+        }
+
+        #[test]
+        fn test_glb_bytes_ecef() {
+            let corners: Vec<[f64; 3]> = (0..8)
+                .map(|i| {
+                    [
+                        (i & 1) as f64 + 1000.0,
+                        ((i & 2) >> 1) as f64 * 2.0,
+                        ((i & 4) >> 2) as f64 * 4.0,
+                    ]
+                })
+                .collect();
+            let glb = glb_bytes(&corners, true);
+            let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+            let gltf: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+            // The positions keep the ECEF axes
+            assert_eq!(gltf["accessors"][0]["max"], json!([0.5, 1.0, 2.0]));
+            assert!(gltf["nodes"][0].get("translation").is_none());
+            let matrix: Vec<f64> =
+                serde_json::from_value(gltf["nodes"][0]["matrix"].clone()).unwrap();
+            assert_eq!(&matrix[12..], &[1000.5, 2.0, -1.0, 1.0]);
+            // The matrix maps the ECEF offset of the last corner to y-up
+            let p = [0.5, 1.0, 2.0];
+            let rotated: Vec<f64> = (0..3)
+                .map(|r| (0..3).map(|c| matrix[c * 4 + r] * p[c]).sum())
+                .collect();
+            assert_eq!(rotated, vec![0.5, 2.0, -1.0]);
+        }
+    }
+}
+
+pub mod meshopt {
+    //! The vertex codec of the
+    //! [EXT_meshopt_compression](https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Vendor/EXT_meshopt_compression)
+    //! extension (the `ATTRIBUTES` mode, version 0), so that the glTF content can be compressed
+    //! in-process, without gltfpack.
+    //! The bytes of each element are delta coded against the previous element, and the
+    //! deltas are packed in groups of 16 with 0, 2, 4 or 8 bits per delta.
+
+    const HEADER: u8 = 0xa0;
+    const GROUP_SIZE: usize = 16;
+    const BLOCK_MAX_SIZE: usize = 256;
+    const TAIL_MIN_SIZE: usize = 32;
+
+    /// Encode the `data` of elements of `stride` bytes with the `ATTRIBUTES` mode. The
+    /// `stride` must be a multiple of 4 and at most 256.
+    pub fn encode_attributes(data: &[u8], stride: usize) -> Vec<u8> {
+        assert!(stride.is_multiple_of(4) && stride <= 256 && data.len().is_multiple_of(stride));
+        let block_size = ((8192 / stride) & !(GROUP_SIZE - 1)).min(BLOCK_MAX_SIZE);
+        let mut encoded: Vec<u8> = vec![HEADER];
+        let first = data.get(..stride).unwrap_or_default().to_vec();
+        let mut last = first.clone();
+        last.resize(stride, 0);
+        for block in data.chunks(block_size * stride) {
+            let block_count = block.len() / stride;
+            let mut deltas = vec![0_u8; block_count.next_multiple_of(GROUP_SIZE)];
+            for (k, last_byte) in last.iter_mut().enumerate() {
+                let mut previous = *last_byte;
+                for i in 0..block_count {
+                    let byte = block[i * stride + k];
+                    deltas[i] = zigzag(byte.wrapping_sub(previous));
+                    previous = byte;
+                }
+                *last_byte = previous;
+                encode_bytes(&deltas, &mut encoded);
+            }
+        }
+        // The tail holds the first element, which is the base of the first deltas
+        let tail_size = stride.max(TAIL_MIN_SIZE);
+        encoded.resize(encoded.len() + tail_size - stride, 0);
+        encoded.extend_from_slice(&first);
+        encoded.resize(encoded.len() + stride - first.len(), 0);
+        encoded
+    }
+
+    fn zigzag(v: u8) -> u8 {
+        (v << 1) ^ ((v as i8 >> 7) as u8)
+    }
+
+    /// Pack the `deltas` in groups of 16 with the smallest bit width. The 2 bit header of
+    /// the groups comes first, and the values that do not fit the bit width are escaped.
+    fn encode_bytes(deltas: &[u8], encoded: &mut Vec<u8>) {
+        let header_start = encoded.len();
+        let nr_groups = deltas.len() / GROUP_SIZE;
+        encoded.resize(header_start + nr_groups.div_ceil(4), 0);
+        for (g, group) in deltas.chunks(GROUP_SIZE).enumerate() {
+            // In the order of the reference encoder, which keeps the first of the equal sizes
+            let (bits_log2, bits): (u8, u32) = [(3, 8), (0, 0), (1, 2), (2, 4)]
+                .into_iter()
+                .min_by_key(|(_, bits)| group_size(group, *bits))
+                .unwrap();
+            encoded[header_start + g / 4] |= bits_log2 << ((g % 4) * 2);
+            match bits {
+                0 => {}
+                8 => encoded.extend_from_slice(group),
+                _ => {
+                    let sentinel = (1_u8 << bits) - 1;
+                    for values in group.chunks(8 / bits as usize) {
+                        let byte = values
+                            .iter()
+                            .fold(0_u8, |byte, v| (byte << bits) | (*v).min(sentinel));
+                        encoded.push(byte);
+                    }
+                    encoded.extend(group.iter().filter(|v| **v >= sentinel));
+                }
+            }
+        }
+    }
+
+    /// The encoded size of the `group` with `bits` per value.
+    fn group_size(group: &[u8], bits: u32) -> usize {
+        match bits {
+            0 if group.iter().all(|v| *v == 0) => 0,
+            0 => usize::MAX,
+            8 => GROUP_SIZE,
+            _ => {
+                let sentinel = (1_u8 << bits) - 1;
+                GROUP_SIZE * bits as usize / 8 + group.iter().filter(|v| **v >= sentinel).count()
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Decode the `ATTRIBUTES` mode, following the reference decoder.
+        fn decode_attributes(encoded: &[u8], count: usize, stride: usize) -> Vec<u8> {
+            assert_eq!(encoded[0], HEADER);
+            let block_size = ((8192 / stride) & !(GROUP_SIZE - 1)).min(BLOCK_MAX_SIZE);
+            let mut last = encoded[encoded.len() - stride..].to_vec();
+            let mut pos = 1;
+            let mut data = vec![0_u8; count * stride];
+            let mut start = 0;
+            while start < count {
+                let block_count = block_size.min(count - start);
+                let aligned = block_count.next_multiple_of(GROUP_SIZE);
+                for (k, last_byte) in last.iter_mut().enumerate() {
+                    let header = &encoded[pos..pos + (aligned / GROUP_SIZE).div_ceil(4)];
+                    pos += header.len();
+                    let mut deltas: Vec<u8> = Vec::with_capacity(aligned);
+                    for g in 0..aligned / GROUP_SIZE {
+                        let bits = [0, 2, 4, 8][((header[g / 4] >> ((g % 4) * 2)) & 3) as usize];
+                        match bits {
+                            0 => deltas.extend([0; GROUP_SIZE]),
+                            8 => {
+                                deltas.extend_from_slice(&encoded[pos..pos + GROUP_SIZE]);
+                                pos += GROUP_SIZE;
+                            }
+                            _ => {
+                                let sentinel = (1_u8 << bits) - 1;
+                                let packed = &encoded[pos..pos + GROUP_SIZE * bits / 8];
+                                pos += packed.len();
+                                for byte in packed {
+                                    for j in (0..8 / bits).rev() {
+                                        let v = (byte >> (j * bits)) & sentinel;
+                                        if v == sentinel {
+                                            deltas.push(encoded[pos]);
+                                            pos += 1;
+                                        } else {
+                                            deltas.push(v);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    for (i, delta) in deltas[..block_count].iter().enumerate() {
+                        let unzigzag = (delta >> 1) ^ (0_u8.wrapping_sub(delta & 1));
+                        *last_byte = last_byte.wrapping_add(unzigzag);
+                        data[(start + i) * stride + k] = *last_byte;
+                    }
+                }
+                start += block_count;
+            }
+            assert_eq!(encoded.len() - pos, stride.max(TAIL_MIN_SIZE));
+            data
+        }
+
+        #[test]
+        fn test_encode_attributes() {
+            assert_eq!(zigzag(1), 2);
+            assert_eq!(zigzag(255), 1);
+            // Slowly changing floats, over more than one block
+            let values: Vec<u8> = (0..1000)
+                .flat_map(|i| [i as f32 * 0.5, 10.0, -(i as f32)])
+                .flat_map(|v| v.to_le_bytes())
+                .collect();
+            let encoded = encode_attributes(&values, 12);
+            assert!(encoded.len() < values.len() / 2);
+            assert_eq!(decode_attributes(&encoded, 1000, 12), values);
+            // Random bytes need the escapes and the raw groups
+            let mut x: u32 = 1;
+            let random: Vec<u8> = (0..4 * 37)
+                .map(|_| {
+                    x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                    (x >> 16) as u8
+                })
+                .collect();
+            let encoded = encode_attributes(&random, 4);
+            assert_eq!(decode_attributes(&encoded, 37, 4), random);
+            assert_eq!(encode_attributes(&[], 4).len(), 1 + TAIL_MIN_SIZE);
+        }
+
+        #[test]
+        fn test_encode_attributes_reference() {
+            // Encoded with meshopt_encodeVertexBuffer of meshoptimizer 0.12
+            let data: Vec<u8> = (0..16_u32)
+                .flat_map(|i| [i, i * 37 % 256, 7, i * i * 13 % 256].map(|b| b as u8))
+                .collect();
+            let mut expected: Vec<u8> = vec![0xa0];
+            expected.extend([0x01, 0x2a, 0xaa, 0xaa, 0xaa]);
+            expected.extend([0x03, 0x00]);
+            expected.extend([0x4a; 15]);
+            expected.extend([0x00]);
+            expected.extend([
+                0x03, 0x00, 0x1a, 0x4e, 0x82, 0xb6, 0xea, 0xe1, 0xad, 0x79, 0x45, 0x11, 0x22, 0x56,
+                0x8a, 0xbe, 0xf2,
+            ]);
+            expected.extend([0x00; 28]);
+            expected.extend([0x00, 0x00, 0x07, 0x00]);
+            assert_eq!(encode_attributes(&data, 4), expected);
+        }
+    }
+}
+
+pub mod gltf {
+    //! Triangulated glTF content, that is written directly from the CityJSONFeatures of the
+    //! tile, thus it does not need geof.
+    //! The surfaces of the CityObjects are triangulated by ear clipping, reprojected to ECEF
+    //! and written as a single mesh with flat normals. The textures and the materials of the
+    //! CityObjects are not written.
+    //! The vertices carry the position of their feature in the tile as the feature ID of
+    //! `EXT_mesh_features`, which indexes a property table of `EXT_structural_metadata` with
+    //! the attributes of the features. For a Batched 3D Model (b3dm), the position is the
+    //! `_BATCHID` instead, and the attributes are in the batch table, for the clients that
+    //! only read the 3D Tiles 1.0 content formats.
+    //! The GeometryInstances are either copied into the mesh, or each geometry template is
+    //! written once and drawn at its instances with `EXT_mesh_gpu_instancing`, or as an
+    //! Instanced 3D Model (i3dm) in a Composite (cmpt) together with the b3dm.
+    //! The MultiPoint geometries, such as the points of a sensor-derived point cloud, are
+    //! written as a point primitive, and their points carry the `_BATCHID` of their feature.
+    use std::collections::BTreeMap;
+    use std::fs::read_to_string;
+    use std::path::Path;
+
+    use serde_json::{json, Value};
+
+    use crate::parser::{CityObjectType, GeometryTemplates, Transform};
+    use crate::proj::Proj;
+
+    /// The program that writes the glTF content of the tiles.
+    #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum)]
+    pub enum ContentWriter {
+        /// Convert the features with the geof subprocess.
+        #[default]
+        Geof,
+        /// Triangulate and write the features directly, without geof.
+        Native,
+    }
+
+    /// A surface with its rings, the first ring is the exterior, the others are the holes.
+    pub type Polygon = Vec<Vec<[f64; 3]>>;
+
+    /// The compression of the geometry of the native content.
+    #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum)]
+    pub enum Compression {
+        /// The buffers are not compressed.
+        None,
+        /// The buffers are compressed with EXT_meshopt_compression, see [super::meshopt].
+        #[default]
+        Meshopt,
+        /// KHR_draco_mesh_compression, which is not built in, because it needs the Draco
+        /// encoder. Draco is applied with gltf-transform in --post-process.
+        Draco,
+    }
+
+    /// The geometry templates of the GeometryInstances, and how the instances are written.
+    pub struct Instancing<'a> {
+        pub templates: &'a GeometryTemplates,
+        /// Write the instances of each template with `EXT_mesh_gpu_instancing`, instead of
+        /// copying the surfaces of the template into the mesh at each instance.
+        pub gpu: bool,
+    }
+
+    /// A GeometryInstance, which places the `template` at the `reference` point, after
+    /// transforming its vertices with the row-major `matrix`.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Instance {
+        pub template: usize,
+        /// The reference point in the coordinates of the features, with the scaled z.
+        pub reference: [f64; 3],
+        pub matrix: [f64; 16],
+    }
+
+    impl Instance {
+        fn from_geometry(geometry: &Value, vertices: &[[f64; 3]]) -> Option<Self> {
+            let template = geometry["template"].as_u64()? as usize;
+            let reference = *vertices.get(geometry["boundaries"][0].as_u64()? as usize)?;
+            let mut matrix = [0.0; 16];
+            for (i, m) in matrix.iter_mut().enumerate() {
+                *m = match geometry["transformationMatrix"][i].as_f64() {
+                    Some(m) => m,
+                    None if i % 5 == 0 => 1.0,
+                    None => 0.0,
+                };
+            }
+            Some(Self {
+                template,
+                reference,
+                matrix,
+            })
+        }
+
+        /// The linear part of the matrix, with the z of the result multiplied with `z_scale`.
+        /// The rows are the rows of the matrix.
+        fn linear(&self, z_scale: f64) -> [[f64; 3]; 3] {
+            let m = &self.matrix;
+            [
+                [m[0], m[1], m[2]],
+                [m[4], m[5], m[6]],
+                [m[8] * z_scale, m[9] * z_scale, m[10] * z_scale],
+            ]
+        }
+
+        /// The translation of the matrix plus the reference point.
+        fn translation(&self, z_scale: f64) -> [f64; 3] {
+            let m = &self.matrix;
+            [
+                self.reference[0] + m[3],
+                self.reference[1] + m[7],
+                self.reference[2] + m[11] * z_scale,
+            ]
+        }
+
+        /// Place the template `polygon` at the instance.
+        fn place(&self, polygon: &Polygon, z_scale: f64) -> Polygon {
+            let l = self.linear(z_scale);
+            let t = self.translation(z_scale);
+            polygon
+                .iter()
+                .map(|ring| {
+                    ring.iter()
+                        .map(|p| {
+                            let r = |i: usize| l[i][0] * p[0] + l[i][1] * p[1] + l[i][2] * p[2];
+                            [r(0) + t[0], r(1) + t[1], r(2) + t[2]]
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+    }
+
+    /// The vertices of the CityJSONFeature, dequantized with the `transform` and with their z
+    /// coordinate multiplied with `z_scale`.
+    fn feature_vertices(feature: &Value, transform: &Transform, z_scale: f64) -> Vec<[f64; 3]> {
+        feature["vertices"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|v| {
+                let c = |i: usize| {
+                    v[i].as_f64().unwrap_or(0.0) * transform.scale[i] + transform.translate[i]
+                };
+                [c(0), c(1), c(2) * z_scale]
+            })
+            .collect()
+    }
+
+    /// The geometries of the CityObjects of the `cityobject_types` in the CityJSONFeature, or
+    /// of all CityObjects if `None`, in the LoD that `lod_of` returns for the CityObject type.
+    /// If it returns `None`, the geometry with the highest LoD is used. The LoD of a
+    /// GeometryInstance is the LoD of its template in the `templates`.
+    fn selected_geometries<'a, F>(
+        feature: &'a Value,
+        templates: Option<&GeometryTemplates>,
+        cityobject_types: Option<&Vec<CityObjectType>>,
+        lod_of: F,
+    ) -> Vec<&'a Value>
+    where
+        F: Fn(&CityObjectType) -> Option<String>,
+    {
+        let geometry_lod = |geometry: &Value| match geometry["type"].as_str() {
+            Some("GeometryInstance") => templates
+                .zip(geometry["template"].as_u64())
+                .and_then(|(t, i)| lod_string(&t.templates.get(i as usize)?["lod"])),
+            _ => lod_string(&geometry["lod"]),
+        };
+        let mut selected: Vec<&Value> = Vec::new();
+        for co in feature["CityObjects"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(_, co)| co)
+        {
+            let Some(cotype) = co["type"]
+                .as_str()
+                .and_then(|t| t.parse::<CityObjectType>().ok())
+            else {
+                continue;
+            };
+            if cityobject_types.is_some_and(|cotypes| !cotypes.contains(&cotype)) {
+                continue;
+            }
+            let geometries: Vec<&Value> = co["geometry"].as_array().into_iter().flatten().collect();
+            let lod = lod_of(&cotype).or_else(|| {
+                geometries
+                    .iter()
+                    .filter_map(|g| geometry_lod(g))
+                    .max_by(|a, b| {
+                        let a: f64 = a.parse().unwrap_or(0.0);
+                        a.total_cmp(&b.parse().unwrap_or(0.0))
+                    })
+            });
+            selected.extend(
+                geometries
+                    .into_iter()
+                    .filter(|g| lod.is_none() || geometry_lod(g) == lod),
+            );
+        }
+        selected
+    }
+
+    /// Collect the surfaces of the selected geometries of the CityJSONFeature, see
+    /// [selected_geometries]. The GeometryInstances are left out, see [feature_instances].
+    /// The vertices are dequantized with the `transform` and their z coordinate is multiplied
+    /// with `z_scale`.
+    pub fn feature_polygons<F>(
+        feature: &Value,
+        transform: &Transform,
+        templates: Option<&GeometryTemplates>,
+        cityobject_types: Option<&Vec<CityObjectType>>,
+        lod_of: F,
+        z_scale: f64,
+    ) -> Vec<Polygon>
+    where
+        F: Fn(&CityObjectType) -> Option<String>,
+    {
+        let vertices = feature_vertices(feature, transform, z_scale);
+        let mut polygons: Vec<Polygon> = Vec::new();
+        for geometry in selected_geometries(feature, templates, cityobject_types, lod_of) {
+            let Some(depth) = surface_depth(geometry) else {
+                continue;
+            };
+            collect_polygons(&geometry["boundaries"], depth, &vertices, &mut polygons);
+        }
+        polygons
+    }
+
+    /// Collect the GeometryInstances of the selected geometries of the CityJSONFeature, see
+    /// [feature_polygons].
+    pub fn feature_instances<F>(
+        feature: &Value,
+        transform: &Transform,
+        templates: &GeometryTemplates,
+        cityobject_types: Option<&Vec<CityObjectType>>,
+        lod_of: F,
+        z_scale: f64,
+    ) -> Vec<Instance>
+    where
+        F: Fn(&CityObjectType) -> Option<String>,
+    {
+        let vertices = feature_vertices(feature, transform, z_scale);
+        selected_geometries(feature, Some(templates), cityobject_types, lod_of)
+            .into_iter()
+            .filter(|g| g["type"] == "GeometryInstance")
+            .filter_map(|g| Instance::from_geometry(g, &vertices))
+            .filter(|instance| instance.template < templates.templates.len())
+            .collect()
+    }
+
+    /// Collect the points of the MultiPoint geometries of the selected geometries of the
+    /// CityJSONFeature, see [feature_polygons].
+    pub fn feature_points<F>(
+        feature: &Value,
+        transform: &Transform,
+        templates: Option<&GeometryTemplates>,
+        cityobject_types: Option<&Vec<CityObjectType>>,
+        lod_of: F,
+        z_scale: f64,
+    ) -> Vec<[f64; 3]>
+    where
+        F: Fn(&CityObjectType) -> Option<String>,
+    {
+        let vertices = feature_vertices(feature, transform, z_scale);
+        selected_geometries(feature, templates, cityobject_types, lod_of)
+            .into_iter()
+            .filter(|g| g["type"] == "MultiPoint")
+            .flat_map(|g| g["boundaries"].as_array().into_iter().flatten())
+            .filter_map(|i| vertices.get(i.as_u64()? as usize).copied())
+            .collect()
+    }
+
+    /// The surfaces of the geometry `template`, in the coordinates of the template.
+    fn template_polygons(templates: &GeometryTemplates, template: usize) -> Vec<Polygon> {
+        let geometry = &templates.templates[template];
+        let mut polygons: Vec<Polygon> = Vec::new();
+        let Some(depth) = surface_depth(geometry) else {
+            return polygons;
+        };
+        collect_polygons(
+            &geometry["boundaries"],
+            depth,
+            &templates.vertices_templates,
+            &mut polygons,
+        );
+        polygons
+    }
+
+    /// How many levels deeper the surfaces are nested in the boundaries of the geometry, than
+    /// the surfaces of a MultiSurface. `None` if the geometry has no surfaces.
+    fn surface_depth(geometry: &Value) -> Option<usize> {
+        match geometry["type"].as_str() {
+            Some("MultiSurface") | Some("CompositeSurface") => Some(0),
+            Some("Solid") => Some(1),
+            Some("MultiSolid") | Some("CompositeSolid") => Some(2),
+            _ => None,
+        }
+    }
+
+    /// The LoD of a geometry, which is a number in the older CityJSON versions.
+    fn lod_string(lod: &Value) -> Option<String> {
+        match lod {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Collect the surfaces of the boundaries, which are nested `depth` levels deeper than
+    /// the surfaces of a MultiSurface.
+    fn collect_polygons(
+        boundaries: &Value,
+        depth: usize,
+        vertices: &[[f64; 3]],
+        polygons: &mut Vec<Polygon>,
+    ) {
+        let Some(items) = boundaries.as_array() else {
+            return;
+        };
+        if depth > 0 {
+            for item in items {
+                collect_polygons(item, depth - 1, vertices, polygons);
+            }
+            return;
+        }
+        for surface in items {
+            let polygon: Polygon = surface
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|ring| {
+                    ring.as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|i| vertices.get(i.as_u64()? as usize).copied())
+                        .collect()
+                })
+                .collect();
+            if polygon.first().is_some_and(|exterior| exterior.len() >= 3) {
+                polygons.push(polygon);
+            }
+        }
+    }
+
+    /// The normal of the ring with Newell's method, which is not normalized. Its direction
+    /// follows the orientation of the ring.
+    fn newell_normal(ring: &[[f64; 3]]) -> [f64; 3] {
+        let mut n = [0.0; 3];
+        for (i, a) in ring.iter().enumerate() {
+            let b = ring[(i + 1) % ring.len()];
+            n[0] += (a[1] - b[1]) * (a[2] + b[2]);
+            n[1] += (a[2] - b[2]) * (a[0] + b[0]);
+            n[2] += (a[0] - b[0]) * (a[1] + b[1]);
+        }
+        n
+    }
+
+    fn cross_2d(o: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+        (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+    }
+
+    /// The point `p` is inside the counter-clockwise triangle `a`, `b`, `c`, or on its
+    /// boundary.
+    fn in_triangle(p: [f64; 2], a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> bool {
+        cross_2d(a, b, p) >= 0.0 && cross_2d(b, c, p) >= 0.0 && cross_2d(c, a, p) >= 0.0
+    }
+
+    /// The segments `a`-`b` and `c`-`d` cross each other, in a point that is not an endpoint.
+    fn segments_cross(a: [f64; 2], b: [f64; 2], c: [f64; 2], d: [f64; 2]) -> bool {
+        let d1 = cross_2d(a, b, c);
+        let d2 = cross_2d(a, b, d);
+        let d3 = cross_2d(c, d, a);
+        let d4 = cross_2d(c, d, b);
+        d1 * d2 < 0.0 && d3 * d4 < 0.0
+    }
+
+    /// Triangulate the polygon by ear clipping. The holes are connected to the exterior with
+    /// bridge edges first. Returns the triangles as indices into the vertices of the rings,
+    /// numbered one ring after the other. The triangles have the orientation of the exterior.
+    pub fn triangulate(polygon: &[Vec<[f64; 3]>]) -> Vec<[usize; 3]> {
+        let Some(exterior) = polygon.first() else {
+            return Vec::new();
+        };
+        let normal = newell_normal(exterior);
+        // Project to the plane of the axes, in which the surface has the largest extent
+        let axis = (0..3)
+            .max_by(|a, b| normal[*a].abs().total_cmp(&normal[*b].abs()))
+            .unwrap_or(2);
+        if normal[axis] == 0.0 {
+            return Vec::new();
+        }
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        // The exterior is counter-clockwise in the projection
+        let flip = normal[axis] < 0.0;
+        let points: Vec<[f64; 2]> = polygon
+            .iter()
+            .flatten()
+            .map(|p| if flip { [p[v], p[u]] } else { [p[u], p[v]] })
+            .collect();
+        let mut offset = 0;
+        let mut rings: Vec<Vec<usize>> = Vec::with_capacity(polygon.len());
+        for (r, ring) in polygon.iter().enumerate() {
+            let mut indices: Vec<usize> = (offset..offset + ring.len()).collect();
+            offset += ring.len();
+            indices.dedup_by(|a, b| points[*a] == points[*b]);
+            let area: f64 = (0..indices.len())
+                .map(|i| {
+                    let (a, b) = (points[indices[i]], points[indices[(i + 1) % indices.len()]]);
+                    a[0] * b[1] - b[0] * a[1]
+                })
+                .sum();
+            // The exterior is counter-clockwise and the holes are clockwise
+            if (r == 0) == (area < 0.0) {
+                indices.reverse();
+            }
+            if indices.len() >= 3 {
+                rings.push(indices);
+            }
+        }
+        if rings.is_empty() {
+            return Vec::new();
+        }
+        let mut outline = rings.remove(0);
+        // Bridge the holes from the right, so that a bridge does not cross a later hole
+        rings.sort_by(|a, b| {
+            let max_x =
+                |ring: &Vec<usize>| ring.iter().map(|i| points[*i][0]).fold(f64::MIN, f64::max);
+            max_x(b).total_cmp(&max_x(a))
+        });
+        for (h, hole) in rings.iter().enumerate() {
+            let Some(m_pos) =
+                (0..hole.len()).max_by(|a, b| points[hole[*a]][0].total_cmp(&points[hole[*b]][0]))
+            else {
+                continue;
+            };
+            let m = points[hole[m_pos]];
+            let edges = |ring: &[usize]| {
+                (0..ring.len())
+                    .map(|i| (points[ring[i]], points[ring[(i + 1) % ring.len()]]))
+                    .collect::<Vec<_>>()
+            };
+            let mut blocking = edges(&outline);
+            for other in &rings[h..] {
+                blocking.extend(edges(other));
+            }
+            let visible = |p: [f64; 2]| !blocking.iter().any(|(a, b)| segments_cross(m, p, *a, *b));
+            let distance = |i: &usize| {
+                let p = points[outline[*i]];
+                (p[0] - m[0]).powi(2) + (p[1] - m[1]).powi(2)
+            };
+            let Some(p_pos) = (0..outline.len())
+                .filter(|i| visible(points[outline[*i]]))
+                .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            else {
+                continue;
+            };
+            let mut bridged: Vec<usize> = Vec::with_capacity(outline.len() + hole.len() + 2);
+            bridged.extend_from_slice(&outline[..=p_pos]);
+            bridged.extend(hole[m_pos..].iter().chain(&hole[..=m_pos]));
+            bridged.extend_from_slice(&outline[p_pos..]);
+            outline = bridged;
+        }
+
+        let mut triangles: Vec<[usize; 3]> = Vec::with_capacity(outline.len() - 2);
+        while outline.len() > 3 {
+            let n = outline.len();
+            let is_ear = |i: usize| {
+                let (a, b, c) = (outline[(i + n - 1) % n], outline[i], outline[(i + 1) % n]);
+                let (pa, pb, pc) = (points[a], points[b], points[c]);
+                cross_2d(pa, pb, pc) > 0.0
+                    && !outline.iter().any(|j| {
+                        let p = points[*j];
+                        p != pa && p != pb && p != pc && in_triangle(p, pa, pb, pc)
+                    })
+            };
+            // A degenerate outline might not have an ear, then a vertex is clipped anyway
+            let i = (0..n).find(|i| is_ear(*i)).unwrap_or(0);
+            triangles.push([outline[(i + n - 1) % n], outline[i], outline[(i + 1) % n]]);
+            outline.remove(i);
+        }
+        triangles.push([outline[0], outline[1], outline[2]]);
+        triangles.retain(|[a, b, c]| cross_2d(points[*a], points[*b], points[*c]) != 0.0);
+        triangles
+    }
+
+    /// Read the CityJSONFeatures of the tile.
+    pub fn read_features<Q: AsRef<Path>>(
+        feature_paths: &[Q],
+    ) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        feature_paths
+            .iter()
+            .map(|path| Ok(serde_json::from_str(&read_to_string(path.as_ref())?)?))
+            .collect()
+    }
+
+    /// The geometry of the content of a tile in ECEF, that is written as a single GLB with
+    /// [Content::glb], or as a composite of a b3dm and i3dm tiles with [Content::cmpt].
+    pub struct Content {
+        polygons: Vec<Polygon>,
+        triangles: Vec<Vec<[usize; 3]>>,
+        /// The batch ID of each polygon.
+        batch_ids: Vec<u32>,
+        instanced: Vec<InstancedMesh>,
+        points: Vec<([f64; 3], u32)>,
+    }
+
+    impl Content {
+        /// Triangulate the CityObjects of the `features`, together with their points. The
+        /// `transformer` must have `EPSG:4978` as target CRS. The batch ID of a surface or a
+        /// point is the index of its feature in `features`. The GeometryInstances are only
+        /// written if there is `instancing`.
+        /// See [feature_polygons] for the other arguments.
+        pub fn new<F>(
+            features: &[Value],
+            transform: &Transform,
+            cityobject_types: Option<&Vec<CityObjectType>>,
+            lod_of: F,
+            z_scale: f64,
+            transformer: &Proj,
+            instancing: Option<&Instancing>,
+        ) -> Result<Self, Box<dyn std::error::Error>>
+        where
+            F: Fn(&CityObjectType) -> Option<String>,
+        {
+            let templates = instancing.map(|instancing| instancing.templates);
+            let mut polygons: Vec<Polygon> = Vec::new();
+            let mut batch_ids: Vec<u32> = Vec::new();
+            let mut instances: BTreeMap<usize, Vec<(Instance, u32)>> = BTreeMap::new();
+            let mut points: Vec<([f64; 3], u32)> = Vec::new();
+            for (batch_id, feature) in features.iter().enumerate() {
+                for p in feature_points(
+                    feature,
+                    transform,
+                    templates,
+                    cityobject_types,
+                    &lod_of,
+                    z_scale,
+                ) {
+                    let (x, y, z) = transformer.convert((p[0], p[1], p[2]))?;
+                    points.push(([x, y, z], batch_id as u32));
+                }
+                let mut feature_polygons = feature_polygons(
+                    feature,
+                    transform,
+                    templates,
+                    cityobject_types,
+                    &lod_of,
+                    z_scale,
+                );
+                if let Some(instancing) = instancing {
+                    for instance in feature_instances(
+                        feature,
+                        transform,
+                        instancing.templates,
+                        cityobject_types,
+                        &lod_of,
+                        z_scale,
+                    ) {
+                        if instancing.gpu {
+                            instances
+                                .entry(instance.template)
+                                .or_default()
+                                .push((instance, batch_id as u32));
+                        } else {
+                            feature_polygons.extend(
+                                template_polygons(instancing.templates, instance.template)
+                                    .iter()
+                                    .map(|polygon| instance.place(polygon, z_scale)),
+                            );
+                        }
+                    }
+                }
+                batch_ids.extend(std::iter::repeat_n(batch_id as u32, feature_polygons.len()));
+                polygons.extend(feature_polygons);
+            }
+            let polygons_ecef = polygons
+                .iter()
+                .map(|polygon| polygon_to_ecef(polygon, transformer))
+                .collect::<Result<Vec<Polygon>, _>>()?;
+            let triangles: Vec<Vec<[usize; 3]>> = polygons.iter().map(|p| triangulate(p)).collect();
+            let mut instanced: Vec<InstancedMesh> = Vec::with_capacity(instances.len());
+            for (template, template_instances) in instances {
+                let polygons = template_polygons(templates.unwrap(), template);
+                let triangles = polygons.iter().map(|p| triangulate(p)).collect();
+                let mut transforms: Vec<InstanceTransform> =
+                    Vec::with_capacity(template_instances.len());
+                for (instance, _) in &template_instances {
+                    transforms.push(InstanceTransform::new(instance, z_scale, transformer)?);
+                }
+                instanced.push(InstancedMesh {
+                    polygons,
+                    triangles,
+                    transforms,
+                    batch_ids: template_instances.iter().map(|(_, id)| *id).collect(),
+                });
+            }
+            Ok(Self {
+                polygons: polygons_ecef,
+                triangles,
+                batch_ids,
+                instanced,
+                points,
+            })
+        }
+
+        /// Append the `polygons` of an external geometry of the feature with the `batch_id`,
+        /// see [crate::external_geometry]. The polygons are in the CRS of the features, their
+        /// z is multiplied with `z_scale`.
+        pub fn push_external(
+            &mut self,
+            polygons: &[Polygon],
+            batch_id: u32,
+            z_scale: f64,
+            transformer: &Proj,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            for polygon in polygons {
+                let scaled: Polygon = polygon
+                    .iter()
+                    .map(|ring| ring.iter().map(|p| [p[0], p[1], p[2] * z_scale]).collect())
+                    .collect();
+                self.triangles.push(triangulate(&scaled));
+                self.polygons.push(polygon_to_ecef(&scaled, transformer)?);
+                self.batch_ids.push(batch_id);
+            }
+            Ok(())
+        }
+
+        /// Write the content as a single GLB. The vertices are stored relative to the center
+        /// of the content and they are rotated from z-up to the y-up of glTF, like the preview
+        /// content. The instances are drawn with `EXT_mesh_gpu_instancing`. With the
+        /// `metadata`, the features can be picked and styled, otherwise the GLB is meant for a
+        /// b3dm.
+        pub fn glb(
+            &self,
+            compression: Compression,
+            metadata: Option<&FeatureMetadata>,
+        ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            glb_bytes(
+                &self.polygons,
+                &self.triangles,
+                &self.batch_ids,
+                &self.instanced,
+                &self.points,
+                compression,
+                metadata,
+            )
+            .ok_or_else(|| "there are no surfaces or points in the selected LoD".into())
+        }
+
+        /// Write the content as a Composite (cmpt) of a b3dm with the surfaces and the points,
+        /// and an Instanced 3D Model (i3dm) for each geometry template. The b3dm has the whole
+        /// `batch_table`, an i3dm has the rows of the features of its instances.
+        pub fn cmpt(
+            &self,
+            batch_table: &Value,
+            compression: Compression,
+        ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            let batch_length = batch_table["id"].as_array().map_or(0, Vec::len);
+            let b3dm = glb_bytes(
+                &self.polygons,
+                &self.triangles,
+                &self.batch_ids,
+                &[],
+                &self.points,
+                compression,
+                None,
+            )
+            .map(|glb| b3dm(&glb, batch_length, batch_table));
+            let tiles: Vec<Vec<u8>> = b3dm
+                .into_iter()
+                .chain(
+                    self.instanced
+                        .iter()
+                        .filter_map(|mesh| i3dm_bytes(mesh, batch_table, compression)),
+                )
+                .collect();
+            if tiles.is_empty() {
+                return Err(
+                    "there are no surfaces, instances or points in the selected LoD".into(),
+                );
+            }
+            Ok(cmpt(&tiles))
+        }
+    }
+
+    /// Convert the `polygon` to ECEF with the `transformer`.
+    fn polygon_to_ecef(
+        polygon: &Polygon,
+        transformer: &Proj,
+    ) -> Result<Polygon, Box<dyn std::error::Error>> {
+        let mut polygon_ecef: Polygon = Vec::with_capacity(polygon.len());
+        for ring in polygon {
+            let mut ring_ecef: Vec<[f64; 3]> = Vec::with_capacity(ring.len());
+            for p in ring {
+                let (x, y, z) = transformer.convert((p[0], p[1], p[2]))?;
+                ring_ecef.push([x, y, z]);
+            }
+            polygon_ecef.push(ring_ecef);
+        }
+        Ok(polygon_ecef)
+    }
+
+    /// The batch table of the b3dm content, with the `id` of each feature and the values of
+    /// the CityObject `attributes` in the `<name>:<type>` format of `--object-attribute`.
+    /// The value of an attribute is taken from the CityObject that has the ID of the feature,
+    /// or else from the first CityObject that has the attribute. Missing values are `null`.
+    pub fn batch_table(features: &[Value], attributes: &[String]) -> Value {
+        let mut table = serde_json::Map::new();
+        table.insert(
+            "id".to_string(),
+            features.iter().map(|f| f["id"].clone()).collect(),
+        );
+        for attribute in attributes {
+            let name = attribute
+                .split_once(':')
+                .map_or(attribute.as_str(), |(name, _)| name);
+            let values: Vec<Value> = features
+                .iter()
+                .map(|feature| {
+                    let cityobjects = &feature["CityObjects"];
+                    let own = feature["id"]
+                        .as_str()
+                        .map(|id| &cityobjects[id]["attributes"][name])
+                        .filter(|v| !v.is_null());
+                    own.or_else(|| {
+                        cityobjects
+                            .as_object()
+                            .into_iter()
+                            .flatten()
+                            .map(|(_, co)| &co["attributes"][name])
+                            .find(|v| !v.is_null())
+                    })
+                    .cloned()
+                    .unwrap_or(Value::Null)
+                })
+                .collect();
+            table.insert(name.to_string(), Value::Array(values));
+        }
+        Value::Object(table)
+    }
+
+    /// The feature IDs and the property table of the features in the glTF content, for
+    /// `EXT_mesh_features` and `EXT_structural_metadata`.
+    pub struct FeatureMetadata<'a> {
+        /// The class of the features, like in the schema of the tileset.
+        class: &'a str,
+        /// The name and the type of each CityObject attribute.
+        attributes: Vec<(&'a str, &'a str)>,
+        /// The values of the attributes, see [batch_table].
+        values: Value,
+        feature_count: usize,
+    }
+
+    impl<'a> FeatureMetadata<'a> {
+        /// The metadata of the `features`, with the CityObject `attributes` in the
+        /// `<name>:<type>` format of `--object-attribute` as the properties of the `class`.
+        pub fn new(
+            features: &[Value],
+            class: &'a str,
+            attributes: &'a [String],
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let mut parsed: Vec<(&str, &str)> = Vec::with_capacity(attributes.len());
+            for attribute in attributes {
+                match attribute.split_once(':') {
+                    Some((name, t @ ("bool" | "int" | "float" | "string"))) => {
+                        parsed.push((name, t))
+                    }
+                    _ => {
+                        return Err(format!(
+                            "attribute must be in the format of 'name:type', where the type is one of 'bool', 'int', 'float', 'string': {attribute}"
+                        )
+                        .into())
+                    }
+                }
+            }
+            Ok(Self {
+                class,
+                attributes: parsed,
+                values: batch_table(features, attributes),
+                feature_count: features.len(),
+            })
+        }
+
+        /// The feature ID set of the `_FEATURE_ID_0` attribute, which indexes the property
+        /// table if there is one.
+        fn feature_ids(&self) -> Value {
+            let mut feature_id = json!({"featureCount": self.feature_count, "attribute": 0});
+            if !self.attributes.is_empty() {
+                feature_id["propertyTable"] = json!(0);
+            }
+            json!({ "featureIds": [feature_id] })
+        }
+
+        /// Append the values of the attributes to the `buffers`, and return the
+        /// `EXT_structural_metadata` extension with the schema and the property table.
+        /// A missing value is the `noData` value of its property, and `false` for a boolean.
+        /// Returns `None` if there are no attributes.
+        fn push_property_table(&self, buffers: &mut Buffers) -> Option<Value> {
+            if self.attributes.is_empty() {
+                return None;
+            }
+            let mut class_properties = serde_json::Map::new();
+            let mut table_properties = serde_json::Map::new();
+            for (name, attribute_type) in &self.attributes {
+                let values = self.values[name].as_array()?;
+                let has_null = values.iter().any(Value::is_null);
+                let (mut class_property, table_property) = match *attribute_type {
+                    "bool" => {
+                        let mut bits = vec![0_u8; values.len().div_ceil(8)];
+                        for (i, v) in values.iter().enumerate() {
+                            if v.as_bool().unwrap_or_default() {
+                                bits[i / 8] |= 1 << (i % 8);
+                            }
+                        }
+                        (
+                            json!({"type": "BOOLEAN"}),
+                            json!({"values": buffers.push_view(&bits)}),
+                        )
+                    }
+                    "int" => {
+                        let bytes: Vec<u8> = values
+                            .iter()
+                            .map(|v| v.as_f64().map_or(i32::MIN, |v| v as i32))
+                            .flat_map(i32::to_le_bytes)
+                            .collect();
+                        let mut property = json!({"type": "SCALAR", "componentType": "INT32"});
+                        if has_null {
+                            property["noData"] = json!(i32::MIN);
+                        }
+                        (property, json!({"values": buffers.push_view(&bytes)}))
+                    }
+                    "float" => {
+                        let floats: Vec<f32> = values
+                            .iter()
+                            .map(|v| v.as_f64().map_or(f32::MIN, |v| v as f32))
+                            .collect();
+                        let mut property = json!({"type": "SCALAR", "componentType": "FLOAT32"});
+                        if has_null {
+                            property["noData"] = json!(f32::MIN);
+                        }
+                        (
+                            property,
+                            json!({"values": buffers.push_view(&f32_bytes(&floats))}),
+                        )
+                    }
+                    _ => {
+                        let mut strings: Vec<u8> = Vec::new();
+                        let mut offsets: Vec<u8> = 0_u32.to_le_bytes().to_vec();
+                        for v in values {
+                            match v {
+                                Value::Null => {}
+                                Value::String(s) => strings.extend_from_slice(s.as_bytes()),
+                                v => strings.extend_from_slice(v.to_string().as_bytes()),
+                            }
+                            offsets.extend((strings.len() as u32).to_le_bytes());
+                        }
+                        let mut property = json!({"type": "STRING"});
+                        if has_null {
+                            property["noData"] = json!("");
+                        }
+                        let table_property = json!({
+                            "values": buffers.push_view(&strings),
+                            "stringOffsets": buffers.push_view(&offsets),
+                            "stringOffsetType": "UINT32"
+                        });
+                        (property, table_property)
+                    }
+                };
+                class_property["description"] = json!(format!("CityObject attribute '{name}'"));
+                class_properties.insert(name.to_string(), class_property);
+                table_properties.insert(name.to_string(), table_property);
+            }
+            Some(json!({
+                "schema": {
+                    "id": "tyler",
+                    "classes": {self.class: {"properties": class_properties}}
+                },
+                "propertyTables": [{
+                    "class": self.class,
+                    "count": self.feature_count,
+                    "properties": table_properties
+                }]
+            }))
+        }
+    }
+
+    /// Wrap the `glb` into a Batched 3D Model with `batch_length` features and the JSON
+    /// `batch_table`. The feature table only has the `BATCH_LENGTH`, because the positions are
+    /// in the glTF node.
+    pub fn b3dm(glb: &[u8], batch_length: usize, batch_table: &Value) -> Vec<u8> {
+        let feature_table = json!({ "BATCH_LENGTH": batch_length });
+        legacy_tile(b"b3dm", None, &feature_table, &[], batch_table, glb)
+    }
+
+    /// Write a tile in a 3D Tiles 1.0 format with the `magic`, that has the feature table
+    /// and the batch table before the `glb`. The `gltf_format` is the extra header field of
+    /// i3dm.
+    fn legacy_tile(
+        magic: &[u8; 4],
+        gltf_format: Option<u32>,
+        feature_table: &Value,
+        feature_table_binary: &[u8],
+        batch_table: &Value,
+        glb: &[u8],
+    ) -> Vec<u8> {
+        let header_length = if gltf_format.is_some() { 32 } else { 28 };
+        // The tables and the GLB start and end at 8 byte boundaries, the JSON is padded with
+        // spaces
+        let mut feature_table = feature_table.to_string().into_bytes();
+        feature_table.resize(
+            (header_length + feature_table.len()).next_multiple_of(8) - header_length,
+            b' ',
+        );
+        let mut feature_table_binary = feature_table_binary.to_vec();
+        feature_table_binary.resize(feature_table_binary.len().next_multiple_of(8), 0);
+        let mut batch_table = batch_table.to_string().into_bytes();
+        batch_table.resize(batch_table.len().next_multiple_of(8), b' ');
+        let glb_length = glb.len().next_multiple_of(8);
+        let length = header_length
+            + feature_table.len()
+            + feature_table_binary.len()
+            + batch_table.len()
+            + glb_length;
+        let mut tile: Vec<u8> = Vec::with_capacity(length);
+        tile.extend_from_slice(magic);
+        tile.extend_from_slice(&1_u32.to_le_bytes());
+        tile.extend_from_slice(&(length as u32).to_le_bytes());
+        tile.extend_from_slice(&(feature_table.len() as u32).to_le_bytes());
+        tile.extend_from_slice(&(feature_table_binary.len() as u32).to_le_bytes());
+        tile.extend_from_slice(&(batch_table.len() as u32).to_le_bytes());
+        tile.extend_from_slice(&0_u32.to_le_bytes());
+        if let Some(gltf_format) = gltf_format {
+            tile.extend_from_slice(&gltf_format.to_le_bytes());
+        }
+        tile.extend_from_slice(&feature_table);
+        tile.extend_from_slice(&feature_table_binary);
+        tile.extend_from_slice(&batch_table);
+        tile.extend_from_slice(glb);
+        tile.resize(length, 0);
+        tile
+    }
+
+    /// Combine the `tiles` into a Composite tile. The tiles must have a length that is a
+    /// multiple of 8, like the b3dm and the i3dm that are written here.
+    pub fn cmpt(tiles: &[Vec<u8>]) -> Vec<u8> {
+        let length = 16 + tiles.iter().map(Vec::len).sum::<usize>();
+        let mut cmpt: Vec<u8> = Vec::with_capacity(length);
+        cmpt.extend_from_slice(b"cmpt");
+        cmpt.extend_from_slice(&1_u32.to_le_bytes());
+        cmpt.extend_from_slice(&(length as u32).to_le_bytes());
+        cmpt.extend_from_slice(&(tiles.len() as u32).to_le_bytes());
+        for tile in tiles {
+            cmpt.extend_from_slice(tile);
+        }
+        cmpt
+    }
+
+    /// Write the instances of the `mesh` as an Instanced 3D Model, with the glTF of the
+    /// template embedded. The rotation and the scale of an instance are in the
+    /// `NORMAL_UP`, `NORMAL_RIGHT` and `SCALE_NON_UNIFORM` of the feature table, and the
+    /// batch table has the rows of `batch_table` of the features of the instances.
+    /// Returns `None` if there are no instances or if the template has no triangles.
+    fn i3dm_bytes(
+        mesh: &InstancedMesh,
+        batch_table: &Value,
+        compression: Compression,
+    ) -> Option<Vec<u8>> {
+        let (mut translations, mut ups, mut rights, mut scales, mut batch_ids) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (transform, batch_id) in mesh.transforms.iter().zip(&mesh.batch_ids) {
+            let Some((rotation, scale)) = orthonormal(&transform.columns) else {
+                continue;
+            };
+            translations.push(transform.translation);
+            rights.extend(rotation[0].map(|c| c as f32));
+            ups.extend(rotation[1].map(|c| c as f32));
+            scales.extend(scale.map(|c| c as f32));
+            batch_ids.push(*batch_id);
+        }
+        if translations.is_empty() {
+            return None;
+        }
+        let mut buffers = Buffers {
+            meshopt: compression == Compression::Meshopt,
+            ..Default::default()
+        };
+        let primitive = buffers.push_mesh(&mesh.polygons, &mesh.triangles, None, [0.0; 3], true)?;
+        let glb = glb_from_buffers(
+            buffers,
+            vec![json!({"mesh": 0})],
+            vec![json!({"primitives": [primitive]})],
+            Vec::new(),
+            Vec::new(),
+            json!({}),
+        );
+
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        for t in &translations {
+            for d in 0..3 {
+                min[d] = min[d].min(t[d]);
+                max[d] = max[d].max(t[d]);
+            }
+        }
+        let center = [
+            (min[0] + max[0]) / 2.0,
+            (min[1] + max[1]) / 2.0,
+            (min[2] + max[2]) / 2.0,
+        ];
+        let positions: Vec<f32> = translations
+            .iter()
+            .flat_map(|t| [t[0] - center[0], t[1] - center[1], t[2] - center[2]])
+            .map(|c| c as f32)
+            .collect();
+        // The batch IDs of the instances index the features that have instances
+        let mut features: Vec<u32> = batch_ids.clone();
+        features.sort_unstable();
+        features.dedup();
+        for batch_id in &mut batch_ids {
+            *batch_id = features.binary_search(batch_id).unwrap() as u32;
+        }
+        let mut binary: Vec<u8> = Vec::new();
+        let mut section = |values: &[f32]| {
+            let offset = binary.len();
+            binary.extend(f32_bytes(values));
+            json!({ "byteOffset": offset })
+        };
+        let mut feature_table = json!({
+            "INSTANCES_LENGTH": translations.len(),
+            "RTC_CENTER": center,
+            "POSITION": section(&positions),
+            "NORMAL_UP": section(&ups),
+            "NORMAL_RIGHT": section(&rights),
+            "SCALE_NON_UNIFORM": section(&scales),
+        });
+        feature_table["BATCH_ID"] =
+            json!({"byteOffset": binary.len(), "componentType": "UNSIGNED_INT"});
+        binary.extend(batch_ids.iter().flat_map(|i| i.to_le_bytes()));
+
+        let rows: serde_json::Map<String, Value> = batch_table
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(name, values)| {
+                let values = features
+                    .iter()
+                    .map(|i| values[*i as usize].clone())
+                    .collect();
+                (name.clone(), Value::Array(values))
+            })
+            .collect();
+        Some(legacy_tile(
+            b"i3dm",
+            Some(1),
+            &feature_table,
+            &binary,
+            &Value::Object(rows),
+            &glb,
+        ))
+    }
+
+    /// The surfaces of a geometry template, that are written once and drawn at each of the
+    /// `transforms` with `EXT_mesh_gpu_instancing`.
+    struct InstancedMesh {
+        polygons: Vec<Polygon>,
+        triangles: Vec<Vec<[usize; 3]>>,
+        transforms: Vec<InstanceTransform>,
+        /// The batch ID of each instance.
+        batch_ids: Vec<u32>,
+    }
+
+    /// The transformation of an instance from the coordinates of the template to ECEF.
+    struct InstanceTransform {
+        translation: [f64; 3],
+        /// The columns of the linear part.
+        columns: [[f64; 3]; 3],
+    }
+
+    impl InstanceTransform {
+        /// The transformation of the `instance`. The reprojection is linearized at the
+        /// reference point, which is accurate for the size of a tree or a lamppost.
+        fn new(
+            instance: &Instance,
+            z_scale: f64,
+            transformer: &Proj,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let t = instance.translation(z_scale);
+            let (x, y, z) = transformer.convert((t[0], t[1], t[2]))?;
+            let translation = [x, y, z];
+            // The derivative of the reprojection along the axes of the input CRS
+            let mut jacobian = [[0.0; 3]; 3];
+            for (d, column) in jacobian.iter_mut().enumerate() {
+                let mut p = t;
+                p[d] += 1.0;
+                let (x, y, z) = transformer.convert((p[0], p[1], p[2]))?;
+                *column = [x - translation[0], y - translation[1], z - translation[2]];
+            }
+            let l = instance.linear(z_scale);
+            let mut columns = [[0.0; 3]; 3];
+            for (j, column) in columns.iter_mut().enumerate() {
+                for (i, c) in column.iter_mut().enumerate() {
+                    *c = (0..3).map(|k| jacobian[k][i] * l[k][j]).sum();
+                }
+            }
+            Ok(Self {
+                translation,
+                columns,
+            })
+        }
+    }
+
+    /// Decompose the linear transformation with the `columns` into a rotation quaternion
+    /// (x, y, z, w) and a scale, see [orthonormal].
+    fn rotation_scale(columns: &[[f64; 3]; 3]) -> Option<([f64; 4], [f64; 3])> {
+        let ([q0, q1, q2], scale) = orthonormal(columns)?;
+        // The rotation matrix, r[row][column]
+        let r = |i: usize, j: usize| [q0, q1, q2][j][i];
+        let trace = r(0, 0) + r(1, 1) + r(2, 2);
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            [
+                (r(2, 1) - r(1, 2)) / s,
+                (r(0, 2) - r(2, 0)) / s,
+                (r(1, 0) - r(0, 1)) / s,
+                s / 4.0,
+            ]
+        } else if r(0, 0) > r(1, 1) && r(0, 0) > r(2, 2) {
+            let s = (1.0 + r(0, 0) - r(1, 1) - r(2, 2)).sqrt() * 2.0;
+            [
+                s / 4.0,
+                (r(0, 1) + r(1, 0)) / s,
+                (r(0, 2) + r(2, 0)) / s,
+                (r(2, 1) - r(1, 2)) / s,
+            ]
+        } else if r(1, 1) > r(2, 2) {
+            let s = (1.0 + r(1, 1) - r(0, 0) - r(2, 2)).sqrt() * 2.0;
+            [
+                (r(0, 1) + r(1, 0)) / s,
+                s / 4.0,
+                (r(1, 2) + r(2, 1)) / s,
+                (r(0, 2) - r(2, 0)) / s,
+            ]
+        } else {
+            let s = (1.0 + r(2, 2) - r(0, 0) - r(1, 1)).sqrt() * 2.0;
+            [
+                (r(0, 2) + r(2, 0)) / s,
+                (r(1, 2) + r(2, 1)) / s,
+                s / 4.0,
+                (r(1, 0) - r(0, 1)) / s,
+            ]
+        };
+        Some((q, scale))
+    }
+
+    /// Decompose the linear transformation with the `columns` into the orthonormal columns
+    /// of a rotation and a scale along them. A shear is not kept, and a mirroring is a
+    /// negative z scale. Returns `None` if the transformation is degenerate.
+    fn orthonormal(columns: &[[f64; 3]; 3]) -> Option<([[f64; 3]; 3], [f64; 3])> {
+        let dot = |a: &[f64; 3], b: &[f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        let [c0, c1, c2] = columns;
+        let s0 = dot(c0, c0).sqrt();
+        let q0 = c0.map(|c| c / s0);
+        let d = dot(&q0, c1);
+        let u1 = [c1[0] - d * q0[0], c1[1] - d * q0[1], c1[2] - d * q0[2]];
+        let s1 = dot(&u1, &u1).sqrt();
+        if s0 == 0.0 || s1 == 0.0 {
+            return None;
+        }
+        let q1 = u1.map(|c| c / s1);
+        let q2 = [
+            q0[1] * q1[2] - q0[2] * q1[1],
+            q0[2] * q1[0] - q0[0] * q1[2],
+            q0[0] * q1[1] - q0[1] * q1[0],
+        ];
+        let s2 = dot(&q2, c2);
+        if s2 == 0.0 {
+            return None;
+        }
+        Some(([q0, q1, q2], [s0, s1, s2]))
+    }
+
+    /// The binary buffer of the glTF with its buffer views and accessors. With meshopt
+    /// compression, the buffer views are in an uncompressed fallback buffer, that has no data,
+    /// and their compressed data is in the binary buffer.
+    #[derive(Default)]
+    struct Buffers {
+        bin: Vec<u8>,
+        buffer_views: Vec<Value>,
+        accessors: Vec<Value>,
+        meshopt: bool,
+        fallback_length: usize,
+    }
+
+    impl Buffers {
+        /// Append the `data` as a buffer view for the `accessor`, and return the index of the
+        /// accessor. The components of the accessor must be 4 bytes. The indices are not
+        /// compressed, because only the vertex codec of meshopt is implemented.
+        fn push(&mut self, data: &[u8], target: Option<u32>, mut accessor: Value) -> usize {
+            let mut buffer_view = if self.meshopt && target != Some(34963) {
+                let stride = 4 * match accessor["type"].as_str() {
+                    Some("VEC3") => 3,
+                    Some("VEC4") => 4,
+                    _ => 1,
+                };
+                let encoded = super::meshopt::encode_attributes(data, stride);
+                let buffer_view = json!({
+                    "buffer": 1,
+                    "byteOffset": self.fallback_length,
+                    "byteLength": data.len(),
+                    "extensions": {"EXT_meshopt_compression": {
+                        "buffer": 0,
+                        "byteOffset": self.bin.len(),
+                        "byteLength": encoded.len(),
+                        "byteStride": stride,
+                        "count": data.len() / stride,
+                        "mode": "ATTRIBUTES"
+                    }}
+                });
+                self.fallback_length += data.len();
+                self.bin.extend_from_slice(&encoded);
+                self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+                buffer_view
+            } else {
+                let buffer_view = json!({
+                    "buffer": 0,
+                    "byteOffset": self.bin.len(),
+                    "byteLength": data.len()
+                });
+                self.bin.extend_from_slice(data);
+                buffer_view
+            };
+            if let Some(target) = target {
+                buffer_view["target"] = json!(target);
+            }
+            accessor["bufferView"] = json!(self.buffer_views.len());
+            self.buffer_views.push(buffer_view);
+            self.accessors.push(accessor);
+            self.accessors.len() - 1
+        }
+
+        /// Append the vertices and the triangles of the `polygons` and return the mesh
+        /// primitive. Each polygon has its own vertices, so that the normals are flat. The
+        /// vertices are relative to the `origin`, and if `y_up`, they and the normals are
+        /// rotated from z-up to y-up. The vertices of a polygon get its `batch_ids` entry.
+        /// Returns `None` if there are no triangles.
+        fn push_mesh(
+            &mut self,
+            polygons: &[Polygon],
+            triangles: &[Vec<[usize; 3]>],
+            batch_ids: Option<&[u32]>,
+            origin: [f64; 3],
+            y_up: bool,
+        ) -> Option<Value> {
+            let rotate = |c: [f64; 3]| if y_up { to_gltf(c) } else { c };
+            let mut positions: Vec<[f32; 3]> = Vec::new();
+            let mut normals: Vec<[f32; 3]> = Vec::new();
+            let mut indices: Vec<u32> = Vec::new();
+            let mut vertex_batch_ids: Vec<f32> = Vec::new();
+            for (i, (polygon, polygon_triangles)) in polygons.iter().zip(triangles).enumerate() {
+                if polygon_triangles.is_empty() {
+                    continue;
+                }
+                let n = newell_normal(&polygon[0]);
+                let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                let normal = rotate([n[0] / length, n[1] / length, n[2] / length]);
+                let offset = positions.len() as u32;
+                for p in polygon.iter().flatten() {
+                    let rel = rotate([p[0] - origin[0], p[1] - origin[1], p[2] - origin[2]]);
+                    positions.push(rel.map(|c| c as f32));
+                    normals.push(normal.map(|c| c as f32));
+                    if let Some(batch_ids) = batch_ids {
+                        vertex_batch_ids.push(batch_ids[i] as f32);
+                    }
+                }
+                for triangle in polygon_triangles {
+                    indices.extend(triangle.iter().map(|i| offset + *i as u32));
+                }
+            }
+            if indices.is_empty() {
+                return None;
+            }
+            let mut pos_min = [f32::MAX; 3];
+            let mut pos_max = [f32::MIN; 3];
+            for p in &positions {
+                for d in 0..3 {
+                    pos_min[d] = pos_min[d].min(p[d]);
+                    pos_max[d] = pos_max[d].max(p[d]);
+                }
+            }
+            let position = self.push(
+                &f32_bytes(positions.as_flattened()),
+                Some(34962),
+                json!({"componentType": 5126, "count": positions.len(), "type": "VEC3", "min": pos_min, "max": pos_max}),
+            );
+            let normal = self.push(
+                &f32_bytes(normals.as_flattened()),
+                Some(34962),
+                json!({"componentType": 5126, "count": normals.len(), "type": "VEC3"}),
+            );
+            let index_bytes: Vec<u8> = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+            let index = self.push(
+                &index_bytes,
+                Some(34963),
+                json!({"componentType": 5125, "count": indices.len(), "type": "SCALAR"}),
+            );
+            let mut primitive = json!({
+                "attributes": {"POSITION": position, "NORMAL": normal},
+                "indices": index,
+                "material": 0
+            });
+            if batch_ids.is_some() {
+                let batch_id = self.push(
+                    &f32_bytes(&vertex_batch_ids),
+                    Some(34962),
+                    json!({"componentType": 5126, "count": vertex_batch_ids.len(), "type": "SCALAR"}),
+                );
+                primitive["attributes"]["_BATCHID"] = json!(batch_id);
+            }
+            Some(primitive)
+        }
+
+        /// Append the `data` as an uncompressed buffer view that starts at an 8 byte boundary,
+        /// as the property tables need, and return the index of the buffer view.
+        fn push_view(&mut self, data: &[u8]) -> usize {
+            self.bin.resize(self.bin.len().next_multiple_of(8), 0);
+            self.buffer_views.push(json!({
+                "buffer": 0,
+                "byteOffset": self.bin.len(),
+                "byteLength": data.len()
+            }));
+            self.bin.extend_from_slice(data);
+            self.buffer_views.len() - 1
+        }
+
+        /// The buffers of the glTF.
+        fn buffers_json(&self) -> Value {
+            if self.meshopt {
+                json!([
+                    {"byteLength": self.bin.len()},
+                    {
+                        "byteLength": self.fallback_length,
+                        "extensions": {"EXT_meshopt_compression": {"fallback": true}}
+                    }
+                ])
+            } else {
+                json!([{"byteLength": self.bin.len()}])
+            }
+        }
+    }
+
+    impl Buffers {
+        /// Append the `points` with their batch IDs and return the point primitive. The
+        /// points are relative to the `origin` and they are rotated from z-up to y-up.
+        /// Returns `None` if there are no points.
+        fn push_points(&mut self, points: &[([f64; 3], u32)], origin: [f64; 3]) -> Option<Value> {
+            if points.is_empty() {
+                return None;
+            }
+            let positions: Vec<[f32; 3]> = points
+                .iter()
+                .map(|(p, _)| {
+                    to_gltf([p[0] - origin[0], p[1] - origin[1], p[2] - origin[2]])
+                        .map(|c| c as f32)
+                })
+                .collect();
+            let mut pos_min = [f32::MAX; 3];
+            let mut pos_max = [f32::MIN; 3];
+            for p in &positions {
+                for d in 0..3 {
+                    pos_min[d] = pos_min[d].min(p[d]);
+                    pos_max[d] = pos_max[d].max(p[d]);
+                }
+            }
+            let position = self.push(
+                &f32_bytes(positions.as_flattened()),
+                Some(34962),
+                json!({"componentType": 5126, "count": positions.len(), "type": "VEC3", "min": pos_min, "max": pos_max}),
+            );
+            let batch_ids: Vec<f32> = points.iter().map(|(_, b)| *b as f32).collect();
+            let batch_id = self.push(
+                &f32_bytes(&batch_ids),
+                Some(34962),
+                json!({"componentType": 5126, "count": batch_ids.len(), "type": "SCALAR"}),
+            );
+            Some(json!({
+                "attributes": {"POSITION": position, "_BATCHID": batch_id},
+                "mode": 0,
+                "material": 0
+            }))
+        }
+    }
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// z-up to y-up
+    fn to_gltf(c: [f64; 3]) -> [f64; 3] {
+        [c[0], c[2], -c[1]]
+    }
+
+    /// Build the GLB from the polygons in ECEF and their triangles, from the `instanced`
+    /// templates and from the `points` in ECEF with their batch IDs. The vertices of a polygon
+    /// get its `batch_ids` entry. With the `metadata`, the batch IDs are written as the feature
+    /// IDs of `EXT_mesh_features` and `EXT_instance_features`, with the property table of
+    /// `EXT_structural_metadata`, otherwise they are the `_BATCHID` of b3dm.
+    /// Returns `None` if there are no triangles and no points.
+    fn glb_bytes(
+        polygons: &[Polygon],
+        triangles: &[Vec<[usize; 3]>],
+        batch_ids: &[u32],
+        instanced: &[InstancedMesh],
+        points: &[([f64; 3], u32)],
+        compression: Compression,
+        metadata: Option<&FeatureMetadata>,
+    ) -> Option<Vec<u8>> {
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        let instance_points = instanced
+            .iter()
+            .flat_map(|mesh| mesh.transforms.iter().map(|t| &t.translation));
+        let all_points = polygons.iter().flatten().flatten().chain(instance_points);
+        for p in all_points.chain(points.iter().map(|(p, _)| p)) {
+            for d in 0..3 {
+                min[d] = min[d].min(p[d]);
+                max[d] = max[d].max(p[d]);
+            }
+        }
+        let center = [
+            (min[0] + max[0]) / 2.0,
+            (min[1] + max[1]) / 2.0,
+            (min[2] + max[2]) / 2.0,
+        ];
+
+        let mut buffers = Buffers {
+            meshopt: compression == Compression::Meshopt,
+            ..Default::default()
+        };
+        let mut meshes: Vec<Value> = Vec::new();
+        let mut nodes: Vec<Value> = Vec::new();
+        let mut primitives: Vec<Value> = buffers
+            .push_mesh(polygons, triangles, Some(batch_ids), center, true)
+            .into_iter()
+            .chain(buffers.push_points(points, center))
+            .collect();
+        let feature_ids = metadata.map(|metadata| metadata.feature_ids());
+        if let Some(feature_ids) = &feature_ids {
+            for primitive in &mut primitives {
+                let attributes = primitive["attributes"].as_object_mut().unwrap();
+                if let Some(batch_id) = attributes.remove("_BATCHID") {
+                    attributes.insert("_FEATURE_ID_0".to_string(), batch_id);
+                    primitive["extensions"] = json!({"EXT_mesh_features": feature_ids});
+                }
+            }
+        }
+        if !primitives.is_empty() {
+            nodes.push(json!({"mesh": meshes.len(), "translation": to_gltf(center)}));
+            meshes.push(json!({ "primitives": primitives }));
+        }
+        for mesh in instanced {
+            let (mut translations, mut rotations, mut scales, mut ids) =
+                (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+            for (transform, batch_id) in mesh.transforms.iter().zip(&mesh.batch_ids) {
+                // The columns of the linear part are rotated to y-up, like the translation
+                let columns = transform.columns.map(to_gltf);
+                let Some((rotation, scale)) = rotation_scale(&columns) else {
+                    continue;
+                };
+                let t = transform.translation;
+                translations.extend(
+                    to_gltf([t[0] - center[0], t[1] - center[1], t[2] - center[2]])
+                        .map(|c| c as f32),
+                );
+                rotations.extend(rotation.map(|c| c as f32));
+                scales.extend(scale.map(|c| c as f32));
+                ids.push(*batch_id as f32);
+            }
+            let count = translations.len() / 3;
+            if count == 0 {
+                continue;
+            }
+            let Some(primitive) =
+                buffers.push_mesh(&mesh.polygons, &mesh.triangles, None, [0.0; 3], false)
+            else {
+                continue;
+            };
+            let translation = buffers.push(
+                &f32_bytes(&translations),
+                None,
+                json!({"componentType": 5126, "count": count, "type": "VEC3"}),
+            );
+            let rotation = buffers.push(
+                &f32_bytes(&rotations),
+                None,
+                json!({"componentType": 5126, "count": count, "type": "VEC4"}),
+            );
+            let scale = buffers.push(
+                &f32_bytes(&scales),
+                None,
+                json!({"componentType": 5126, "count": count, "type": "VEC3"}),
+            );
+            let mut node = json!({
+                "mesh": meshes.len(),
+                "translation": to_gltf(center),
+                "extensions": {"EXT_mesh_gpu_instancing": {"attributes": {
+                    "TRANSLATION": translation,
+                    "ROTATION": rotation,
+                    "SCALE": scale
+                }}}
+            });
+            if let Some(feature_ids) = &feature_ids {
+                let feature_id = buffers.push(
+                    &f32_bytes(&ids),
+                    None,
+                    json!({"componentType": 5126, "count": count, "type": "SCALAR"}),
+                );
+                let extensions = &mut node["extensions"];
+                extensions["EXT_mesh_gpu_instancing"]["attributes"]["_FEATURE_ID_0"] =
+                    json!(feature_id);
+                extensions["EXT_instance_features"] = feature_ids.clone();
+            }
+            nodes.push(node);
+            meshes.push(json!({"primitives": [primitive]}));
+        }
+        if meshes.is_empty() {
+            return None;
+        }
+        let mut required: Vec<&str> = Vec::new();
+        let mut used: Vec<&str> = Vec::new();
+        let mut extensions = json!({});
+        if !instanced.is_empty() {
+            required.push("EXT_mesh_gpu_instancing");
+        }
+        if let Some(metadata) = metadata {
+            used.push("EXT_mesh_features");
+            if !instanced.is_empty() {
+                used.push("EXT_instance_features");
+            }
+            if let Some(structural_metadata) = metadata.push_property_table(&mut buffers) {
+                used.push("EXT_structural_metadata");
+                extensions["EXT_structural_metadata"] = structural_metadata;
+            }
+        }
+        Some(glb_from_buffers(
+            buffers, nodes, meshes, required, used, extensions,
+        ))
+    }
+
+    /// Build the GLB of the `nodes` and the `meshes`, that have their data in the `buffers`,
+    /// with a single grey material. The `required` extensions are required, next to
+    /// `EXT_meshopt_compression` if the buffers are compressed, and the `used` extensions are
+    /// only used. The `extensions` object is set on the root of the glTF.
+    fn glb_from_buffers(
+        buffers: Buffers,
+        nodes: Vec<Value>,
+        meshes: Vec<Value>,
+        mut required: Vec<&str>,
+        used: Vec<&str>,
+        extensions: Value,
+    ) -> Vec<u8> {
+        let mut gltf: Value = json!({
+            "asset": {"version": "2.0", "generator": "tyler"},
+            "scene": 0,
+            "scenes": [{"nodes": (0..nodes.len()).collect::<Vec<usize>>()}],
+            "nodes": nodes,
+            "meshes": meshes,
+            "materials": [{
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [0.8, 0.8, 0.8, 1.0],
+                    "metallicFactor": 0.0,
+                    "roughnessFactor": 1.0
+                },
+                "doubleSided": true
+            }],
+            "buffers": buffers.buffers_json(),
+            "accessors": buffers.accessors,
+            "bufferViews": buffers.buffer_views
+        });
+        if buffers.meshopt {
+            required.push("EXT_meshopt_compression");
+        }
+        if !required.is_empty() || !used.is_empty() {
+            gltf["extensionsUsed"] = json!([required.as_slice(), used.as_slice()].concat());
+        }
+        if !required.is_empty() {
+            gltf["extensionsRequired"] = json!(required);
+        }
+        if extensions.as_object().is_some_and(|e| !e.is_empty()) {
+            gltf["extensions"] = extensions;
+        }
+        super::preview::glb_container(&gltf, buffers.bin)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// The area of the triangles in the xy plane.
+        fn area(polygon: &[Vec<[f64; 3]>], triangles: &[[usize; 3]]) -> f64 {
+            let points: Vec<[f64; 3]> = polygon.iter().flatten().copied().collect();
+            triangles
+                .iter()
+                .map(|[a, b, c]| {
+                    let (a, b, c) = (points[*a], points[*b], points[*c]);
+                    ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])) / 2.0
+                })
+                .sum()
+        }
+
+        #[test]
+        fn test_triangulate() {
+            // An L-shape, which has a reflex vertex
+            let l_shape: Polygon = vec![vec![
+                [0.0, 0.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [2.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [1.0, 2.0, 0.0],
+                [0.0, 2.0, 0.0],
+            ]];
+            let triangles = triangulate(&l_shape);
+            assert_eq!(triangles.len(), 4);
+            assert_eq!(area(&l_shape, &triangles), 3.0);
+
+            // A square with a square hole, both clockwise, thus the triangles are clockwise
+            let with_hole: Polygon = vec![
+                vec![
+                    [0.0, 0.0, 5.0],
+                    [0.0, 4.0, 5.0],
+                    [4.0, 4.0, 5.0],
+                    [4.0, 0.0, 5.0],
+                ],
+                vec![
+                    [1.0, 1.0, 5.0],
+                    [1.0, 3.0, 5.0],
+                    [3.0, 3.0, 5.0],
+                    [3.0, 1.0, 5.0],
+                ],
+            ];
+            let triangles = triangulate(&with_hole);
+            assert_eq!(triangles.len(), 8);
+            assert_eq!(area(&with_hole, &triangles), -12.0);
+
+            // A vertical wall
+            let wall: Polygon = vec![vec![
+                [0.0, 0.0, 0.0],
+                [3.0, 0.0, 0.0],
+                [3.0, 0.0, 2.0],
+                [0.0, 0.0, 2.0],
+            ]];
+            assert_eq!(triangulate(&wall).len(), 2);
+        }
+
+        #[test]
+        fn test_feature_polygons() {
+            let feature = json!({
+                "type": "CityJSONFeature",
+                "CityObjects": {
+                    "a": {"type": "Building", "geometry": [
+                        {"type": "MultiSurface", "lod": "1.2", "boundaries": [[[0, 1, 2]]]},
+                        {"type": "Solid", "lod": "2.2", "boundaries": [[[[0, 1, 2]], [[0, 2, 3]]]]}
+                    ]},
+                    "b": {"type": "PlantCover", "geometry": [
+                        {"type": "MultiSurface", "lod": 1, "boundaries": [[[0, 1, 3]]]}
+                    ]}
+                },
+                "vertices": [[0, 0, 0], [1000, 0, 0], [1000, 1000, 0], [0, 1000, 1000]]
+            });
+            let transform = Transform {
+                scale: [0.001, 0.001, 0.001],
+                translate: [100.0, 200.0, 0.0],
+            };
+            // The highest LoD of each CityObject
+            let polygons = feature_polygons(&feature, &transform, None, None, |_| None, 2.0);
+            assert_eq!(polygons.len(), 3);
+            let buildings = vec![CityObjectType::Building];
+            let lod_1_2 = |_: &CityObjectType| Some("1.2".to_string());
+            let polygons =
+                feature_polygons(&feature, &transform, None, Some(&buildings), lod_1_2, 2.0);
+            assert_eq!(
+                polygons,
+                vec![vec![vec![
+                    [100.0, 200.0, 0.0],
+                    [101.0, 200.0, 0.0],
+                    [101.0, 201.0, 0.0]
+                ]]]
+            );
+        }
+
+        #[test]
+        fn test_glb_bytes() {
+            let polygon: Polygon = vec![vec![
+                [6378137.0, 0.0, 0.0],
+                [6378137.0, 2.0, 0.0],
+                [6378137.0, 2.0, 2.0],
+                [6378137.0, 0.0, 2.0],
+            ]];
+            let triangles = vec![triangulate(&polygon)];
+            let polygons = vec![polygon];
+            let glb = glb_bytes(
+                &polygons,
+                &triangles,
+                &[0],
+                &[],
+                &[],
+                Compression::None,
+                None,
+            )
+            .unwrap();
+            assert_eq!(&glb[0..4], b"glTF");
+            let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+            let gltf: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+            assert_eq!(gltf["accessors"][0]["count"], 4);
+            assert_eq!(gltf["accessors"][2]["count"], 6);
+            assert_eq!(
+                gltf["nodes"][0]["translation"],
+                json!([6378137.0, 1.0, -1.0])
+            );
+            assert_eq!(gltf["accessors"][3]["count"], 4);
+            assert!(glb_bytes(&[], &[], &[], &[], &[], Compression::None, None).is_none());
+
+            // With meshopt, the buffer views are in the fallback buffer
+            let glb = glb_bytes(
+                &polygons,
+                &triangles,
+                &[0],
+                &[],
+                &[],
+                Compression::Meshopt,
+                None,
+            )
+            .unwrap();
+            let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+            let gltf: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+            assert_eq!(
+                gltf["extensionsRequired"],
+                json!(["EXT_meshopt_compression"])
+            );
+            assert_eq!(gltf["buffers"][1]["byteLength"], 4 * 12 * 2 + 4 * 4);
+            let positions = &gltf["bufferViews"][0];
+            assert_eq!(positions["buffer"], 1);
+            assert_eq!(positions["byteLength"], 4 * 12);
+            let meshopt = &positions["extensions"]["EXT_meshopt_compression"];
+            assert_eq!(meshopt["byteStride"], 12);
+            assert_eq!(meshopt["count"], 4);
+            let indices = &gltf["bufferViews"][2];
+            assert_eq!(indices["buffer"], 0);
+            assert_eq!(indices["target"], 34963);
+            assert!(indices.get("extensions").is_none());
+            let bin_length =
+                u32::from_le_bytes(glb[20 + json_length..24 + json_length].try_into().unwrap());
+            assert_eq!(gltf["buffers"][0]["byteLength"], bin_length);
+
+            // Only points
+            let points = [([6378137.0, 0.0, 0.0], 0), ([6378137.0, 4.0, 2.0], 1)];
+            let glb = glb_bytes(&[], &[], &[], &[], &points, Compression::None, None).unwrap();
+            let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+            let gltf: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+            let primitive = &gltf["meshes"][0]["primitives"][0];
+            assert_eq!(primitive["mode"], 0);
+            assert_eq!(gltf["accessors"][0]["count"], 2);
+            assert_eq!(gltf["accessors"][0]["max"], json!([0.0, 1.0, 2.0]));
+            assert_eq!(
+                gltf["nodes"][0]["translation"],
+                json!([6378137.0, 1.0, -2.0])
+            );
+
+            // A template that is drawn twice
+            let template: Polygon = vec![vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]];
+            let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+            let instanced = InstancedMesh {
+                triangles: vec![triangulate(&template)],
+                polygons: vec![template],
+                transforms: vec![
+                    InstanceTransform {
+                        translation: [6378137.0, 0.0, 0.0],
+                        columns: identity,
+                    },
+                    InstanceTransform {
+                        translation: [6378137.0, 10.0, 0.0],
+                        columns: identity,
+                    },
+                ],
+                batch_ids: vec![0, 0],
+            };
+            let glb = glb_bytes(&[], &[], &[], &[instanced], &[], Compression::None, None).unwrap();
+            let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+            let gltf: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+            let node = &gltf["nodes"][0];
+            assert_eq!(node["translation"], json!([6378137.0, 0.0, -5.0]));
+            let attributes = &node["extensions"]["EXT_mesh_gpu_instancing"]["attributes"];
+            assert_eq!(
+                gltf["accessors"][attributes["ROTATION"].as_u64().unwrap() as usize]["count"],
+                2
+            );
+            assert!(gltf["meshes"][0]["primitives"][0]["attributes"]
+                .get("_BATCHID")
+                .is_none());
+            assert_eq!(
+                gltf["extensionsRequired"],
+                json!(["EXT_mesh_gpu_instancing"])
+            );
+        }
+
+        #[test]
+        fn test_feature_instances() {
+            let templates: GeometryTemplates = serde_json::from_value(json!({
+                "templates": [
+                    {"type": "MultiSurface", "lod": "1", "boundaries": [[[0, 1, 2]]]},
+                    {"type": "MultiSurface", "lod": "2", "boundaries": [[[0, 1, 2]]]}
+                ],
+                "vertices-templates": [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 1.0]]
+            }))
+            .unwrap();
+            let feature = json!({
+                "type": "CityJSONFeature",
+                "CityObjects": {
+                    "tree": {"type": "SolitaryVegetationObject", "geometry": [
+                        {"type": "GeometryInstance", "template": 0, "boundaries": [0],
+                         "transformationMatrix": [0.0, -2.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 1.0]},
+                        {"type": "GeometryInstance", "template": 1, "boundaries": [0]}
+                    ]}
+                },
+                "vertices": [[10, 20, 30]]
+            });
+            let transform = Transform {
+                scale: [1.0, 1.0, 1.0],
+                translate: [0.0, 0.0, 0.0],
+            };
+            // The instance of the highest LoD, without a matrix
+            let instances =
+                feature_instances(&feature, &transform, &templates, None, |_| None, 1.0);
+            assert_eq!(instances.len(), 1);
+            assert_eq!(instances[0].template, 1);
+            assert_eq!(instances[0].matrix[5], 1.0);
+            assert!(
+                feature_polygons(&feature, &transform, Some(&templates), None, |_| None, 1.0)
+                    .is_empty()
+            );
+
+            let lod_1 = |_: &CityObjectType| Some("1".to_string());
+            let instances = feature_instances(&feature, &transform, &templates, None, lod_1, 0.5);
+            assert_eq!(instances[0].reference, [10.0, 20.0, 15.0]);
+            let placed = instances[0].place(&template_polygons(&templates, 0)[0], 0.5);
+            assert_eq!(
+                placed,
+                vec![vec![
+                    [10.0, 20.0, 15.0],
+                    [10.0, 22.0, 15.0],
+                    [8.0, 20.0, 16.0]
+                ]]
+            );
+        }
+
+        #[test]
+        fn test_feature_points() {
+            let feature = json!({
+                "type": "CityJSONFeature",
+                "CityObjects": {
+                    "scan": {"type": "GenericCityObject", "geometry": [
+                        {"type": "MultiPoint", "lod": "0", "boundaries": [0, 2, 5]},
+                        {"type": "MultiSurface", "lod": "0", "boundaries": [[[0, 1, 2]]]}
+                    ]}
+                },
+                "vertices": [[0, 0, 0], [10, 0, 0], [10, 10, 2]]
+            });
+            let transform = Transform {
+                scale: [0.1, 0.1, 0.1],
+                translate: [5.0, 5.0, 0.0],
+            };
+            let points = feature_points(&feature, &transform, None, None, |_| None, 2.0);
+            assert_eq!(points, vec![[5.0, 5.0, 0.0], [6.0, 6.0, 0.4]]);
+            let polygons = feature_polygons(&feature, &transform, None, None, |_| None, 2.0);
+            assert_eq!(polygons.len(), 1);
+        }
+
+        #[test]
+        fn test_rotation_scale() {
+            // A rotation of 90 degrees around z, with a scale of 2
+            let columns = [[0.0, 2.0, 0.0], [-2.0, 0.0, 0.0], [0.0, 0.0, 2.0]];
+            let (q, scale) = rotation_scale(&columns).unwrap();
+            let h = 0.5_f64.sqrt();
+            for (a, b) in q.iter().zip([0.0, 0.0, h, h]) {
+                assert!((a - b).abs() < 1e-12);
+            }
+            assert_eq!(scale, [2.0, 2.0, 2.0]);
+            // A mirroring
+            let columns = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]];
+            assert_eq!(rotation_scale(&columns).unwrap().1, [1.0, 1.0, -1.0]);
+            assert!(rotation_scale(&[[0.0; 3]; 3]).is_none());
+        }
+
+        #[test]
+        fn test_b3dm() {
+            let features = vec![
+                json!({"id": "a", "CityObjects": {
+                    "a": {"type": "Building", "attributes": {"height": 10.5}},
+                    "a-0": {"type": "BuildingPart", "attributes": {"name": "part"}}
+                }}),
+                json!({"id": "b", "CityObjects": {"b": {"type": "Building"}}}),
+            ];
+            let attributes = vec!["height:float".to_string(), "name:string".to_string()];
+            let table = batch_table(&features, &attributes);
+            assert_eq!(
+                table,
+                json!({"id": ["a", "b"], "height": [10.5, null], "name": ["part", null]})
+            );
+
+            let glb = vec![1_u8; 12];
+            let b3dm = b3dm(&glb, 2, &table);
+            let header: Vec<u32> = b3dm[4..28]
+                .chunks(4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                .collect();
+            assert_eq!(&b3dm[0..4], b"b3dm");
+            assert_eq!(header[0], 1);
+            assert_eq!(header[1] as usize, b3dm.len());
+            assert_eq!(b3dm.len() % 8, 0);
+            let feature_table_end = 28 + header[2] as usize;
+            assert_eq!(feature_table_end % 8, 0);
+            let feature_table: Value =
+                serde_json::from_slice(&b3dm[28..feature_table_end]).unwrap();
+            assert_eq!(feature_table["BATCH_LENGTH"], 2);
+            let batch_table_end = feature_table_end + header[4] as usize;
+            let batch_table: Value =
+                serde_json::from_slice(&b3dm[feature_table_end..batch_table_end]).unwrap();
+            assert_eq!(batch_table, table);
+            assert_eq!(&b3dm[batch_table_end..batch_table_end + 12], &glb[..]);
+        }
+
+        #[test]
+        fn test_feature_metadata() {
+            let features = vec![
+                json!({"id": "a", "CityObjects": {
+                    "a": {"type": "Building", "attributes": {"height": 10.5, "name": "Zuid"}}
+                }}),
+                json!({"id": "b", "CityObjects": {"b": {"type": "Building"}}}),
+            ];
+            let attributes = vec!["height:float".to_string(), "name:string".to_string()];
+            let metadata = FeatureMetadata::new(&features, "building", &attributes).unwrap();
+            assert!(FeatureMetadata::new(&features, "building", &["height".to_string()]).is_err());
+            let polygons: Vec<Polygon> = vec![vec![vec![
+                [6378137.0, 0.0, 0.0],
+                [6378137.0, 1.0, 0.0],
+                [6378137.0, 0.0, 1.0],
+            ]]];
+            let triangles: Vec<Vec<[usize; 3]>> = polygons.iter().map(|p| triangulate(p)).collect();
+            let glb = glb_bytes(
+                &polygons,
+                &triangles,
+                &[1],
+                &[],
+                &[],
+                Compression::Meshopt,
+                Some(&metadata),
+            )
+            .unwrap();
+            let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+            let gltf: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+            let bin = &glb[20 + json_length + 8..];
+            let primitive = &gltf["meshes"][0]["primitives"][0];
+            assert!(primitive["attributes"].get("_BATCHID").is_none());
+            assert!(primitive["attributes"].get("_FEATURE_ID_0").is_some());
+            assert_eq!(
+                primitive["extensions"]["EXT_mesh_features"],
+                json!({"featureIds": [{"featureCount": 2, "attribute": 0, "propertyTable": 0}]})
+            );
+            assert_eq!(
+                gltf["extensionsUsed"],
+                json!([
+                    "EXT_meshopt_compression",
+                    "EXT_mesh_features",
+                    "EXT_structural_metadata"
+                ])
+            );
+            assert_eq!(
+                gltf["extensionsRequired"],
+                json!(["EXT_meshopt_compression"])
+            );
+
+            let structural_metadata = &gltf["extensions"]["EXT_structural_metadata"];
+            let properties = &structural_metadata["schema"]["classes"]["building"]["properties"];
+            assert_eq!(properties["height"]["componentType"], "FLOAT32");
+            assert_eq!(properties["name"]["noData"], "");
+            let table = &structural_metadata["propertyTables"][0];
+            assert_eq!(table["class"], "building");
+            assert_eq!(table["count"], 2);
+            let view = |property: &str, key: &str| {
+                let view = &gltf["bufferViews"]
+                    [table["properties"][property][key].as_u64().unwrap() as usize];
+                assert_eq!(view["buffer"], 0);
+                let offset = view["byteOffset"].as_u64().unwrap() as usize;
+                assert_eq!(offset % 8, 0);
+                &bin[offset..offset + view["byteLength"].as_u64().unwrap() as usize]
+            };
+            let heights: Vec<f32> = view("height", "values")
+                .chunks(4)
+                .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+                .collect();
+            assert_eq!(heights, [10.5, f32::MIN]);
+            assert_eq!(view("name", "values"), b"Zuid");
+            let offsets: Vec<u32> = view("name", "stringOffsets")
+                .chunks(4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                .collect();
+            assert_eq!(offsets, [0, 4, 4]);
+
+            // Without attributes there are only feature IDs
+            let metadata = FeatureMetadata::new(&features, "building", &[]).unwrap();
+            let glb = glb_bytes(
+                &polygons,
+                &triangles,
+                &[1],
+                &[],
+                &[],
+                Compression::None,
+                Some(&metadata),
+            )
+            .unwrap();
+            let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+            let gltf: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+            assert!(gltf.get("extensions").is_none());
+            assert_eq!(gltf["extensionsUsed"], json!(["EXT_mesh_features"]));
+            assert!(
+                gltf["meshes"][0]["primitives"][0]["extensions"]["EXT_mesh_features"]["featureIds"]
+                    [0]
+                .get("propertyTable")
+                .is_none()
+            );
+        }
+
+        #[test]
+        fn test_cmpt() {
+            let polygon: Polygon = vec![vec![
+                [6378137.0, 0.0, 0.0],
+                [6378137.0, 1.0, 0.0],
+                [6378137.0, 0.0, 1.0],
+            ]];
+            let template: Polygon = vec![vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]];
+            // A rotation of 90 degrees around z
+            let rotated = [[0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]];
+            let content = Content {
+                triangles: vec![triangulate(&polygon)],
+                polygons: vec![polygon],
+                batch_ids: vec![0],
+                instanced: vec![InstancedMesh {
+                    triangles: vec![triangulate(&template)],
+                    polygons: vec![template],
+                    transforms: vec![
+                        InstanceTransform {
+                            translation: [6378137.0, 10.0, 0.0],
+                            columns: rotated,
+                        },
+                        InstanceTransform {
+                            translation: [6378137.0, 20.0, 0.0],
+                            columns: rotated,
+                        },
+                    ],
+                    batch_ids: vec![1, 1],
+                }],
+                points: Vec::new(),
+            };
+            let table = json!({"id": ["a", "b"], "height": [10.5, 3.0]});
+            let cmpt = content.cmpt(&table, Compression::None).unwrap();
+            let u32_at = |bytes: &[u8], i: usize| {
+                u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap()) as usize
+            };
+            assert_eq!(&cmpt[0..4], b"cmpt");
+            assert_eq!(u32_at(&cmpt, 8), cmpt.len());
+            assert_eq!(u32_at(&cmpt, 12), 2);
+            assert_eq!(&cmpt[16..20], b"b3dm");
+            let i3dm = &cmpt[16 + u32_at(&cmpt, 24)..];
+            assert_eq!(&i3dm[0..4], b"i3dm");
+            assert_eq!(u32_at(i3dm, 8), i3dm.len());
+            assert_eq!(u32_at(i3dm, 28), 1);
+            let feature_table_end = 32 + u32_at(i3dm, 12);
+            let feature_table: Value =
+                serde_json::from_slice(&i3dm[32..feature_table_end]).unwrap();
+            assert_eq!(feature_table["INSTANCES_LENGTH"], 2);
+            assert_eq!(feature_table["RTC_CENTER"], json!([6378137.0, 15.0, 0.0]));
+            let binary = &i3dm[feature_table_end..feature_table_end + u32_at(i3dm, 16)];
+            let vec3 = |name: &str| {
+                let offset = feature_table[name]["byteOffset"].as_u64().unwrap() as usize;
+                binary[offset..offset + 12]
+                    .chunks(4)
+                    .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+                    .collect::<Vec<f32>>()
+            };
+            assert_eq!(vec3("POSITION"), [0.0, -5.0, 0.0]);
+            assert_eq!(vec3("NORMAL_RIGHT"), [0.0, 1.0, 0.0]);
+            assert_eq!(vec3("NORMAL_UP"), [-1.0, 0.0, 0.0]);
+            assert_eq!(vec3("SCALE_NON_UNIFORM"), [1.0, 1.0, 1.0]);
+            let batch_table_start = feature_table_end + binary.len();
+            let batch_table: Value = serde_json::from_slice(
+                &i3dm[batch_table_start..batch_table_start + u32_at(i3dm, 20)],
+            )
+            .unwrap();
+            assert_eq!(batch_table, json!({"id": ["b"], "height": [3.0]}));
+            let batch_id_offset = feature_table["BATCH_ID"]["byteOffset"].as_u64().unwrap();
+            assert_eq!(u32_at(binary, batch_id_offset as usize), 0);
+        }
+
+        /// The external geometry of a feature without geometry is in the content.
+        #[test]
+        fn test_push_external() {
+            let dir = std::env::temp_dir().join("tyler_test_push_external");
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let path_obj = dir.join("e001.obj");
+            std::fs::write(
+                &path_obj,
+                "v 85100 446400 0\nv 85110 446400 0\nv 85110 446400 10\nv 85100 446400 10\nf 1 2 3 4\n",
+            )
+            .unwrap();
+            let feature = json!({"type": "CityJSONFeature", "id": "e001", "CityObjects": {"e001": {"type": "Building", "+externalGeometry": [{"uri": "e001.obj"}]}}, "vertices": []});
+            let transformer = Proj::new_known_crs("EPSG:7415", "EPSG:4978", None).unwrap();
+            let transform = Transform {
+                scale: [0.001, 0.001, 0.001],
+                translate: [85000.0, 446300.0, 0.0],
+            };
+            let mut content = Content::new(
+                &[feature],
+                &transform,
+                None,
+                |_| Some("2.2".to_string()),
+                1.0,
+                &transformer,
+                None,
+            )
+            .unwrap();
+            assert!(content.glb(Compression::None, None).is_err());
+            let polygons = crate::external_geometry::read(&path_obj).unwrap();
+            content
+                .push_external(&polygons, 0, 2.0, &transformer)
+                .unwrap();
+            let glb = content.glb(Compression::None, None).unwrap();
+            let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+            let gltf: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+            let primitive = &gltf["meshes"][0]["primitives"][0];
+            let positions =
+                &gltf["accessors"][primitive["attributes"]["POSITION"].as_u64().unwrap() as usize];
+            assert_eq!(positions["count"], 4);
+            assert_eq!(
+                gltf["accessors"][primitive["indices"].as_u64().unwrap() as usize]["count"],
+                6
+            );
+            // The wall is 10 m wide, and 20 m high with the z scale, along the y-up of glTF
+            let extent: Vec<f64> = (0..3)
+                .map(|i| {
+                    positions["max"][i].as_f64().unwrap() - positions["min"][i].as_f64().unwrap()
+                })
+                .collect();
+            assert!((extent[1] - 20.0).abs() < 0.5, "{extent:?}");
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
+
+pub mod cityjson {
+    //! CityJSON tiles, that are merged directly from the CityJSONFeatures of the tile, thus they
+    //! do not need the conversion subprocess.
+    //! The features share the `transform` of the CityJSON metadata file, so their vertices are
+    //! only appended and the vertex indices of their geometry boundaries are offset.
+    //! Each tile has its own metadata, and the tiles are listed in an index file.
+    use std::fs::{read_to_string, File};
+    use std::io::BufWriter;
+    use std::path::{Path, PathBuf};
+
+    use serde::Serialize;
+    use serde_json::{json, Value};
+
+    use crate::proj::Proj;
+    use crate::spatial_structs::Bbox;
+
+    /// The metadata properties that are copied from the source to each tile. The other
+    /// properties, eg. the identifier or the extent, describe the whole dataset.
+    const METADATA_COPIED: [&str; 2] = ["referenceSystem", "pointOfContact"];
+
+    /// Read the CityJSON metadata file as the base of the tiles, without any CityObjects and
+    /// vertices.
+    pub fn base_from_metadata<P: AsRef<Path>>(
+        path_metadata: P,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut base: Value = serde_json::from_str(&read_to_string(path_metadata)?)?;
+        base["CityObjects"] = json!({});
+        base["vertices"] = json!([]);
+        let mut metadata = serde_json::Map::new();
+        for key in METADATA_COPIED {
+            if let Some(value) = base["metadata"].get(key) {
+                metadata.insert(key.to_string(), value.clone());
+            }
+        }
+        base["metadata"] = Value::Object(metadata);
+        // The extended metadata contains the feature counts of the whole dataset
+        if let Some(base) = base.as_object_mut() {
+            base.remove("+metadata-extended");
+        }
+        if let Some(extensions) = base["extensions"].as_object_mut() {
+            extensions.remove("MetadataExtended");
+        }
+        Ok(base)
+    }
+
+    /// The CityJSON referenceSystem of an EPSG code.
+    pub fn reference_system(epsg: u16) -> Value {
+        json!(format!("https://www.opengis.net/def/crs/EPSG/0/{epsg}"))
+    }
+
+    /// Reproject the vertices of the CityJSON object into the CRS of `epsg`, with the
+    /// `transformer`. The reprojected vertices are quantized with the same scale as the source
+    /// vertices, and the translation of the transform is set to the minimum of the reprojected
+    /// vertices. Thus the output CRS should be a projected CRS with the same unit.
+    pub fn reproject(
+        cm: &mut Value,
+        transformer: &Proj,
+        epsg: u16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let scale: Vec<f64> = (0..3)
+            .map(|i| cm["transform"]["scale"][i].as_f64().unwrap_or(1.0))
+            .collect();
+        let translate: Vec<f64> = (0..3)
+            .map(|i| cm["transform"]["translate"][i].as_f64().unwrap_or(0.0))
+            .collect();
+        let mut reprojected: Vec<[f64; 3]> = Vec::new();
+        for vertex in cm["vertices"].as_array().into_iter().flatten() {
+            let mut v = [0.0; 3];
+            for (i, c) in v.iter_mut().enumerate() {
+                *c = vertex[i].as_f64().ok_or("invalid vertex")? * scale[i] + translate[i];
+            }
+            let (x, y, z) = transformer.convert((v[0], v[1], v[2]))?;
+            reprojected.push([x, y, z]);
+        }
+        let mut translate_new = [0.0; 3];
+        if !reprojected.is_empty() {
+            for (i, t) in translate_new.iter_mut().enumerate() {
+                *t = reprojected.iter().map(|v| v[i]).fold(f64::MAX, f64::min);
+            }
+        }
+        let vertices: Vec<Value> = reprojected
+            .iter()
+            .map(|v| {
+                json!([
+                    ((v[0] - translate_new[0]) / scale[0]).round() as i64,
+                    ((v[1] - translate_new[1]) / scale[1]).round() as i64,
+                    ((v[2] - translate_new[2]) / scale[2]).round() as i64,
+                ])
+            })
+            .collect();
+        cm["vertices"] = Value::Array(vertices);
+        cm["transform"]["translate"] = json!(translate_new);
+        cm["metadata"]["referenceSystem"] = reference_system(epsg);
+        Ok(())
+    }
+
+    /// Set the metadata of a tile, which is the tile ID as the identifier and the extent of
+    /// the vertices of the tile.
+    pub fn set_tile_metadata(cm: &mut Value, tile_id: &str) {
+        cm["metadata"]["identifier"] = json!(tile_id);
+        if let Some(extent) = geographical_extent(cm) {
+            cm["metadata"]["geographicalExtent"] = json!(extent);
+        }
+    }
+
+    /// Compute the extent of the (quantized) vertices of the CityJSON object, in real-world
+    /// coordinates.
+    pub fn geographical_extent(cm: &Value) -> Option<Bbox> {
+        let scale = &cm["transform"]["scale"];
+        let translate = &cm["transform"]["translate"];
+        let mut extent: Option<Bbox> = None;
+        for vertex in cm["vertices"].as_array()? {
+            let mut v = [0.0; 3];
+            for (i, c) in v.iter_mut().enumerate() {
+                *c = vertex[i].as_f64()? * scale[i].as_f64().unwrap_or(1.0)
+                    + translate[i].as_f64().unwrap_or(0.0);
+            }
+            let e = extent.get_or_insert([v[0], v[1], v[2], v[0], v[1], v[2]]);
+            for i in 0..3 {
+                e[i] = e[i].min(v[i]);
+                e[i + 3] = e[i + 3].max(v[i]);
+            }
+        }
+        extent
+    }
+
+    /// An entry of the tile index.
+    #[derive(Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct TileIndexEntry {
+        pub id: String,
+        /// The path of the tile, relative to the index file.
+        pub path: PathBuf,
+        pub geographical_extent: Option<Bbox>,
+        pub nr_cityobjects: usize,
+    }
+
+    impl TileIndexEntry {
+        pub fn new(id: String, path: PathBuf, cm: &Value) -> Self {
+            Self {
+                id,
+                path,
+                geographical_extent: geographical_extent(cm),
+                nr_cityobjects: cm["CityObjects"].as_object().map_or(0, |cos| cos.len()),
+            }
+        }
+    }
+
+    /// Write the index of the tiles, which maps the tile IDs to the tile files.
+    pub fn write_index<P: AsRef<Path>>(
+        path: P,
+        reference_system: &Value,
+        tiles: &[TileIndexEntry],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let index = json!({
+            "referenceSystem": reference_system,
+            "tiles": tiles,
+        });
+        to_file(&index, path)
+    }
+
+    /// Merge the CityJSONFeatures into a copy of the `base` CityJSON object.
+    pub fn merge_features<P: AsRef<Path>>(
+        base: &Value,
+        feature_paths: &[P],
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut cm = base.clone();
+        let mut cityobjects = serde_json::Map::new();
+        let mut vertices: Vec<Value> = Vec::new();
+        for path in feature_paths {
+            let mut feature: Value = serde_json::from_str(&read_to_string(path.as_ref())?)?;
+            if feature["type"] != "CityJSONFeature" {
+                return Err(format!("{:?} is not a CityJSONFeature", path.as_ref()).into());
+            }
+            let offset = vertices.len() as u64;
+            if let Some(Value::Object(cos)) = feature.get_mut("CityObjects").map(Value::take) {
+                for (id, mut co) in cos {
+                    if let Some(Value::Array(geometries)) = co.get_mut("geometry") {
+                        for geometry in geometries.iter_mut() {
+                            if let Some(boundaries) = geometry.get_mut("boundaries") {
+                                offset_boundaries(boundaries, offset);
+                            }
+                        }
+                    }
+                    cityobjects.insert(id, co);
+                }
+            }
+            if let Some(Value::Array(vtx)) = feature.get_mut("vertices").map(Value::take) {
+                vertices.extend(vtx);
+            }
+        }
+        cm["CityObjects"] = Value::Object(cityobjects);
+        cm["vertices"] = Value::Array(vertices);
+        Ok(cm)
+    }
+
+    /// Add the `offset` to each vertex index in the (nested) geometry boundaries.
+    fn offset_boundaries(boundaries: &mut Value, offset: u64) {
+        match boundaries {
+            Value::Array(items) => {
+                for item in items.iter_mut() {
+                    offset_boundaries(item, offset);
+                }
+            }
+            Value::Number(n) => {
+                if let Some(i) = n.as_u64() {
+                    *boundaries = json!(i + offset);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Write the CityJSON object to a file, creating the parent directories if needed.
+    pub fn to_file<P: AsRef<Path>>(cm: &Value, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = File::create(path.as_ref())?;
+        serde_json::to_writer(BufWriter::new(file), cm)?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_merge_features() -> Result<(), Box<dyn std::error::Error>> {
+            let dir = std::env::temp_dir().join("tyler_test_merge_features");
+            std::fs::create_dir_all(&dir)?;
+            let feature = |id: &str| {
+                json!({
+                    "type": "CityJSONFeature",
+                    "id": id,
+                    "CityObjects": {id: {"type": "Building", "geometry": [
+                        {"type": "MultiSurface", "lod": "1", "boundaries": [[[0, 1, 2]]]}
+                    ]}},
+                    "vertices": [[0, 0, 0], [1, 0, 0], [0, 1, 0]]
+                })
+            };
+            let paths = [dir.join("a.city.jsonl"), dir.join("b.city.jsonl")];
+            std::fs::write(&paths[0], feature("a").to_string())?;
+            std::fs::write(&paths[1], feature("b").to_string())?;
+            let base = json!({"type": "CityJSON", "version": "2.0", "transform": {}});
+            let cm = merge_features(&base, &paths)?;
+            assert_eq!(cm["vertices"].as_array().unwrap().len(), 6);
+            assert_eq!(
+                cm["CityObjects"]["a"]["geometry"][0]["boundaries"],
+                json!([[[0, 1, 2]]])
+            );
+            assert_eq!(
+                cm["CityObjects"]["b"]["geometry"][0]["boundaries"],
+                json!([[[3, 4, 5]]])
+            );
+            assert_eq!(cm["transform"], json!({}));
+            std::fs::remove_dir_all(&dir)?;
+            Ok(())
+        }
+
+        #[test]
+        fn test_set_tile_metadata() {
+            let mut cm = json!({
+                "type": "CityJSON",
+                "transform": {"scale": [0.5, 0.5, 1.0], "translate": [100.0, 200.0, 0.0]},
+                "metadata": {"referenceSystem": "https://www.opengis.net/def/crs/EPSG/0/7415"},
+                "CityObjects": {},
+                "vertices": [[0, 4, 1], [2, 0, -1]]
+            });
+            set_tile_metadata(&mut cm, "1/0/1");
+            assert_eq!(cm["metadata"]["identifier"], "1/0/1");
+            assert_eq!(
+                cm["metadata"]["geographicalExtent"],
+                json!([100.0, 200.0, -1.0, 101.0, 202.0, 1.0])
+            );
+            cm["vertices"] = json!([]);
+            assert!(geographical_extent(&cm).is_none());
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Gzip compression of the output files, eg. a `tileset.json.gz` next to the `tileset.json`,
+//! for the hosting that serves pre-compressed files.
+//! The data is compressed into a single deflate block with the fixed Huffman codes, and the
+//! repetitions are found with a hash chain, which keeps the encoder free of dependencies. It
+//! compresses less than zlib, but the JSON of a tileset is repetitive enough. The data that
+//! does not compress, such as the meshopt compressed content, is stored in uncompressed
+//! blocks instead.
+// Copyright 2023 Balázs Dukai, Ravi Peters
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::raster::crc32;
+
+/// The maximum length of a stored block.
+const STORED_MAX_SIZE: usize = 65535;
+/// The maximum distance of a match.
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// The number of the earlier positions with the same hash that are compared for a match.
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+/// Marks an empty slot of the hash chain.
+const NONE: usize = usize::MAX;
+
+/// The base of the length codes 257-285, and the number of their extra bits.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// The base of the distance codes 0-29, and the number of their extra bits.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Compress the `data` into the gzip format (RFC 1952).
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // Deflate, without flags, modification time and extra flags, on an unknown OS
+    let mut out: Vec<u8> = vec![0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Write a gzip-compressed copy of the file at `path` next to it, with the `.gz` extension
+/// appended, eg. `tileset.json.gz`. Returns the path of the copy.
+pub fn write_copy(path: &Path) -> std::io::Result<PathBuf> {
+    let data = fs::read(path)?;
+    let mut path_gz = path.as_os_str().to_owned();
+    path_gz.push(".gz");
+    let path_gz = PathBuf::from(path_gz);
+    fs::write(&path_gz, compress(&data))?;
+    Ok(path_gz)
+}
+
+/// A raw deflate stream (RFC 1951) of a single block with the fixed Huffman codes, or of
+/// stored blocks if that is smaller.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let compressed = deflate_fixed(data);
+    let nr_stored_blocks = data.len().div_ceil(STORED_MAX_SIZE).max(1);
+    if data.len() + 5 * nr_stored_blocks < compressed.len() {
+        deflate_stored(data)
+    } else {
+        compressed
+    }
+}
+
+/// A raw deflate stream of uncompressed blocks.
+fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(data.len() + 5);
+    let mut blocks = data.chunks(STORED_MAX_SIZE).peekable();
+    if blocks.peek().is_none() {
+        // The final, empty block
+        return vec![1, 0, 0, 0xFF, 0xFF];
+    }
+    while let Some(block) = blocks.next() {
+        // The block header is padded to the byte, then the length and its complement follow
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out
+}
+
+/// A raw deflate stream of a single block with the fixed Huffman codes.
+fn deflate_fixed(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // The final block, compressed with the fixed Huffman codes
+    writer.write(1, 1);
+    writer.write(1, 2);
+    let mut chain = HashChain::new();
+    let mut i = 0;
+    while i < data.len() {
+        let (length, distance) = chain.longest_match(data, i);
+        if length >= MIN_MATCH {
+            writer.write_match(length, distance);
+            for j in i..i + length {
+                chain.insert(data, j);
+            }
+            i += length;
+        } else {
+            writer.write_symbol(data[i] as u16);
+            chain.insert(data, i);
+            i += 1;
+        }
+    }
+    // End of block
+    writer.write_symbol(256);
+    writer.finish()
+}
+
+/// The earlier positions of the 3-byte sequences, by the hash of the sequence.
+struct HashChain {
+    /// The last position of each hash.
+    head: Vec<usize>,
+    /// The previous position with the same hash, of the positions in the window.
+    previous: Vec<usize>,
+}
+
+impl HashChain {
+    fn new() -> Self {
+        Self {
+            head: vec![NONE; 1 << HASH_BITS],
+            previous: vec![NONE; WINDOW_SIZE],
+        }
+    }
+
+    fn hash(data: &[u8], i: usize) -> usize {
+        let sequence = u32::from_le_bytes([data[i], data[i + 1], data[i + 2], 0]);
+        (sequence.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], i: usize) {
+        if i + MIN_MATCH <= data.len() {
+            let hash = Self::hash(data, i);
+            self.previous[i % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = i;
+        }
+    }
+
+    /// The length and the distance of the longest match of the data at `i` in the window.
+    /// The length is 0 if there is no match.
+    fn longest_match(&self, data: &[u8], i: usize) -> (usize, usize) {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if i + MIN_MATCH > data.len() {
+            return (best_length, best_distance);
+        }
+        let max_length = MAX_MATCH.min(data.len() - i);
+        let mut candidate = self.head[Self::hash(data, i)];
+        let mut nr_compared = 0;
+        // The slot of a candidate is only reused by a position that is a window later, so
+        // the chain is valid as long as the candidates are in the window
+        while candidate != NONE && i - candidate <= WINDOW_SIZE && nr_compared < MAX_CHAIN {
+            let length = data[candidate..]
+                .iter()
+                .zip(&data[i..i + max_length])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best_length {
+                (best_length, best_distance) = (length, i - candidate);
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.previous[candidate % WINDOW_SIZE];
+            nr_compared += 1;
+        }
+        (best_length, best_distance)
+    }
+}
+
+/// Writes the bits from the least significant bit, in the order of deflate.
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    nr_bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, nr_bits: u32) {
+        self.bits |= (value as u64) << self.nr_bits;
+        self.nr_bits += nr_bits;
+        while self.nr_bits >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.nr_bits -= 8;
+        }
+    }
+
+    /// The Huffman codes are written from the most significant bit.
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// Write a literal/length symbol with its fixed Huffman code.
+    fn write_symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let code = LENGTH_BASE
+            .iter()
+            .rposition(|&base| base as usize <= length)
+            .unwrap();
+        self.write_symbol(257 + code as u16);
+        self.write(
+            (length - LENGTH_BASE[code] as usize) as u32,
+            LENGTH_EXTRA[code] as u32,
+        );
+        let code = DISTANCE_BASE
+            .iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap();
+        // The distance codes are 5 bits long
+        self.write_code(code as u32, 5);
+        self.write(
+            (distance - DISTANCE_BASE[code] as usize) as u32,
+            DISTANCE_EXTRA[code] as u32,
+        );
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nr_bits > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn inflate(stream: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        flate2::read::DeflateDecoder::new(stream)
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_deflate() {
+        let tile = r#"{"boundingVolume":{"box":[0.0,0.0,0.0,1.0,0.0,0.0,0.0,1.0,0.0,0.0,0.0,1.0]},"geometricError":0.0,"refine":"REPLACE","content":{"uri":"t/1/0/0.glb"}}"#;
+        let json: String = (0..200).map(|_| tile).collect::<Vec<_>>().join(",");
+        let mut data: Vec<u8> = json.into_bytes();
+        // Literals of the 9-bit codes and a match that is longer than the maximum
+        data.extend((0..=255u8).chain([255; 600]));
+        for input in [&b""[..], b"a", b"abcabcabc", &data] {
+            assert_eq!(inflate(&deflate(input)), input);
+            assert_eq!(inflate(&deflate_stored(input)), input);
+        }
+        assert!(deflate(&data).len() < data.len() / 10);
+        // Random bytes are stored, in more than one block
+        let mut x: u32 = 1;
+        let random: Vec<u8> = (0..100_000)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect();
+        let stream = deflate(&random);
+        assert_eq!(stream.len(), random.len() + 2 * 5);
+        assert_eq!(inflate(&stream), random);
+    }
+
+    #[test]
+    fn test_compress() {
+        let data = b"{\"asset\":{\"version\":\"1.1\"}}";
+        let gz = compress(data);
+        assert_eq!(gz[..4], [0x1F, 0x8B, 8, 0]);
+        let trailer = &gz[gz.len() - 8..];
+        assert_eq!(trailer[..4], crc32(data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        let mut out: Vec<u8> = Vec::new();
+        flate2::read::GzDecoder::new(&gz[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+    }
+}
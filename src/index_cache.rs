@@ -0,0 +1,312 @@
+//! Cache of the feature index.
+//! Indexing the features reads every CityJSONFeature file, which takes a long time for large
+//! datasets. The indexed [World] and the [QuadTree] are written to a cache file in the output
+//! directory, and the next run with `--reuse-index` loads them instead of indexing the features
+//! again, as long as the inputs and the indexing options are unchanged.
+// Copyright 2023 Balázs Dukai, Ravi Peters
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::parser::{CityObjectType, World};
+use crate::spatial_structs::{QuadTree, QuadTreeCapacity, QuadTreeDepth};
+
+/// The name of the cache file in the output directory.
+pub const INDEX_CACHE_FILE: &str = "index_cache.bincode";
+
+/// The size and the modification time of an input file. A file is considered unchanged if
+/// both are the same, the content is not compared.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileStamp {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// The options that the feature index depends on. An option that changes the world or the
+/// quadtree before they are written to the cache must be a member, otherwise a stale index is
+/// reused when the option changes.
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexParams {
+    pub grid_cellsize: f64,
+    pub object_type: Option<Vec<CityObjectType>>,
+    pub include_ids: Option<PathBuf>,
+    pub exclude_ids: Option<PathBuf>,
+    pub grid_minz: Option<i32>,
+    pub grid_maxz: Option<i32>,
+    pub origin: Option<[f64; 3]>,
+    pub extent: Option<[f64; 6]>,
+    pub clip_to_extent: bool,
+    pub grid_insert_bbox: bool,
+    pub seed: u64,
+    pub priority_attribute: Option<String>,
+    pub group_attribute: Option<String>,
+    pub feature_weights: Option<PathBuf>,
+    pub grid_extent: Option<[f64; 4]>,
+    pub grid_rectangular: bool,
+    pub grid_auto_refine: bool,
+    pub large_footprint_size: Option<f64>,
+    pub capacity: QuadTreeCapacity,
+    pub depth: QuadTreeDepth,
+}
+
+impl IndexParams {
+    /// The hash of the serialized parameters. A different hash only causes the features to be
+    /// indexed again, so it does not matter that the hasher can change between Rust versions.
+    pub fn hash(&self) -> Result<u64, bincode::Error> {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        bincode::serialize(self)?.hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+}
+
+/// Identifies the index, by the tyler version, the options that the index depends on and the
+/// stamps of the input files. The cached index is only reused if its key is equal to the key
+/// of the current run.
+///
+/// `options` - The hash of the [IndexParams].
+///
+/// `inputs` - The CityJSON metadata, the CityJSONFeature files in the order of the file names,
+/// and other input files of the indexing, such as the feature weights.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexKey {
+    pub tyler_version: String,
+    pub options: u64,
+    pub inputs: Vec<FileStamp>,
+}
+
+impl IndexKey {
+    /// Collect the stamps of the `metadata`, of the CityJSONFeature files in the `features`
+    /// directory and of the `other_inputs`. Only the file metadata is read, which is much
+    /// faster than parsing the features.
+    pub fn new(
+        params: &IndexParams,
+        metadata: &Path,
+        features: &Path,
+        other_inputs: &[&Path],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut inputs: Vec<FileStamp> = vec![FileStamp::new(metadata)?];
+        for path in WalkDir::new(features)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(World::jsonl_path)
+        {
+            inputs.push(FileStamp::new(&path)?);
+        }
+        for path in other_inputs {
+            inputs.push(FileStamp::new(path)?);
+        }
+        Ok(Self {
+            tyler_version: clap::crate_version!().to_string(),
+            options: params.hash()?,
+            inputs,
+        })
+    }
+}
+
+/// Write the key, the world and the quadtree into the cache file at `path`. The key is
+/// written first, so that a stale cache is recognized without reading the index.
+pub fn write(
+    path: &Path,
+    key: &IndexKey,
+    world: &World,
+    quadtree: &QuadTree,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Written to a temporary file first, so that an interrupted write does not leave a
+    // truncated cache behind
+    let path_tmp = path.with_extension("bincode.tmp");
+    let mut writer = BufWriter::new(File::create(&path_tmp)?);
+    bincode::serialize_into(&mut writer, key)?;
+    bincode::serialize_into(&mut writer, world)?;
+    bincode::serialize_into(&mut writer, quadtree)?;
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(path_tmp, path)?;
+    Ok(())
+}
+
+/// Read the world and the quadtree from the cache file at `path`, if the file exists and its
+/// key is equal to `key`. Returns `None` if the index needs to be built again.
+pub fn read(
+    path: &Path,
+    key: &IndexKey,
+) -> Result<Option<(World, QuadTree)>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        info!("There is no cached index in {path:?}");
+        return Ok(None);
+    }
+    let mut reader = BufReader::new(File::open(path)?);
+    // The key of a cache of another tyler version might not be readable
+    let key_cached: Option<IndexKey> = bincode::deserialize_from(&mut reader).ok();
+    match key_cached {
+        Some(key_cached) if key_cached == *key => {
+            let world: World = bincode::deserialize_from(&mut reader)?;
+            let quadtree: QuadTree = bincode::deserialize_from(&mut reader)?;
+            Ok(Some((world, quadtree)))
+        }
+        Some(key_cached) if key_cached.tyler_version != key.tyler_version => {
+            info!(
+                "The cached index was created by tyler {}, indexing the features again",
+                key_cached.tyler_version
+            );
+            Ok(None)
+        }
+        Some(key_cached) if key_cached.options != key.options => {
+            info!("The indexing options changed since the cached index was created, indexing the features again");
+            Ok(None)
+        }
+        Some(_) => {
+            info!("The input files changed since the cached index was created, indexing the features again");
+            Ok(None)
+        }
+        None => {
+            info!("The cached index cannot be read, indexing the features again");
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial_structs::WeightedCriteria;
+
+    fn params() -> IndexParams {
+        IndexParams {
+            grid_cellsize: 20.0,
+            object_type: None,
+            include_ids: None,
+            exclude_ids: None,
+            grid_minz: None,
+            grid_maxz: None,
+            origin: None,
+            extent: None,
+            clip_to_extent: false,
+            grid_insert_bbox: false,
+            seed: 0,
+            priority_attribute: None,
+            group_attribute: None,
+            feature_weights: None,
+            grid_extent: None,
+            grid_rectangular: false,
+            grid_auto_refine: false,
+            large_footprint_size: None,
+            capacity: QuadTreeCapacity::Vertices(1000),
+            depth: QuadTreeDepth::default(),
+        }
+    }
+
+    /// Changing any of the parameters changes the hash, so the cached index is not reused.
+    #[test]
+    fn test_index_params_hash() {
+        let changes: Vec<fn(&mut IndexParams)> = vec![
+            |p| p.grid_cellsize = 50.0,
+            |p| p.object_type = Some(vec![CityObjectType::Building]),
+            |p| p.include_ids = Some(PathBuf::from("include.txt")),
+            |p| p.exclude_ids = Some(PathBuf::from("exclude.txt")),
+            |p| p.grid_minz = Some(-5),
+            |p| p.grid_maxz = Some(300),
+            |p| p.origin = Some([1.0, 2.0, 3.0]),
+            |p| p.extent = Some([0.0, 0.0, 0.0, 1.0, 1.0, 1.0]),
+            |p| p.clip_to_extent = true,
+            |p| p.grid_insert_bbox = true,
+            |p| p.seed = 1,
+            |p| p.priority_attribute = Some("height".to_string()),
+            |p| p.group_attribute = Some("type".to_string()),
+            |p| p.feature_weights = Some(PathBuf::from("weights.csv")),
+            |p| p.grid_extent = Some([0.0, 0.0, 1.0, 1.0]),
+            |p| p.grid_rectangular = true,
+            |p| p.grid_auto_refine = true,
+            |p| p.large_footprint_size = Some(100.0),
+            |p| p.capacity = QuadTreeCapacity::Objects(1000),
+            |p| p.capacity = QuadTreeCapacity::Vertices(2000),
+            |p| {
+                p.capacity = QuadTreeCapacity::Weighted(1000, WeightedCriteria::default());
+            },
+            |p| p.depth.min = 2,
+            |p| p.depth.max = Some(10),
+        ];
+        let hash = params().hash().unwrap();
+        assert_eq!(params().hash().unwrap(), hash);
+        for change in changes {
+            let mut changed = params();
+            change(&mut changed);
+            assert_ne!(changed.hash().unwrap(), hash, "{changed:?}");
+        }
+    }
+
+    #[test]
+    fn test_index_cache() {
+        let fixture = crate::fixtures::TilingFixture::load("tiling_sample", 20.0);
+        let world = &fixture.world;
+        let quadtree = QuadTree::from_world(
+            world,
+            QuadTreeCapacity::Vertices(1000),
+            QuadTreeDepth::default(),
+        );
+        let key = IndexKey::new(
+            &params(),
+            &world.path_metadata,
+            &world.path_features_root,
+            &[],
+        )
+        .unwrap();
+        // The metadata and the features
+        assert!(key.inputs.len() > 1);
+        assert_eq!(key.inputs[0].path, world.path_metadata);
+
+        let dir = std::env::temp_dir().join(format!("tyler-index-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(INDEX_CACHE_FILE);
+        assert!(read(&path, &key).unwrap().is_none());
+        write(&path, &key, world, &quadtree).unwrap();
+        let (world_cached, quadtree_cached) = read(&path, &key).unwrap().unwrap();
+        assert_eq!(world_cached.grid.length, world.grid.length);
+        assert_eq!(world_cached.features.len(), world.features.len());
+        assert_eq!(
+            quadtree_cached.collect_leaves().len(),
+            quadtree.collect_leaves().len()
+        );
+
+        // Another option or a changed input invalidates the cache
+        let other_options = IndexKey {
+            options: key.options + 1,
+            ..key.clone()
+        };
+        assert!(read(&path, &other_options).unwrap().is_none());
+        let mut other_inputs = key.clone();
+        other_inputs.inputs[1].size += 1;
+        assert!(read(&path, &other_inputs).unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
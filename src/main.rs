@@ -12,21 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 mod cli;
+mod daemon;
+mod external_geometry;
+#[cfg(test)]
+mod fixtures;
 mod formats;
+mod gzip;
+mod index_cache;
 mod parser;
 mod proj;
+mod raster;
+mod report;
 mod spatial_structs;
+mod tile_index;
+mod tools;
 
 use core::time::Duration;
+use std::collections::{BTreeMap, HashSet};
 use std::env;
+use std::ffi::OsString;
 use std::fs;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::time::Instant;
 
-use crate::formats::cesium3dtiles::{Tile, TileId};
+use crate::formats::cesium3dtiles::{InteriorLod, Tile, TileId};
 use clap::Parser;
 use log::{debug, info, log_enabled, warn, Level};
+use morton_encoding::morton_encode;
 use rayon::prelude::*;
 use subprocess::{Exec, Redirection};
 
@@ -37,11 +52,13 @@ struct SubprocessConfig {
     script: PathBuf,
     timeout: Option<Duration>,
     verbose: bool,
+    post_process: Vec<String>,
 }
 
 #[derive(Debug, Clone, clap::ValueEnum, Eq, PartialEq)]
 #[clap(rename_all = "lower")]
 pub enum Formats {
+    #[value(name = "3dtiles")]
     _3DTiles,
     CityJSON,
 }
@@ -55,6 +72,84 @@ impl ToString for Formats {
     }
 }
 
+/// The formats of the debug exports.
+#[derive(Debug, Clone, Copy, clap::ValueEnum, Eq, PartialEq)]
+pub enum DebugExport {
+    /// The quadtree nodes as GeoJSON polygons in WGS84, for a web map.
+    Geojson,
+}
+
+/// Assigns a distinct color to the content of each tile or each tileset level, for visually
+/// checking the tile boundaries, the refinement and the LoD switching.
+#[derive(Debug, Clone, Copy, clap::ValueEnum, Eq, PartialEq)]
+pub enum DebugColors {
+    Tile,
+    Level,
+}
+
+impl DebugColors {
+    /// Returns the hex rgb-color (eg. #FF0000) for the content of the tile.
+    /// The `seed` shifts the colors, so that a different seed gives a different palette.
+    fn color(&self, nodeid: &spatial_structs::QuadTreeNodeId, seed: u64) -> String {
+        let key: u64 = match self {
+            DebugColors::Tile => {
+                (nodeid.x as u64).wrapping_mul(73_856_093)
+                    ^ (nodeid.y as u64).wrapping_mul(19_349_663)
+                    ^ (nodeid.level as u64).wrapping_mul(83_492_791)
+            }
+            DebugColors::Level => nodeid.level as u64,
+        };
+        // Stepping the hue with the golden ratio keeps the consecutive keys far apart on the
+        // color wheel.
+        let hue = (key.wrapping_add(seed) as f64 * 0.618_033_988_749_895).fract();
+        let (s, v) = (0.65, 0.95);
+        let h6 = hue * 6.0;
+        let f = h6.fract();
+        let (p, q, t) = (v * (1.0 - s), v * (1.0 - f * s), v * (1.0 - (1.0 - f) * s));
+        let (r, g, b) = match h6 as u8 {
+            0 => (v, t, p),
+            1 => (q, v, p),
+            2 => (p, v, t),
+            3 => (p, q, v),
+            4 => (t, p, v),
+            _ => (v, p, q),
+        };
+        format!(
+            "#{:02X}{:02X}{:02X}",
+            (r * 255.0).round() as u8,
+            (g * 255.0).round() as u8,
+            (b * 255.0).round() as u8
+        )
+    }
+}
+
+/// The number of times that --grid-auto-refine halves the grid cell size at most.
+const MAX_GRID_REFINEMENTS: usize = 4;
+
+/// The geof arguments that select the LoD for each CityObject type.
+const GEOF_LOD_ARGS: [&str; 20] = [
+    "lodBuilding",
+    "lodBuildingPart",
+    "lodBuildingInstallation",
+    "lodTINRelief",
+    "lodRoad",
+    "lodRailway",
+    "lodTransportSquare",
+    "lodWaterBody",
+    "lodPlantCover",
+    "lodSolitaryVegetationObject",
+    "lodLandUse",
+    "lodCityFurniture",
+    "lodBridge",
+    "lodBridgePart",
+    "lodBridgeInstallation",
+    "lodBridgeConstructionElement",
+    "lodTunnel",
+    "lodTunnelPart",
+    "lodTunnelInstallation",
+    "lodGenericCityObject",
+];
+
 #[derive(Default, Debug)]
 struct DebugData {
     world: Option<PathBuf>,
@@ -62,6 +157,151 @@ struct DebugData {
     tiles_results: Option<PathBuf>,
 }
 
+/// Does the content of the tile contain a feature with priority, see [parser::Feature].
+fn tile_has_priority(
+    world: &parser::World,
+    quadtree: &spatial_structs::QuadTree,
+    tile_index: Option<&tile_index::TileIndex>,
+    tile: &Tile,
+) -> bool {
+    let is_priority = |fid: &usize| world.features[*fid].priority;
+    match tile_index {
+        Some(tile_index) => tile_index.tiles[tile.id.x]
+            .feature_ids
+            .iter()
+            .any(is_priority),
+        None => quadtree.node(&(&tile.id).into()).is_some_and(|node| {
+            node.cells()
+                .into_iter()
+                .any(|cellid| world.grid.cell(cellid).feature_ids.iter().any(is_priority))
+        }),
+    }
+}
+
+/// The statistics of the features in the content of the tile, for the tile metadata.
+fn tile_content_stats(
+    world: &parser::World,
+    quadtree: &spatial_structs::QuadTree,
+    tile_index: Option<&tile_index::TileIndex>,
+    tile: &Tile,
+) -> Option<formats::cesium3dtiles::TileContentStats> {
+    let fids: Vec<usize> = match tile_index {
+        Some(tile_index) => tile_index.tiles[tile.id.x].feature_ids.clone(),
+        None => tile_features(world, quadtree.node(&(&tile.id).into())?),
+    };
+    let mut stats = formats::cesium3dtiles::TileContentStats {
+        nr_features: fids.len(),
+        ..Default::default()
+    };
+    for fid in &fids {
+        let feature = &world.features[*fid];
+        for cotype in &feature.cityobject_types {
+            *stats
+                .cityobject_types
+                .entry(String::from(cotype.clone()))
+                .or_default() += 1;
+        }
+        let bbox = feature.bbox_qc.to_bbox(&world.transform, None, None);
+        let [min, max] = stats.height.get_or_insert([bbox[2], bbox[5]]);
+        *min = min.min(bbox[2]);
+        *max = max.max(bbox[5]);
+    }
+    Some(stats)
+}
+
+/// The content group of the tile, which is the most common group value of its features, see
+/// [parser::Feature]. The ties are broken by the order of the values.
+fn tile_group(
+    world: &parser::World,
+    quadtree: &spatial_structs::QuadTree,
+    tile_index: Option<&tile_index::TileIndex>,
+    tile: &Tile,
+) -> Option<String> {
+    let fids: Vec<usize> = match tile_index {
+        Some(tile_index) => tile_index.tiles[tile.id.x].feature_ids.clone(),
+        None => quadtree
+            .node(&(&tile.id).into())
+            .map(|node| tile_features(world, node))
+            .unwrap_or_default(),
+    };
+    let mut counts: BTreeMap<&String, usize> = BTreeMap::new();
+    for fid in &fids {
+        if let Some(group) = &world.features[*fid].group {
+            *counts.entry(group).or_default() += 1;
+        }
+    }
+    let max_count = counts.values().max()?;
+    counts
+        .iter()
+        .find(|(_, count)| *count == max_count)
+        .map(|(group, _)| group.to_string())
+}
+
+/// The `bbox` enlarged with the bounding box of the features.
+fn features_bbox(
+    world: &parser::World,
+    fids: &[usize],
+    bbox: spatial_structs::Bbox,
+) -> spatial_structs::Bbox {
+    fids.iter().fold(bbox, |mut bbox, fid| {
+        let b = world.features[*fid]
+            .bbox_qc
+            .to_bbox(&world.transform, None, None);
+        for i in 0..3 {
+            bbox[i] = bbox[i].min(b[i]);
+            bbox[i + 3] = bbox[i + 3].max(b[i + 3]);
+        }
+        bbox
+    })
+}
+
+/// The features of a tile, ordered by the Morton-code of their centroid.
+/// A feature that is in several cells of the tile is only returned once.
+/// The ordering keeps the features that are close to each other also close in the content,
+/// which improves the compression and makes the content independent of the directory order.
+fn tile_features(world: &parser::World, qtree_node: &spatial_structs::QuadTree) -> Vec<usize> {
+    let mut fids: Vec<usize> = qtree_node.feature_ids(&world.grid);
+    // The quantized coordinates can be negative, so we encode them relative to the minimum
+    let min_x = fids
+        .iter()
+        .map(|fid| world.features[*fid].centroid_qc[0])
+        .min()
+        .unwrap_or_default();
+    let min_y = fids
+        .iter()
+        .map(|fid| world.features[*fid].centroid_qc[1])
+        .min()
+        .unwrap_or_default();
+    fids.sort_by_cached_key(|fid| {
+        let [x, y] = world.features[*fid].centroid_qc;
+        // Same as in QuadTree::from_grid, morton_encode needs [y,x] for creating a Z-curve.
+        let mc = morton_encode([(y - min_y) as u64, (x - min_x) as u64]);
+        (mc, *fid)
+    });
+    fids
+}
+
+/// Write the path as a line, without a lossy conversion of the non-UTF8 paths on Unix, where
+/// the paths are bytes. On Windows the paths are written as UTF-8.
+fn write_path_line<W: Write>(writer: &mut W, path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        writer.write_all(path.as_os_str().as_bytes())?;
+    }
+    #[cfg(not(unix))]
+    writer.write_all(path.to_string_lossy().as_bytes())?;
+    writer.write_all(b"\n")
+}
+
+/// Format a `--name=path` argument for geof, without a lossy conversion of the path.
+/// The path is made absolute, because geof runs in the features root directory.
+fn path_arg(name: &str, path: &Path) -> OsString {
+    let mut arg = OsString::from(format!("--{name}="));
+    arg.push(std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()));
+    arg
+}
+
 /// Write the list of feature paths for a tile into a text file, instead of passing
 /// super long paths-string to the subprocess, because with very long arguments we can
 /// get an 'Argument list too long' error.
@@ -69,7 +309,7 @@ struct DebugData {
 fn write_inputs(
     world: &parser::World,
     path_features_input_dir: &Path,
-    qtree_node: &spatial_structs::QuadTree,
+    fids: &[usize],
     file_name: &str,
 ) -> PathBuf {
     let path_features_input_file = path_features_input_dir
@@ -88,30 +328,545 @@ fn write_inputs(
         )
     });
     let mut feature_input = BufWriter::new(_fi_file);
-    for cellid in qtree_node.cells() {
-        let cell = world.grid.cell(cellid);
-        for fid in cell.feature_ids.iter() {
-            let fp = world.features[*fid]
-                .path_jsonl
-                .clone()
-                .into_os_string()
-                .into_string()
-                .unwrap();
-            writeln!(feature_input, "{}", fp)
-                .expect("should be able to write feature path to the input file");
-        }
+    // The feature paths are relative to the features root, which is the working directory of
+    // geof
+    for &fid in fids {
+        write_path_line(&mut feature_input, &world.features[fid].path_jsonl)
+            .expect("should be able to write feature path to the input file");
     }
     path_features_input_file
 }
 
+/// Write the features of each tile into a CityJSON file in the `output_dir`. The `tiles` are
+/// the tile IDs with their features, either the leaves of the quadtree or the tiles of the
+/// tile index.
+/// The files are named by the tile ID, the same way as the 3D Tiles content, and they are
+/// listed in the `index.json` file.
+/// If `output_crs` is set, the tiles are reprojected into it.
+/// Returns the number of tiles that were written.
+fn export_cityjson(
+    world: &parser::World,
+    tiles: Vec<(String, Vec<usize>)>,
+    output_dir: &Path,
+    output_crs: Option<u16>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let base = formats::cityjson::base_from_metadata(&world.path_metadata)?;
+    let crs_from = format!("EPSG:{}", world.crs.to_epsg()?);
+    let reference_system = match output_crs {
+        Some(epsg) => {
+            // Fail early if PROJ does not know the transformation
+            proj::Proj::new_known_crs(&crs_from, &format!("EPSG:{epsg}"), None)?;
+            formats::cityjson::reference_system(epsg)
+        }
+        None => base["metadata"]["referenceSystem"].clone(),
+    };
+    let results: Vec<(String, Result<formats::cityjson::TileIndexEntry, String>)> = tiles
+        .into_par_iter()
+        .map(|(tileid, fids)| {
+            let path = PathBuf::from(format!("{tileid}.city.json"));
+            let feature_paths: Vec<PathBuf> = fids
+                .into_iter()
+                .map(|fid| world.feature_path(fid))
+                .collect();
+            let res = formats::cityjson::merge_features(&base, &feature_paths)
+                .and_then(|mut cm| {
+                    if let Some(epsg) = output_crs {
+                        // The transformer cannot be shared between the threads
+                        let transformer =
+                            proj::Proj::new_known_crs(&crs_from, &format!("EPSG:{epsg}"), None)?;
+                        formats::cityjson::reproject(&mut cm, &transformer, epsg)?;
+                    }
+                    formats::cityjson::set_tile_metadata(&mut cm, &tileid);
+                    formats::cityjson::to_file(&cm, output_dir.join(&path))?;
+                    Ok(formats::cityjson::TileIndexEntry::new(
+                        tileid.clone(),
+                        path,
+                        &cm,
+                    ))
+                })
+                .map_err(|e| e.to_string());
+            (tileid, res)
+        })
+        .collect();
+    let mut index: Vec<formats::cityjson::TileIndexEntry> = Vec::with_capacity(results.len());
+    for (tileid, res) in results {
+        match res {
+            Ok(entry) => index.push(entry),
+            Err(e) => warn!("Failed to write the CityJSON tile {tileid}: {e}"),
+        }
+    }
+    formats::cityjson::write_index(output_dir.join("index.json"), &reference_system, &index)?;
+    Ok(index.len())
+}
+
+/// Write the preview content of a tile, which is the wireframe of the bounding boxes of the
+/// features in the tile.
+fn write_preview(
+    world: &parser::World,
+    fids: &[usize],
+    tile: Tile,
+    output_file: &Path,
+    z_scale: f64,
+    ecef: bool,
+) -> (Option<Tile>, Option<report::TileReport>) {
+    let start = Instant::now();
+    let mut tile_report = report::TileReport {
+        tile_id: tile.id.to_string(),
+        ..Default::default()
+    };
+    let bboxes: Vec<spatial_structs::Bbox> = fids
+        .iter()
+        .map(|fid| {
+            let bbox = world.features[*fid]
+                .bbox_qc
+                .to_bbox(&world.transform, None, None);
+            spatial_structs::bbox_scale_z(&bbox, z_scale)
+        })
+        .collect();
+    let res = world.crs.to_epsg().and_then(|epsg| {
+        let transformer = proj::Proj::new_known_crs(&format!("EPSG:{epsg}"), "EPSG:4978", None)?;
+        formats::preview::write_bbox_glb(output_file, &bboxes, &transformer, ecef)
+    });
+    tile_report.duration_ms = start.elapsed().as_millis();
+    match res {
+        Ok(content_size) => {
+            tile_report.success = true;
+            tile_report.content_size = Some(content_size);
+            (None, Some(tile_report))
+        }
+        Err(e) => {
+            warn!(
+                "Failed to write the preview content of tile {}: {}",
+                &tile.id, e
+            );
+            (Some(tile), Some(tile_report))
+        }
+    }
+}
+
+/// The options of the native content writer.
+struct NativeOptions<'a> {
+    z_scale: f64,
+    /// The metadata class of the features in the glTF content.
+    metadata_class: &'a str,
+    /// The CityObject attributes of the features, in the property table of the glTF content,
+    /// or in the batch table of the b3dm.
+    attributes: &'a [String],
+    /// Wrap the GLB into a b3dm, see [formats::gltf::b3dm].
+    b3dm: bool,
+    /// Write the GeometryInstances with `EXT_mesh_gpu_instancing`, or as i3dm in a cmpt
+    /// together with the b3dm.
+    gpu_instancing: bool,
+    compression: formats::gltf::Compression,
+}
+
+/// Write the content of a tile with the native glTF writer, see [formats::gltf::Content].
+fn write_native<F>(
+    world: &parser::World,
+    fids: &[usize],
+    tile: Tile,
+    output_file: &Path,
+    lod_of: F,
+    options: &NativeOptions,
+) -> (Option<Tile>, Option<report::TileReport>)
+where
+    F: Fn(&parser::CityObjectType) -> Option<String>,
+{
+    let start = Instant::now();
+    let mut tile_report = report::TileReport {
+        tile_id: tile.id.to_string(),
+        ..Default::default()
+    };
+    let feature_paths: Vec<PathBuf> = fids
+        .iter()
+        .map(|fid| {
+            world
+                .path_features_root
+                .join(&world.features[*fid].path_jsonl)
+        })
+        .collect();
+    let res = world.crs.to_epsg().and_then(|epsg| {
+        let transformer = proj::Proj::new_known_crs(&format!("EPSG:{epsg}"), "EPSG:4978", None)?;
+        let features = formats::gltf::read_features(&feature_paths)?;
+        let gpu = options.gpu_instancing;
+        let templates = world.geometry_templates.as_ref();
+        let instancing = templates.map(|templates| formats::gltf::Instancing { templates, gpu });
+        let mut geometry = formats::gltf::Content::new(
+            &features,
+            &world.transform,
+            world.cityobject_types.as_ref(),
+            lod_of,
+            options.z_scale,
+            &transformer,
+            instancing.as_ref(),
+        )?;
+        for (batch_id, fid) in fids.iter().enumerate() {
+            // The URLs are not fetched, they are reported before the conversion
+            for path in world.features[*fid]
+                .external_geometry
+                .iter()
+                .filter(|uri| !parser::ExternalGeometry::is_url(uri))
+            {
+                match external_geometry::read(Path::new(path)) {
+                    Ok(polygons) => geometry.push_external(
+                        &polygons,
+                        batch_id as u32,
+                        options.z_scale,
+                        &transformer,
+                    )?,
+                    Err(e) => warn!("Failed to read the external geometry {path}: {e}"),
+                }
+            }
+        }
+        let content = if options.b3dm {
+            let batch_table = formats::gltf::batch_table(&features, options.attributes);
+            if gpu {
+                geometry.cmpt(&batch_table, options.compression)?
+            } else {
+                let glb = geometry.glb(options.compression, None)?;
+                formats::gltf::b3dm(&glb, features.len(), &batch_table)
+            }
+        } else {
+            let metadata = formats::gltf::FeatureMetadata::new(
+                &features,
+                options.metadata_class,
+                options.attributes,
+            )?;
+            geometry.glb(options.compression, Some(&metadata))?
+        };
+        fs::write(output_file, &content)?;
+        Ok(content.len() as u64)
+    });
+    tile_report.duration_ms = start.elapsed().as_millis();
+    match res {
+        Ok(content_size) => {
+            tile_report.success = true;
+            tile_report.content_size = Some(content_size);
+            (None, Some(tile_report))
+        }
+        Err(e) => {
+            warn!("Failed to write the content of tile {}: {}", &tile.id, e);
+            (Some(tile), Some(tile_report))
+        }
+    }
+}
+
+/// The partitions of the features that the tileset is generated from.
+struct TilesetSources<'a> {
+    world: &'a parser::World,
+    quadtree: &'a spatial_structs::QuadTree,
+    tile_index: Option<&'a tile_index::TileIndex>,
+    partition_tree: Option<&'a dyn spatial_structs::PartitionTree>,
+    /// The tile index is a flat grid of cells.
+    flat_grid: bool,
+    /// The large features that are the content of the interior tiles, for
+    /// --large-feature-policy promote.
+    promoted: &'a spatial_structs::PromotedFeatures,
+}
+
+/// Generate the tileset from the `sources` and apply the tileset options of the `cli`. The
+/// tileset is generated again when the oversized tiles are split, therefore all the options
+/// that change the tileset are applied here. Returns the tileset and the LoD of the content of
+/// the interior tiles.
+fn build_tileset(
+    sources: &TilesetSources,
+    cli: &crate::cli::Cli,
+    lod_leaf: &str,
+    metadata_class: &str,
+) -> Result<(formats::cesium3dtiles::Tileset, Option<InteriorLod>), Box<dyn std::error::Error>> {
+    let TilesetSources {
+        world,
+        quadtree,
+        tile_index,
+        partition_tree,
+        flat_grid,
+        promoted,
+    } = *sources;
+    let geometric_error_above_leaf = cli.geometric_error_above_leaf.unwrap();
+    let mut tileset = match (tile_index, partition_tree) {
+        (_, Some(tree)) => {
+            info!("Generating 3D Tiles tileset from the partition tree");
+            formats::cesium3dtiles::Tileset::from_partition_tree(
+                tree,
+                world,
+                geometric_error_above_leaf,
+                cli.z_scale,
+            )
+        }
+        (Some(_), None) if flat_grid => {
+            info!("Generating a flat 3D Tiles tileset from the grid");
+            formats::cesium3dtiles::Tileset::from_grid(
+                world,
+                geometric_error_above_leaf,
+                cli.z_scale,
+            )
+        }
+        (Some(tile_index), None) => {
+            info!("Generating a flat 3D Tiles tileset from the tile index");
+            formats::cesium3dtiles::Tileset::from_tile_index(
+                tile_index,
+                world,
+                geometric_error_above_leaf,
+                cli.z_scale,
+            )
+        }
+        (None, None) => {
+            info!("Generating 3D Tiles tileset");
+            formats::cesium3dtiles::Tileset::from_quadtree(
+                quadtree,
+                world,
+                &formats::cesium3dtiles::TilesetOptions {
+                    geometric_error_above_leaf,
+                    geometric_error_scheme: cli.geometric_error_scheme,
+                    cellsize: cli.grid_cellsize.unwrap(),
+                    minz: cli.grid_minz,
+                    maxz: cli.grid_maxz,
+                    content_bv_from_tile: cli.cesium3dtiles_content_bv_from_tile,
+                    content_add_bv: cli.cesium3dtiles_content_add_bv,
+                    z_scale: cli.z_scale,
+                    tight_bounds: cli.tight_bounds,
+                },
+            )
+        }
+    };
+    if cli.refine != formats::cesium3dtiles::Refinement::Replace {
+        tileset.set_refine(cli.refine);
+    }
+    if let Some(lod_interior) = &cli.lod_interior {
+        info!(
+            "Adding content with LoD {} to the interior tiles on the {} levels above the {}",
+            lod_interior,
+            cli.lod_interior_levels,
+            if cli.lod_interior_adaptive {
+                "leaves"
+            } else {
+                "deepest level"
+            }
+        );
+        tileset.add_content(Some(cli.lod_interior_levels), cli.lod_interior_adaptive);
+    }
+    if let Some(spatial_structs::LargeFeaturePolicy::Promote) = cli.large_feature_policy {
+        let node_id = |tile: &Tile| spatial_structs::QuadTreeNodeId::from(&tile.id);
+        let nr_tiles = tileset.add_promoted_content(
+            |tile| promoted.nodes.contains_key(&node_id(tile)),
+            |tile| {
+                quadtree.node(&node_id(tile)).is_some_and(|node| {
+                    tile_features(world, node)
+                        .iter()
+                        .all(|fid| promoted.feature_ids.contains(fid))
+                })
+            },
+        );
+        info!(
+            "Promoted {} large features to the content of {nr_tiles} interior tiles",
+            promoted.feature_ids.len()
+        );
+    }
+    set_root_options(
+        &mut tileset,
+        cli.root_geometric_error_max,
+        cli.root_padding,
+        cli.root_content,
+    );
+    if cli.tile_naming != spatial_structs::TileNaming::LevelXY {
+        tileset.rename_content(cli.tile_naming, world.grid.quadtree_max_level());
+    }
+    let lod_interior = interior_lod(
+        &tileset,
+        cli.lod_interior.as_ref(),
+        &cli.lod_interior_cascade,
+        cli.lod_interior_adaptive,
+    );
+    // The implicit tiles cannot have tile metadata without a metadata subtree
+    if !cli.cesium3dtiles_implicit {
+        tileset.add_lod_metadata(lod_leaf, lod_interior.as_ref(), cli.content_lod_names);
+        if cli.tile_metadata_stats {
+            let nr_tiles = tileset
+                .add_tile_stats(|tile| tile_content_stats(world, quadtree, tile_index, tile));
+            info!("Added the content statistics to the metadata of {nr_tiles} tiles");
+        }
+    }
+    if let Some(attribute) = &cli.priority_attribute {
+        let nr_tiles =
+            tileset.add_priority_group(|tile| tile_has_priority(world, quadtree, tile_index, tile));
+        info!("{nr_tiles} tiles contain features with the priority attribute '{attribute}'");
+    }
+    if let Some(attribute) = &cli.group_attribute {
+        let nr_groups = tileset.add_attribute_groups(attribute, |tile| {
+            tile_group(world, quadtree, tile_index, tile)
+        });
+        info!("Created {nr_groups} content groups from the attribute '{attribute}'");
+    }
+    if !metadata_class.is_empty() {
+        tileset.add_feature_class(
+            metadata_class,
+            cli.object_attribute.as_deref().unwrap_or_default(),
+        )?;
+    }
+    tileset.set_version(cli.tileset_version);
+    tileset.convert_bounding_volumes(cli.bounding_volume)?;
+    Ok((tileset, lod_interior))
+}
+
+/// Apply the --root-* options to the tileset.
+fn set_root_options(
+    tileset: &mut formats::cesium3dtiles::Tileset,
+    geometric_error_max: Option<f64>,
+    padding: Option<f64>,
+    root_content: bool,
+) {
+    if let Some(max) = geometric_error_max {
+        tileset.limit_geometric_error(max);
+    }
+    if let Some(padding) = padding {
+        tileset.pad_root(padding);
+    }
+    if root_content && !tileset.add_root_content() {
+        warn!("The root tile is a leaf, it has content already");
+    }
+}
+
+/// The LoD of the content of the interior tiles of the `tileset`, if there is a `lod` for the
+/// interior tiles.
+fn interior_lod(
+    tileset: &formats::cesium3dtiles::Tileset,
+    lod: Option<&String>,
+    cascade: &[String],
+    adaptive: bool,
+) -> Option<InteriorLod> {
+    lod.map(|lod| InteriorLod {
+        lod: lod.clone(),
+        cascade: cascade.to_vec(),
+        levels: tileset.interior_levels(adaptive),
+    })
+}
+
+/// Write the feature table of a tile, see [parser::World::feature_table].
+fn write_feature_table(
+    world: &parser::World,
+    fids: &[usize],
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let table = world.feature_table(fids)?;
+    let file = File::create(path)?;
+    serde_json::to_writer(
+        BufWriter::new(file),
+        &serde_json::json!({ "features": table }),
+    )?;
+    Ok(())
+}
+
+/// Write the offset of the OBJ content of a tile next to it (`<content>.offset.json`), which is
+/// the offset of the tile content, see [spatial_structs::content_offset]. The OBJ is written by
+/// geof, but its directory is created here.
+fn write_obj_offset(
+    world: &parser::World,
+    bbox: &spatial_structs::Bbox,
+    origin: Option<&[f64; 3]>,
+    path_obj: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path_obj.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let path_offset = path_obj.with_extension("offset.json");
+    let file = File::create(path_offset)?;
+    serde_json::to_writer(
+        BufWriter::new(file),
+        &serde_json::json!({
+            "crs": format!("EPSG:{}", world.crs.to_epsg()?),
+            "offset": spatial_structs::content_offset(bbox, origin),
+        }),
+    )?;
+    Ok(())
+}
+
+/// Write a provisional `tileset.json` and `report.json` into the `output` directory while the
+/// tiles are converted, see `--snapshot-interval`. The content of the tiles that are not
+/// converted yet, or that failed, is removed from the provisional tileset. The implicit tileset
+/// is not written, because its subtrees are only created at the end of the run. The files are
+/// written to a temporary file first and then renamed, so that the readers never see a
+/// partially written file.
+fn write_snapshot(
+    output: &Path,
+    tileset: Option<&formats::cesium3dtiles::Tileset>,
+    finished: &[(TileId, report::TileReport)],
+    progress: report::Progress,
+    seed: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(tileset) = tileset {
+        let converted: HashSet<&TileId> = finished
+            .iter()
+            .filter(|(_, tile_report)| tile_report.success)
+            .map(|(tileid, _)| tileid)
+            .collect();
+        let mut provisional = tileset.clone();
+        provisional.remove_content(|tile| !converted.contains(&tile.id));
+        let path_tmp = output.join("tileset.json.tmp");
+        provisional.to_file(&path_tmp)?;
+        fs::rename(path_tmp, output.join("tileset.json"))?;
+    }
+    let mut run_report = report::RunReport::new();
+    run_report.seed = seed;
+    run_report.tiles = finished.iter().map(|(_, t)| t.clone()).collect();
+    run_report.progress = Some(progress);
+    let path_tmp = output.join("report.json.tmp");
+    run_report.to_file(&path_tmp)?;
+    fs::rename(path_tmp, output.join("report.json"))?;
+    Ok(())
+}
+
+/// Write the output of the conversion subprocess of a tile into its own log file, so that the
+/// output of the tiles that are converted in parallel does not get interleaved on the console.
+fn write_tile_log(
+    path: &Path,
+    cmd_string: &str,
+    exit_status: &subprocess::ExitStatus,
+    duration: Duration,
+    stdout: &str,
+    stderr: &str,
+) {
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            warn!(
+                "Failed to create the directory {:?} for the tile log: {}",
+                parent, e
+            );
+            return;
+        }
+    }
+    let log = format!(
+        "command:\n{}\n\nexit status: {:?}\nduration: {:.3}s\n\nstdout:\n{}\n\nstderr:\n{}\n",
+        cmd_string,
+        exit_status,
+        duration.as_secs_f64(),
+        stdout,
+        stderr
+    );
+    if let Err(e) = fs::write(path, log) {
+        warn!("Failed to write the tile log {:?}: {}", path, e);
+    }
+}
+
+/// Run the tile conversion subprocess.
+/// Returns the tile if the conversion failed, and the report of the conversion.
+/// If `log_file` is provided, the subprocess output is written to the file instead of the
+/// console.
 fn run_subprocess(
     subprocess_config: &SubprocessConfig,
     tile: Tile,
     output_file: PathBuf,
     cmd: Exec,
-) -> Option<Tile> {
+    log_file: Option<&Path>,
+    stats: report::TileStats,
+) -> (Option<Tile>, report::TileReport) {
     let cmd_string = cmd.to_cmdline_lossy();
     debug!("{cmd_string}");
+    let mut tile_report = report::TileReport {
+        tile_id: tile.id.to_string(),
+        stats: Some(stats),
+        ..Default::default()
+    };
+    let start = Instant::now();
     let redirection_stdout = Redirection::Pipe; // Redirection::Pipe | subprocess::NullFile
     let redirection_stderr = Redirection::Pipe; // Redirection::Merge
     let exec = cmd.stdout(redirection_stdout).stderr(redirection_stderr);
@@ -128,10 +883,14 @@ fn run_subprocess(
                     };
                     _exit_status = status;
                 } else {
-                    warn!(
-                        "Tile {} timed out, conversion subprocess command:\n{}",
-                        &tile.id, cmd_string
-                    );
+                    if log_file.is_some() {
+                        warn!("Tile {} timed out after {:?}", &tile.id, timeout);
+                    } else {
+                        warn!(
+                            "Tile {} timed out, conversion subprocess command:\n{}",
+                            &tile.id, cmd_string
+                        );
+                    }
                     popen.kill().unwrap();
                     popen.wait().unwrap();
                     _exit_status = popen.exit_status().unwrap();
@@ -140,10 +899,24 @@ fn run_subprocess(
                 (stdout_opt, stderr_opt) = popen.communicate(None).unwrap();
                 _exit_status = popen.wait().unwrap();
             }
+            let duration = start.elapsed();
+            tile_report.duration_ms = duration.as_millis();
+            if let Some(log_path) = log_file {
+                write_tile_log(
+                    log_path,
+                    &cmd_string,
+                    &_exit_status,
+                    duration,
+                    stdout_opt.as_deref().unwrap_or_default(),
+                    stderr_opt.as_deref().unwrap_or_default(),
+                );
+            }
 
             // The stderr is Redirection::Merge-d into the stdout
             if !output_file.exists() {
-                if subprocess_config.verbose {
+                if let Some(log_path) = log_file {
+                    warn!("Tile {} conversion failed, see {:?}", tile.id, log_path);
+                } else if subprocess_config.verbose {
                     warn!(
                         "Tile {} conversion failed, conversion subprocess command:\n{}\nsubprocess stdout:\n{}\nsubprocess stderr:\n{}",
                         tile.id, cmd_string, stdout_opt.unwrap_or_default(), stderr_opt.unwrap_or_default(),
@@ -154,81 +927,279 @@ fn run_subprocess(
                         tile.id, cmd_string
                     );
                 }
-                return Some(tile);
+                return (Some(tile), tile_report);
             }
         }
         Err(popen_error) => {
             warn!("{}", popen_error);
-            return Some(tile);
+            tile_report.duration_ms = start.elapsed().as_millis();
+            return (Some(tile), tile_report);
+        }
+    }
+    for template in &subprocess_config.post_process {
+        if let Err(e) = run_post_process(
+            &stats.fill(template),
+            &output_file,
+            subprocess_config.verbose,
+        ) {
+            warn!("Tile {} post-processing failed, {}", tile.id, e);
+            tile_report.duration_ms = start.elapsed().as_millis();
+            return (Some(tile), tile_report);
+        }
+    }
+    tile_report.duration_ms = start.elapsed().as_millis();
+    tile_report.success = true;
+    tile_report.content_size = fs::metadata(&output_file).ok().map(|m| m.len());
+    (None, tile_report)
+}
+
+/// Run a post-processing command on the converted tile content. The `{tile}` placeholder in
+/// the `template` is replaced by the path of the content file, which is modified in place.
+/// The placeholders of the [report::TileStats] are already replaced in the `template`.
+fn run_post_process(template: &str, output_file: &Path, verbose: bool) -> Result<(), String> {
+    let cmd_string = template.replace("{tile}", &quoted_path(output_file)?);
+    debug!("{cmd_string}");
+    let capture = Exec::shell(&cmd_string)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("command:\n{cmd_string}\n{e}"))?;
+    if capture.success() && output_file.exists() {
+        Ok(())
+    } else if verbose {
+        Err(format!(
+            "command:\n{}\nexit status: {:?}\noutput:\n{}",
+            cmd_string,
+            capture.exit_status,
+            capture.stdout_str()
+        ))
+    } else {
+        Err(format!(
+            "command:\n{}\nexit status: {:?}",
+            cmd_string, capture.exit_status
+        ))
+    }
+}
+
+/// Quote a placeholder value for the shell of [Exec::shell], so that the spaces and the
+/// special characters in the value are not interpreted by the shell.
+fn shell_quote(value: &str) -> String {
+    #[cfg(unix)]
+    {
+        let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-+=@%:,./".contains(c);
+        if !value.is_empty() && value.chars().all(is_plain) {
+            value.to_string()
+        } else {
+            format!("'{}'", value.replace('\'', r"'\''"))
         }
     }
-    None
+    // The paths cannot contain a '"' on Windows
+    #[cfg(not(unix))]
+    format!("\"{value}\"")
+}
+
+/// The shell-quoted path for a placeholder. The paths that are not valid UTF-8 are rejected,
+/// because they cannot be substituted into the command string without a lossy conversion.
+fn quoted_path(path: &Path) -> Result<String, String> {
+    path.to_str()
+        .map(shell_quote)
+        .ok_or_else(|| format!("the path {path:?} is not valid UTF-8"))
+}
+
+/// Run the hook command after a tile is finished. The placeholders in the `template` are
+/// replaced by the values from the `tile_report` and the `content_path`. The hook does not
+/// affect the result of the tile, if it fails, only a warning is logged.
+fn run_post_tile_cmd(template: &str, tile_report: &report::TileReport, content_path: &Path) {
+    let path = match quoted_path(content_path) {
+        Ok(path) => path,
+        Err(e) => {
+            warn!(
+                "Tile {} post-tile command is not run, {}",
+                tile_report.tile_id, e
+            );
+            return;
+        }
+    };
+    let cmd_string = template
+        .replace("{tile_id}", &shell_quote(&tile_report.tile_id))
+        .replace("{path}", &path)
+        .replace("{success}", &tile_report.success.to_string())
+        .replace("{duration_ms}", &tile_report.duration_ms.to_string())
+        .replace(
+            "{content_size}",
+            &tile_report
+                .content_size
+                .map(|size| size.to_string())
+                .unwrap_or_default(),
+        );
+    let cmd_string = match &tile_report.stats {
+        Some(stats) => stats.fill(&cmd_string),
+        None => cmd_string,
+    };
+    debug!("{cmd_string}");
+    match Exec::shell(&cmd_string)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+    {
+        Ok(capture) if capture.success() => {}
+        Ok(capture) => warn!(
+            "Tile {} post-tile command failed with {:?}:\n{}\n{}",
+            tile_report.tile_id,
+            capture.exit_status,
+            cmd_string,
+            capture.stdout_str()
+        ),
+        Err(e) => warn!(
+            "Tile {} post-tile command failed:\n{}\n{}",
+            tile_report.tile_id, cmd_string, e
+        ),
+    }
+}
+
+/// Run a run-level hook command. The `{output}` and `{report}` placeholders in the `template`
+/// are replaced by the output directory and the path of the run report.
+/// Returns an error if the command fails, so that the run fails too.
+fn run_hook_cmd(
+    template: &str,
+    output: &Path,
+    report_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cmd_string = template
+        .replace("{output}", &quoted_path(output)?)
+        .replace("{report}", &quoted_path(report_path)?);
+    debug!("{cmd_string}");
+    let capture = Exec::shell(&cmd_string)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()?;
+    info!("{}", capture.stdout_str().trim_end());
+    if capture.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "the command failed with {:?}:\n{}",
+            capture.exit_status, cmd_string
+        )
+        .into())
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
     // --- Begin argument parsing
+    if env::args()
+        .nth(1)
+        .is_some_and(|arg| cli::ToolsCli::is_command(&arg))
+    {
+        return tools::run(cli::ToolsCli::parse().command);
+    }
     let cli = crate::cli::Cli::parse();
     debug!("{:?}", &cli);
     info!("tyler version: {}", clap::crate_version!());
+    // The LoD label of the leaf content, for the tile metadata
+    let lod_leaf = cli.lod_leaf();
+    // The LoD of each CityObject type for the native content writer, because some of the
+    // arguments are moved out of the cli later
+    let lod_per_type: BTreeMap<parser::CityObjectType, String> = parser::CityObjectType::CORE
+        .into_iter()
+        .filter_map(|cotype| {
+            let lod = cli.lod_of(&cotype)?.to_string();
+            Some((cotype, lod))
+        })
+        .collect();
+    if cli.compression == formats::gltf::Compression::Draco {
+        return Err(
+            "--compression draco is not built into tyler, compress the content with Draco in \
+            --post-process instead, eg. --post-process \"gltf-transform draco {tile} {tile}\""
+                .into(),
+        );
+    }
     if !cli.output.is_dir() {
         fs::create_dir_all(&cli.output)?;
         info!("Created output directory {:#?}", &cli.output);
     }
+    let report_path = cli.output.join("report.json");
+    if let Some(template) = &cli.pre_run_cmd {
+        info!("Running the pre-run command");
+        run_hook_cmd(template, &cli.output, &report_path)?;
+    }
     // Since we have a default value, we can safely unwrap.
     let grid_cellsize = cli.grid_cellsize.unwrap();
-    let geometric_error_above_leaf = cli.geometric_error_above_leaf.unwrap();
-    let format = Formats::_3DTiles; // override --format
+    // The tile content is converted with geof, the CityJSON tiles are merged by tyler
+    let format = Formats::_3DTiles;
+    let export_3dtiles = cli.format.contains(&Formats::_3DTiles);
+    // The 3D Tiles 1.0 format of the native content, instead of glTF
+    let legacy_extension = match (cli.content_b3dm, cli.content_instancing) {
+        (true, true) => Some("cmpt"),
+        (true, false) => Some("b3dm"),
+        (false, _) => None,
+    };
     let subprocess_config = match format {
         Formats::_3DTiles => {
             #[allow(unused)]
             let mut exe = PathBuf::new();
-            if let Some(exe_g) = cli.exe_geof {
+            if let Some(exe_g) = cli.exe_geof.clone() {
                 assert!(exe_g.exists() && exe_g.is_file(), "geoflow executable must be an existing file for generating 3D Tiles, exe_geof: {:?}", &exe_g);
-                exe = exe_g;
+                // Not canonicalized, see cli::existing_path, but absolute, because geof runs in
+                // the features root directory
+                exe = std::path::absolute(&exe_g).unwrap_or(exe_g);
             } else {
                 debug!(
                     "exe_geof is not set for generating 3D Tiles, defaulting to 'geof' in the filesystem PATH"
                 );
                 exe = PathBuf::from("geof");
             }
-            let res = Exec::cmd(&exe)
-                .arg("--version")
-                .arg("--verbose")
-                .stdout(Redirection::Pipe)
-                .stderr(Redirection::Merge)
-                .capture();
-            let res_plugins = Exec::cmd(&exe)
-                .arg("--list-plugins")
-                .arg("--verbose")
-                .stdout(Redirection::Pipe)
-                .stderr(Redirection::Merge)
-                .capture();
-            if let Ok(capture_data) = res {
-                let plugins_stdout_str = res_plugins.unwrap().stdout_str();
-                info!(
-                    "geof version:\n{}{}",
-                    capture_data.stdout_str(),
-                    plugins_stdout_str
-                );
-            } else if let Err(popen_error) = res {
-                panic!("Could not execute geof ({:?}):\n{}", &exe, popen_error)
+            // The preview content and the native content are generated without geof
+            if export_3dtiles
+                && !cli.content_preview
+                && cli.content_writer == formats::gltf::ContentWriter::Geof
+            {
+                let res = Exec::cmd(&exe)
+                    .arg("--version")
+                    .arg("--verbose")
+                    .stdout(Redirection::Pipe)
+                    .stderr(Redirection::Merge)
+                    .capture();
+                let res_plugins = Exec::cmd(&exe)
+                    .arg("--list-plugins")
+                    .arg("--verbose")
+                    .stdout(Redirection::Pipe)
+                    .stderr(Redirection::Merge)
+                    .capture();
+                if let Ok(capture_data) = res {
+                    let plugins_stdout_str = res_plugins.unwrap().stdout_str();
+                    info!(
+                        "geof version:\n{}{}",
+                        capture_data.stdout_str(),
+                        plugins_stdout_str
+                    );
+                } else if let Err(popen_error) = res {
+                    panic!("Could not execute geof ({:?}):\n{}", &exe, popen_error)
+                }
             }
+            // The same flowchart with an additional OBJ writer
+            let geof_flowchart = if cli.obj_export {
+                "createGLB_OBJ.json"
+            } else {
+                "createGLB.json"
+            };
             let geof_flowchart_path = match env::var("TYLER_RESOURCES_DIR") {
-                Ok(val) => PathBuf::from(val).join("geof").join("createGLB.json"),
+                Ok(val) => std::path::absolute(val)?.join("geof").join(geof_flowchart),
                 Err(_) => PathBuf::from(env!("CARGO_MANIFEST_DIR"))
                     .join("resources")
                     .join("geof")
-                    .join("createGLB.json"),
+                    .join(geof_flowchart),
             };
             let timeout = cli.timeout.map(|t| Duration::new(t, 0));
             SubprocessConfig {
-                output_extension: "glb".to_string(),
+                output_extension: legacy_extension.unwrap_or("glb").to_string(),
                 exe,
                 script: geof_flowchart_path,
                 timeout,
                 verbose: cli.verbose_geof,
+                post_process: cli.post_process.clone(),
             }
         }
         Formats::CityJSON => {
@@ -251,21 +1222,82 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     debug!("{:?}", &subprocess_config);
     // Since we have a default value, it is safe to unwrap
     // let qtree_capacity = 0; // override cli.qtree_capacity
-    let qtree_criteria = spatial_structs::QuadTreeCriteria::Vertices; // override --qtree-criteria
-    let quadtree_capacity = match qtree_criteria {
+    let quadtree_capacity = match cli.qtree_criteria {
+        _ if cli.feature_weights.is_some() => {
+            spatial_structs::QuadTreeCapacity::Weight(cli.qtree_capacity.unwrap())
+        }
         spatial_structs::QuadTreeCriteria::Objects => {
             spatial_structs::QuadTreeCapacity::Objects(cli.qtree_capacity.unwrap())
         }
         spatial_structs::QuadTreeCriteria::Vertices => {
             spatial_structs::QuadTreeCapacity::Vertices(cli.qtree_capacity.unwrap())
         }
+        spatial_structs::QuadTreeCriteria::Triangles => {
+            spatial_structs::QuadTreeCapacity::Triangles(cli.qtree_capacity.unwrap())
+        }
+        spatial_structs::QuadTreeCriteria::Bytes => {
+            spatial_structs::QuadTreeCapacity::Bytes(cli.qtree_capacity.unwrap())
+        }
+        spatial_structs::QuadTreeCriteria::Weighted => spatial_structs::QuadTreeCapacity::Weighted(
+            cli.qtree_capacity.unwrap(),
+            cli.qtree_criteria_weights.unwrap_or_default(),
+        ),
     };
+    let quadtree_depth = spatial_structs::QuadTreeDepth {
+        min: cli.qtree_min_depth.unwrap_or_default(),
+        max: cli.qtree_max_depth,
+    };
+    if quadtree_depth
+        .max
+        .is_some_and(|max| max < quadtree_depth.min)
+    {
+        return Err(format!(
+            "--qtree-min-depth {} is deeper than --qtree-max-depth {}",
+            quadtree_depth.min,
+            quadtree_depth.max.unwrap()
+        )
+        .into());
+    }
+    if cli.tileset_version == formats::cesium3dtiles::TilesetVersion::V1_0
+        && (cli.cesium3dtiles_implicit
+            || cli.priority_attribute.is_some()
+            || cli.group_attribute.is_some())
+    {
+        return Err(
+            "--tileset-version 1.0 does not support implicit tiling and content groups, \
+            remove --3dtiles-implicit, --priority-attribute and --group-attribute"
+                .into(),
+        );
+    }
+    if cli.bounding_volume == formats::cesium3dtiles::BoundingVolumeType::Sphere
+        && cli.cesium3dtiles_implicit
+    {
+        return Err("--bounding-volume sphere is not supported with --3dtiles-implicit".into());
+    }
+    if cli.z_scale != 1.0
+        && cli.content_writer != formats::gltf::ContentWriter::Native
+        && !cli.content_preview
+    {
+        // geof writes the z coordinates as they are, so only the bounding volumes would be
+        // scaled
+        return Err(
+            "--z-scale needs --content-writer native or --content-preview, geof does not scale \
+            the content"
+                .into(),
+        );
+    }
+    if cli.content_b3dm && cli.content_writer != formats::gltf::ContentWriter::Native {
+        return Err("--content-b3dm needs --content-writer native".into());
+    }
+    if cli.content_instancing && cli.content_writer != formats::gltf::ContentWriter::Native {
+        return Err("--content-instancing needs --content-writer native".into());
+    }
     let metadata_class: String = match format {
         Formats::_3DTiles => {
-            if cli.cesium3dtiles_metadata_class.is_none() {
+            if export_3dtiles && cli.cesium3dtiles_metadata_class.is_none() {
                 panic!("metadata_class must be set for writing 3D Tiles")
             } else {
-                cli.cesium3dtiles_metadata_class.unwrap()
+                cli.cesium3dtiles_metadata_class.clone().unwrap_or_default()
             }
         }
         Formats::CityJSON => "".to_string(),
@@ -283,7 +1315,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             None
         }
     };
-    let debug_data = match cli.debug_load_data {
+    // Check PROJ before the indexing, so that a missing proj.db is reported with an actionable
+    // message instead of failing in the middle of the run. Only the output that is
+    // reprojected needs PROJ.
+    info!("PROJ version {}", proj::proj_info().version);
+    let crs_from = format!(
+        "EPSG:{}",
+        parser::CityJSONMetadata::from_file(&cli.metadata)?
+            .metadata
+            .reference_system
+            .to_epsg()?
+    );
+    if let Err(e) = proj::check_transformation(&crs_from) {
+        if export_3dtiles || cli.output_crs.is_some() {
+            return Err(e.into());
+        }
+        warn!("{e}\nContinuing, because the CityJSON output in the input CRS does not need PROJ");
+    }
+    let debug_data = match &cli.debug_load_data {
         None => DebugData::default(),
         Some(dir_path) => {
             if dir_path.is_dir() {
@@ -317,35 +1366,206 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // types are best passed by reference, because it is "expensive" to Clone them
     // (they don't implement Copy). When we move a value, we explicitly transfer
     // ownership of the value (eg cli.object_type).
-    let world: parser::World = match debug_data.world {
-        None => {
+    // The options and the input files that the feature index depends on, for --reuse-index
+    let index_cache_path = cli.output.join(index_cache::INDEX_CACHE_FILE);
+    let index_key = match cli.reuse_index {
+        true => {
+            let params = index_cache::IndexParams {
+                grid_cellsize,
+                object_type: cli.object_type.clone(),
+                include_ids: cli.include_ids.clone(),
+                exclude_ids: cli.exclude_ids.clone(),
+                grid_minz: cli.grid_minz,
+                grid_maxz: cli.grid_maxz,
+                origin: cli.origin,
+                extent: cli.extent,
+                clip_to_extent: cli.clip_to_extent,
+                grid_insert_bbox: cli.grid_insert_bbox,
+                seed: cli.seed,
+                priority_attribute: cli.priority_attribute.clone(),
+                group_attribute: cli.group_attribute.clone(),
+                feature_weights: cli.feature_weights.clone(),
+                grid_extent: cli.grid_extent,
+                grid_rectangular: cli.grid_rectangular,
+                grid_auto_refine: cli.grid_auto_refine,
+                large_footprint_size: cli.large_footprint_size,
+                capacity: quadtree_capacity,
+                depth: quadtree_depth,
+            };
+            let other_inputs: Vec<&Path> =
+                [&cli.include_ids, &cli.exclude_ids, &cli.feature_weights]
+                    .into_iter()
+                    .flatten()
+                    .map(|path| path.as_path())
+                    .collect();
+            info!("Checking the input files for the cached feature index");
+            Some(index_cache::IndexKey::new(
+                &params,
+                &cli.metadata,
+                &cli.features,
+                &other_inputs,
+            )?)
+        }
+        false => None,
+    };
+    let (world_cached, quadtree_cached) = match &index_key {
+        Some(key) => match index_cache::read(&index_cache_path, key)? {
+            Some((world, quadtree)) => {
+                info!("Loaded the cached feature index from {index_cache_path:?}");
+                (Some(world), Some(quadtree))
+            }
+            None => (None, None),
+        },
+        None => (None, None),
+    };
+
+    let world: parser::World = match (world_cached, debug_data.world) {
+        (Some(world), _) => world,
+        (None, None) => {
+            let id_filter =
+                parser::IdFilter::from_files(cli.include_ids.as_ref(), cli.exclude_ids.as_ref())?;
             let mut world = parser::World::new(
                 &cli.metadata,
                 &cli.features,
                 grid_cellsize,
-                cli.object_type,
-                cli.grid_minz,
-                cli.grid_maxz,
+                parser::WorldOptions {
+                    cityobject_types: cli.object_type.clone(),
+                    id_filter,
+                    minz: cli.grid_minz,
+                    maxz: cli.grid_maxz,
+                    origin: cli.origin,
+                    extent: cli.extent,
+                },
             )?;
-            world.index_with_grid(); // todo input: in general, build a line index
+            world.clip_to_extent = cli.clip_to_extent;
+            world.insert_bbox = cli.grid_insert_bbox;
+            world.seed = cli.seed;
+            world.priority_attribute = cli.priority_attribute.clone();
+            world.group_attribute = cli.group_attribute.clone();
+            let feature_weights = match &cli.feature_weights {
+                Some(path) => Some(parser::FeatureWeights::from_csv(path)?),
+                None => None,
+            };
+            if let Some(grid_extent) = &cli.grid_extent {
+                world.pin_grid(grid_extent);
+                info!("Pinned the grid to the extent {:?}", grid_extent);
+            }
+            if cli.grid_rectangular {
+                world.crop_grid();
+                info!(
+                    "Using a rectangular grid of {} columns and {} rows, instead of {} x {} cells",
+                    world.grid.nr_columns, world.grid.nr_rows, world.grid.length, world.grid.length
+                );
+            }
+            world.index_with_grid(feature_weights.as_ref()); // todo input: in general, build a line index
+            let mut nr_refined = 0;
+            while let Some(mismatch) = world.grid.capacity_mismatch(quadtree_capacity) {
+                if cli.grid_auto_refine && nr_refined < MAX_GRID_REFINEMENTS {
+                    nr_refined += 1;
+                    info!(
+                        "{} grid cells exceed the --qtree-capacity, halving the grid cell size",
+                        mismatch.nr_cells
+                    );
+                    world.refine_grid(feature_weights.as_ref());
+                } else {
+                    warn!(
+                        "{} grid cells exceed the --qtree-capacity of {}, the fullest has {} items, and the quadtree cannot split them. Use a --grid-cellsize of at most {}, a --qtree-capacity of at least {}, or --grid-auto-refine",
+                        mismatch.nr_cells,
+                        quadtree_capacity.limit(),
+                        mismatch.max_items,
+                        mismatch.suggested_cellsize,
+                        mismatch.max_items
+                    );
+                    break;
+                }
+            }
+            if let Some(size) = cli.large_footprint_size {
+                let nr_large = world.assign_large_features(size);
+                info!("Assigned {nr_large} features of at least {size} to all of their grid cells");
+            }
             world
         }
-        Some(world_path) => {
+        (None, Some(world_path)) => {
             info!("Loading world from bincode {world_path:?}");
             let world_file = File::open(world_path)?;
             bincode::deserialize_from(world_file)?
         }
     };
 
+    // The native content writer reads the external geometry from the local files
+    let nr_external = world
+        .features
+        .iter()
+        .filter(|feature| {
+            feature.external_geometry.iter().any(|uri| {
+                cli.content_writer == formats::gltf::ContentWriter::Geof
+                    || parser::ExternalGeometry::is_url(uri)
+            })
+        })
+        .count();
+    if nr_external > 0 && !cli.content_preview {
+        warn!(
+            "{nr_external} features reference external geometry (+externalGeometry) that is not written into the tile content, because it is a URL or the content is written by geof. These features are tiled by their extent, but only the geometry in the features is converted"
+        );
+    }
+
+    if !world.features_outside_extent.is_empty() {
+        let nr_outside = world.features_outside_extent.len();
+        world.export_features_outside_extent(&cli.output, false)?;
+        if cli.grid_export {
+            world.export_features_outside_extent(&debug_data_output_path, true)?;
+        }
+        if cli.strict_extent {
+            return Err(format!(
+                "{nr_outside} features are outside of the extent that is declared in the metadata, see features_outside_extent.tsv"
+            )
+            .into());
+        }
+        warn!(
+            "{nr_outside} features are outside of the extent that is declared in the metadata, see features_outside_extent.tsv"
+        );
+    }
+
+    if !world.features_crs_mismatch.is_empty() {
+        let nr_mismatch = world.features_crs_mismatch.len();
+        world.export_features_crs_mismatch(&cli.output)?;
+        if cli.strict_crs {
+            return Err(format!(
+                "{nr_mismatch} features declare a different CRS than the metadata, see features_crs_mismatch.tsv"
+            )
+            .into());
+        }
+        warn!(
+            "{nr_mismatch} features declare a different CRS than the metadata and they are not included in the tileset, see features_crs_mismatch.tsv"
+        );
+    }
+
+    let origin_path = cli.output.join("origin.json");
     info!(
-        "Computed grid statistics: {}",
-        world.grid.compute_statistics()
+        "Writing the origin and transform parameters to {:?}",
+        &origin_path
     );
+    world.export_origin(&origin_path, cli.origin)?;
 
     if cli.grid_export {
         info!("Exporting the grid to TSV to {:?}", &debug_data_output_path);
         world.export_grid(cli.grid_export_features, Some(&debug_data_output_path))?;
     }
+    if cli.density_export {
+        info!(
+            "Exporting the density of the grid cells to {:?}",
+            &cli.output
+        );
+        world.grid.export_density(quadtree_capacity, &cli.output)?;
+    }
+    if cli.grid_stats {
+        let grid_stats = world.grid.stats();
+        info!("Computed grid statistics: {}", grid_stats);
+        let grid_stats_path = cli.output.join("grid_stats.json");
+        info!("Writing the grid statistics to {:?}", &grid_stats_path);
+        serde_json::to_writer_pretty(File::create(&grid_stats_path)?, &grid_stats)?;
+        return Ok(());
+    }
     if log_enabled!(Level::Debug) {
         debug!(
             "Exporting the world instance to bincode to {:?}",
@@ -354,18 +1574,100 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         world.export_bincode(Some("world"), Some(&debug_data_output_path))?;
     }
 
-    // Build quadtree
-    let quadtree: spatial_structs::QuadTree = match debug_data.quadtree {
-        None => {
+    let tile_index = match &cli.tile_index {
+        Some(path) => {
+            info!("Assigning the features to the tiles of the tile index {path:?}");
+            let mut tile_index = tile_index::TileIndex::from_geojson(path, &cli.tile_index_id)?;
+            let unassigned = tile_index.assign(&world);
+            if !unassigned.is_empty() {
+                warn!(
+                    "{} features are not in any of the tiles of the tile index, they are not included in the tileset",
+                    unassigned.len()
+                );
+            }
+            Some(tile_index)
+        }
+        None => None,
+    };
+
+    // Build quadtree, which also provides the extent of the content when a tile index is used
+    let mut quadtree: spatial_structs::QuadTree = match (quadtree_cached, debug_data.quadtree) {
+        (Some(quadtree), _) => quadtree,
+        (None, None) => {
             info!("Building quadtree");
-            spatial_structs::QuadTree::from_world(&world, quadtree_capacity)
+            let quadtree =
+                spatial_structs::QuadTree::from_world(&world, quadtree_capacity, quadtree_depth);
+            if let Some(key) = &index_key {
+                info!("Writing the feature index to {index_cache_path:?}");
+                index_cache::write(&index_cache_path, key, &world, &quadtree)?;
+            }
+            quadtree
         }
-        Some(quadtree_path) => {
+        (None, Some(quadtree_path)) => {
             info!("Loading quadtree from bincode {quadtree_path:?}");
             let quadtree_file = File::open(quadtree_path)?;
             bincode::deserialize_from(quadtree_file)?
         }
     };
+    if let Some(max_features) = cli.qtree_merge_features {
+        let nr_merged = quadtree.merge_sparse_leaves(&world.grid, max_features, quadtree_depth.min);
+        info!("Merged the sparse leaves of {nr_merged} quadtree nodes");
+    }
+    if let Some(max_bytes) = cli.max_tile_bytes {
+        let nr_split =
+            quadtree.split_over_budget(&world.grid, max_bytes as usize, quadtree_capacity);
+        info!("Split {nr_split} quadtree nodes whose estimated content size exceeds {max_bytes} bytes");
+    }
+
+    // A flat tileset of the grid cells is a tile index of the cells
+    let flat_grid = cli.flat_grid || (cli.flat_grid_auto && quadtree.leaves_are_cells());
+    let tile_index = if let (true, Some(z_cellsize)) = (flat_grid, cli.grid_cellsize_z) {
+        info!("Using the grid cells, sliced into layers of {z_cellsize}, as the tiles of a flat tileset");
+        let voxel_grid = spatial_structs::VoxelGrid::from_world(&world, z_cellsize);
+        Some(tile_index::TileIndex::from_voxel_grid(
+            &world.grid,
+            &voxel_grid,
+        ))
+    } else if flat_grid {
+        info!("Using the grid cells as the tiles of a flat tileset");
+        Some(tile_index::TileIndex::from_grid(&world.grid))
+    } else {
+        tile_index
+    };
+    // The leaves of the octree and the kd-tree are converted like the tiles of a tile index
+    let partition_tree: Option<Box<dyn spatial_structs::PartitionTree>> = match cli.partition_scheme
+    {
+        None | Some(spatial_structs::PartitionScheme::Quadtree) => None,
+        Some(spatial_structs::PartitionScheme::Octree) => {
+            info!("Building octree");
+            Some(Box::new(spatial_structs::Octree::from_world(
+                &world,
+                quadtree_capacity,
+                spatial_structs::OctreeSplitZ {
+                    min_level: cli.octree_z_level,
+                    min_height: cli.octree_z_height,
+                },
+            )))
+        }
+        Some(spatial_structs::PartitionScheme::Kdtree) => {
+            info!("Building kd-tree");
+            Some(Box::new(spatial_structs::KdTree::from_world(
+                &world,
+                quadtree_capacity,
+            )))
+        }
+    };
+    let mut tile_index = match partition_tree.as_deref() {
+        Some(tree) => Some(tile_index::TileIndex::from_partition_tree(tree)),
+        None => tile_index,
+    };
+    if let (Some(tile_index), true) = (tile_index.as_mut(), cli.hilbert_order) {
+        tile_index.sort_hilbert(&world.grid);
+    }
+    if let (Some(tile_index), Some(size)) = (tile_index.as_mut(), cli.large_footprint_size) {
+        let nr_large = tile_index.assign_large_features(&world, size);
+        info!("Assigned {nr_large} features of at least {size} to all of their tiles");
+    }
 
     if cli.grid_export {
         info!(
@@ -374,6 +1676,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         );
         quadtree.export(&world, Some(&debug_data_output_path))?;
     }
+    if cli.debug_export == Some(DebugExport::Geojson) {
+        let quadtree_geojson_path = cli.output.join("quadtree_wgs84.geojson");
+        info!(
+            "Exporting the quadtree to GeoJSON to {:?}",
+            &quadtree_geojson_path
+        );
+        quadtree.export_geojson_wgs84(&world.grid, &quadtree_geojson_path)?;
+    }
     if log_enabled!(Level::Debug) {
         debug!(
             "Exporting the quadtree instance to bincode to {:?}",
@@ -382,23 +1692,66 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         quadtree.export_bincode(Some("quadtree"), Some(&debug_data_output_path))?;
     }
 
+    // CityJSON
+    if cli.format.contains(&Formats::CityJSON) {
+        let path_cityjson = cli.output.join("cityjson");
+        info!("Writing CityJSON tiles to {:?}", &path_cityjson);
+        let tiles: Vec<(String, Vec<usize>)> = match &tile_index {
+            Some(tile_index) => tile_index
+                .tiles
+                .iter()
+                .filter(|t| !t.feature_ids.is_empty())
+                .map(|t| (t.file_stem(), t.feature_ids.clone()))
+                .collect(),
+            None => quadtree
+                .collect_leaves()
+                .into_iter()
+                .filter(|leaf| leaf.nr_items > 0)
+                .map(|leaf| {
+                    (
+                        cli.tile_naming
+                            .file_stem(&leaf.id, world.grid.quadtree_max_level()),
+                        tile_features(&world, leaf),
+                    )
+                })
+                .collect(),
+        };
+        let nr_tiles = export_cityjson(&world, tiles, &path_cityjson, cli.output_crs)?;
+        info!("Wrote {nr_tiles} CityJSON tiles");
+    }
+    if !export_3dtiles {
+        if let Some(template) = &cli.post_run_cmd {
+            info!("Running the post-run command");
+            run_hook_cmd(template, &cli.output, &report_path)?;
+        }
+        return Ok(());
+    }
+
     // 3D Tiles
 
     let tileset_path = cli.output.join("tileset.json");
     let subtrees_path = cli.output.join("subtrees");
     let tileset_path_unpruned = cli.output.join("tileset_unpruned.json");
     let subtrees_path_unpruned = cli.output.join("subtrees_unpruned");
-    info!("Generating 3D Tiles tileset");
-    let mut tileset = formats::cesium3dtiles::Tileset::from_quadtree(
-        &quadtree,
-        &world,
-        geometric_error_above_leaf,
-        grid_cellsize,
-        cli.grid_minz,
-        cli.grid_maxz,
-        cli.cesium3dtiles_content_bv_from_tile,
-        cli.cesium3dtiles_content_add_bv,
-    );
+    let promoted = match cli.large_feature_policy {
+        Some(spatial_structs::LargeFeaturePolicy::Promote) => {
+            quadtree.promote_large_features(&world)
+        }
+        _ => spatial_structs::PromotedFeatures::default(),
+    };
+    let (mut tileset, mut lod_interior) = build_tileset(
+        &TilesetSources {
+            world: &world,
+            quadtree: &quadtree,
+            tile_index: tile_index.as_ref(),
+            partition_tree: partition_tree.as_deref(),
+            flat_grid,
+            promoted: &promoted,
+        },
+        &cli,
+        &lod_leaf,
+        &metadata_class,
+    )?;
 
     if cli.grid_export {
         info!(
@@ -408,7 +1761,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         tileset.export(Some(&debug_data_output_path))?;
     }
 
-    let (tiles, _subtrees) = match cli.cesium3dtiles_implicit {
+    let (mut tiles, _subtrees) = match cli.cesium3dtiles_implicit {
         true => {
             let mut tileset_implicit = tileset.clone();
             // FIXME: here we have a Vec<(Tile, TileId)> in 'tiles' instead of Vec<&Tile>, because of the
@@ -455,7 +1808,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             tiles_subtrees
         }
         false => {
-            let just_tiles = tileset.collect_leaves();
+            let just_tiles = tileset.collect_tiles_with_content();
             // FIXME: here we need Vec<(Tile, TileId)> instead of Vec<&Tile>, for the same reason
             //  as above
             let tiles: Vec<(Tile, TileId)> = just_tiles
@@ -469,6 +1822,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             (tiles, vec![])
         }
     };
+    if cli.hilbert_order {
+        // The tiles of a tile index are already ordered, their ID is their position
+        let order = world.grid.hilbert_order();
+        tiles.sort_by_cached_key(|(tile, _)| match &tile_index {
+            Some(_) => tile.id.x as u64,
+            None => spatial_structs::hilbert_encode(order, tile.id.x as u64, tile.id.y as u64),
+        });
+    }
 
     // Export by calling a subprocess to merge the .jsonl files and convert them to the
     // target format
@@ -483,63 +1844,165 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Some(attributes) => attributes.join(","),
     };
 
+    let mut run_report = report::RunReport::new();
+    run_report.seed = cli.seed;
     let path_output_tiles = cli.output.join("t");
-    let path_features_input_dir = cli.output.join("inputs");
+    let path_output_obj = cli.output.join("tiles_obj");
+    // A subdirectory of --inputs-dir, so that removing it cannot remove anything else
+    let path_features_input_dir = match &cli.inputs_dir {
+        Some(dir) => dir.join(format!("tyler-{}", std::process::id())),
+        None => cli.output.join("inputs"),
+    };
     // TODO: need to refactor this parallel loop somehow that it does not only read the
     //  3d tiles tiles, but also works with cityjson output
     if !cli.cesium3dtiles_tileset_only {
         fs::create_dir_all(&path_output_tiles)?;
         info!("Created output directory {:#?}", &path_output_tiles);
         fs::create_dir_all(&path_features_input_dir)?;
-        info!("Created output directory {:#?}", &path_features_input_dir);
+        info!("Created input directory {:#?}", &path_features_input_dir);
+
+        let path_tile_logs = cli.output.join("logs");
+        if cli.tile_logs {
+            fs::create_dir_all(&path_tile_logs)?;
+            info!("Writing the tile conversion logs to {:#?}", &path_tile_logs);
+        }
 
         let tiles_len = tiles.len();
-        let tiles_failed_iter = tiles.into_par_iter().map(|(tile, tileid)| {
+        let conversion_start = Instant::now();
+        let native_options = NativeOptions {
+            z_scale: cli.z_scale,
+            metadata_class: &metadata_class,
+            attributes: cli.object_attribute.as_deref().unwrap_or_default(),
+            b3dm: cli.content_b3dm,
+            gpu_instancing: cli.content_instancing,
+            compression: cli.compression,
+        };
+        // The name of the content file is taken from the content URI, which is labeled with
+        // the LoD if --content-lod-names is set, and which is the tile index ID for a tile
+        // index. The implicit tiles are named by their implicit tile ID.
+        let content_file_name = |tile: &Tile, tileid: &TileId| match tile.content_file_stem() {
+            Some(stem) if !cli.cesium3dtiles_implicit => stem.to_string(),
+            _ => tileid.to_string(),
+        };
+        // The quadtree is passed in explicitly, because it is modified when the oversized tiles
+        // are split.
+        let convert_tile_content = |quadtree: &spatial_structs::QuadTree,
+                                    tile: Tile,
+                                    tileid: TileId,
+                                    lod: Option<&InteriorLod>| {
             #[allow(unused)]
-            let mut tile_failed: Option<Tile> = None;
+            let mut tile_failed: (Option<Tile>, Option<report::TileReport>) = (None, None);
             let tileid_grid = &tile.id;
             let qtree_nodeid: spatial_structs::QuadTreeNodeId = tileid_grid.into();
-            let qtree_node = quadtree
-                .node(&qtree_nodeid)
-                .unwrap_or_else(|| panic!("did not find tile {} in quadtree", tileid_grid));
-            if qtree_node.nr_items == 0 {
-                // The Tileset.prune() method removes the empty tiles from the tileset,
-                //  so skipping the tile conversion without failure is ok if it's empty.
-                debug!("Tile is empty ({}), skipping conversion", tileid_grid);
-                return tile_failed;
-            }
-            let tileid_string = tileid.to_string();
-            let file_name = tileid_string;
-            let output_file = path_output_tiles
-                .join(&file_name)
-                .with_extension(&subprocess_config.output_extension);
-            let path_features_input_file = write_inputs(
-                &world,
-                &path_features_input_dir,
-                qtree_node,
-                file_name.as_str(),
-            );
-
             // We use the quadtree node bbox here instead of the Tileset.Tile bounding
             // volume, because the Tile is in EPSG:4979 and we need the input data CRS
-            let b = qtree_node.bbox(&world.grid);
+            let (fids, b, is_interior) = match &tile_index {
+                Some(tile_index) => {
+                    let index_tile = &tile_index.tiles[tileid_grid.x];
+                    let b = index_tile.bbox_3d(&world.grid);
+                    (index_tile.feature_ids.clone(), b, false)
+                }
+                None => {
+                    let qtree_node = quadtree
+                        .node(&qtree_nodeid)
+                        .unwrap_or_else(|| panic!("did not find tile {} in quadtree", tileid_grid));
+                    if qtree_node.nr_items == 0 {
+                        // The Tileset.prune() method removes the empty tiles from the tileset,
+                        //  so skipping the tile conversion without failure is ok if it's empty.
+                        debug!("Tile is empty ({}), skipping conversion", tileid_grid);
+                        return tile_failed;
+                    }
+                    let b = qtree_node.bbox(&world.grid);
+                    match promoted.nodes.get(&qtree_nodeid) {
+                        // The promoted features are converted completely, thus not clipped
+                        // to the node, and with the LoD of the leaves
+                        Some(fids) => (fids.clone(), features_bbox(&world, fids, b), false),
+                        None => {
+                            let mut fids = tile_features(&world, qtree_node);
+                            fids.retain(|fid| !promoted.feature_ids.contains(fid));
+                            (fids, b, !qtree_node.children.is_empty())
+                        }
+                    }
+                }
+            };
+            let stats = report::TileStats {
+                level: tile.id.level,
+                nr_features: fids.len(),
+                nr_vertices: fids
+                    .iter()
+                    .map(|fid| world.features[*fid].nr_vertices as usize)
+                    .sum(),
+                capacity: cli.qtree_capacity.unwrap_or_default(),
+            };
+            let file_name = content_file_name(&tile, &tileid);
+            // Not with_extension(), because the LoD in the name can contain a dot
+            let output_file = path_output_tiles.join(format!(
+                "{file_name}.{}",
+                &subprocess_config.output_extension
+            ));
+            if cli.feature_table {
+                let path_table = path_output_tiles.join(format!("{file_name}.features.json"));
+                if let Err(e) = write_feature_table(&world, &fids, &path_table) {
+                    warn!(
+                        "Failed to write the feature table of tile {}: {}",
+                        &tile.id, e
+                    );
+                }
+            }
+            if cli.content_preview {
+                return write_preview(
+                    &world,
+                    &fids,
+                    tile,
+                    &output_file,
+                    cli.z_scale,
+                    cli.content_ecef,
+                );
+            }
+            if cli.content_writer == formats::gltf::ContentWriter::Native {
+                // The content of the interior tiles uses the same LoD for each type
+                let lod_interior = lod
+                    .filter(|_| is_interior)
+                    .map(|interior_lod| interior_lod.lod_of(&tile.id).to_string());
+                return write_native(
+                    &world,
+                    &fids,
+                    tile,
+                    &output_file,
+                    |cotype| {
+                        lod_interior
+                            .clone()
+                            .or_else(|| lod_per_type.get(cotype).cloned())
+                    },
+                    &native_options,
+                );
+            }
+            let path_obj = cli
+                .obj_export
+                .then(|| path_output_obj.join(format!("{file_name}.obj")));
+            if let Some(path_obj) = &path_obj {
+                if let Err(e) = write_obj_offset(&world, &b, cli.origin.as_ref(), path_obj) {
+                    warn!("Failed to write the OBJ offset of tile {}: {}", &tile.id, e);
+                }
+            }
+            let path_features_input_file =
+                write_inputs(&world, &path_features_input_dir, &fids, file_name.as_str());
+
             // We need to string-format all the arguments with an = separator, because that's what
             // geof can accept.
             // TODO: maybe replace the subprocess carte with std::process to remove the dependency
             let mut cmd = Exec::cmd(&subprocess_config.exe)
+                .cwd(&world.path_features_root)
                 .arg(&subprocess_config.script)
                 .arg(format!(
                     "--output_format={}",
                     &format.to_string().to_lowercase()
                 ))
-                .arg(format!("--output_file={}", &output_file.to_str().unwrap()))
-                .arg(format!(
-                    "--path_metadata={}",
-                    &world.path_metadata.to_str().unwrap()
-                ))
-                .arg(format!(
-                    "--path_features_input_file={}",
-                    &path_features_input_file.to_str().unwrap()
+                .arg(path_arg("output_file", &output_file))
+                .arg(path_arg("path_metadata", &world.path_metadata))
+                .arg(path_arg(
+                    "path_features_input_file",
+                    &path_features_input_file,
                 ))
                 .arg(format!("--min_x={}", b[0]))
                 .arg(format!("--min_y={}", b[1]))
@@ -560,239 +2023,268 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             if cli.verbose_geof {
                 cmd = cmd.arg("--verbose".to_string())
             }
+            // The content of each tile is stored relative to its own offset, to keep the
+            // precision of the tiles that are far from the origin of the data
+            let [offset_x, offset_y, offset_z] =
+                spatial_structs::content_offset(&b, cli.origin.as_ref());
+            cmd = cmd
+                .arg(format!("--GF_PROCESS_OFFSET_X={offset_x}"))
+                .arg(format!("--GF_PROCESS_OFFSET_Y={offset_y}"))
+                .arg(format!("--GF_PROCESS_OFFSET_Z={offset_z}"));
+            if let Some(path_obj) = &path_obj {
+                cmd = cmd.arg(path_arg("output_file_obj", path_obj));
+            }
 
             if format == Formats::_3DTiles {
                 // geof specific args
                 // colors
-                if cli.color_building.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorBuilding={}",
-                        cli.color_building.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_building_part.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorBuildingPart={}",
-                        cli.color_building_part.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_building_installation.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorBuildingInstallation={}",
-                        cli.color_building_installation.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_tin_relief.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorTINRelief={}",
-                        cli.color_tin_relief.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_road.is_some() {
-                    cmd = cmd.arg(format!("--colorRoad={}", cli.color_road.as_ref().unwrap()));
-                }
-                if cli.color_railway.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorRailway={}",
-                        cli.color_railway.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_transport_square.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorTransportSquare={}",
-                        cli.color_transport_square.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_water_body.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorWaterBody={}",
-                        cli.color_water_body.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_plant_cover.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorPlantCover={}",
-                        cli.color_plant_cover.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_solitary_vegetation_object.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorSolitaryVegetationObject={}",
-                        cli.color_solitary_vegetation_object.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_land_use.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorLandUse={}",
-                        cli.color_land_use.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_city_furniture.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorCityFurniture={}",
-                        cli.color_city_furniture.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_bridge.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorBridge={}",
-                        cli.color_bridge.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_bridge_part.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorBridgePart={}",
-                        cli.color_bridge_part.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_bridge_installation.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorBridgeInstallation={}",
-                        cli.color_bridge_installation.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_bridge_construction_element.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorBridgeConstructionElement={}",
-                        cli.color_bridge_construction_element.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_tunnel.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorTunnel={}",
-                        cli.color_tunnel.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_tunnel_part.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorTunnelPart={}",
-                        cli.color_tunnel_part.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_tunnel_installation.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorTunnelInstallation={}",
-                        cli.color_tunnel_installation.as_ref().unwrap()
-                    ));
-                }
-                if cli.color_generic_city_object.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--colorGenericCityObject={}",
-                        cli.color_generic_city_object.as_ref().unwrap()
-                    ));
+                if let Some(debug_colors) = cli.content_debug_colors {
+                    // The same color for all types, the geof color arguments are named like
+                    // the LoD arguments
+                    let color = debug_colors.color(&qtree_nodeid, cli.seed);
+                    for lod_arg in GEOF_LOD_ARGS {
+                        let cotype = lod_arg.trim_start_matches("lod");
+                        cmd = cmd.arg(format!("--color{cotype}={color}"));
+                    }
+                } else {
+                    if cli.color_building.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorBuilding={}",
+                            cli.color_building.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_building_part.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorBuildingPart={}",
+                            cli.color_building_part.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_building_installation.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorBuildingInstallation={}",
+                            cli.color_building_installation.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_tin_relief.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorTINRelief={}",
+                            cli.color_tin_relief.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_road.is_some() {
+                        cmd = cmd.arg(format!("--colorRoad={}", cli.color_road.as_ref().unwrap()));
+                    }
+                    if cli.color_railway.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorRailway={}",
+                            cli.color_railway.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_transport_square.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorTransportSquare={}",
+                            cli.color_transport_square.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_water_body.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorWaterBody={}",
+                            cli.color_water_body.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_plant_cover.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorPlantCover={}",
+                            cli.color_plant_cover.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_solitary_vegetation_object.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorSolitaryVegetationObject={}",
+                            cli.color_solitary_vegetation_object.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_land_use.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorLandUse={}",
+                            cli.color_land_use.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_city_furniture.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorCityFurniture={}",
+                            cli.color_city_furniture.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_bridge.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorBridge={}",
+                            cli.color_bridge.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_bridge_part.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorBridgePart={}",
+                            cli.color_bridge_part.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_bridge_installation.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorBridgeInstallation={}",
+                            cli.color_bridge_installation.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_bridge_construction_element.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorBridgeConstructionElement={}",
+                            cli.color_bridge_construction_element.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_tunnel.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorTunnel={}",
+                            cli.color_tunnel.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_tunnel_part.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorTunnelPart={}",
+                            cli.color_tunnel_part.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_tunnel_installation.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorTunnelInstallation={}",
+                            cli.color_tunnel_installation.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.color_generic_city_object.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--colorGenericCityObject={}",
+                            cli.color_generic_city_object.as_ref().unwrap()
+                        ));
+                    }
                 }
 
                 // lod filter
-                if cli.lod_building.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodBuilding={}",
-                        cli.lod_building.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_building_part.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodBuildingPart={}",
-                        cli.lod_building_part.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_building_installation.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodBuildingInstallation={}",
-                        cli.lod_building_installation.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_tin_relief.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodTINRelief={}",
-                        cli.lod_tin_relief.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_road.is_some() {
-                    cmd = cmd.arg(format!("--lodRoad={}", cli.lod_road.as_ref().unwrap()));
-                }
-                if cli.lod_railway.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodRailway={}",
-                        cli.lod_railway.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_transport_square.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodTransportSquare={}",
-                        cli.lod_transport_square.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_water_body.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodWaterBody={}",
-                        cli.lod_water_body.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_plant_cover.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodPlantCover={}",
-                        cli.lod_plant_cover.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_solitary_vegetation_object.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodSolitaryVegetationObject={}",
-                        cli.lod_solitary_vegetation_object.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_land_use.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodLandUse={}",
-                        cli.lod_land_use.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_city_furniture.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodCityFurniture={}",
-                        cli.lod_city_furniture.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_bridge.is_some() {
-                    cmd = cmd.arg(format!("--lodBridge={}", cli.lod_bridge.as_ref().unwrap()));
-                }
-                if cli.lod_bridge_part.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodBridgePart={}",
-                        cli.lod_bridge_part.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_bridge_installation.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodBridgeInstallation={}",
-                        cli.lod_bridge_installation.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_bridge_construction_element.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodBridgeConstructionElement={}",
-                        cli.lod_bridge_construction_element.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_tunnel.is_some() {
-                    cmd = cmd.arg(format!("--lodTunnel={}", cli.lod_tunnel.as_ref().unwrap()));
-                }
-                if cli.lod_tunnel_part.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodTunnelPart={}",
-                        cli.lod_tunnel_part.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_tunnel_installation.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodTunnelInstallation={}",
-                        cli.lod_tunnel_installation.as_ref().unwrap()
-                    ));
-                }
-                if cli.lod_generic_city_object.is_some() {
-                    cmd = cmd.arg(format!(
-                        "--lodGenericCityObject={}",
-                        cli.lod_generic_city_object.as_ref().unwrap()
-                    ));
+                if let Some(interior_lod) = lod.filter(|_| is_interior) {
+                    // The content of the interior tiles uses the same LoD for each type
+                    let lod_interior = interior_lod.lod_of(&tile.id);
+                    for lod_arg in GEOF_LOD_ARGS {
+                        cmd = cmd.arg(format!("--{lod_arg}={lod_interior}"));
+                    }
+                } else {
+                    if cli.lod_building.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodBuilding={}",
+                            cli.lod_building.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_building_part.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodBuildingPart={}",
+                            cli.lod_building_part.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_building_installation.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodBuildingInstallation={}",
+                            cli.lod_building_installation.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_tin_relief.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodTINRelief={}",
+                            cli.lod_tin_relief.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_road.is_some() {
+                        cmd = cmd.arg(format!("--lodRoad={}", cli.lod_road.as_ref().unwrap()));
+                    }
+                    if cli.lod_railway.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodRailway={}",
+                            cli.lod_railway.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_transport_square.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodTransportSquare={}",
+                            cli.lod_transport_square.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_water_body.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodWaterBody={}",
+                            cli.lod_water_body.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_plant_cover.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodPlantCover={}",
+                            cli.lod_plant_cover.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_solitary_vegetation_object.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodSolitaryVegetationObject={}",
+                            cli.lod_solitary_vegetation_object.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_land_use.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodLandUse={}",
+                            cli.lod_land_use.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_city_furniture.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodCityFurniture={}",
+                            cli.lod_city_furniture.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_bridge.is_some() {
+                        cmd = cmd.arg(format!("--lodBridge={}", cli.lod_bridge.as_ref().unwrap()));
+                    }
+                    if cli.lod_bridge_part.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodBridgePart={}",
+                            cli.lod_bridge_part.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_bridge_installation.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodBridgeInstallation={}",
+                            cli.lod_bridge_installation.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_bridge_construction_element.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodBridgeConstructionElement={}",
+                            cli.lod_bridge_construction_element.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_tunnel.is_some() {
+                        cmd = cmd.arg(format!("--lodTunnel={}", cli.lod_tunnel.as_ref().unwrap()));
+                    }
+                    if cli.lod_tunnel_part.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodTunnelPart={}",
+                            cli.lod_tunnel_part.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_tunnel_installation.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodTunnelInstallation={}",
+                            cli.lod_tunnel_installation.as_ref().unwrap()
+                        ));
+                    }
+                    if cli.lod_generic_city_object.is_some() {
+                        cmd = cmd.arg(format!(
+                            "--lodGenericCityObject={}",
+                            cli.lod_generic_city_object.as_ref().unwrap()
+                        ));
+                    }
                 }
 
                 if let Some(ref cotypes) = world.cityobject_types {
@@ -800,10 +2292,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         || cotypes.contains(&parser::CityObjectType::BuildingPart)
                     {
                         cmd = cmd.arg("--simplify_error=0.0").arg("--skip_clip=true");
-                    } else if cli.simplification_max_error.is_some() {
+                    } else if let Some(max_error) = cli.simplification_max_error {
                         cmd = cmd.arg(format!(
                             "--simplify_error={}",
-                            cli.simplification_max_error.as_ref().unwrap()
+                            stats.simplify_error(max_error, &cli.qtree_criteria)
                         ));
                     }
                 }
@@ -815,18 +2307,110 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 cmd = cmd.env("PROJ_DATA", pd);
             }
 
-            tile_failed = run_subprocess(&subprocess_config, tile, output_file, cmd);
+            // The log path is relative to the output directory, because that is how it is
+            // referenced in the run report.
+            let tile_log = cli
+                .tile_logs
+                .then(|| PathBuf::from("logs").join(format!("{file_name}.log")));
+            let log_file = tile_log.as_ref().map(|p| cli.output.join(p));
+            let (failed, mut tile_report) = run_subprocess(
+                &subprocess_config,
+                tile,
+                output_file,
+                cmd,
+                log_file.as_deref(),
+                stats,
+            );
+            tile_report.log = tile_log;
+            // Remove the input files as soon as possible, because they take up memory if the
+            // --inputs-dir is on a tmpfs
+            if !log_enabled!(Level::Debug) {
+                let _ = fs::remove_file(&path_features_input_file);
+            }
+            tile_failed = (failed, Some(tile_report));
             tile_failed
-        });
+        };
+        // Run the --post-tile-cmd hook as soon as the tile is finished
+        let convert_tile = |quadtree: &spatial_structs::QuadTree,
+                            tile: Tile,
+                            tileid: TileId,
+                            lod_interior: Option<&InteriorLod>| {
+            let content_path = path_output_tiles.join(format!(
+                "{}.{}",
+                content_file_name(&tile, &tileid),
+                &subprocess_config.output_extension
+            ));
+            let index_position = tile.id.x;
+            let mut tile_result = convert_tile_content(quadtree, tile, tileid, lod_interior);
+            // The tiles of a tile index are reported by their tile index ID
+            if let (Some(tile_index), Some(tile_report)) = (&tile_index, &mut tile_result.1) {
+                tile_report.tile_id = tile_index.tiles[index_position].id.clone();
+            }
+            if let (Some(template), Some(tile_report)) = (&cli.post_tile_cmd, &tile_result.1) {
+                run_post_tile_cmd(template, tile_report, &content_path);
+            }
+            tile_result
+        };
 
-        let mut tiles_results: Vec<Option<Tile>> = Vec::with_capacity(tiles_len + 2);
+        #[allow(clippy::type_complexity)]
+        let mut tiles_results: Vec<(Option<Tile>, Option<report::TileReport>)> =
+            Vec::with_capacity(tiles_len + 2);
         if let Some(tiles_results_path) = debug_data.tiles_results {
             info!("Loading tiles_results from {tiles_results_path:?}");
             let tiles_results_file = File::open(tiles_results_path)?;
             tiles_results = bincode::deserialize_from(tiles_results_file)?
         } else {
             info!("Converting and optimizing {tiles_len} tiles");
-            tiles_failed_iter.collect_into_vec(&mut tiles_results);
+            // The finished tiles are collected for the provisional snapshots
+            let finished: Mutex<Vec<(TileId, report::TileReport)>> = Mutex::new(Vec::new());
+            let (conversion_done, conversion_done_rx) = mpsc::channel::<()>();
+            std::thread::scope(|scope| {
+                if let Some(interval) = cli.snapshot_interval {
+                    let (finished, output) = (&finished, &cli.output);
+                    let snapshot_tileset = (!cli.cesium3dtiles_implicit).then_some(&tileset);
+                    scope.spawn(move || {
+                        while let Err(mpsc::RecvTimeoutError::Timeout) =
+                            conversion_done_rx.recv_timeout(Duration::from_secs(interval))
+                        {
+                            let finished = finished.lock().unwrap().clone();
+                            let progress = report::Progress {
+                                nr_tiles: tiles_len,
+                                nr_finished: finished.len(),
+                                elapsed_s: conversion_start.elapsed().as_secs_f64(),
+                            };
+                            info!(
+                                "Writing a snapshot of the tileset, {} of {} tiles are finished",
+                                progress.nr_finished, tiles_len
+                            );
+                            if let Err(e) = write_snapshot(
+                                output,
+                                snapshot_tileset,
+                                &finished,
+                                progress,
+                                cli.seed,
+                            ) {
+                                warn!("Failed to write the snapshot of the tileset: {e}");
+                            }
+                        }
+                    });
+                }
+                tiles
+                    .into_par_iter()
+                    .map(|(tile, tileid)| {
+                        let id = tile.id.clone();
+                        let tile_result =
+                            convert_tile(&quadtree, tile, tileid, lod_interior.as_ref());
+                        if let (Some(_), Some(tile_report)) =
+                            (cli.snapshot_interval, &tile_result.1)
+                        {
+                            finished.lock().unwrap().push((id, tile_report.clone()));
+                        }
+                        tile_result
+                    })
+                    .collect_into_vec(&mut tiles_results);
+                // Stop the snapshots
+                drop(conversion_done);
+            });
             if log_enabled!(Level::Debug) {
                 debug!(
                     "Exporting the tiles_results instance to bincode to {:?}",
@@ -837,8 +2421,138 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 bincode::serialize_into(tiles_results_file, &tiles_results)?;
             }
         }
-        let tiles_failed: Vec<Tile> = tiles_results.into_iter().flatten().collect();
-        info!("Done");
+        let (tiles_failed, tile_reports): (Vec<Option<Tile>>, Vec<Option<report::TileReport>>) =
+            tiles_results.into_iter().unzip();
+        let mut tiles_failed: Vec<Tile> = tiles_failed.into_iter().flatten().collect();
+        run_report.tiles = tile_reports.into_iter().flatten().collect();
+        info!(
+            "Converted {} tiles in {:.1}s, {} failed",
+            run_report.tiles.len(),
+            conversion_start.elapsed().as_secs_f64(),
+            run_report.nr_tiles_failed()
+        );
+
+        if let Some(max_content_size) = cli.max_content_size {
+            let mut oversized = run_report.flag_oversized(max_content_size);
+            if !oversized.is_empty() {
+                warn!(
+                    "{} tiles exceed the maximum content size of {} bytes: {}",
+                    oversized.len(),
+                    max_content_size,
+                    oversized.join(", ")
+                );
+            }
+            if cli.split_oversized_content && cli.cesium3dtiles_implicit {
+                warn!("Splitting the oversized tiles is not supported for implicit tiling");
+            } else if cli.split_oversized_content {
+                while !oversized.is_empty() {
+                    // Split the oversized leaves one level deeper and replace their content
+                    // with the content of their children.
+                    let mut split_nodes: Vec<spatial_structs::QuadTreeNodeId> = Vec::new();
+                    for tile_id in &oversized {
+                        let qtree_nodeid: spatial_structs::QuadTreeNodeId = tile_id.parse()?;
+                        let is_split = quadtree
+                            .node_mut(&qtree_nodeid)
+                            .map(|node| node.split(&world.grid, quadtree_capacity))
+                            .unwrap_or(false);
+                        if is_split {
+                            // Only the leaves are split
+                            let file_name = formats::cesium3dtiles::content_stem(
+                                &cli.tile_naming
+                                    .file_stem(&qtree_nodeid, world.grid.quadtree_max_level()),
+                                cli.content_lod_names.then_some(lod_leaf.as_str()),
+                            );
+                            fs::remove_file(path_output_tiles.join(format!(
+                                "{file_name}.{}",
+                                &subprocess_config.output_extension
+                            )))?;
+                            if let Some(tile_report) =
+                                run_report.tiles.iter_mut().find(|t| &t.tile_id == tile_id)
+                            {
+                                tile_report.split = true;
+                            }
+                            split_nodes.push(qtree_nodeid);
+                        } else {
+                            warn!("Tile {tile_id} exceeds the maximum content size, but it cannot be split further");
+                        }
+                    }
+                    if split_nodes.is_empty() {
+                        break;
+                    }
+                    info!(
+                        "Split {} oversized tiles, converting their children",
+                        split_nodes.len()
+                    );
+                    (tileset, lod_interior) = build_tileset(
+                        &TilesetSources {
+                            world: &world,
+                            quadtree: &quadtree,
+                            tile_index: tile_index.as_ref(),
+                            partition_tree: partition_tree.as_deref(),
+                            flat_grid,
+                            promoted: &promoted,
+                        },
+                        &cli,
+                        &lod_leaf,
+                        &metadata_class,
+                    )?;
+                    // The split tiles only have content if they received interior content
+                    let mut children_ids: Vec<TileId> = split_nodes
+                        .iter()
+                        .filter_map(|id| quadtree.node(id))
+                        .flat_map(|node| node.children.iter().map(|c| TileId::from(&c.id)))
+                        .collect();
+                    children_ids.extend(split_nodes.iter().map(TileId::from));
+                    let children: Vec<(Tile, TileId)> = tileset
+                        .collect_tiles_with_content()
+                        .into_iter()
+                        .filter(|t| children_ids.contains(&t.id))
+                        .map(|t| (t.clone(), t.id.clone()))
+                        .collect();
+                    #[allow(clippy::type_complexity)]
+                    let children_results: Vec<(
+                        Option<Tile>,
+                        Option<report::TileReport>,
+                    )> = children
+                        .into_par_iter()
+                        .map(|(tile, tileid)| {
+                            convert_tile(&quadtree, tile, tileid, lod_interior.as_ref())
+                        })
+                        .collect();
+                    for (failed, tile_report) in children_results {
+                        tiles_failed.extend(failed);
+                        run_report.tiles.extend(tile_report);
+                    }
+                    oversized = run_report.flag_oversized(max_content_size);
+                }
+            }
+        }
+
+        if cli.precision_diagnostics {
+            info!("Computing the coordinate precision diagnostics of the tiles");
+            let crs_from = format!("EPSG:{}", world.crs.to_epsg()?);
+            let to_output = proj::Proj::new_known_crs(&crs_from, "EPSG:4978", None)?;
+            let from_output = proj::Proj::new_known_crs("EPSG:4978", &crs_from, None)?;
+            for tile_report in run_report.tiles.iter_mut().filter(|t| t.success) {
+                let qtree_nodeid: spatial_structs::QuadTreeNodeId = tile_report.tile_id.parse()?;
+                if let Some(qtree_node) = quadtree.node(&qtree_nodeid) {
+                    let bbox = qtree_node.node_content_bbox(&world, cli.grid_minz, cli.grid_maxz);
+                    let offset = spatial_structs::content_offset(
+                        &qtree_node.bbox(&world.grid),
+                        cli.origin.as_ref(),
+                    );
+                    tile_report.max_displacement = Some(report::max_displacement(
+                        &bbox,
+                        &offset,
+                        &to_output,
+                        &from_output,
+                    )?);
+                }
+            }
+            if let Some(max_d) = run_report.max_displacement() {
+                info!("Maximal coordinate displacement in the tiles: {max_d:.6}");
+            }
+        }
 
         if !log_enabled!(Level::Debug) {
             fs::remove_dir_all(path_features_input_dir)?;
@@ -849,7 +2563,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             debug!("{}, removing failed from the tileset: {}", i, failed.id);
         }
         // Remove tiles that failed the gltf conversion
-        tileset.prune(&tiles_failed, &quadtree);
+        match tile_index {
+            Some(_) => tileset.remove_tiles(&tiles_failed),
+            None => tileset.prune(&tiles_failed, &quadtree),
+        }
         if cli.cesium3dtiles_implicit {
             // FIXME: here we re-create the implicit tileset from the pruned tileset,
             //  because it is simpler than flipping the bits of the unavailable tiles,
@@ -867,6 +2584,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 subtrees_dir_option,
                 Some(&debug_data_output_path),
             );
+            if let Some(base) = &cli.cesium3dtiles_content_base_uri {
+                tileset.set_content_base_uri(base);
+            }
             info!("Writing subtrees for implicit tiling");
             fs::create_dir_all(&subtrees_path)?;
             for (subtree_id, subtree_bytes) in subtrees {
@@ -884,6 +2604,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         } else {
+            if let Some(extension) = legacy_extension {
+                tileset.use_legacy_content(extension);
+            }
+            if let Some(base) = &cli.cesium3dtiles_content_base_uri {
+                tileset.set_content_base_uri(base);
+            }
             let available_levels = tileset.available_levels();
             // A five level deep tree is still managable in size.
             if available_levels > 5 {
@@ -912,11 +2638,71 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 for (filename, child_tileset) in &external_tilesets {
                     let tileset_path = cli.output.join(filename);
                     child_tileset.to_file(&tileset_path)?;
+                    if cli.cesium3dtiles_gzip {
+                        gzip::write_copy(&tileset_path)?;
+                    }
                 }
             }
         }
         info!("Writing 3D Tiles tileset");
         tileset.to_file(&tileset_path)?;
+        if cli.cesium3dtiles_gzip {
+            let path_gz = gzip::write_copy(&tileset_path)?;
+            info!("Wrote the compressed tileset to {path_gz:?}");
+        }
+
+        // The catalogue entries are named after the output directory
+        let collection_id = cli
+            .output
+            .canonicalize()?
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "tyler".to_string());
+        let extent = spatial_structs::InputBbox::new(
+            &quadtree.node_content_bbox(&world, cli.grid_minz, cli.grid_maxz),
+            world.crs.to_epsg()?,
+        );
+        if cli.ogc_geovolumes {
+            let collections =
+                formats::geovolumes::Collections::new(&collection_id, &extent, "tileset.json")?;
+            let collections_path = cli.output.join("collections.json");
+            info!("Writing OGC API - 3D GeoVolumes collections to {collections_path:?}");
+            collections.to_file(&collections_path)?;
+        }
+
+        if cli.viewer_html {
+            let viewer_path = cli.output.join("viewer.html");
+            info!("Writing the CesiumJS viewer page to {viewer_path:?}");
+            formats::viewer::to_file("tileset.json", &viewer_path)?;
+        }
+
+        info!("Writing run report to {:?}", &report_path);
+        run_report.to_file(&report_path)?;
+
+        if cli.stac {
+            let mut assets = vec![("report", "report.json")];
+            if cli.ogc_geovolumes {
+                assets.push(("collections", "collections.json"));
+            }
+            let (collection, item) = formats::stac::collection_and_item(
+                &collection_id,
+                &extent,
+                &format!(
+                    "Tiled from the CityJSONFeatures in {}",
+                    cli.features.display()
+                ),
+                &assets,
+                cli.seed,
+            )?;
+            info!("Writing STAC Collection and Item to {:?}", &cli.output);
+            formats::stac::to_file(&collection, cli.output.join("collection.json"))?;
+            formats::stac::to_file(&item, cli.output.join("item.json"))?;
+        }
+    }
+
+    if let Some(template) = &cli.post_run_cmd {
+        info!("Running the post-run command");
+        run_hook_cmd(template, &cli.output, &report_path)?;
     }
 
     Ok(())
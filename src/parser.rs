@@ -14,9 +14,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::fs::{read_to_string, File};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
 use log::{debug, error, info, warn};
@@ -25,28 +26,71 @@ use serde::{Deserialize, Serialize};
 use serde_json::from_str;
 use walkdir::WalkDir;
 
-use crate::spatial_structs::{BboxQc, Cell, CellId};
+use crate::spatial_structs::{
+    bbox_contains, bbox_to_wkt, mix_seed, Bbox, Bbox2D, BboxQc, Cell, CellId,
+};
 
 /// Represents the "world" that contains some features and needs to be partitioned into
 /// tiles.
 ///
 /// # Members
 ///
-/// `path_features_root` - The path to the root directory containing all features.
+/// `path_features_root` - The path to the root directory containing all features. The paths of
+/// the features are stored relative to it, see [World::feature_path].
 ///
 /// `path_metadata` - The path to the JSON file that stores the
 /// [CityJSON object](https://www.cityjson.org/specs/1.1.3/#cityjson-object)
 /// (also called CityJSON metadata in *tyler*).
 ///
 /// `cityobject_types` - The World only contains features of these types.
+///
+/// `id_filter` - The World only contains the features that are selected by their CityObject IDs.
+///
+/// `extent_declared` - The `geographicalExtent` from the CityJSON metadata, if it is declared,
+/// or the extent that overrides it.
+///
+/// `clip_to_extent` - Leave out the features whose bounding box center is outside of
+/// `extent_declared`.
+///
+/// `insert_bbox` - Insert the features into every grid cell that their bounding box
+/// intersects, see [crate::spatial_structs::SquareGrid::insert_bbox].
+///
+/// `seed` - Seed for breaking the ties in the cell assignment of the features, so that the
+/// assignment does not depend on the iteration order.
+///
+/// `priority_attribute` - The CityObject attribute that marks the features that are important
+/// for loading the tileset, see [Feature::priority].
+///
+/// `group_attribute` - The CityObject attribute that assigns the features to the content
+/// groups, see [Feature::group].
+///
+/// `features_outside_extent` - The indices of the features in `features` that are not
+/// completely within `extent_declared`.
+///
+/// `features_crs_mismatch` - The CityJSONFeature files that declare a different CRS than the
+/// CityJSON metadata, together with the CRS that they declare. These features are not part of
+/// the World.
 #[derive(Serialize, Deserialize)]
 pub struct World {
     pub cityobject_types: Option<Vec<CityObjectType>>,
+    pub clip_to_extent: bool,
     pub crs: Crs,
+    pub extent_declared: Option<Bbox>,
+    /// The extent that the grid covers, which is computed from the features, unless it is
+    /// overridden.
+    pub extent: Bbox,
     pub features: FeatureSet,
+    pub features_crs_mismatch: Vec<(PathBuf, String)>,
+    pub features_outside_extent: Vec<usize>,
+    pub geometry_templates: Option<GeometryTemplates>,
     pub grid: crate::spatial_structs::SquareGrid,
+    pub group_attribute: Option<String>,
+    pub id_filter: IdFilter,
+    pub insert_bbox: bool,
     pub path_features_root: PathBuf,
     pub path_metadata: PathBuf,
+    pub priority_attribute: Option<String>,
+    pub seed: u64,
     pub transform: Transform,
 }
 
@@ -55,6 +99,36 @@ struct ExtentQcResult {
     nr_features: usize,
     cityobject_types_ignored: Vec<CityObjectType>,
     nr_features_ignored: usize,
+    nr_features_excluded: usize,
+    features_crs_mismatch: Vec<(PathBuf, String)>,
+}
+
+impl ExtentQcResult {
+    fn new(extent_qc: BboxQc) -> Self {
+        Self {
+            extent_qc,
+            nr_features: 0,
+            cityobject_types_ignored: Vec::new(),
+            nr_features_ignored: 0,
+            nr_features_excluded: 0,
+            features_crs_mismatch: Vec::new(),
+        }
+    }
+
+    /// Merge the results of another directory into this one.
+    fn merge(&mut self, other: ExtentQcResult) {
+        self.extent_qc.update_with(&other.extent_qc);
+        self.nr_features += other.nr_features;
+        self.nr_features_ignored += other.nr_features_ignored;
+        self.nr_features_excluded += other.nr_features_excluded;
+        for cotype in other.cityobject_types_ignored {
+            if !self.cityobject_types_ignored.contains(&cotype) {
+                self.cityobject_types_ignored.push(cotype);
+            }
+        }
+        self.features_crs_mismatch
+            .extend(other.features_crs_mismatch);
+    }
 }
 
 struct FeatureDirsFiles {
@@ -66,22 +140,45 @@ struct FeatureDirsFiles {
 struct FeatureInGridCells {
     feature: Feature,
     cells: Vec<(CellId, Cell)>,
+    /// The feature does not have a weight in the [FeatureWeights].
+    weight_missing: bool,
+}
+
+/// The options of [World::new], which select the features and set the extent of the grid.
+#[derive(Debug, Default)]
+pub struct WorldOptions {
+    pub cityobject_types: Option<Vec<CityObjectType>>,
+    pub id_filter: IdFilter,
+    /// The minimum and maximum z of the grid, if they are not computed from the features.
+    pub minz: Option<i32>,
+    pub maxz: Option<i32>,
+    /// The origin that the grid is aligned to.
+    pub origin: Option<[f64; 3]>,
+    /// The extent of the grid, instead of the extent of the features.
+    pub extent: Option<Bbox>,
 }
 
 impl World {
     pub fn new<P: AsRef<Path>>(
         path_metadata: P,
         path_features_root: P,
-        cellsize: u32,
-        cityobject_types: Option<Vec<CityObjectType>>,
-        arg_minz: Option<i32>,
-        arg_maxz: Option<i32>,
+        cellsize: f64,
+        options: WorldOptions,
     ) -> Result<Self, Box<dyn std::error::Error>> {
+        let WorldOptions {
+            cityobject_types,
+            id_filter,
+            minz: arg_minz,
+            maxz: arg_maxz,
+            origin,
+            extent,
+        } = options;
         let path_features_root = path_features_root.as_ref().to_path_buf();
         let path_metadata = path_metadata.as_ref().to_path_buf();
         let cm = CityJSONMetadata::from_file(&path_metadata)?;
-        let crs = cm.metadata.reference_system;
-        let transform = cm.transform;
+        if let Some(extent) = &cm.metadata.geographical_extent {
+            info!("Declared extent in the metadata: {}", bbox_to_wkt(extent));
+        }
 
         info!(
             "Computing extent from the features of type {:?}",
@@ -100,49 +197,66 @@ impl World {
         let extents: Vec<ExtentQcResult> = feature_dirs_files
             .feature_dirs
             .into_par_iter()
-            .filter_map(|dir| Self::extent_qc(dir, cityobject_types.as_ref()))
+            .filter_map(|dir| Self::extent_qc(dir, cityobject_types.as_ref(), &id_filter, &cm))
             .collect();
-        let mut nr_features = 0;
-        let mut nr_features_ignored = 0;
-        let mut extent_qc = Self::extent_qc_init(&path_features_root, cityobject_types.as_ref())
-            .unwrap_or_else(|| {
-                panic!(
-                    "Did not find any CityJSONFeature of type {:?} in {}",
-                    cityobject_types,
-                    path_features_root.display()
-                )
-            });
-        let mut cityobject_types_ignored: Vec<CityObjectType> = Vec::new();
-        for (i, extent) in extents.iter().enumerate() {
-            nr_features += extent.nr_features;
-            nr_features_ignored += extent.nr_features_ignored;
-            if i == 0 {
-                extent_qc = extent.extent_qc.clone();
-            } else {
-                extent_qc.update_with(&extent.extent_qc);
-            }
-            for cotype in &extent.cityobject_types_ignored {
-                if !cityobject_types_ignored.contains(cotype) {
-                    cityobject_types_ignored.push(*cotype);
-                }
-            }
-        }
+        let extent_qc_init = Self::extent_qc_init(
+            &path_features_root,
+            cityobject_types.as_ref(),
+            &id_filter,
+            &cm,
+        )
+        .ok_or_else(|| {
+            format!(
+                "Did not find any CityJSONFeature of type {:?} in {}",
+                cityobject_types,
+                path_features_root.display()
+            )
+        })?;
         // Walk the files at the root and update the counters
+        let mut extent_result = ExtentQcResult::new(extent_qc_init);
         for feature_path in &feature_dirs_files.feature_files {
             Self::extent_qc_file(
                 cityobject_types.as_ref(),
-                &mut extent_qc,
-                &mut nr_features,
-                &mut nr_features_ignored,
-                &mut cityobject_types_ignored,
+                &id_filter,
+                &cm,
+                &mut extent_result,
                 feature_path,
             );
         }
+        for extent in extents {
+            extent_result.merge(extent);
+        }
+        let ExtentQcResult {
+            extent_qc,
+            nr_features,
+            cityobject_types_ignored,
+            nr_features_ignored,
+            nr_features_excluded,
+            features_crs_mismatch,
+        } = extent_result;
+        if !id_filter.is_empty() {
+            info!(
+                "Excluded {} features by their CityObject IDs",
+                nr_features_excluded
+            );
+        }
+        if !features_crs_mismatch.is_empty() {
+            info!(
+                "Ignored {} features that declare a different CRS than the metadata ({})",
+                features_crs_mismatch.len(),
+                cm.metadata.reference_system.0
+            );
+        }
+        let crs = cm.metadata.reference_system;
+        let extent_declared = extent.or(cm.metadata.geographical_extent);
+        let transform = cm.transform;
+        let geometry_templates = cm.geometry_templates;
         if nr_features == 0 {
-            panic!(
+            return Err(format!(
                 "Did not find any CityJSONFeatures of type {:?}",
                 cityobject_types
-            );
+            )
+            .into());
         }
         info!(
             "Found {} features of type {:?}",
@@ -153,15 +267,32 @@ impl World {
             nr_features_ignored, &cityobject_types_ignored
         );
         debug!("extent_qc: {:?}", &extent_qc);
-        let extent_rw = extent_qc.to_bbox(&transform, arg_minz, arg_maxz);
+        let mut extent_rw = extent_qc.to_bbox(&transform, arg_minz, arg_maxz);
         info!(
             "Computed extent from features: {}",
             crate::spatial_structs::bbox_to_wkt(&extent_rw)
         );
+        if let Some(extent) = extent {
+            info!("Overriding the extent with {}", bbox_to_wkt(&extent));
+            extent_rw = extent;
+            if let Some(minz) = arg_minz {
+                extent_rw[2] = minz as f64;
+            }
+            if let Some(maxz) = arg_maxz {
+                extent_rw[5] = maxz as f64;
+            }
+        }
 
         // Allocate the grid, but at this point it is still empty
         let epsg = crs.to_epsg()?;
-        let grid = crate::spatial_structs::SquareGrid::new(&extent_rw, cellsize, epsg);
+        let mut grid = match origin {
+            Some(origin) => {
+                info!("Aligning the grid to the origin {:?}", origin);
+                crate::spatial_structs::SquareGrid::new_snapped(&extent_rw, cellsize, epsg, &origin)
+            }
+            None => crate::spatial_structs::SquareGrid::new(&extent_rw, cellsize, epsg),
+        };
+        grid.choose_storage(nr_features);
         debug!("{}", grid);
 
         // Allocate the features container, but at this point it is still empty
@@ -169,11 +300,22 @@ impl World {
         features.resize(nr_features + 1, Feature::default());
 
         Ok(Self {
+            clip_to_extent: false,
+            insert_bbox: false,
+            priority_attribute: None,
+            group_attribute: None,
+            seed: 0,
             features,
+            features_crs_mismatch,
+            features_outside_extent: Vec::new(),
+            geometry_templates,
             crs,
+            extent_declared,
+            extent: extent_rw,
             transform,
             grid,
             cityobject_types,
+            id_filter,
             path_features_root,
             path_metadata,
         })
@@ -185,7 +327,11 @@ impl World {
     fn find_feature_dirs_and_files(path_features_root: &PathBuf) -> FeatureDirsFiles {
         let mut path_features_root_dirs: Vec<PathBuf> = Vec::new();
         let mut path_features_root_files: Vec<PathBuf> = Vec::new();
-        for entry_res in WalkDir::new(path_features_root).min_depth(1).max_depth(1) {
+        for entry_res in WalkDir::new(path_features_root)
+            .min_depth(1)
+            .max_depth(1)
+            .sort_by_file_name()
+        {
             if let Ok(entry) = entry_res {
                 if entry.file_type().is_dir() {
                     path_features_root_dirs.push(entry.path().to_path_buf());
@@ -213,6 +359,8 @@ impl World {
     fn extent_qc<P: AsRef<Path> + std::fmt::Debug>(
         path_features: P,
         cityobject_types: Option<&Vec<CityObjectType>>,
+        id_filter: &IdFilter,
+        cm: &CityJSONMetadata,
     ) -> Option<ExtentQcResult> {
         // Do a first loop over the features to calculate their extent and their number.
         // Need a mutable iterator, because .next() consumes the next value and advances the iterator.
@@ -223,27 +371,31 @@ impl World {
         // We do not use extent_qc_init() here, because we need to collect the CityObject types
         // and counts accurately, and we want to retain the position of the features_enum_iter
         // for the full iteration after the first feature has been found.
-        let mut extent_qc = BboxQc([0, 0, 0, 0, 0, 0]);
+        let mut result = ExtentQcResult::new(BboxQc([0, 0, 0, 0, 0, 0]));
         let mut found_feature_type = false;
-        let mut nr_features = 0;
-        let mut nr_features_ignored = 0;
-        let mut cityobject_types_ignored: Vec<CityObjectType> = Vec::new();
         // Iterate only until the first feature is found
         #[allow(clippy::while_let_on_iterator)]
         while let Some(feature_path) = features_enum_iter.next() {
             match CityJSONFeatureVertices::from_file(&feature_path) {
                 Ok(cf) => {
-                    if let Some(eqc) = cf.bbox_of_types(cityobject_types) {
-                        extent_qc = eqc;
+                    if let Some(crs) = cf.crs_differs(&cm.metadata.reference_system) {
+                        result
+                            .features_crs_mismatch
+                            .push((feature_path, crs.0.clone()));
+                    } else if !id_filter.accepts(&cf) {
+                        result.nr_features_excluded += 1;
+                    } else if let Some(eqc) = cf.bbox_with_external(cityobject_types, &cm.transform)
+                    {
+                        result.extent_qc = eqc;
                         found_feature_type = true;
-                        nr_features += 1;
+                        result.nr_features += 1;
                         break;
                     } else {
                         for (_, co) in cf.cityobjects.iter() {
-                            if !cityobject_types_ignored.contains(&co.cotype) {
-                                cityobject_types_ignored.push(co.cotype);
+                            if !result.cityobject_types_ignored.contains(&co.cotype) {
+                                result.cityobject_types_ignored.push(co.cotype.clone());
                             }
-                            nr_features_ignored += 1;
+                            result.nr_features_ignored += 1;
                         }
                     }
                 }
@@ -256,21 +408,9 @@ impl World {
             return None;
         }
         for feature_path in features_enum_iter {
-            Self::extent_qc_file(
-                cityobject_types,
-                &mut extent_qc,
-                &mut nr_features,
-                &mut nr_features_ignored,
-                &mut cityobject_types_ignored,
-                &feature_path,
-            );
+            Self::extent_qc_file(cityobject_types, id_filter, cm, &mut result, &feature_path);
         }
-        Some(ExtentQcResult {
-            extent_qc,
-            nr_features,
-            cityobject_types_ignored,
-            nr_features_ignored,
-        })
+        Some(result)
     }
 
     /// Initialize a [BboxQc] from the first feature of the correct type that is found in the
@@ -278,6 +418,8 @@ impl World {
     fn extent_qc_init<P: AsRef<Path> + std::fmt::Debug>(
         path_features: P,
         cityobject_types: Option<&Vec<CityObjectType>>,
+        id_filter: &IdFilter,
+        cm: &CityJSONMetadata,
     ) -> Option<BboxQc> {
         let features_enum_iter = WalkDir::new(&path_features)
             .into_iter()
@@ -286,7 +428,12 @@ impl World {
         for feature_path in features_enum_iter {
             match CityJSONFeatureVertices::from_file(&feature_path) {
                 Ok(cf) => {
-                    let extent_qc_op = cf.bbox_of_types(cityobject_types);
+                    if cf.crs_differs(&cm.metadata.reference_system).is_some()
+                        || !id_filter.accepts(&cf)
+                    {
+                        continue;
+                    }
+                    let extent_qc_op = cf.bbox_with_external(cityobject_types, &cm.transform);
                     if extent_qc_op.is_some() {
                         return extent_qc_op;
                     }
@@ -301,14 +448,20 @@ impl World {
 
     fn extent_qc_file(
         cityobject_types: Option<&Vec<CityObjectType>>,
-        extent_qc: &mut BboxQc,
-        nr_features: &mut usize,
-        nr_features_ignored: &mut usize,
-        cityobject_types_ignored: &mut Vec<CityObjectType>,
+        id_filter: &IdFilter,
+        cm: &CityJSONMetadata,
+        result: &mut ExtentQcResult,
         feature_path: &PathBuf,
     ) {
         if let Ok(cf) = CityJSONFeatureVertices::from_file(feature_path) {
-            if let Some(bbox_qc) = cf.bbox_of_types(cityobject_types) {
+            if let Some(crs) = cf.crs_differs(&cm.metadata.reference_system) {
+                result
+                    .features_crs_mismatch
+                    .push((feature_path.clone(), crs.0.clone()));
+            } else if !id_filter.accepts(&cf) {
+                result.nr_features_excluded += 1;
+            } else if let Some(bbox_qc) = cf.bbox_with_external(cityobject_types, &cm.transform) {
+                let extent_qc = &mut result.extent_qc;
                 let [x_min, y_min, z_min, x_max, y_max, z_max] = bbox_qc.0;
                 if x_min < extent_qc.0[0] {
                     extent_qc.0[0] = x_min
@@ -325,13 +478,13 @@ impl World {
                 } else if z_max > extent_qc.0[5] {
                     extent_qc.0[5] = z_max
                 }
-                *nr_features += 1;
+                result.nr_features += 1;
             } else {
                 for (_, co) in cf.cityobjects.iter() {
-                    if !cityobject_types_ignored.contains(&co.cotype) {
-                        cityobject_types_ignored.push(co.cotype);
+                    if !result.cityobject_types_ignored.contains(&co.cotype) {
+                        result.cityobject_types_ignored.push(co.cotype.clone());
                     }
-                    *nr_features_ignored += 1;
+                    result.nr_features_ignored += 1;
                 }
             }
         } else {
@@ -363,7 +516,8 @@ impl World {
     }
 
     // Loop through the features and assign the features to the grid cells.
-    pub fn index_with_grid(&mut self) {
+    // If `weights` are provided, the grid cells also sum up the weights of their features.
+    pub fn index_with_grid(&mut self, weights: Option<&FeatureWeights>) {
         let feature_dirs_files = Self::find_feature_dirs_and_files(&self.path_features_root);
         info!("Counting vertices in grid cells");
         // todo input: split input file by newline?
@@ -373,9 +527,10 @@ impl World {
             .into_par_iter()
             .map(|dir| {
                 WalkDir::new(dir)
+                    .sort_by_file_name()
                     .into_iter()
                     .filter_map(Self::jsonl_path)
-                    .filter_map(|feature_path| self.index_feature_path(&feature_path))
+                    .filter_map(|feature_path| self.index_feature_path(&feature_path, weights))
                     .collect()
             })
             .collect();
@@ -383,10 +538,11 @@ impl World {
         let features_in_cells_files: Vec<FeatureInGridCells> = feature_dirs_files
             .feature_files
             .iter()
-            .filter_map(|feature_path| self.index_feature_path(feature_path))
+            .filter_map(|feature_path| self.index_feature_path(feature_path, weights))
             .collect();
 
         let mut fcount: usize = 0;
+        let mut nr_weights_missing: usize = 0;
         for (fid, feature_in_cells) in features_in_cells_dirs
             .iter()
             .flatten()
@@ -394,27 +550,156 @@ impl World {
             .enumerate()
         {
             self.features[fid] = feature_in_cells.feature.clone();
+            if let Some(extent) = &self.extent_declared {
+                let bbox = feature_in_cells
+                    .feature
+                    .bbox_qc
+                    .to_bbox(&self.transform, None, None);
+                if !bbox_contains(extent, &bbox) {
+                    self.features_outside_extent.push(fid);
+                }
+            }
             for (cellid, cell) in &feature_in_cells.cells {
                 let grid_cell = self.grid.cell_mut(cellid);
                 grid_cell.nr_vertices += cell.nr_vertices;
                 if !grid_cell.feature_ids.contains(&fid) {
-                    grid_cell.feature_ids.push(fid)
+                    grid_cell.feature_ids.push(fid);
+                    grid_cell.weight += feature_in_cells.feature.weight;
+                    grid_cell.nr_triangles += feature_in_cells.feature.nr_triangles;
                 }
             }
+            // The vertices and the weight stay in the cells above, so that a feature is counted
+            // once for the capacity
+            if self.insert_bbox {
+                let bbox = feature_in_cells
+                    .feature
+                    .bbox_qc
+                    .to_bbox(&self.transform, None, None);
+                self.grid.insert_bbox(&bbox, fid);
+            }
+            if feature_in_cells.weight_missing {
+                nr_weights_missing += 1;
+            }
             fcount += 1;
         }
         debug!("indexed {} features", fcount);
+        if nr_weights_missing > 0 {
+            warn!(
+                "{} features do not have a weight, they are counted with weight {}",
+                nr_weights_missing,
+                FeatureWeights::DEFAULT_WEIGHT
+            );
+        }
+    }
+
+    /// Replace the grid with a grid of the same cell size whose origin and extent are pinned
+    /// to the `grid_extent`, see [crate::spatial_structs::SquareGrid::new_pinned]. The grid
+    /// must be empty, thus it is called before [World::index_with_grid].
+    pub fn pin_grid(&mut self, grid_extent: &[f64; 4]) {
+        self.grid = crate::spatial_structs::SquareGrid::new_pinned(
+            &self.extent,
+            grid_extent,
+            self.grid.cellsize(),
+            self.grid.epsg,
+        );
+        self.grid.choose_storage(self.features.len());
+        let [minx, miny, _, maxx, maxy, _] = self.extent;
+        if minx < grid_extent[0]
+            || miny < grid_extent[1]
+            || maxx > grid_extent[2]
+            || maxy > grid_extent[3]
+        {
+            warn!(
+                "The extent of the features {} is not within the grid extent {:?}, the parts of the features outside of the grid are left out",
+                bbox_to_wkt(&self.extent),
+                grid_extent
+            );
+        }
+    }
+
+    /// Replace the square grid with a rectangular grid that only stores the cells that cover
+    /// the extent, see [crate::spatial_structs::SquareGrid::cropped]. The grid must be empty,
+    /// thus it is called before [World::index_with_grid].
+    pub fn crop_grid(&mut self) {
+        self.grid = self.grid.cropped(&self.extent);
+        self.grid.choose_storage(self.features.len());
+    }
+
+    /// Halve the cell size of the grid and index the features again, see
+    /// [crate::spatial_structs::SquareGrid::refined].
+    pub fn refine_grid(&mut self, weights: Option<&FeatureWeights>) {
+        self.grid = self.grid.refined();
+        self.grid.choose_storage(self.features.len());
+        self.features_outside_extent.clear();
+        self.index_with_grid(weights);
+    }
+
+    /// The features whose 2D bounding box is at least `min_size` long on one of its sides,
+    /// together with their bounding box.
+    pub fn large_features(&self, min_size: f64) -> Vec<(usize, Bbox2D)> {
+        self.features
+            .iter()
+            .enumerate()
+            .filter_map(|(fid, feature)| {
+                let b = feature.bbox_qc.to_bbox(&self.transform, None, None);
+                let large = b[3] - b[0] >= min_size || b[4] - b[1] >= min_size;
+                large.then_some((fid, [b[0], b[1], b[3], b[4]]))
+            })
+            .collect()
+    }
+
+    /// Add the features that are at least `min_size` long to every grid cell that their
+    /// bounding box intersects, not only to the cells that received them by their vertices.
+    /// The vertex count and the weight of the cells are not changed, so that a long feature
+    /// does not make all of its cells full.
+    /// Returns the number of large features.
+    pub fn assign_large_features(&mut self, min_size: f64) -> usize {
+        let large_features = self.large_features(min_size);
+        for (fid, b) in &large_features {
+            self.grid
+                .insert_bbox(&[b[0], b[1], 0.0, b[2], b[3], 0.0], *fid);
+        }
+        large_features.len()
     }
 
     /// Indexes a CityJSONFeature file.
-    fn index_feature_path(&self, feature_path: &PathBuf) -> Option<FeatureInGridCells> {
+    fn index_feature_path(
+        &self,
+        feature_path: &PathBuf,
+        weights: Option<&FeatureWeights>,
+    ) -> Option<FeatureInGridCells> {
         // todo input: adapt to interate the newline-split file and index per line
         let cf = CityJSONFeatureVertices::from_file(feature_path);
         if let Ok(featurevertices) = cf {
+            if featurevertices.crs_differs(&self.crs).is_some()
+                || !self.id_filter.accepts(&featurevertices)
+            {
+                // Already reported when computing the extent
+                return None;
+            }
+            if self.clip_to_extent && !self.center_in_extent(&featurevertices) {
+                return None;
+            }
             let cell_vtx_cnt = self.count_vertices(&featurevertices);
             if !cell_vtx_cnt.is_empty() {
                 // We found at least one CityObject of the required type
-                self.feature_to_cells(feature_path, &featurevertices, cell_vtx_cnt)
+                let mut feature_in_cells =
+                    self.feature_to_cells(feature_path, &featurevertices, cell_vtx_cnt)?;
+                if let Some(attribute) = &self.priority_attribute {
+                    feature_in_cells.feature.priority =
+                        featurevertices.has_priority(attribute, self.cityobject_types.as_ref());
+                }
+                if let Some(attribute) = &self.group_attribute {
+                    feature_in_cells.feature.group =
+                        featurevertices.group_value(attribute, self.cityobject_types.as_ref());
+                }
+                if let Some(weights) = weights {
+                    let weight = weights.weight(&featurevertices);
+                    feature_in_cells.weight_missing = weight.is_none();
+                    feature_in_cells.feature.weight =
+                        weight.unwrap_or(FeatureWeights::DEFAULT_WEIGHT);
+                }
+                Some(feature_in_cells)
             } else {
                 None
             }
@@ -452,6 +737,10 @@ impl World {
                                 (vtx_qc[1] as f64 * self.transform.scale[1])
                                     + self.transform.translate[1],
                             ];
+                            // The grid does not cover the whole data if the extent is overridden
+                            if !self.grid.contains_point(&vtx_rw) {
+                                continue;
+                            }
                             let cellid = self.grid.locate_point(&vtx_rw);
                             *cell_vtx_cnt.entry(cellid).or_insert(1) += 1;
                         }
@@ -462,10 +751,27 @@ impl World {
         // After counting the object vertices in the cells, we need to
         // assign the object to the cells that intersect with its bbox,
         // because of https://github.com/3DGI/tyler/issues/28
-        if let Some(bbox_qc) = featurevertices.bbox_of_types(self.cityobject_types.as_ref()) {
-            let bbox = bbox_qc.to_bbox(&self.transform, None, None);
-            let intersecting_cellids = self.grid.intersect_bbox(&bbox);
-            for cellid in intersecting_cellids {
+        // The CityObjects that reference external geometry are assigned by their extent.
+        if let Some(bbox_qc) =
+            featurevertices.bbox_with_external(self.cityobject_types.as_ref(), &self.transform)
+        {
+            let mut bbox = bbox_qc.to_bbox(&self.transform, None, None);
+            let grid_bbox = &self.grid.bbox;
+            let intersecting_cellids = if bbox[0] < grid_bbox[3]
+                && bbox[1] < grid_bbox[4]
+                && bbox[3] >= grid_bbox[0]
+                && bbox[4] >= grid_bbox[1]
+            {
+                bbox[0] = bbox[0].max(grid_bbox[0]);
+                bbox[1] = bbox[1].max(grid_bbox[1]);
+                self.grid.intersect_bbox(&bbox)
+            } else {
+                Vec::new()
+            };
+            for cellid in intersecting_cellids
+                .into_iter()
+                .filter(|cellid| self.grid.contains_cell(cellid))
+            {
                 // Just add a new entry with the intersecting cell to the map, but no not
                 // increase the vertex count, because the vertices have been counted
                 // already, these might be cells where the object does not actually have a
@@ -477,17 +783,58 @@ impl World {
         cell_vtx_cnt
     }
 
+    /// Returns `true` if the bounding box center of the feature is within the `extent_declared`,
+    /// or if there is no extent.
+    fn center_in_extent(&self, featurevertices: &CityJSONFeatureVertices) -> bool {
+        let Some(extent) = &self.extent_declared else {
+            return true;
+        };
+        let Some(bbox_qc) =
+            featurevertices.bbox_with_external(self.cityobject_types.as_ref(), &self.transform)
+        else {
+            return true;
+        };
+        let bbox = bbox_qc.to_bbox(&self.transform, None, None);
+        let center = [(bbox[0] + bbox[3]) / 2.0, (bbox[1] + bbox[4]) / 2.0];
+        center[0] >= extent[0]
+            && center[0] <= extent[3]
+            && center[1] >= extent[1]
+            && center[1] <= extent[4]
+    }
+
     /// Converts the [CityJSONFeatureVertices] into a [Feature] and returns the grid cells where
     /// where the feature is located.
     fn feature_to_cells(
         &self,
-        feature_path: &PathBuf,
+        feature_path: &Path,
         featurevertices: &CityJSONFeatureVertices,
         cell_vtx_cnt: HashMap<CellId, usize>,
     ) -> Option<FeatureInGridCells> {
         // TODO: what other cityobject types need to have 1-1 cell assignment?
         if let Some(ref cotypes) = self.cityobject_types {
-            let feature = featurevertices.to_feature(feature_path);
+            // The features store their path relative to the root, because the root is the same
+            // long prefix for all of them
+            let mut feature = featurevertices.to_feature(
+                feature_path
+                    .strip_prefix(&self.path_features_root)
+                    .unwrap_or(feature_path),
+            );
+            feature.external_geometry = featurevertices.external_geometry(cotypes, feature_path);
+            feature.nr_triangles = featurevertices.triangle_count(Some(cotypes));
+            feature.cityobject_types = featurevertices.cityobject_types(Some(cotypes));
+            if featurevertices.vertices.is_empty() {
+                // The feature only has external geometry, so it is located by the extent of
+                // its CityObjects
+                if let Some(bbox_qc) =
+                    featurevertices.bbox_with_external(Some(cotypes), &self.transform)
+                {
+                    feature.centroid_qc = [
+                        (bbox_qc.0[0] + bbox_qc.0[3]) / 2,
+                        (bbox_qc.0[1] + bbox_qc.0[4]) / 2,
+                    ];
+                    feature.bbox_qc = bbox_qc;
+                }
+            }
             let mut cells: Vec<(CellId, Cell)> = Vec::with_capacity(cell_vtx_cnt.len());
             if cotypes.contains(&CityObjectType::Building)
                 || cotypes.contains(&CityObjectType::BuildingPart)
@@ -500,16 +847,21 @@ impl World {
                 // require different cell-assignment methods into the same tileset.
                 // E.g. terrain features need to be duplicated across cells, buildings need to
                 // unique. The tileset for them must be generated separately.
+                // The ties are broken with the seed, because the iteration order of the
+                // HashMap differs between the runs.
                 let (cellid, nr_vertices) = cell_vtx_cnt
                     .iter()
-                    .max_by(|a, b| a.1.cmp(b.1))
-                    .map(|(k, v)| (k, v))
+                    .max_by_key(|(cellid, nr_vertices)| {
+                        let key = ((cellid.row as u64) << 32) | cellid.column as u64;
+                        (**nr_vertices, mix_seed(self.seed, key))
+                    })
                     .unwrap();
                 cells.push((
                     *cellid,
                     Cell {
                         feature_ids: Vec::new(),
                         nr_vertices: *nr_vertices,
+                        ..Default::default()
                     },
                 ));
             } else {
@@ -519,16 +871,48 @@ impl World {
                         Cell {
                             feature_ids: Vec::new(),
                             nr_vertices: *nr_vertices,
+                            ..Default::default()
                         },
                     ));
                 }
             }
-            Some(FeatureInGridCells { feature, cells })
+            Some(FeatureInGridCells {
+                feature,
+                cells,
+                weight_missing: false,
+            })
         } else {
             None
         }
     }
 
+    /// The path of the CityJSONFeature file of the feature `fid`.
+    pub fn feature_path(&self, fid: usize) -> PathBuf {
+        self.path_features_root.join(&self.features[fid].path_jsonl)
+    }
+
+    /// The feature table of a tile, that maps the features `fids` in the order of the tile
+    /// content to their CityObject IDs and their CityJSONFeature file.
+    /// The CityObject IDs are read from the feature files.
+    pub fn feature_table(
+        &self,
+        fids: &[usize],
+    ) -> Result<Vec<FeatureTableEntry>, Box<dyn std::error::Error>> {
+        fids.iter()
+            .enumerate()
+            .map(|(feature_id, &fid)| {
+                let path = self.feature_path(fid);
+                let ids = CityJSONFeatureIds::from_file(&path)?;
+                Ok(FeatureTableEntry {
+                    feature_id,
+                    cityobjects: ids.cityobject_ids(),
+                    id: ids.id,
+                    path: path.to_string_lossy().to_string(),
+                })
+            })
+            .collect()
+    }
+
     /// Export the grid of the World into the working directory.
     pub fn export_grid(
         &self,
@@ -543,6 +927,90 @@ impl World {
         }
     }
 
+    /// Export the list of features that are outside of the declared extent into
+    /// `features_outside_extent.tsv` in the `output_dir`.
+    /// If `geojson` is true, the bounding boxes of the features are also exported to
+    /// `features_outside_extent.geojson`.
+    pub fn export_features_outside_extent(
+        &self,
+        output_dir: &Path,
+        geojson: bool,
+    ) -> std::io::Result<()> {
+        let mut file_tsv = File::create(output_dir.join("features_outside_extent.tsv"))?;
+        file_tsv.write_all("fid\tpath\twkt\n".as_bytes())?;
+        let mut features_geojson: Vec<serde_json::Value> = Vec::new();
+        for fid in &self.features_outside_extent {
+            let feature = &self.features[*fid];
+            let bbox = feature.bbox_qc.to_bbox(&self.transform, None, None);
+            file_tsv.write_all(
+                format!(
+                    "{}\t{}\t{}\n",
+                    fid,
+                    self.feature_path(*fid).display(),
+                    bbox_to_wkt(&bbox)
+                )
+                .as_bytes(),
+            )?;
+            if geojson {
+                features_geojson.push(serde_json::json!({
+                    "type": "Feature",
+                    "properties": {
+                        "fid": fid,
+                        "path": self.feature_path(*fid).to_string_lossy(),
+                    },
+                    "geometry": crate::spatial_structs::bbox_to_geojson(&bbox),
+                }));
+            }
+        }
+        if geojson {
+            let file_geojson = File::create(output_dir.join("features_outside_extent.geojson"))?;
+            serde_json::to_writer(
+                file_geojson,
+                &serde_json::json!({
+                    "type": "FeatureCollection",
+                    "features": features_geojson,
+                }),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Export the CityJSONFeature files that declare a different CRS than the metadata into
+    /// the `features_crs_mismatch.tsv` file in `output_dir`.
+    pub fn export_features_crs_mismatch(&self, output_dir: &Path) -> std::io::Result<()> {
+        let mut file_tsv = File::create(output_dir.join("features_crs_mismatch.tsv"))?;
+        file_tsv.write_all("path\tcrs\n".as_bytes())?;
+        for (path, crs) in &self.features_crs_mismatch {
+            file_tsv.write_all(format!("{}\t{}\n", path.display(), crs).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Export the origin of the grid and the coordinate transformation parameters into a
+    /// JSON file, so that separately generated tilesets can be aligned.
+    /// `origin` is the local origin that was forced with `--origin`, if any.
+    pub fn export_origin<P: AsRef<Path>>(
+        &self,
+        path: P,
+        origin: Option<[f64; 3]>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let sidecar = serde_json::json!({
+            "crs": format!("EPSG:{}", self.crs.to_epsg()?),
+            "origin": origin.unwrap_or_else(|| self.grid.origin()),
+            "grid": {
+                "origin": self.grid.origin(),
+                "cellsize": self.grid.cellsize(),
+                "length": self.grid.length,
+                "columns": self.grid.nr_columns,
+                "rows": self.grid.nr_rows,
+            },
+            "transform": &self.transform,
+        });
+        let file_out = std::io::BufWriter::new(File::create(path.as_ref())?);
+        serde_json::to_writer_pretty(file_out, &sidecar)?;
+        Ok(())
+    }
+
     pub fn export_bincode(
         &self,
         name: Option<&str>,
@@ -557,6 +1025,116 @@ impl World {
     }
 }
 
+/// Selects the CityJSONFeatures by the IDs of their CityObjects.
+///
+/// A feature is selected if it contains at least one of the `include` IDs (or `include` is
+/// not set), and it does not contain any of the `exclude` IDs.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct IdFilter {
+    pub include: Option<HashSet<String>>,
+    pub exclude: HashSet<String>,
+}
+
+impl IdFilter {
+    /// Read the include and exclude IDs from files that contain one CityObject ID per line.
+    pub fn from_files<P: AsRef<Path>>(
+        include: Option<P>,
+        exclude: Option<P>,
+    ) -> std::io::Result<Self> {
+        let include = match include {
+            Some(path) => Some(Self::read_ids(path)?),
+            None => None,
+        };
+        let exclude = match exclude {
+            Some(path) => Self::read_ids(path)?,
+            None => HashSet::new(),
+        };
+        Ok(Self { include, exclude })
+    }
+
+    fn read_ids<P: AsRef<Path>>(path: P) -> std::io::Result<HashSet<String>> {
+        let ids: HashSet<String> = read_to_string(path.as_ref())?
+            .lines()
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+            .collect();
+        info!("Read {} CityObject IDs from {:?}", ids.len(), path.as_ref());
+        Ok(ids)
+    }
+
+    /// Returns `true` if the filter does not select anything, thus all features are accepted.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_empty()
+    }
+
+    /// Returns `true` if the feature is selected by the filter.
+    pub fn accepts(&self, cf: &CityJSONFeatureVertices) -> bool {
+        if let Some(include) = &self.include {
+            if !cf.cityobjects.keys().any(|id| include.contains(id)) {
+                return false;
+            }
+        }
+        !cf.cityobjects.keys().any(|id| self.exclude.contains(id))
+    }
+}
+
+/// Precomputed weights of the features, eg. the content size or the rendering cost from a
+/// previous run, which are used as the quadtree capacity measure instead of the vertex count.
+/// The weights are given per CityObject ID, and the weight of a feature is the sum of the
+/// weights of its CityObjects.
+#[derive(Debug, Default)]
+pub struct FeatureWeights(HashMap<String, f64>);
+
+impl FeatureWeights {
+    /// The weight of the features that do not have a weight.
+    pub const DEFAULT_WEIGHT: usize = 1;
+
+    /// Read the weights from a CSV file with the CityObject ID in the first column and the
+    /// weight in the second column. A header line is skipped.
+    pub fn from_csv<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut weights: HashMap<String, f64> = HashMap::new();
+        for (i, line) in read_to_string(path.as_ref())?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut columns = line.split(',').map(str::trim);
+            let id = columns.next().unwrap_or_default();
+            match columns.next().map(str::parse::<f64>) {
+                Some(Ok(weight)) if weight >= 0.0 => {
+                    weights.insert(id.to_string(), weight);
+                }
+                // The header
+                _ if i == 0 => continue,
+                _ => {
+                    return Err(format!(
+                        "invalid weight on line {} of {:?}: {}",
+                        i + 1,
+                        path.as_ref(),
+                        line
+                    )
+                    .into())
+                }
+            }
+        }
+        info!("Read {} weights from {:?}", weights.len(), path.as_ref());
+        Ok(Self(weights))
+    }
+
+    /// The weight of a feature, rounded to an integer. Returns `None` if none of the
+    /// CityObjects of the feature has a weight.
+    pub fn weight(&self, cf: &CityJSONFeatureVertices) -> Option<usize> {
+        let mut weight: Option<f64> = None;
+        for id in cf.cityobjects.keys() {
+            if let Some(w) = self.0.get(id) {
+                *weight.get_or_insert(0.0) += w;
+            }
+        }
+        weight.map(|w| w.round() as usize)
+    }
+}
+
 /// A partial [CityJSON object](https://www.cityjson.org/specs/1.1.3/#cityjson-object).
 /// It is partial, because we only store the metadata that is necessary for parsing the
 /// CityJSONFeatures.
@@ -564,6 +1142,18 @@ impl World {
 pub struct CityJSONMetadata {
     pub transform: Transform,
     pub metadata: Metadata,
+    #[serde(default, rename = "geometry-templates")]
+    pub geometry_templates: Option<GeometryTemplates>,
+}
+
+/// The [geometry templates](https://www.cityjson.org/specs/1.1.3/#geometry-templates) that
+/// are placed by the GeometryInstances of the CityObjects. The vertices of the templates are
+/// not quantized.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct GeometryTemplates {
+    pub templates: Vec<serde_json::Value>,
+    #[serde(rename = "vertices-templates")]
+    pub vertices_templates: Vec<[f64; 3]>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -576,6 +1166,8 @@ pub struct Transform {
 #[serde(rename_all = "camelCase")]
 pub struct Metadata {
     pub reference_system: Crs,
+    #[serde(default)]
+    pub geographical_extent: Option<Bbox>,
 }
 
 /// Coordinate Reference System as defined by the
@@ -594,7 +1186,7 @@ impl Crs {
     /// ```
     pub fn to_epsg(&self) -> Result<u16, Box<dyn std::error::Error>> {
         let parts: Vec<&str> = self.0.split('/').collect();
-        if let Some(authority) = parts.get(parts.len() - 3) {
+        if let Some(authority) = parts.len().checked_sub(3).and_then(|i| parts.get(i)) {
             if *authority != "EPSG" {
                 return Err(Box::try_from(format!(
                     "the CRS definition should be EPSG: {}",
@@ -604,7 +1196,7 @@ impl Crs {
             }
         }
         return if let Some(c) = parts.last() {
-            let code: u16 = c.parse::<u16>().unwrap();
+            let code: u16 = c.parse::<u16>()?;
             Ok(code)
         } else {
             Err(Box::try_from(format!(
@@ -630,6 +1222,17 @@ pub struct CityJSONFeatureVertices {
     #[serde(rename = "CityObjects")]
     pub cityobjects: HashMap<String, CityObject>,
     pub vertices: Vec<[i64; 3]>,
+    #[serde(default)]
+    pub metadata: Option<FeatureMetadata>,
+}
+
+/// The `metadata` member of a CityJSONFeature. CityJSONFeatures normally do not carry
+/// metadata, but some producers write the `referenceSystem` into each feature.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureMetadata {
+    #[serde(default)]
+    pub reference_system: Option<Crs>,
 }
 
 impl CityJSONMetadata {
@@ -647,6 +1250,49 @@ impl CityJSONFeatureVertices {
         Ok(cf)
     }
 
+    /// Return the CRS of the feature if it declares one that differs from `crs`.
+    /// Features that do not declare a CRS are assumed to be in `crs`.
+    pub fn crs_differs(&self, crs: &Crs) -> Option<&Crs> {
+        let crs_feature = self.metadata.as_ref()?.reference_system.as_ref()?;
+        let same = match (crs_feature.to_epsg(), crs.to_epsg()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => crs_feature.0 == crs.0,
+        };
+        if same {
+            None
+        } else {
+            Some(crs_feature)
+        }
+    }
+
+    /// The number of triangles of the surfaces of the CityObjects of the `cityobject_types`,
+    /// or of all CityObjects if `None`. A surface of `n` vertices with `h` holes is
+    /// triangulated into `n + 2h - 2` triangles.
+    pub fn triangle_count(&self, cityobject_types: Option<&Vec<CityObjectType>>) -> usize {
+        let surface_triangles = |srf: &Surface| {
+            let nr_vertices: usize = srf.iter().map(|ring| ring.len()).sum();
+            let nr_holes = srf.len().saturating_sub(1);
+            (nr_vertices + 2 * nr_holes).saturating_sub(2)
+        };
+        self.cityobjects
+            .values()
+            .filter(|co| co.is_of_types(cityobject_types))
+            .flat_map(|co| co.geometry.iter().flatten())
+            .map(|geom| match geom {
+                Geometry::MultiSurface { boundaries } => {
+                    boundaries.iter().map(surface_triangles).sum::<usize>()
+                }
+                Geometry::Solid { boundaries } => boundaries
+                    .iter()
+                    .flatten()
+                    .map(surface_triangles)
+                    .sum::<usize>(),
+                // The templates are not known here
+                Geometry::MultiPoint { .. } | Geometry::Instance { .. } => 0,
+            })
+            .sum()
+    }
+
     /// Return the number of vertices of the feature.
     /// We assume that the number of vertices in a feature does not exceed 65535 (thus `u16`).
     fn vertex_count(&self) -> u16 {
@@ -717,7 +1363,8 @@ impl CityJSONFeatureVertices {
     /// Compute the 3D bounding box of only the provided CityObject types in the feature.
     /// Returns quantized coordinates.
     pub fn bbox_of_types(&self, cityobject_types: Option<&Vec<CityObjectType>>) -> Option<BboxQc> {
-        let [mut x_min, mut y_min, mut z_min] = self.vertices[0];
+        // Features that only reference external geometry do not have vertices
+        let [mut x_min, mut y_min, mut z_min] = *self.vertices.first()?;
         let [mut x_max, mut y_max, mut z_max] = self.vertices[0];
         let mut found_co_geometry = false;
         for (_, co) in self.cityobjects.iter() {
@@ -785,6 +1432,30 @@ impl CityJSONFeatureVertices {
                                 }
                                 found_co_geometry = true;
                             }
+                            // Only the reference point of an instance, because the templates
+                            // are not known here
+                            Geometry::MultiPoint { boundaries }
+                            | Geometry::Instance { boundaries } => {
+                                for vtx in boundaries {
+                                    let [x, y, z] = &self.vertices[*vtx];
+                                    if *x < x_min {
+                                        x_min = *x
+                                    } else if *x > x_max {
+                                        x_max = *x
+                                    }
+                                    if *y < y_min {
+                                        y_min = *y
+                                    } else if *y > y_max {
+                                        y_max = *y
+                                    }
+                                    if *z < z_min {
+                                        z_min = *z
+                                    } else if *z > z_max {
+                                        z_max = *z
+                                    }
+                                }
+                                found_co_geometry = true;
+                            }
                         }
                     }
                 }
@@ -797,11 +1468,53 @@ impl CityJSONFeatureVertices {
         }
     }
 
+    /// Compute the 3D bounding box of only the provided CityObject types in the feature,
+    /// including the `geographicalExtent` of the CityObjects that reference external geometry.
+    /// Returns quantized coordinates.
+    pub fn bbox_with_external(
+        &self,
+        cityobject_types: Option<&Vec<CityObjectType>>,
+        transform: &Transform,
+    ) -> Option<BboxQc> {
+        let mut bbox_qc = self.bbox_of_types(cityobject_types);
+        for co in self.cityobjects.values() {
+            if co.external_geometry.is_empty() || !co.is_of_types(cityobject_types) {
+                continue;
+            }
+            if let Some(extent) = &co.geographical_extent {
+                let extent_qc = BboxQc::from_bbox(extent, transform);
+                match bbox_qc {
+                    Some(ref mut b) => b.update_with(&extent_qc),
+                    None => bbox_qc = Some(extent_qc),
+                }
+            }
+        }
+        bbox_qc
+    }
+
+    /// Resolve the external geometry references of the provided CityObject types in the
+    /// feature. See [ExternalGeometry::resolve].
+    pub fn external_geometry(
+        &self,
+        cityobject_types: &Vec<CityObjectType>,
+        feature_path: &Path,
+    ) -> Vec<String> {
+        self.cityobjects
+            .values()
+            .filter(|co| co.is_of_types(Some(cityobject_types)))
+            .flat_map(|co| co.external_geometry.iter())
+            .filter_map(|eg| eg.resolve(feature_path))
+            .collect()
+    }
+
     /// Compute the 2D quantized centroid and the 3D bounding box in one loop.
     ///
     /// Combines the [centroid_quantized] and [bbox] methods to compute the values in a single
     /// loop over the vertices.
     fn centroid_bbox_qc(&self) -> [i64; 8] {
+        if self.vertices.is_empty() {
+            return [0; 8];
+        }
         let mut x_sum: i64 = 0;
         let mut y_sum: i64 = 0;
         let [mut x_min, mut y_min, mut z_min] = self.vertices[0];
@@ -836,6 +1549,8 @@ impl CityJSONFeatureVertices {
         Feature {
             centroid_qc: [ctr_bbox[0], ctr_bbox[1]],
             nr_vertices: self.vertex_count(),
+            nr_triangles: 0,
+            cityobject_types: Vec::new(),
             path_jsonl: path.as_ref().to_path_buf(),
             bbox_qc: BboxQc([
                 ctr_bbox[2],
@@ -845,8 +1560,69 @@ impl CityJSONFeatureVertices {
                 ctr_bbox[6],
                 ctr_bbox[7],
             ]),
+            external_geometry: Vec::new(),
+            weight: 0,
+            priority: false,
+            group: None,
         }
     }
+
+    /// The type of each CityObject of the `cityobject_types`, sorted.
+    pub fn cityobject_types(
+        &self,
+        cityobject_types: Option<&Vec<CityObjectType>>,
+    ) -> Vec<CityObjectType> {
+        let mut types: Vec<CityObjectType> = self
+            .cityobjects
+            .values()
+            .filter(|co| co.is_of_types(cityobject_types))
+            .map(|co| co.cotype.clone())
+            .collect();
+        types.sort();
+        types
+    }
+
+    /// Returns true if a CityObject of the `cityobject_types` has a true value in the
+    /// `attribute`. The true values are `true`, a non-zero number and a string other than
+    /// `""`, `"false"`, `"no"` and `"0"`.
+    pub fn has_priority(
+        &self,
+        attribute: &str,
+        cityobject_types: Option<&Vec<CityObjectType>>,
+    ) -> bool {
+        self.cityobjects
+            .values()
+            .filter(|co| co.is_of_types(cityobject_types))
+            .filter_map(|co| co.attributes.get(attribute))
+            .any(|value| match value {
+                serde_json::Value::Bool(b) => *b,
+                serde_json::Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+                serde_json::Value::String(s) => {
+                    !matches!(s.to_lowercase().as_str(), "" | "false" | "no" | "0")
+                }
+                _ => false,
+            })
+    }
+
+    /// The value of the `attribute` of the CityObjects of the `cityobject_types`, which
+    /// assigns the feature to a content group. Strings and numbers are used as they are. If
+    /// the CityObjects have different values, the first one in the sort order is used, so
+    /// that the group does not depend on the order of the CityObjects.
+    pub fn group_value(
+        &self,
+        attribute: &str,
+        cityobject_types: Option<&Vec<CityObjectType>>,
+    ) -> Option<String> {
+        self.cityobjects
+            .values()
+            .filter(|co| co.is_of_types(cityobject_types))
+            .filter_map(|co| match co.attributes.get(attribute)? {
+                serde_json::Value::String(s) if !s.is_empty() => Some(s.clone()),
+                serde_json::Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+            .min()
+    }
 }
 
 /// Stores the information that is computed from a CityJSONFeature.
@@ -854,9 +1630,30 @@ impl CityJSONFeatureVertices {
 pub struct Feature {
     pub(crate) centroid_qc: [i64; 2],
     pub(crate) nr_vertices: u16,
+    /// The number of triangles of the CityObjects of the selected types, see
+    /// [CityJSONFeatureVertices::triangle_count].
+    #[serde(default)]
+    pub nr_triangles: usize,
+    /// The type of each CityObject of the selected types, see
+    /// [CityJSONFeatureVertices::cityobject_types].
+    #[serde(default)]
+    pub cityobject_types: Vec<CityObjectType>,
+    /// The path of the CityJSONFeature file, relative to [World::path_features_root].
     pub path_jsonl: PathBuf,
     // todo input: need line number in file
     pub bbox_qc: BboxQc,
+    /// The resolved references to the external geometry of the feature.
+    pub external_geometry: Vec<String>,
+    /// The weight of the feature from the [FeatureWeights], if the weights are used.
+    pub weight: usize,
+    /// The feature is important for loading the tileset, because one of its CityObjects has
+    /// a true value in the priority attribute, see [CityJSONFeatureVertices::has_priority].
+    #[serde(default)]
+    pub priority: bool,
+    /// The value of the group attribute of the feature, see
+    /// [CityJSONFeatureVertices::group_value].
+    #[serde(default)]
+    pub group: Option<String>,
 }
 
 impl Feature {
@@ -869,10 +1666,56 @@ impl Feature {
     }
 }
 
-#[derive(
-    Debug, Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Ord, PartialOrd, Eq, PartialEq,
-)]
-#[clap(rename_all = "PascalCase")]
+/// The IDs of a CityJSONFeature, without its geometry.
+#[derive(Deserialize, Debug)]
+pub struct CityJSONFeatureIds {
+    /// The ID of the parent CityObject of the feature.
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(rename = "CityObjects")]
+    cityobjects: BTreeMap<String, serde::de::IgnoredAny>,
+}
+
+impl CityJSONFeatureIds {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let cf_str = read_to_string(path.as_ref())?;
+        let cf: CityJSONFeatureIds = from_str(&cf_str)?;
+        Ok(cf)
+    }
+
+    /// The IDs of the CityObjects of the feature, the parent CityObject first and the others
+    /// sorted.
+    pub fn cityobject_ids(&self) -> Vec<String> {
+        let parent = self
+            .id
+            .as_ref()
+            .filter(|id| self.cityobjects.contains_key(*id));
+        parent
+            .into_iter()
+            .chain(self.cityobjects.keys().filter(|id| Some(*id) != parent))
+            .cloned()
+            .collect()
+    }
+}
+
+/// An entry of the feature table of a tile, see [World::feature_table].
+#[derive(Serialize, Debug)]
+pub struct FeatureTableEntry {
+    /// The position of the feature in the input of the tile conversion.
+    pub feature_id: usize,
+    /// The ID of the parent CityObject of the feature.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub cityobjects: Vec<String>,
+    /// The path of the CityJSONFeature file.
+    pub path: String,
+}
+
+/// The [CityObject types](https://www.cityjson.org/specs/1.1.3/#the-different-city-objects)
+/// of CityJSON, and the types that are defined by CityJSON Extensions, eg. `+NoiseBuilding`.
+/// The Extension types keep their name, including the `+` prefix.
+#[derive(Debug, Serialize, Deserialize, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[serde(try_from = "String", into = "String")]
 pub enum CityObjectType {
     Bridge,
     BridgePart,
@@ -889,6 +1732,7 @@ pub enum CityObjectType {
     BuildingRoom,
     BuildingUnit,
     CityFurniture,
+    CityObjectGroup,
     LandUse,
     OtherConstruction,
     PlantCover,
@@ -899,13 +1743,96 @@ pub enum CityObjectType {
     Railway,
     Waterway,
     TransportSquare,
-    #[serde(rename = "+GenericCityObject")]
+    Tunnel,
+    TunnelPart,
+    TunnelInstallation,
+    TunnelConstructiveElement,
+    TunnelHollowSpace,
+    TunnelFurniture,
+    /// It is a core type since CityJSON 1.1, and `+GenericCityObject` in earlier versions.
     GenericCityObject,
+    /// A type that is defined by a CityJSON Extension.
+    Extension(String),
+}
+
+impl CityObjectType {
+    /// The CityObject types of the CityJSON specification.
+    pub const CORE: [CityObjectType; 33] = [
+        CityObjectType::Bridge,
+        CityObjectType::BridgePart,
+        CityObjectType::BridgeInstallation,
+        CityObjectType::BridgeConstructiveElement,
+        CityObjectType::BridgeRoom,
+        CityObjectType::BridgeFurniture,
+        CityObjectType::Building,
+        CityObjectType::BuildingPart,
+        CityObjectType::BuildingInstallation,
+        CityObjectType::BuildingConstructiveElement,
+        CityObjectType::BuildingFurniture,
+        CityObjectType::BuildingStorey,
+        CityObjectType::BuildingRoom,
+        CityObjectType::BuildingUnit,
+        CityObjectType::CityFurniture,
+        CityObjectType::CityObjectGroup,
+        CityObjectType::LandUse,
+        CityObjectType::OtherConstruction,
+        CityObjectType::PlantCover,
+        CityObjectType::SolitaryVegetationObject,
+        CityObjectType::TINRelief,
+        CityObjectType::WaterBody,
+        CityObjectType::Road,
+        CityObjectType::Railway,
+        CityObjectType::Waterway,
+        CityObjectType::TransportSquare,
+        CityObjectType::Tunnel,
+        CityObjectType::TunnelPart,
+        CityObjectType::TunnelInstallation,
+        CityObjectType::TunnelConstructiveElement,
+        CityObjectType::TunnelHollowSpace,
+        CityObjectType::TunnelFurniture,
+        CityObjectType::GenericCityObject,
+    ];
 }
 
 impl fmt::Display for CityObjectType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            CityObjectType::Extension(name) => write!(f, "{}", name),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl std::str::FromStr for CityObjectType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "+GenericCityObject" {
+            return Ok(CityObjectType::GenericCityObject);
+        }
+        if let Some(cotype) = Self::CORE.iter().find(|cotype| cotype.to_string() == s) {
+            return Ok(cotype.clone());
+        }
+        match s.strip_prefix('+') {
+            Some(name) if !name.is_empty() => Ok(CityObjectType::Extension(s.to_string())),
+            _ => Err(format!(
+                "unknown CityObject type '{s}', the types of the CityJSON Extensions must start with '+'"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for CityObjectType {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<CityObjectType> for String {
+    fn from(value: CityObjectType) -> Self {
+        value.to_string()
     }
 }
 
@@ -920,15 +1847,86 @@ type Solid = Vec<Shell>;
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type")]
 enum Geometry {
-    MultiSurface { boundaries: MultiSurface },
-    Solid { boundaries: Solid },
+    MultiSurface {
+        boundaries: MultiSurface,
+    },
+    Solid {
+        boundaries: Solid,
+    },
+    MultiPoint {
+        boundaries: Vec<usize>,
+    },
+    /// A geometry template that is placed at the reference point in the `boundaries`.
+    #[serde(rename = "GeometryInstance")]
+    Instance {
+        boundaries: Vec<usize>,
+    },
 }
 
 #[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct CityObject {
     #[serde(rename = "type")]
     pub cotype: CityObjectType,
     geometry: Option<Vec<Geometry>>,
+    geographical_extent: Option<crate::spatial_structs::Bbox>,
+    #[serde(default, rename = "+externalGeometry")]
+    external_geometry: Vec<ExternalGeometry>,
+    #[serde(default)]
+    attributes: HashMap<String, serde_json::Value>,
+}
+
+impl CityObject {
+    /// Returns true if the CityObject is one of the `cityobject_types`, or if there is no
+    /// type selection.
+    fn is_of_types(&self, cityobject_types: Option<&Vec<CityObjectType>>) -> bool {
+        match cityobject_types {
+            None => true,
+            Some(cotypes) => cotypes.contains(&self.cotype),
+        }
+    }
+}
+
+/// Reference to a geometry that is stored outside of the CityJSONFeature, for instance a glTF
+/// or GML file, as it is done by some CityJSON Extensions.
+/// The CityObject needs to have a `geographicalExtent`, otherwise it cannot be located in the
+/// grid.
+///
+/// ```json
+/// "+externalGeometry": [{"uri": "models/building_1.glb", "type": "glTF"}]
+/// ```
+#[derive(Deserialize, Debug)]
+pub struct ExternalGeometry {
+    pub uri: String,
+}
+
+impl ExternalGeometry {
+    /// Returns true if the `uri` is a URL, otherwise it is a local path.
+    pub fn is_url(uri: &str) -> bool {
+        uri.starts_with("http://") || uri.starts_with("https://")
+    }
+
+    /// Resolve the reference.
+    /// URLs are linked as they are. Local paths are resolved relative to the directory of the
+    /// feature file, and they are only returned if the file exists.
+    pub fn resolve(&self, feature_path: &Path) -> Option<String> {
+        if Self::is_url(&self.uri) {
+            return Some(self.uri.clone());
+        }
+        let path = feature_path
+            .parent()
+            .map(|dir| dir.join(&self.uri))
+            .unwrap_or_else(|| PathBuf::from(&self.uri));
+        if path.exists() {
+            Some(path.to_string_lossy().to_string())
+        } else {
+            warn!(
+                "The external geometry {} of the feature {:?} does not exist",
+                &self.uri, feature_path
+            );
+            None
+        }
+    }
 }
 
 #[cfg(test)]
@@ -966,6 +1964,7 @@ mod tests {
             "vertices": []
         }"#;
         let cm: CityJSONMetadata = from_str(cityjson_str)?;
+        assert!(cm.metadata.geographical_extent.is_none());
         println!("{:#?}", cm.metadata.reference_system);
         println!("{:#?}, {:#?}", cm.transform.scale, cm.transform.translate);
         Ok(())
@@ -981,6 +1980,263 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_triangle_count() -> serde_json::Result<()> {
+        // A quad with a triangular hole, and a cube
+        let cityjsonfeature_str = r#"{"type":"CityJSONFeature","CityObjects":{"r1":{"type":"Road","geometry":[{"type":"MultiSurface","lod":"1","boundaries":[[[0,1,2,3],[4,5,6]]]}]},"b1":{"type":"Building","geometry":[{"type":"Solid","lod":"1","boundaries":[[[[0,1,2,3]],[[0,1,2,3]],[[0,1,2,3]],[[0,1,2,3]],[[0,1,2,3]],[[0,1,2,3]]]]}]}},"vertices":[[0,0,0],[10,0,0],[10,10,0],[0,10,0],[2,2,0],[4,2,0],[2,4,0]],"id":"r1"}"#;
+        let cf: CityJSONFeatureVertices = from_str(cityjsonfeature_str)?;
+        assert_eq!(cf.triangle_count(Some(&vec![CityObjectType::Road])), 7);
+        assert_eq!(cf.triangle_count(Some(&vec![CityObjectType::Building])), 12);
+        assert_eq!(cf.triangle_count(None), 19);
+        Ok(())
+    }
+
+    #[test]
+    fn test_geometry_instance() -> serde_json::Result<()> {
+        let cityjsonfeature_str = r#"{"type":"CityJSONFeature","CityObjects":{"t1":{"type":"SolitaryVegetationObject","geometry":[{"type":"GeometryInstance","template":0,"boundaries":[0],"transformationMatrix":[1,0,0,0,0,1,0,0,0,0,1,0,0,0,0,1]}]}},"vertices":[[5,6,7],[0,0,0]],"id":"t1"}"#;
+        let cf: CityJSONFeatureVertices = from_str(cityjsonfeature_str)?;
+        assert_eq!(cf.bbox_of_types(None), Some(BboxQc([5, 6, 7, 5, 6, 7])));
+        assert_eq!(cf.triangle_count(None), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multipoint() -> serde_json::Result<()> {
+        let cityjsonfeature_str = r#"{"type":"CityJSONFeature","CityObjects":{"p1":{"type":"GenericCityObject","geometry":[{"type":"MultiPoint","lod":"0","boundaries":[0,1,2]}]}},"vertices":[[5,6,7],[1,9,3],[8,2,4]],"id":"p1"}"#;
+        let cf: CityJSONFeatureVertices = from_str(cityjsonfeature_str)?;
+        assert_eq!(cf.bbox_of_types(None), Some(BboxQc([1, 2, 3, 8, 9, 7])));
+        assert_eq!(cf.triangle_count(None), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_geometry() -> serde_json::Result<()> {
+        let cityjsonfeature_str = r#"{"type":"CityJSONFeature","CityObjects":{"b1":{"type":"Building","geographicalExtent":[1.0,2.0,0.0,3.0,4.0,10.0],"+externalGeometry":[{"uri":"https://example.com/b1.glb","type":"glTF"},{"uri":"does_not_exist.gml"}]}},"vertices":[],"id":"b1"}"#;
+        let cf: CityJSONFeatureVertices = from_str(cityjsonfeature_str)?;
+        let transform = Transform {
+            scale: [0.1, 0.1, 0.1],
+            translate: [0.0, 0.0, 0.0],
+        };
+        assert!(cf.bbox_of_types(None).is_none());
+        assert_eq!(
+            cf.bbox_with_external(None, &transform),
+            Some(BboxQc([10, 20, 0, 30, 40, 100]))
+        );
+        let cotypes = vec![CityObjectType::Building];
+        assert_eq!(
+            cf.external_geometry(&cotypes, Path::new("features/b1.city.jsonl")),
+            vec!["https://example.com/b1.glb".to_string()]
+        );
+        assert!(cf
+            .bbox_with_external(Some(&vec![CityObjectType::Road]), &transform)
+            .is_none());
+        Ok(())
+    }
+
+    /// A feature that only has external geometry is still in a tile.
+    #[test]
+    fn test_external_geometry_tile() {
+        use crate::spatial_structs::{QuadTree, QuadTreeCapacity, QuadTreeDepth};
+        let world = crate::fixtures::TilingFixture::load("external_geometry", 20.0).world;
+        let fid = world
+            .features
+            .iter()
+            .position(|feature| feature.path_jsonl == Path::new("e000.city.jsonl"))
+            .unwrap();
+        assert_eq!(
+            world.features[fid].external_geometry,
+            vec!["https://example.com/e000.glb".to_string()]
+        );
+        let quadtree = QuadTree::from_world(
+            &world,
+            QuadTreeCapacity::Vertices(15000),
+            QuadTreeDepth::default(),
+        );
+        let tiles: Vec<&QuadTree> = quadtree
+            .collect_leaves()
+            .into_iter()
+            .filter(|leaf| leaf.feature_ids(&world.grid).contains(&fid))
+            .collect();
+        assert!(!tiles.is_empty());
+        for tile in tiles {
+            assert!(tile.nr_items > 0);
+            let content_bbox = tile.node_content_bbox(&world, None, None);
+            assert!(content_bbox[0] >= 85100.0 && content_bbox[3] <= 85120.0);
+        }
+    }
+
+    #[test]
+    fn test_cityobject_type() -> serde_json::Result<()> {
+        for cotype in CityObjectType::CORE {
+            assert_eq!(cotype.to_string().parse::<CityObjectType>(), Ok(cotype));
+        }
+        assert_eq!(
+            "+GenericCityObject".parse::<CityObjectType>(),
+            Ok(CityObjectType::GenericCityObject)
+        );
+        assert!("NoiseBuilding".parse::<CityObjectType>().is_err());
+        assert!("+".parse::<CityObjectType>().is_err());
+        // The features with Extension types are not dropped
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{"n1":{"type":"+NoiseBuilding","geometry":[{"type":"MultiSurface","lod":"1","boundaries":[[[0,1,2]]]}]}},"vertices":[[0,0,0],[1,0,0],[1,1,1]],"id":"n1"}"#,
+        )?;
+        let noise_building = CityObjectType::Extension("+NoiseBuilding".to_string());
+        assert_eq!(cf.cityobjects["n1"].cotype, noise_building);
+        assert!(cf.bbox_of_types(Some(&vec![noise_building])).is_some());
+        assert!(cf
+            .bbox_of_types(Some(&vec![CityObjectType::Building]))
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_id_filter() -> serde_json::Result<()> {
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{"b1":{"type":"Building"},"b1-0":{"type":"BuildingPart"}},"vertices":[]}"#,
+        )?;
+        assert!(IdFilter::default().accepts(&cf));
+        let include_other = IdFilter {
+            include: Some(HashSet::from(["b2".to_string()])),
+            exclude: HashSet::new(),
+        };
+        assert!(!include_other.accepts(&cf));
+        let include_part = IdFilter {
+            include: Some(HashSet::from(["b1-0".to_string()])),
+            exclude: HashSet::new(),
+        };
+        assert!(include_part.accepts(&cf));
+        let exclude_part = IdFilter {
+            include: None,
+            exclude: HashSet::from(["b1-0".to_string()]),
+        };
+        assert!(!exclude_part.accepts(&cf));
+        Ok(())
+    }
+
+    #[test]
+    fn test_feature_weights() -> Result<(), Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join("tyler_test_feature_weights.csv");
+        std::fs::write(&path, "id,weight\nb1,10.4\nb1-0,5\n\nb2,3\n")?;
+        let weights = FeatureWeights::from_csv(&path)?;
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{"b1":{"type":"Building"},"b1-0":{"type":"BuildingPart"}},"vertices":[]}"#,
+        )?;
+        assert_eq!(weights.weight(&cf), Some(15));
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{"b3":{"type":"Building"}},"vertices":[]}"#,
+        )?;
+        assert_eq!(weights.weight(&cf), None);
+        std::fs::write(&path, "id,weight\nb1,abc\n")?;
+        assert!(FeatureWeights::from_csv(&path).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_cityobject_types() -> serde_json::Result<()> {
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{"b1":{"type":"Building"},"b1-0":{"type":"BuildingPart"},"b1-1":{"type":"BuildingPart"}},"vertices":[]}"#,
+        )?;
+        assert_eq!(
+            cf.cityobject_types(None),
+            vec![
+                CityObjectType::Building,
+                CityObjectType::BuildingPart,
+                CityObjectType::BuildingPart
+            ]
+        );
+        assert_eq!(
+            cf.cityobject_types(Some(&vec![CityObjectType::Building])),
+            vec![CityObjectType::Building]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_priority() -> serde_json::Result<()> {
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{"b1":{"type":"Building","attributes":{"landmark":false}},"b1-0":{"type":"BuildingPart","attributes":{"landmark":"yes"}}},"vertices":[]}"#,
+        )?;
+        assert!(cf.has_priority("landmark", None));
+        assert!(!cf.has_priority("landmark", Some(&vec![CityObjectType::Building])));
+        assert!(!cf.has_priority("monument", None));
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{"b1":{"type":"Building","attributes":{"landmark":0,"rank":2}}},"vertices":[]}"#,
+        )?;
+        assert!(!cf.has_priority("landmark", None));
+        assert!(cf.has_priority("rank", None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refine_grid() {
+        let mut world = crate::fixtures::TilingFixture::load("tiling_sample", 20.0).world;
+        let grid_fids = |world: &World| {
+            let mut fids: Vec<usize> = world
+                .grid
+                .into_iter()
+                .flat_map(|(_, c)| c.feature_ids.iter().copied())
+                .collect();
+            fids.sort();
+            fids.dedup();
+            fids
+        };
+        let length = world.grid.length;
+        let fids = grid_fids(&world);
+        world.refine_grid(None);
+        assert_eq!(world.grid.length, length * 2);
+        // The same features are in the cells of the new grid
+        assert_eq!(grid_fids(&world), fids);
+    }
+
+    #[test]
+    fn test_group_value() -> serde_json::Result<()> {
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{"b1":{"type":"Building","attributes":{"district":"west"}},"b1-0":{"type":"BuildingPart","attributes":{"district":"east","batch":3}}},"vertices":[]}"#,
+        )?;
+        assert_eq!(cf.group_value("district", None), Some("east".to_string()));
+        assert_eq!(
+            cf.group_value("district", Some(&vec![CityObjectType::Building])),
+            Some("west".to_string())
+        );
+        assert_eq!(cf.group_value("batch", None), Some("3".to_string()));
+        assert_eq!(cf.group_value("owner", None), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cityobject_ids() -> serde_json::Result<()> {
+        let cf: CityJSONFeatureIds = from_str(
+            r#"{"type":"CityJSONFeature","id":"b1","CityObjects":{"b1-1":{"type":"BuildingPart"},"b1":{"type":"Building","geometry":[]},"b1-0":{"type":"BuildingPart"}},"vertices":[[0,0,0]]}"#,
+        )?;
+        assert_eq!(cf.cityobject_ids(), vec!["b1", "b1-0", "b1-1"]);
+        let cf: CityJSONFeatureIds = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{"r2":{"type":"Road"},"r1":{"type":"Road"}},"vertices":[]}"#,
+        )?;
+        assert_eq!(cf.cityobject_ids(), vec!["r1", "r2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_crs_differs() -> serde_json::Result<()> {
+        let crs: Crs = from_str(r#""https://www.opengis.net/def/crs/EPSG/0/7415""#)?;
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{},"vertices":[],"metadata":{"referenceSystem":"https://www.opengis.net/def/crs/EPSG/0/28992"}}"#,
+        )?;
+        assert_eq!(
+            cf.crs_differs(&crs).map(|c| c.0.as_str()),
+            Some("https://www.opengis.net/def/crs/EPSG/0/28992")
+        );
+        let cf: CityJSONFeatureVertices = from_str(
+            r#"{"type":"CityJSONFeature","CityObjects":{},"vertices":[],"metadata":{"referenceSystem":"http://www.opengis.net/def/crs/EPSG/0/7415"}}"#,
+        )?;
+        assert!(cf.crs_differs(&crs).is_none());
+        let cf: CityJSONFeatureVertices =
+            from_str(r#"{"type":"CityJSONFeature","CityObjects":{},"vertices":[]}"#)?;
+        assert!(cf.crs_differs(&crs).is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_centroid() -> serde_json::Result<()> {
         let pb: PathBuf = test_data_dir().join("3dbag_feature_x71.city.jsonl");
@@ -175,6 +175,41 @@ impl Proj {
     }
 }
 
+/// The version of the linked PROJ library and the paths where it searches for its data files,
+/// like `proj.db`.
+pub struct ProjInfo {
+    pub version: String,
+    pub search_path: String,
+}
+
+pub fn proj_info() -> ProjInfo {
+    let info = unsafe { proj_sys::proj_info() };
+    let search_path = if info.searchpath.is_null() {
+        String::new()
+    } else {
+        unsafe { _string(info.searchpath) }.unwrap_or_default()
+    };
+    ProjInfo {
+        version: format!("{}.{}.{}", info.major, info.minor, info.patch),
+        search_path,
+    }
+}
+
+/// Check that PROJ can create the transformation from `crs_from` to EPSG:4978, which every 3D
+/// Tiles output needs. The error tells where PROJ searched for its data files and how to fix
+/// it, instead of failing later in a tile conversion.
+pub fn check_transformation(crs_from: &str) -> Result<(), String> {
+    Proj::new_known_crs(crs_from, "EPSG:4978", None)
+        .map(|_| ())
+        .map_err(|e| {
+            let info = proj_info();
+            format!(
+                "PROJ {} cannot create the transformation from {} to EPSG:4978: {}. PROJ searched for its data files (proj.db) in '{}'. Set the PROJ_DATA environment variable to the directory that contains proj.db, eg. /usr/share/proj.",
+                info.version, crs_from, e, info.search_path
+            )
+        })
+}
+
 /// Errors originating in PROJ which can occur during projection and conversion
 #[derive(Error, Debug)]
 pub enum ProjError {
@@ -0,0 +1,205 @@
+//! Minimal raster output for the debug exports.
+//! The images are written as PNG with a world file, so that they can be opened in a GIS on
+//! top of the data. The PNG is not compressed, the deflate stream only has stored blocks,
+//! which keeps the encoder free of dependencies. The exported rasters are small, they have a
+//! pixel per grid cell.
+// Copyright 2023 Balázs Dukai, Ravi Peters
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+/// The maximum length of a stored deflate block.
+const STORED_BLOCK_LENGTH: usize = 65535;
+
+/// An RGBA image, with the rows from the top to the bottom.
+pub struct RgbaImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl RgbaImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0, 0, 0, 0]; width * height],
+        }
+    }
+
+    pub fn set(&mut self, column: usize, row: usize, pixel: [u8; 4]) {
+        self.pixels[row * self.width + column] = pixel;
+    }
+
+    /// Encode the image as PNG.
+    pub fn to_png(&self) -> Vec<u8> {
+        // Each row starts with the filter type, 0 is no filter
+        let mut raw: Vec<u8> = Vec::with_capacity(self.height * (self.width * 4 + 1));
+        for row in self.pixels.chunks(self.width.max(1)).take(self.height) {
+            raw.push(0);
+            for pixel in row {
+                raw.extend_from_slice(pixel);
+            }
+        }
+        let mut ihdr: Vec<u8> = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(self.width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // Bit depth 8, color type 6 (RGBA), default compression, filter and no interlace
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut png: Vec<u8> = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    pub fn write_png(&self, path: &Path) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&self.to_png())?;
+        writer.flush()
+    }
+}
+
+/// Write the world file of a raster with square pixels, which is the affine transformation
+/// from the pixel to the map coordinates. `top_left` is the corner of the top-left pixel, the
+/// world file refers to the center of the pixel.
+pub fn write_world_file(path: &Path, top_left: [f64; 2], pixel_size: f64) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{pixel_size}")?;
+    writeln!(file, "0.0")?;
+    writeln!(file, "0.0")?;
+    writeln!(file, "{}", -pixel_size)?;
+    writeln!(file, "{}", top_left[0] + pixel_size / 2.0)?;
+    writeln!(file, "{}", top_left[1] - pixel_size / 2.0)
+}
+
+/// Linear interpolation of the colors of a ramp at `t`, which is clamped to `[0, 1]`.
+pub fn color_ramp(ramp: &[[u8; 3]], t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0) * (ramp.len() - 1) as f64;
+    let i = (t.floor() as usize).min(ramp.len() - 2);
+    let f = t - i as f64;
+    let mut color = [0; 3];
+    for (c, (a, b)) in color.iter_mut().zip(ramp[i].iter().zip(ramp[i + 1].iter())) {
+        *c = (*a as f64 + (*b as f64 - *a as f64) * f).round() as u8;
+    }
+    color
+}
+
+fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of uncompressed (stored) deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let nr_blocks = data.len().div_ceil(STORED_BLOCK_LENGTH).max(1);
+    let mut out: Vec<u8> = Vec::with_capacity(data.len() + nr_blocks * 5 + 6);
+    // Deflate with a 32K window and no preset dictionary, the check bits make it a multiple
+    // of 31
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(STORED_BLOCK_LENGTH).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        out.push(is_final as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        let data = vec![7u8; STORED_BLOCK_LENGTH + 10];
+        let z = zlib_stored(&data);
+        assert_eq!(((z[0] as u16) << 8 | z[1] as u16) % 31, 0);
+        // Two blocks, only the second is final
+        assert_eq!(z.len(), 2 + 2 * 5 + data.len() + 4);
+        assert_eq!(z[2], 0);
+        assert_eq!(&z[3..5], &[0xFF, 0xFF]);
+        assert_eq!(z[2 + 5 + STORED_BLOCK_LENGTH], 1);
+        assert_eq!(zlib_stored(&[]).len(), 2 + 5 + 4);
+    }
+
+    #[test]
+    fn test_to_png() {
+        let mut image = RgbaImage::new(2, 3);
+        image.set(1, 2, [255, 0, 0, 255]);
+        let png = image.to_png();
+        assert_eq!(&png[..8], &PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..20], &2u32.to_be_bytes());
+        assert_eq!(&png[20..24], &3u32.to_be_bytes());
+        assert_eq!(
+            &png[png.len() - 12..],
+            &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn test_color_ramp() {
+        let ramp = [[0, 0, 0], [100, 200, 250]];
+        assert_eq!(color_ramp(&ramp, 0.5), [50, 100, 125]);
+        assert_eq!(color_ramp(&ramp, 2.0), [100, 200, 250]);
+        assert_eq!(
+            color_ramp(&[[0, 0, 0], [10, 10, 10], [30, 30, 30]], 0.75),
+            [20, 20, 20]
+        );
+    }
+}
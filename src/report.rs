@@ -0,0 +1,352 @@
+//! Run report.
+//! The report summarizes the outcome of a run, and it is written to `report.json` in the
+//! output directory.
+// Copyright 2023 Balázs Dukai, Ravi Peters
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::proj::{Proj, ProjError};
+use crate::spatial_structs::{to_single_precision, Bbox, QuadTreeCriteria};
+
+/// Summary of a run.
+///
+/// # Members
+///
+/// `seed` - The `--seed` of the run, which is needed for reproducing the output.
+///
+/// `tiles` - The outcome of the conversion of each tile that has content.
+///
+/// `progress` - Only set in the provisional reports that are written during the conversion
+/// (`--snapshot-interval`), then `tiles` are the tiles that are finished so far.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RunReport {
+    pub tyler_version: String,
+    pub seed: u64,
+    pub tiles: Vec<TileReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+}
+
+impl RunReport {
+    pub fn new() -> Self {
+        Self {
+            tyler_version: clap::crate_version!().to_string(),
+            seed: 0,
+            tiles: Vec::new(),
+            progress: None,
+        }
+    }
+
+    pub fn nr_tiles_failed(&self) -> usize {
+        self.tiles.iter().filter(|t| !t.success).count()
+    }
+
+    /// Flag the tiles whose content is larger than `max_content_size` bytes.
+    /// Returns the IDs of the newly flagged tiles.
+    pub fn flag_oversized(&mut self, max_content_size: u64) -> Vec<String> {
+        let mut flagged: Vec<String> = Vec::new();
+        for tile in self.tiles.iter_mut() {
+            if let Some(content_size) = tile.content_size {
+                if content_size > max_content_size && !tile.oversized {
+                    tile.oversized = true;
+                    flagged.push(tile.tile_id.clone());
+                }
+            }
+        }
+        flagged
+    }
+
+    /// The largest `max_displacement` of the tiles, if the precision diagnostics were computed.
+    pub fn max_displacement(&self) -> Option<f64> {
+        self.tiles
+            .iter()
+            .filter_map(|t| t.max_displacement)
+            .reduce(f64::max)
+    }
+
+    /// Write the report to a JSON file.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let file_out = BufWriter::new(File::create(path.as_ref())?);
+        serde_json::to_writer_pretty(file_out, self)?;
+        Ok(())
+    }
+}
+
+/// The progress of the conversion, in a provisional [RunReport].
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub nr_tiles: usize,
+    pub nr_finished: usize,
+    pub elapsed_s: f64,
+}
+
+/// The outcome of the conversion of a single tile.
+///
+/// `log` is the path of the per-tile log file, relative to the output directory. It is only
+/// set if the tile logs are written (`--tile-logs`).
+///
+/// `content_size` is the size of the tile content in bytes. The tile is `oversized` if its
+/// content is larger than the `--max-content-size`, and it is `split` if its content was
+/// replaced by the content of its children.
+///
+/// `max_displacement` is the result of the precision diagnostics (`--precision-diagnostics`),
+/// see [max_displacement].
+///
+/// `stats` are the statistics of the tile content that were passed to the conversion.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TileReport {
+    pub tile_id: String,
+    pub success: bool,
+    pub duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_size: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub oversized: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub split: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_displacement: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<TileStats>,
+}
+
+/// The most that the simplification error is scaled with the load of a tile, see
+/// [TileStats::simplify_error].
+pub const MAX_SIMPLIFY_SCALE: f64 = 4.0;
+
+/// Statistics of the content of a tile. They scale the simplification of the conversion with
+/// the load of the tile, and they are passed to the post-processing commands.
+///
+/// `capacity` is the target load of a tile, the `--qtree-capacity`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TileStats {
+    pub level: u16,
+    pub nr_features: usize,
+    pub nr_vertices: usize,
+    pub capacity: usize,
+}
+
+impl TileStats {
+    /// The statistics with the names of the conversion arguments and the placeholders.
+    pub fn named_values(&self) -> [(&'static str, usize); 4] {
+        [
+            ("tile_level", self.level as usize),
+            ("tile_nr_features", self.nr_features),
+            ("tile_nr_vertices", self.nr_vertices),
+            ("tile_capacity", self.capacity),
+        ]
+    }
+
+    /// The maximum simplification error of the tile, which is the `max_error` of
+    /// `--simplification-max-error` scaled with the load of the tile, the number of items of the
+    /// quadtree `criteria` relative to the capacity. A tile within its capacity keeps the
+    /// `max_error`, and an overloaded tile is simplified more, up to [MAX_SIMPLIFY_SCALE] times
+    /// the `max_error`. The load is only known for the objects and the vertices criteria.
+    pub fn simplify_error(&self, max_error: f64, criteria: &QuadTreeCriteria) -> f64 {
+        let nr_items = match criteria {
+            QuadTreeCriteria::Objects => self.nr_features,
+            QuadTreeCriteria::Vertices => self.nr_vertices,
+            _ => return max_error,
+        };
+        if self.capacity == 0 {
+            return max_error;
+        }
+        let load = nr_items as f64 / self.capacity as f64;
+        max_error * load.clamp(1.0, MAX_SIMPLIFY_SCALE)
+    }
+
+    /// Replace the `{tile_level}`, `{tile_nr_features}`, `{tile_nr_vertices}` and
+    /// `{tile_capacity}` placeholders in the `template`.
+    pub fn fill(&self, template: &str) -> String {
+        self.named_values()
+            .iter()
+            .fold(template.to_string(), |acc, (name, value)| {
+                acc.replace(&format!("{{{name}}}"), &value.to_string())
+            })
+    }
+}
+
+/// The corners and the center of a 3D bounding box.
+fn bbox_sample_points(bbox: &Bbox) -> Vec<[f64; 3]> {
+    let mut points: Vec<[f64; 3]> = Vec::with_capacity(9);
+    for x in [bbox[0], bbox[3]] {
+        for y in [bbox[1], bbox[4]] {
+            for z in [bbox[2], bbox[5]] {
+                points.push([x, y, z]);
+            }
+        }
+    }
+    points.push([
+        (bbox[0] + bbox[3]) * 0.5,
+        (bbox[1] + bbox[4]) * 0.5,
+        (bbox[2] + bbox[5]) * 0.5,
+    ]);
+    points
+}
+
+/// Compute the maximal displacement (in input units) of the corners and the center of the
+/// tile `bbox` in a round trip from the input CRS to the output frame and back.
+/// Before the reprojection, the input coordinates are stored as 32-bit floats relative to the
+/// `offset` of the tile content (see [crate::spatial_structs::content_offset]). In the output
+/// frame, the coordinates are stored as 32-bit floats relative to the center of the tile, like
+/// the vertex positions of the glTF content. Thus the displacement includes both the
+/// reprojection and the quantization errors.
+///
+/// `to_output` converts from the input CRS to EPSG:4978, `from_output` does the reverse.
+pub fn max_displacement(
+    bbox: &Bbox,
+    offset: &[f64; 3],
+    to_output: &Proj,
+    from_output: &Proj,
+) -> Result<f64, ProjError> {
+    let points = bbox_sample_points(bbox);
+    let center = points[points.len() - 1];
+    let center_out = to_output.convert((center[0], center[1], center[2]))?;
+    let mut max_d: f64 = 0.0;
+    for p in points {
+        let p_in = to_single_precision(&p, offset);
+        let p_out = to_output.convert((p_in[0], p_in[1], p_in[2]))?;
+        // Relative to the tile center, in single precision
+        let rel = [
+            (p_out.0 - center_out.0) as f32,
+            (p_out.1 - center_out.1) as f32,
+            (p_out.2 - center_out.2) as f32,
+        ];
+        let p_back = from_output.convert((
+            center_out.0 + rel[0] as f64,
+            center_out.1 + rel[1] as f64,
+            center_out.2 + rel[2] as f64,
+        ))?;
+        let d = ((p_back.0 - p[0]).powi(2) + (p_back.1 - p[1]).powi(2) + (p_back.2 - p[2]).powi(2))
+            .sqrt();
+        max_d = max_d.max(d);
+    }
+    Ok(max_d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nr_tiles_failed() {
+        let mut report = RunReport::new();
+        report.tiles.push(TileReport {
+            tile_id: "1/0/0".to_string(),
+            success: true,
+            duration_ms: 10,
+            log: None,
+            ..Default::default()
+        });
+        report.tiles.push(TileReport {
+            tile_id: "1/1/0".to_string(),
+            success: false,
+            duration_ms: 10,
+            log: Some(PathBuf::from("logs/1/1/0.log")),
+            ..Default::default()
+        });
+        assert_eq!(report.nr_tiles_failed(), 1);
+        let j = serde_json::to_string(&report.tiles[0]).unwrap();
+        assert!(!j.contains("log"));
+        // Only the provisional reports have the progress
+        assert!(!serde_json::to_string(&report).unwrap().contains("progress"));
+        report.progress = Some(Progress {
+            nr_tiles: 10,
+            nr_finished: 2,
+            elapsed_s: 1.5,
+        });
+        let j = serde_json::to_string(&report).unwrap();
+        assert!(j.contains(r#""progress":{"nr_tiles":10,"nr_finished":2,"elapsed_s":1.5}"#));
+    }
+
+    #[test]
+    fn test_tile_stats_fill() {
+        let stats = TileStats {
+            level: 5,
+            nr_features: 12,
+            nr_vertices: 3400,
+            capacity: 42000,
+        };
+        assert_eq!(
+            stats
+                .fill("simplify {tile} --ratio {tile_nr_vertices}/{tile_capacity} -l {tile_level}"),
+            "simplify {tile} --ratio 3400/42000 -l 5"
+        );
+    }
+
+    #[test]
+    fn test_tile_stats_simplify_error() {
+        let mut stats = TileStats {
+            level: 5,
+            nr_features: 12,
+            nr_vertices: 21000,
+            capacity: 42000,
+        };
+        let vertices = QuadTreeCriteria::Vertices;
+        assert_eq!(stats.simplify_error(1.5, &vertices), 1.5);
+        stats.nr_vertices = 84000;
+        assert_eq!(stats.simplify_error(1.5, &vertices), 3.0);
+        stats.nr_vertices = 10 * 42000;
+        assert_eq!(
+            stats.simplify_error(1.5, &vertices),
+            1.5 * MAX_SIMPLIFY_SCALE
+        );
+        assert_eq!(stats.simplify_error(1.5, &QuadTreeCriteria::Bytes), 1.5);
+    }
+
+    #[test]
+    fn test_flag_oversized() {
+        let mut report = RunReport::new();
+        for (i, size) in [Some(100), Some(2000), None].iter().enumerate() {
+            report.tiles.push(TileReport {
+                tile_id: format!("1/{i}/0"),
+                success: size.is_some(),
+                content_size: *size,
+                ..Default::default()
+            });
+        }
+        assert_eq!(report.flag_oversized(1000), vec!["1/1/0".to_string()]);
+        // Already flagged tiles are not returned again
+        assert!(report.flag_oversized(1000).is_empty());
+        assert!(report.tiles[1].oversized);
+    }
+
+    #[test]
+    fn test_bbox_sample_points() {
+        let points = bbox_sample_points(&[0.0, 0.0, 0.0, 2.0, 4.0, 6.0]);
+        assert_eq!(points.len(), 9);
+        assert!(points.contains(&[2.0, 0.0, 6.0]));
+        assert_eq!(points[8], [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_max_displacement_summary() {
+        let mut report = RunReport::new();
+        assert!(report.max_displacement().is_none());
+        for d in [Some(0.001), None, Some(0.003)] {
+            report.tiles.push(TileReport {
+                max_displacement: d,
+                ..Default::default()
+            });
+        }
+        assert_eq!(report.max_displacement(), Some(0.003));
+    }
+}
@@ -14,15 +14,20 @@
 // limitations under the License.
 use crate::parser::FeatureSet;
 use log::{debug, warn};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
 use morton_encoding::{morton_decode, morton_encode};
+use rayon::prelude::*;
+
+/// The number of grid cells from which the subtrees of the [QuadTree] are merged in parallel.
+const MERGE_PARALLEL_MIN_TILES: usize = 4096;
 
 /// Quadtree
 ///
@@ -30,32 +35,38 @@ use morton_encoding::{morton_decode, morton_encode};
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct QuadTree {
     pub id: QuadTreeNodeId,
-    side_length: u64,
+    side_length: f64,
     pub children: Vec<QuadTree>,
     cells: Vec<CellId>,
     pub nr_items: usize,
 }
 
 impl QuadTree {
-    pub fn from_world(world: &crate::parser::World, limit: QuadTreeCapacity) -> Self {
-        Self::from_grid(&world.grid, limit)
+    pub fn from_world(
+        world: &crate::parser::World,
+        limit: QuadTreeCapacity,
+        depth: QuadTreeDepth,
+    ) -> Self {
+        Self::from_grid(&world.grid, limit, depth)
     }
 
-    fn from_grid(grid: &SquareGrid, limit: QuadTreeCapacity) -> Self {
-        let mut merge_limit: usize = 0;
+    /// The leaves are created and the nodes are merged in parallel. The node IDs only depend
+    /// on the position of the nodes in the Morton-order, thus they are the same in every run.
+    fn from_grid(grid: &SquareGrid, limit: QuadTreeCapacity, depth: QuadTreeDepth) -> Self {
         let nr_cells = grid.length.pow(2) as f64;
         let max_level = (nr_cells.ln() / 4.0_f64.ln()).ceil() as u16;
         debug!("Calculated maximum level for quadtree: {}", &max_level);
         // Somehow, the morton_encoding::morton_encode needs [y,x] for creating a Z-curve.
         //  If [x,y] is used, it creates an N-curve.
         //  crate::spatial_structs::interleave(x,y) creates a Z-curve.
-        let mut mortoncodes: Vec<u128> = grid
-            .into_iter()
-            .map(|(cellid, _)| morton_encode([cellid.row as u64, cellid.column as u64]))
+        // All the cells of the square, also the empty cells outside of a rectangular grid
+        let mut mortoncodes: Vec<u128> = (0..grid.length as u64)
+            .flat_map(|column| (0..grid.length as u64).map(move |row| morton_encode([row, column])))
             .collect();
-        mortoncodes.sort();
+        mortoncodes.par_sort_unstable();
+        // The parallel collect keeps the Morton-order
         let tiles_morton: Vec<QuadTree> = mortoncodes
-            .iter()
+            .par_iter()
             .map(|mc| {
                 let coords: [u64; 2] = morton_decode(*mc);
                 coords
@@ -65,48 +76,58 @@ impl QuadTree {
                     row: y as usize,
                     column: x as usize,
                 };
-                let items: usize;
-                match limit {
-                    QuadTreeCapacity::Objects(l) => {
-                        // Use the number of features as a limit
-                        items = grid.cell(&cellid).feature_ids.len();
-                        merge_limit = l;
-                    }
-                    QuadTreeCapacity::Vertices(l) => {
-                        // Use the number of vertices as a limit
-                        items = grid.cell(&cellid).nr_vertices;
-                        merge_limit = l;
-                    }
-                }
                 QuadTree {
                     id: QuadTreeNodeId::new(x as usize, y as usize, max_level),
-                    side_length: grid.cellsize as u64,
+                    side_length: grid.cellsize,
                     children: Vec::new(),
                     cells: vec![cellid],
-                    nr_items: items,
+                    // The number of features, vertices or the sum of the feature weights
+                    nr_items: limit.cell_items(grid.cell(&cellid)),
                 }
             })
             .collect();
-        Self::merge_tiles(0, tiles_morton, merge_limit)
+        Self::merge_tiles(0, tiles_morton, limit.limit(), depth)
     }
 
-    fn merge_tiles(level: u16, tiles: Vec<QuadTree>, limit: usize) -> QuadTree {
+    fn merge_tiles(
+        level: u16,
+        mut tiles: Vec<QuadTree>,
+        limit: usize,
+        depth: QuadTreeDepth,
+    ) -> QuadTree {
         let len_tiles = tiles.len();
-        if len_tiles > 4 {
+        if len_tiles == 1 {
+            // A grid of a single cell, eg. a dataset with a single feature. The cell is the
+            // root, there is nothing to merge.
+            let mut tile = tiles.pop().unwrap();
+            tile.id = QuadTreeNodeId::new(tile.id.x, tile.id.y, level);
+            tile
+        } else if len_tiles > 4 {
             let q0: usize = len_tiles / 4;
             let q1: usize = q0 * 2;
             let q2: usize = q0 * 3;
             let next_level = level + 1;
-            Self::merge_tiles(
-                level,
+            let tiles_q3 = tiles.split_off(q2);
+            let tiles_q2 = tiles.split_off(q1);
+            let tiles_q1 = tiles.split_off(q0);
+            let merge = |tiles: Vec<QuadTree>| Self::merge_tiles(next_level, tiles, limit, depth);
+            // The small subtrees are merged sequentially, because the parallel tasks would
+            // cost more than the merge
+            let children = if len_tiles >= MERGE_PARALLEL_MIN_TILES {
+                let ((c0, c1), (c2, c3)) = rayon::join(
+                    || rayon::join(|| merge(tiles), || merge(tiles_q1)),
+                    || rayon::join(|| merge(tiles_q2), || merge(tiles_q3)),
+                );
+                vec![c0, c1, c2, c3]
+            } else {
                 vec![
-                    Self::merge_tiles(next_level, tiles[0..q0].to_vec(), limit),
-                    Self::merge_tiles(next_level, tiles[q0..q1].to_vec(), limit),
-                    Self::merge_tiles(next_level, tiles[q1..q2].to_vec(), limit),
-                    Self::merge_tiles(next_level, tiles[q2..].to_vec(), limit),
-                ],
-                limit,
-            )
+                    merge(tiles),
+                    merge(tiles_q1),
+                    merge(tiles_q2),
+                    merge(tiles_q3),
+                ]
+            };
+            Self::merge_tiles(level, children, limit, depth)
         } else {
             let sum_items: usize = tiles.iter().map(|t| t.nr_items).sum();
             let mut cells: Vec<CellId> = Vec::new();
@@ -120,10 +141,10 @@ impl QuadTree {
             // FIXME: this also adds the quadtree if sum_items == 0 so the parent will have 4
             //  children instead of 3. Probably should return Option<Quadtree>.
             //  Currently these empty tiles are removed in Tile.prune().
-            if sum_items <= limit {
+            if depth.is_leaf(level, sum_items, limit) {
                 QuadTree {
                     id,
-                    side_length: tiles[0].side_length * 2,
+                    side_length: tiles[0].side_length * 2.0,
                     children: vec![],
                     cells,
                     nr_items: sum_items,
@@ -138,8 +159,8 @@ impl QuadTree {
                 }
                 QuadTree {
                     id,
-                    side_length: tiles[0].side_length * 2,
-                    children: tiles.clone(),
+                    side_length: tiles[0].side_length * 2.0,
+                    children: tiles,
                     cells: vec![],
                     nr_items: sum_items,
                 }
@@ -147,7 +168,6 @@ impl QuadTree {
         }
     }
 
-    #[allow(dead_code)]
     fn collect_leaves_recurse<'collect>(&'collect self, leaves: &mut Vec<&'collect QuadTree>) {
         if !self.children.is_empty() {
             for child in self.children.iter() {
@@ -158,22 +178,84 @@ impl QuadTree {
         }
     }
 
-    #[allow(dead_code)]
     pub fn collect_leaves(&self) -> Vec<&Self> {
         let mut leaves: Vec<&QuadTree> = Vec::new();
         self.collect_leaves_recurse(&mut leaves);
         leaves
     }
 
+    /// Is each non-empty leaf a single grid cell, thus none of the cells were merged by the
+    /// quadtree. In this case the quadtree only adds empty levels above the grid cells, and a
+    /// flat tileset of the cells is equivalent.
+    pub fn leaves_are_cells(&self) -> bool {
+        self.collect_leaves()
+            .iter()
+            .filter(|leaf| leaf.nr_items > 0)
+            .all(|leaf| leaf.cells.len() == 1)
+    }
+
+    /// Move the features that are larger than their leaf to the lowest ancestor node that is
+    /// at least as large as the feature, or to the root, see [LargeFeaturePolicy::Promote].
+    /// The size of a feature is the longer side of its 2D bounding box.
+    pub fn promote_large_features(&self, world: &crate::parser::World) -> PromotedFeatures {
+        fn recurse<'a>(
+            node: &'a QuadTree,
+            ancestors: &mut Vec<&'a QuadTree>,
+            world: &crate::parser::World,
+            promoted: &mut PromotedFeatures,
+        ) {
+            if !node.children.is_empty() {
+                ancestors.push(node);
+                for child in &node.children {
+                    recurse(child, ancestors, world, promoted);
+                }
+                ancestors.pop();
+                return;
+            }
+            let Some(root) = ancestors.first() else {
+                // The root is a leaf, there is nowhere to promote to
+                return;
+            };
+            for cellid in node.cells() {
+                for fid in &world.grid.cell(cellid).feature_ids {
+                    if promoted.feature_ids.contains(fid) {
+                        continue;
+                    }
+                    let b = world.features[*fid]
+                        .bbox_qc
+                        .to_bbox(&world.transform, None, None);
+                    let size = (b[3] - b[0]).max(b[4] - b[1]);
+                    if size <= node.side_length {
+                        continue;
+                    }
+                    let target = ancestors
+                        .iter()
+                        .rev()
+                        .find(|a| a.side_length >= size)
+                        .unwrap_or(root);
+                    promoted
+                        .nodes
+                        .entry(target.id.clone())
+                        .or_default()
+                        .push(*fid);
+                    promoted.feature_ids.insert(*fid);
+                }
+            }
+        }
+        let mut promoted = PromotedFeatures::default();
+        recurse(self, &mut Vec::new(), world, &mut promoted);
+        promoted
+    }
+
     pub fn bbox(&self, grid: &SquareGrid) -> Bbox {
-        let minx = grid.origin[0] + (self.id.x * grid.cellsize as usize) as f64;
-        let miny = grid.origin[1] + (self.id.y * grid.cellsize as usize) as f64;
+        let minx = grid.origin[0] + self.id.x as f64 * grid.cellsize;
+        let miny = grid.origin[1] + self.id.y as f64 * grid.cellsize;
         [
             minx,
             miny,
             grid.bbox[2],
-            minx + self.side_length as f64,
-            miny + self.side_length as f64,
+            minx + self.side_length,
+            miny + self.side_length,
             grid.bbox[5],
         ]
     }
@@ -225,6 +307,149 @@ impl QuadTree {
         None
     }
 
+    /// Breadth-first search for a node, returning a mutable reference.
+    pub fn node_mut(&mut self, id: &QuadTreeNodeId) -> Option<&mut QuadTree> {
+        let mut q = VecDeque::new();
+        q.push_back(self);
+
+        while let Some(n) = q.pop_front() {
+            if &n.id == id {
+                return Some(n);
+            } else {
+                for child in n.children.iter_mut() {
+                    q.push_back(child);
+                }
+            }
+        }
+        // Did not find the node
+        None
+    }
+
+    /// Split a leaf node into four children, by distributing its grid cells into the quadrants
+    /// of the node.
+    /// The children are created in Morton-order, the same way as in [QuadTree::from_grid].
+    /// Returns `false` if the node cannot be split, because it is not a leaf or it consists of a
+    /// single grid cell.
+    pub fn split(&mut self, grid: &SquareGrid, limit: QuadTreeCapacity) -> bool {
+        if !self.children.is_empty() || self.side_length <= grid.cellsize {
+            return false;
+        }
+        let side_length_child = self.side_length / 2.0;
+        let nr_cells_child = (side_length_child / grid.cellsize).round() as usize;
+        let mut children: Vec<QuadTree> = Vec::with_capacity(4);
+        for [dx, dy] in [[0, 0], [1, 0], [0, 1], [1, 1]] {
+            let x = self.id.x + dx * nr_cells_child;
+            let y = self.id.y + dy * nr_cells_child;
+            let cells: Vec<CellId> = self
+                .cells
+                .iter()
+                .filter(|c| {
+                    c.column >= x
+                        && c.column < x + nr_cells_child
+                        && c.row >= y
+                        && c.row < y + nr_cells_child
+                })
+                .copied()
+                .collect();
+            let nr_items = cells
+                .iter()
+                .map(|cellid| limit.cell_items(grid.cell(cellid)))
+                .sum();
+            children.push(QuadTree {
+                id: QuadTreeNodeId::new(x, y, self.id.level + 1),
+                side_length: side_length_child,
+                children: vec![],
+                cells,
+                nr_items,
+            });
+        }
+        self.cells = vec![];
+        self.children = children;
+        true
+    }
+
+    /// Collapse the sibling leaves into their parent, if they have at most `max_features`
+    /// features together, so that the sparse areas do not produce many tiny tiles. The merge
+    /// is repeated upwards, but the nodes above the `min_level` are not merged, see
+    /// [QuadTreeDepth]. The capacity is not checked, because the merged leaves have few
+    /// features.
+    /// Returns the number of nodes that became a leaf.
+    pub fn merge_sparse_leaves(
+        &mut self,
+        grid: &SquareGrid,
+        max_features: usize,
+        min_level: u16,
+    ) -> usize {
+        if self.children.is_empty() {
+            return 0;
+        }
+        let mut nr_merged: usize = self
+            .children
+            .iter_mut()
+            .map(|child| child.merge_sparse_leaves(grid, max_features, min_level))
+            .sum();
+        if self.id.level < min_level || self.children.iter().any(|c| !c.children.is_empty()) {
+            return nr_merged;
+        }
+        let cells: Vec<CellId> = self
+            .children
+            .iter()
+            .flat_map(|child| child.cells.iter().copied())
+            .collect();
+        // A feature can be in several cells
+        if self.feature_ids(grid).len() <= max_features {
+            self.cells = cells;
+            self.children = Vec::new();
+            nr_merged += 1;
+        }
+        nr_merged
+    }
+
+    /// Split the leaves whose estimated content size exceeds `max_bytes` (see [ContentSize]),
+    /// until each leaf fits in the budget or it is a single grid cell. It caps the size of the
+    /// tiles independently of the `limit`, which still counts the `nr_items` of the new nodes,
+    /// like in [QuadTree::split].
+    /// Returns the number of nodes that were split.
+    pub fn split_over_budget(
+        &mut self,
+        grid: &SquareGrid,
+        max_bytes: usize,
+        limit: QuadTreeCapacity,
+    ) -> usize {
+        let mut nr_split: usize = 0;
+        if self.children.is_empty() {
+            let content_size: usize = self
+                .cells
+                .iter()
+                .map(|cellid| ContentSize.cell_items(grid.cell(cellid)))
+                .sum();
+            if content_size <= max_bytes || !self.split(grid, limit) {
+                return 0;
+            }
+            nr_split += 1;
+        }
+        nr_split
+            + self
+                .children
+                .iter_mut()
+                .map(|child| child.split_over_budget(grid, max_bytes, limit))
+                .sum::<usize>()
+    }
+
+    /// The features in the cells of the node, sorted. A feature that is in several cells of
+    /// the node, eg. because it was inserted with [SquareGrid::insert_bbox], is only returned
+    /// once.
+    pub fn feature_ids(&self, grid: &SquareGrid) -> Vec<usize> {
+        let mut fids: Vec<usize> = self
+            .cells()
+            .into_iter()
+            .flat_map(|cellid| grid.cell(cellid).feature_ids.iter().copied())
+            .collect();
+        fids.sort_unstable();
+        fids.dedup();
+        fids
+    }
+
     pub fn cells(&self) -> Vec<&CellId> {
         let mut cellids: Vec<&CellId> = Vec::new();
         let mut q = VecDeque::new();
@@ -248,6 +473,10 @@ impl QuadTree {
         bbox_to_wkt(&self.bbox(grid))
     }
 
+    /// Exports the nodes into a TSV file per level, with the WKT of the node boundary, and a TSV
+    /// file per level with the WKT of the bounding box of the node content.
+    /// All the nodes are also written to `quadtree.geojson` as polygons, with their ID, level,
+    /// number of items and whether they are a leaf as attributes, see [write_geojson].
     pub fn export(
         &self,
         world: &crate::parser::World,
@@ -255,6 +484,7 @@ impl QuadTree {
     ) -> std::io::Result<()> {
         let mut q = VecDeque::new();
         q.push_back(self);
+        let mut features_geojson: Vec<serde_json::Value> = Vec::new();
         let mut quadtree_level: u16 = self.id.level;
         let [outdir_quadtree, outdir_quadtree_content] = match output_dir {
             None => [Path::new(""), Path::new("")],
@@ -311,11 +541,71 @@ impl QuadTree {
                     .as_bytes(),
                 )
                 .expect("cannot write quadtree node content");
+            features_geojson.push(serde_json::json!({
+                "type": "Feature",
+                "properties": {
+                    "node_id": node.id.to_string(),
+                    "level": node.id.level,
+                    "x": node.id.x,
+                    "y": node.id.y,
+                    "nr_items": node.nr_items,
+                    "nr_cells": node.cells.len(),
+                    "leaf": node.children.is_empty(),
+                    "content_wkt": wkt_content_bbox,
+                },
+                "geometry": bbox_to_geojson(&node.bbox(&world.grid)),
+            }));
 
             for child in &node.children {
                 q.push_back(child);
             }
         }
+        write_geojson(
+            &outdir_quadtree.join("quadtree.geojson"),
+            Some(world.grid.epsg),
+            features_geojson,
+        )
+    }
+
+    /// Exports the leaves and the internal nodes into a GeoJSON file at `path`, in WGS84
+    /// longitude, latitude, so that they can be put on a web map. The corners of the node
+    /// boundaries are reprojected, and the nodes have their `node_id`, `level`, `nr_items` and
+    /// `leaf` as properties.
+    pub fn export_geojson_wgs84(
+        &self,
+        grid: &SquareGrid,
+        path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let transformer =
+            crate::proj::Proj::new_known_crs(&format!("EPSG:{}", grid.epsg), "EPSG:4326", None)?;
+        let mut features_geojson: Vec<serde_json::Value> = Vec::new();
+        let mut q = VecDeque::from([self]);
+        while let Some(node) = q.pop_front() {
+            let [minx, miny, _, maxx, maxy, _] = node.bbox(grid);
+            let mut ring: Vec<[f64; 2]> = Vec::with_capacity(5);
+            for [x, y] in [
+                [minx, miny],
+                [maxx, miny],
+                [maxx, maxy],
+                [minx, maxy],
+                [minx, miny],
+            ] {
+                let (lon, lat, _) = transformer.convert((x, y, 0.0))?;
+                ring.push([lon, lat]);
+            }
+            features_geojson.push(serde_json::json!({
+                "type": "Feature",
+                "properties": {
+                    "node_id": node.id.to_string(),
+                    "level": node.id.level,
+                    "nr_items": node.nr_items,
+                    "leaf": node.children.is_empty(),
+                },
+                "geometry": {"type": "Polygon", "coordinates": [ring]},
+            }));
+            q.extend(node.children.iter());
+        }
+        write_geojson(path, None, features_geojson)?;
         Ok(())
     }
 
@@ -333,7 +623,7 @@ impl QuadTree {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct QuadTreeNodeId {
     pub x: usize,
     pub y: usize,
@@ -352,15 +642,879 @@ impl Display for QuadTreeNodeId {
     }
 }
 
+/// Parse a node ID from its `level/x/y` string representation.
+impl FromStr for QuadTreeNodeId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('/').collect();
+        if let [level, x, y] = parts[..] {
+            let level = level.parse::<u16>().map_err(|e| e.to_string())?;
+            let x = x.parse::<usize>().map_err(|e| e.to_string())?;
+            let y = y.parse::<usize>().map_err(|e| e.to_string())?;
+            Ok(Self::new(x, y, level))
+        } else {
+            Err(format!(
+                "node ID must be in the format of 'level/x/y': {}",
+                s
+            ))
+        }
+    }
+}
+
+/// How the content of the quadtree tiles is named, in the content URIs and in the file names of
+/// the content, the tile inputs and the CityJSON tiles. The tile IDs are not affected.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TileNaming {
+    /// `<level>/<x>/<y>`, where `x` and `y` are the column and row of the lower-left grid cell
+    /// of the node.
+    #[default]
+    #[value(name = "level-x-y")]
+    LevelXY,
+    /// The quadkey of the node, with a digit for each level below the root, which is
+    /// `x + 2 * y` of the child on that level. The rows are counted from the south, unlike in
+    /// the Bing Maps quadkeys. The root is named `root`.
+    Quadkey,
+    /// `<level>-<morton>`, where `morton` is the Morton-code of the node among the nodes of
+    /// its level.
+    Morton,
+}
+
+impl TileNaming {
+    /// The file stem of the node `id` in a quadtree whose nodes on `max_level` consist of a
+    /// single grid cell, see [SquareGrid::quadtree_max_level].
+    pub fn file_stem(self, id: &QuadTreeNodeId, max_level: u16) -> String {
+        // The position of the node among the nodes of its level
+        let shift = max_level.saturating_sub(id.level);
+        let x = (id.x >> shift) as u64;
+        let y = (id.y >> shift) as u64;
+        match self {
+            TileNaming::LevelXY => id.to_string(),
+            TileNaming::Quadkey if id.level == 0 => "root".to_string(),
+            TileNaming::Quadkey => (0..id.level)
+                .rev()
+                .map(|i| char::from(b'0' + (((x >> i) & 1) + 2 * ((y >> i) & 1)) as u8))
+                .collect(),
+            TileNaming::Morton => format!("{}-{}", id.level, interleave(&x, &y)),
+        }
+    }
+}
+
+/// What to do with the features that are larger than the leaf tile that they are in, eg.
+/// stadiums and terrain patches.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LargeFeaturePolicy {
+    /// Keep the feature in the content of its leaf.
+    #[default]
+    Leaf,
+    /// Move the feature to the content of the lowest ancestor tile that is at least as large
+    /// as the feature, so that it is rendered early and it is not clipped to the leaf.
+    Promote,
+}
+
+/// The features that are moved from the leaves to the content of an ancestor node of the
+/// quadtree, see [QuadTree::promote_large_features].
+#[derive(Debug, Default)]
+pub struct PromotedFeatures {
+    /// The features that are promoted to each node.
+    pub nodes: HashMap<QuadTreeNodeId, Vec<usize>>,
+    /// All the promoted features, which are left out of the leaves.
+    pub feature_ids: HashSet<usize>,
+}
+
+/// The tree that partitions the features into the tiles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PartitionScheme {
+    /// The quadtree of the grid cells.
+    #[default]
+    Quadtree,
+    /// The [Octree], which also subdivides the height.
+    Octree,
+    /// The [KdTree], which splits at the median feature centroid.
+    Kdtree,
+}
+
+/// A tree that partitions the features into its leaves, which become the tiles, as an
+/// alternative to the [QuadTree]. Unlike the quadtree nodes, which consist of grid cells, the
+/// leaves store their features, because each feature is assigned to one leaf by its centroid.
+pub trait PartitionTree {
+    /// The box of the node, in the input CRS.
+    fn bbox(&self) -> Bbox;
+    fn children(&self) -> Vec<&dyn PartitionTree>;
+    /// The features of a leaf.
+    fn feature_ids(&self) -> &[usize];
+    fn level(&self) -> u16;
+    /// The node ID as a file stem, which names the content of the leaf.
+    fn file_stem(&self) -> String;
+}
+
+impl dyn PartitionTree + '_ {
+    /// The leaves in depth-first order.
+    pub fn collect_leaves(&self) -> Vec<&dyn PartitionTree> {
+        fn collect<'a>(node: &'a dyn PartitionTree, leaves: &mut Vec<&'a dyn PartitionTree>) {
+            let children = node.children();
+            if children.is_empty() {
+                leaves.push(node);
+            }
+            for child in children {
+                collect(child, leaves);
+            }
+        }
+        let mut leaves: Vec<&dyn PartitionTree> = Vec::new();
+        collect(self, &mut leaves);
+        leaves
+    }
+
+    /// The bounding box of the node and all the features in it. The features can extend
+    /// beyond the node, because they are assigned by their centroid.
+    pub fn content_bbox(&self, world: &crate::parser::World) -> Bbox {
+        let mut bbox = self.bbox();
+        for leaf in self.collect_leaves() {
+            for fid in leaf.feature_ids() {
+                let b = world.features[*fid]
+                    .bbox_qc
+                    .to_bbox(&world.transform, None, None);
+                for i in 0..3 {
+                    bbox[i] = bbox[i].min(b[i]);
+                    bbox[i + 3] = bbox[i + 3].max(b[i + 3]);
+                }
+            }
+        }
+        bbox
+    }
+}
+
+/// A feature in a [PartitionTree], with its centroid and its number of items for the capacity.
+/// The z of the centroid is in the middle of the height of the feature.
+type PartitionItem = (usize, [f64; 3], usize);
+
+/// The features in the grid of the world as [PartitionItem], and the box of the grid.
+fn partition_items(
+    world: &crate::parser::World,
+    limit: QuadTreeCapacity,
+) -> (Vec<PartitionItem>, Bbox) {
+    let grid = &world.grid;
+    let mut fids: Vec<usize> = grid
+        .into_iter()
+        .flat_map(|(_, cell)| cell.feature_ids.iter().copied())
+        .collect();
+    // A feature can be in several cells
+    fids.sort();
+    fids.dedup();
+    let items: Vec<PartitionItem> = fids
+        .into_iter()
+        .map(|fid| {
+            let feature = &world.features[fid];
+            let [x, y] = feature.centroid(&world.transform);
+            let b = feature.bbox_qc.to_bbox(&world.transform, None, None);
+            (
+                fid,
+                [x, y, (b[2] + b[5]) / 2.0],
+                limit.feature_items(feature),
+            )
+        })
+        .collect();
+    let side_length = grid.length as f64 * grid.cellsize;
+    let bbox = [
+        grid.origin[0],
+        grid.origin[1],
+        grid.bbox[2],
+        grid.origin[0] + side_length,
+        grid.origin[1] + side_length,
+        grid.bbox[5],
+    ];
+    (items, bbox)
+}
+
+/// An octree of the features, that also subdivides the height, for the tall datasets where
+/// the quadtree leaves would be badly balanced, eg. high-rise districts.
+/// A node is split into eight children if it is at least as tall as wide, otherwise into four
+/// children like a quadtree node, so that the flat areas are not sliced into thin layers. The
+/// rule can be replaced with [OctreeSplitZ].
+/// The capacity is the same as of the [QuadTree].
+///
+/// Each feature is assigned to the node that contains its centroid. The empty nodes are not
+/// stored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Octree {
+    pub id: OctreeNodeId,
+    /// The box of the node, in the input CRS.
+    pub bbox: Bbox,
+    pub children: Vec<Octree>,
+    /// The features of a leaf.
+    pub feature_ids: Vec<usize>,
+    pub nr_items: usize,
+}
+
+impl Octree {
+    pub fn from_world(
+        world: &crate::parser::World,
+        limit: QuadTreeCapacity,
+        split_z: OctreeSplitZ,
+    ) -> Self {
+        let (items, bbox) = partition_items(world, limit);
+        Self::build(
+            OctreeNodeId::default(),
+            bbox,
+            items,
+            limit.limit(),
+            world.grid.cellsize,
+            split_z,
+        )
+    }
+
+    /// Split the node recursively until its items fit in the `limit`, or its side length
+    /// would be smaller than `min_side_length`.
+    fn build(
+        id: OctreeNodeId,
+        bbox: Bbox,
+        items: Vec<PartitionItem>,
+        limit: usize,
+        min_side_length: f64,
+        split_z: OctreeSplitZ,
+    ) -> Self {
+        let nr_items: usize = items.iter().map(|(_, _, n)| n).sum();
+        let side_length = bbox[3] - bbox[0];
+        if nr_items <= limit || items.len() <= 1 || side_length / 2.0 < min_side_length {
+            return Self {
+                id,
+                bbox,
+                children: Vec::new(),
+                feature_ids: items.into_iter().map(|(fid, _, _)| fid).collect(),
+                nr_items,
+            };
+        }
+        let split_z_node = split_z.is_split(id.level, &bbox);
+        let mid = [
+            (bbox[0] + bbox[3]) / 2.0,
+            (bbox[1] + bbox[4]) / 2.0,
+            (bbox[2] + bbox[5]) / 2.0,
+        ];
+        let mut octants: Vec<Vec<PartitionItem>> =
+            vec![Vec::new(); if split_z_node { 8 } else { 4 }];
+        for item in items {
+            let dx = (item.1[0] >= mid[0]) as usize;
+            let dy = (item.1[1] >= mid[1]) as usize;
+            let dz = (split_z_node && item.1[2] >= mid[2]) as usize;
+            octants[dz * 4 + dy * 2 + dx].push(item);
+        }
+        let children = octants
+            .into_iter()
+            .enumerate()
+            .filter(|(_, items)| !items.is_empty())
+            .map(|(i, items)| {
+                let [dx, dy, dz] = [i & 1, (i >> 1) & 1, i >> 2];
+                let (min_z, max_z) = match (split_z_node, dz) {
+                    (false, _) => (bbox[2], bbox[5]),
+                    (true, 0) => (bbox[2], mid[2]),
+                    (true, _) => (mid[2], bbox[5]),
+                };
+                let child_bbox = [
+                    if dx == 0 { bbox[0] } else { mid[0] },
+                    if dy == 0 { bbox[1] } else { mid[1] },
+                    min_z,
+                    if dx == 0 { mid[0] } else { bbox[3] },
+                    if dy == 0 { mid[1] } else { bbox[4] },
+                    max_z,
+                ];
+                let child_id = OctreeNodeId {
+                    x: id.x * 2 + dx,
+                    y: id.y * 2 + dy,
+                    z: id.z * 2 + dz,
+                    level: id.level + 1,
+                };
+                Self::build(child_id, child_bbox, items, limit, min_side_length, split_z)
+            })
+            .collect();
+        Self {
+            id,
+            bbox,
+            children,
+            feature_ids: Vec::new(),
+            nr_items,
+        }
+    }
+}
+
+impl PartitionTree for Octree {
+    fn bbox(&self) -> Bbox {
+        self.bbox
+    }
+
+    fn children(&self) -> Vec<&dyn PartitionTree> {
+        self.children
+            .iter()
+            .map(|c| c as &dyn PartitionTree)
+            .collect()
+    }
+
+    fn feature_ids(&self) -> &[usize] {
+        &self.feature_ids
+    }
+
+    fn level(&self) -> u16 {
+        self.id.level
+    }
+
+    fn file_stem(&self) -> String {
+        self.id.file_stem()
+    }
+}
+
+/// When the [Octree] splits the height of a node too, for a hybrid of the quadtree and the
+/// octree, eg. to split only the clusters of towers in z in a country-wide dataset. If neither
+/// is set, the height of a node is split if the node is at least as tall as wide.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OctreeSplitZ {
+    /// Split the height of the nodes on this level and below.
+    pub min_level: Option<u16>,
+    /// Split the height of the nodes that are taller than this, in the units of the input CRS.
+    pub min_height: Option<f64>,
+}
+
+impl OctreeSplitZ {
+    fn is_split(&self, level: u16, bbox: &Bbox) -> bool {
+        let height = bbox[5] - bbox[2];
+        match (self.min_level, self.min_height) {
+            (None, None) => height >= bbox[3] - bbox[0],
+            (min_level, min_height) => {
+                min_level.is_some_and(|min_level| level >= min_level)
+                    || min_height.is_some_and(|min_height| height > min_height)
+            }
+        }
+    }
+}
+
+/// The ID of an [Octree] node.
+/// The `z` is the index of the node along the z-axis as if each level split the height, thus
+/// the nodes of a level have a unique ID also if only some of their ancestors were split in z.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct OctreeNodeId {
+    pub x: usize,
+    pub y: usize,
+    pub z: usize,
+    pub level: u16,
+}
+
+impl OctreeNodeId {
+    /// The ID as a file stem, `<level>-<x>-<y>-<z>`.
+    pub fn file_stem(&self) -> String {
+        format!("{}-{}-{}-{}", self.level, self.x, self.y, self.z)
+    }
+}
+
+impl Display for OctreeNodeId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}/{}", self.level, self.x, self.y, self.z)
+    }
+}
+
+/// A 2D kd-tree of the features, for the datasets with a very uneven feature density, eg. a
+/// dense city core with sparse rural edges, where the quadtree would waste levels on the
+/// sparse areas.
+/// A node is split in two at the median of the feature centroids, along the longer side of
+/// the node, thus the children have about the same number of features. The capacity is the
+/// same as of the [QuadTree].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KdTree {
+    pub id: KdTreeNodeId,
+    /// The box of the node, in the input CRS.
+    pub bbox: Bbox,
+    pub children: Vec<KdTree>,
+    /// The features of a leaf.
+    pub feature_ids: Vec<usize>,
+    pub nr_items: usize,
+}
+
+impl KdTree {
+    pub fn from_world(world: &crate::parser::World, limit: QuadTreeCapacity) -> Self {
+        let (items, bbox) = partition_items(world, limit);
+        Self::build(KdTreeNodeId::default(), bbox, items, limit.limit())
+    }
+
+    /// Split the node recursively until its items fit in the `limit`, or the centroids of its
+    /// features cannot be separated.
+    fn build(id: KdTreeNodeId, bbox: Bbox, mut items: Vec<PartitionItem>, limit: usize) -> Self {
+        let nr_items: usize = items.iter().map(|(_, _, n)| n).sum();
+        let leaf = |items: Vec<PartitionItem>| Self {
+            id: id.clone(),
+            bbox,
+            children: Vec::new(),
+            feature_ids: items.into_iter().map(|(fid, _, _)| fid).collect(),
+            nr_items,
+        };
+        if nr_items <= limit || items.len() <= 1 {
+            return leaf(items);
+        }
+        // Split along the longer side, or the other side if the centroids are all the same
+        // along the longer side
+        let longer = if bbox[3] - bbox[0] >= bbox[4] - bbox[1] {
+            0
+        } else {
+            1
+        };
+        let Some((axis, split)) = [longer, 1 - longer]
+            .into_iter()
+            .find_map(|axis| Self::median_split(&mut items, axis).map(|split| (axis, split)))
+        else {
+            return leaf(items);
+        };
+        let value = items[split].1[axis];
+        let right = items.split_off(split);
+        let mut bbox_left = bbox;
+        bbox_left[axis + 3] = value;
+        let mut bbox_right = bbox;
+        bbox_right[axis] = value;
+        let children = vec![
+            Self::build(id.child(0), bbox_left, items, limit),
+            Self::build(id.child(1), bbox_right, right, limit),
+        ];
+        Self {
+            id,
+            bbox,
+            children,
+            feature_ids: Vec::new(),
+            nr_items,
+        }
+    }
+
+    /// Sort the items along the `axis` and return the position of the first item of the
+    /// upper half. The items with the same coordinate as the median are kept together in the
+    /// upper half. Returns `None` if all the items have the same coordinate.
+    fn median_split(items: &mut [PartitionItem], axis: usize) -> Option<usize> {
+        items.sort_by(|a, b| a.1[axis].total_cmp(&b.1[axis]));
+        let median = items[items.len() / 2].1[axis];
+        let split = items.partition_point(|item| item.1[axis] < median);
+        if split > 0 {
+            Some(split)
+        } else {
+            // The median is the minimum, so split after it instead
+            let split = items.partition_point(|item| item.1[axis] <= median);
+            (split < items.len()).then_some(split)
+        }
+    }
+}
+
+impl PartitionTree for KdTree {
+    fn bbox(&self) -> Bbox {
+        self.bbox
+    }
+
+    fn children(&self) -> Vec<&dyn PartitionTree> {
+        self.children
+            .iter()
+            .map(|c| c as &dyn PartitionTree)
+            .collect()
+    }
+
+    fn feature_ids(&self) -> &[usize] {
+        &self.feature_ids
+    }
+
+    fn level(&self) -> u16 {
+        self.id.level
+    }
+
+    fn file_stem(&self) -> String {
+        self.id.file_stem()
+    }
+}
+
+/// The ID of a [KdTree] node. The `index` is the path from the root, with a bit for each
+/// level, that is `0` for the lower and `1` for the upper child.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct KdTreeNodeId {
+    pub index: u64,
+    pub level: u16,
+}
+
+impl KdTreeNodeId {
+    fn child(&self, side: u64) -> Self {
+        Self {
+            index: self.index * 2 + side,
+            level: self.level + 1,
+        }
+    }
+
+    /// The ID as a file stem, `<level>-<index>`.
+    pub fn file_stem(&self) -> String {
+        format!("{}-{}", self.level, self.index)
+    }
+}
+
+impl Display for KdTreeNodeId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.level, self.index)
+    }
+}
+
+/// A 2D bounding box in the format of `[minx, miny, maxx, maxy]`.
+pub type Bbox2D = [f64; 4];
+
+fn bbox2d_intersects(a: &Bbox2D, b: &Bbox2D) -> bool {
+    a[0] <= b[2] && b[0] <= a[2] && a[1] <= b[3] && b[1] <= a[3]
+}
+
+fn bbox2d_union(a: &Bbox2D, b: &Bbox2D) -> Bbox2D {
+    [
+        a[0].min(b[0]),
+        a[1].min(b[1]),
+        a[2].max(b[2]),
+        a[3].max(b[3]),
+    ]
+}
+
+/// R-tree of the 2D bounding boxes of items, eg. features, for finding the items that
+/// intersect an area.
+/// The tree is static, it is bulk-loaded with the Sort-Tile-Recursive (STR) algorithm, which
+/// packs the nodes full and keeps the overlap between them small.
+#[derive(Debug, Default)]
+pub struct RTree {
+    root: Option<RTreeNode>,
+}
+
+#[derive(Debug)]
+enum RTreeNode {
+    Leaf {
+        bbox: Bbox2D,
+        items: Vec<(usize, Bbox2D)>,
+    },
+    Node {
+        bbox: Bbox2D,
+        children: Vec<RTreeNode>,
+    },
+}
+
+impl RTreeNode {
+    fn bbox(&self) -> &Bbox2D {
+        match self {
+            Self::Leaf { bbox, .. } | Self::Node { bbox, .. } => bbox,
+        }
+    }
+}
+
+impl RTree {
+    /// The maximum number of entries of a node.
+    const NODE_CAPACITY: usize = 16;
+
+    /// Bulk-load the tree from the items, which are an ID and a bounding box.
+    pub fn new(items: Vec<(usize, Bbox2D)>) -> Self {
+        if items.is_empty() {
+            return Self::default();
+        }
+        let mut nodes: Vec<RTreeNode> = Self::str_pack(items, |(_, b)| *b)
+            .into_iter()
+            .map(|items| RTreeNode::Leaf {
+                bbox: items
+                    .iter()
+                    .map(|(_, b)| *b)
+                    .reduce(|a, b| bbox2d_union(&a, &b))
+                    .unwrap(),
+                items,
+            })
+            .collect();
+        while nodes.len() > 1 {
+            nodes = Self::str_pack(nodes, |node| *node.bbox())
+                .into_iter()
+                .map(|children| RTreeNode::Node {
+                    bbox: children
+                        .iter()
+                        .map(|c| *c.bbox())
+                        .reduce(|a, b| bbox2d_union(&a, &b))
+                        .unwrap(),
+                    children,
+                })
+                .collect();
+        }
+        Self { root: nodes.pop() }
+    }
+
+    /// Sort-Tile-Recursive packing of the entries into groups of [Self::NODE_CAPACITY].
+    /// The entries are sorted by the x of their center and cut into vertical slices, then
+    /// each slice is sorted by the y of the center and cut into the groups.
+    fn str_pack<T, F: Fn(&T) -> Bbox2D>(mut entries: Vec<T>, bbox_of: F) -> Vec<Vec<T>> {
+        let center = |entry: &T, axis: usize| {
+            let b = bbox_of(entry);
+            (b[axis] + b[axis + 2]) / 2.0
+        };
+        let nr_groups = entries.len().div_ceil(Self::NODE_CAPACITY);
+        let nr_slices = (nr_groups as f64).sqrt().ceil() as usize;
+        let slice_size = nr_slices * Self::NODE_CAPACITY;
+        entries.sort_by(|a, b| center(a, 0).total_cmp(&center(b, 0)));
+        let mut groups: Vec<Vec<T>> = Vec::with_capacity(nr_groups);
+        while !entries.is_empty() {
+            let rest = entries.split_off(slice_size.min(entries.len()));
+            let mut slice = std::mem::replace(&mut entries, rest);
+            slice.sort_by(|a, b| center(a, 1).total_cmp(&center(b, 1)));
+            while !slice.is_empty() {
+                let rest = slice.split_off(Self::NODE_CAPACITY.min(slice.len()));
+                groups.push(std::mem::replace(&mut slice, rest));
+            }
+        }
+        groups
+    }
+
+    /// The IDs of the items whose bounding box intersects the `bbox`, in no particular order.
+    /// The boundary is included.
+    pub fn search(&self, bbox: &Bbox2D) -> Vec<usize> {
+        let mut found: Vec<usize> = Vec::new();
+        let mut stack: Vec<&RTreeNode> = self.root.iter().collect();
+        while let Some(node) = stack.pop() {
+            if !bbox2d_intersects(node.bbox(), bbox) {
+                continue;
+            }
+            match node {
+                RTreeNode::Leaf { items, .. } => found.extend(
+                    items
+                        .iter()
+                        .filter(|(_, b)| bbox2d_intersects(b, bbox))
+                        .map(|(id, _)| *id),
+                ),
+                RTreeNode::Node { children, .. } => stack.extend(children.iter()),
+            }
+        }
+        found
+    }
+}
+
 /// We have these double enum, QuadTreeCapacity and QuadTreeCapacityType, because of
 /// how the CLI arguments are parsed. In the quadtree, we need QuadTreeCapacity, because
 /// it can hold both the leaf capacity and the capacity type. But clap can only parse
 /// into unit variants (I think), so we take the the capacity and capacity type as
 /// separate arguments.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub enum QuadTreeCapacity {
     Objects(usize),
     Vertices(usize),
+    /// The sum of the precomputed feature weights.
+    Weight(usize),
+    Triangles(usize),
+    /// The estimated size of the content in bytes, see [ContentSize].
+    Bytes(usize),
+    /// A weighted combination of the other criteria, see [WeightedCriteria].
+    Weighted(usize, WeightedCriteria),
+}
+
+impl QuadTreeCapacity {
+    /// The capacity of a leaf.
+    pub fn limit(self) -> usize {
+        match self {
+            Self::Objects(l)
+            | Self::Vertices(l)
+            | Self::Weight(l)
+            | Self::Triangles(l)
+            | Self::Bytes(l)
+            | Self::Weighted(l, _) => l,
+        }
+    }
+
+    /// The [SplitCriterion] that counts the items of the capacity.
+    pub fn criterion(&self) -> &dyn SplitCriterion {
+        match self {
+            Self::Objects(_) => &ObjectCount,
+            Self::Vertices(_) => &VertexCount,
+            Self::Weight(_) => &WeightSum,
+            Self::Triangles(_) => &TriangleCount,
+            Self::Bytes(_) => &ContentSize,
+            Self::Weighted(_, weighted) => weighted,
+        }
+    }
+
+    /// The number of items of a single feature.
+    pub fn feature_items(self, feature: &crate::parser::Feature) -> usize {
+        self.criterion().feature_items(feature)
+    }
+
+    /// The number of items of a grid cell.
+    pub fn cell_items(self, cell: &Cell) -> usize {
+        self.criterion().cell_items(cell)
+    }
+}
+
+/// Counts the items of the features and the grid cells for the [QuadTreeCapacity], which
+/// drives the splitting of the quadtree. A new criterion needs a variant in
+/// [QuadTreeCapacity] and [QuadTreeCriteria] to be selectable from the CLI.
+pub trait SplitCriterion {
+    /// The number of items of a single feature.
+    fn feature_items(&self, feature: &crate::parser::Feature) -> usize;
+    /// The number of items of a grid cell, which is the sum of the items of its features.
+    fn cell_items(&self, cell: &Cell) -> usize;
+}
+
+/// The number of features.
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectCount;
+
+impl SplitCriterion for ObjectCount {
+    fn feature_items(&self, _feature: &crate::parser::Feature) -> usize {
+        1
+    }
+
+    fn cell_items(&self, cell: &Cell) -> usize {
+        cell.feature_ids.len()
+    }
+}
+
+/// The number of vertices of the features.
+#[derive(Debug, Clone, Copy)]
+pub struct VertexCount;
+
+impl SplitCriterion for VertexCount {
+    fn feature_items(&self, feature: &crate::parser::Feature) -> usize {
+        feature.nr_vertices as usize
+    }
+
+    fn cell_items(&self, cell: &Cell) -> usize {
+        cell.nr_vertices
+    }
+}
+
+/// The sum of the precomputed feature weights, see [crate::parser::FeatureWeights].
+#[derive(Debug, Clone, Copy)]
+pub struct WeightSum;
+
+impl SplitCriterion for WeightSum {
+    fn feature_items(&self, feature: &crate::parser::Feature) -> usize {
+        feature.weight
+    }
+
+    fn cell_items(&self, cell: &Cell) -> usize {
+        cell.weight
+    }
+}
+
+/// The number of triangles of the features, see
+/// [crate::parser::CityJSONFeatureVertices::triangle_count].
+#[derive(Debug, Clone, Copy)]
+pub struct TriangleCount;
+
+impl SplitCriterion for TriangleCount {
+    fn feature_items(&self, feature: &crate::parser::Feature) -> usize {
+        feature.nr_triangles
+    }
+
+    fn cell_items(&self, cell: &Cell) -> usize {
+        cell.nr_triangles
+    }
+}
+
+/// The estimated size of the glTF content of the features in bytes, from the number of
+/// vertices and triangles, without the attributes and the compression.
+#[derive(Debug, Clone, Copy)]
+pub struct ContentSize;
+
+impl ContentSize {
+    /// A position and a normal of 3 x f32.
+    const BYTES_PER_VERTEX: usize = 24;
+    /// 3 x u32 indices.
+    const BYTES_PER_TRIANGLE: usize = 12;
+
+    fn estimate(nr_vertices: usize, nr_triangles: usize) -> usize {
+        nr_vertices * Self::BYTES_PER_VERTEX + nr_triangles * Self::BYTES_PER_TRIANGLE
+    }
+}
+
+impl SplitCriterion for ContentSize {
+    fn feature_items(&self, feature: &crate::parser::Feature) -> usize {
+        Self::estimate(feature.nr_vertices as usize, feature.nr_triangles)
+    }
+
+    fn cell_items(&self, cell: &Cell) -> usize {
+        Self::estimate(cell.nr_vertices, cell.nr_triangles)
+    }
+}
+
+/// The weighted sum of the number of features, vertices, triangles and the estimated content
+/// bytes, eg. to balance the draw calls and the size of the tiles. The sum is rounded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct WeightedCriteria {
+    pub objects: f64,
+    pub vertices: f64,
+    pub triangles: f64,
+    pub bytes: f64,
+}
+
+impl WeightedCriteria {
+    fn combine(&self, items: [usize; 4]) -> usize {
+        (self.objects * items[0] as f64
+            + self.vertices * items[1] as f64
+            + self.triangles * items[2] as f64
+            + self.bytes * items[3] as f64)
+            .round() as usize
+    }
+}
+
+impl SplitCriterion for WeightedCriteria {
+    fn feature_items(&self, feature: &crate::parser::Feature) -> usize {
+        self.combine([
+            ObjectCount.feature_items(feature),
+            VertexCount.feature_items(feature),
+            TriangleCount.feature_items(feature),
+            ContentSize.feature_items(feature),
+        ])
+    }
+
+    fn cell_items(&self, cell: &Cell) -> usize {
+        self.combine([
+            ObjectCount.cell_items(cell),
+            VertexCount.cell_items(cell),
+            TriangleCount.cell_items(cell),
+            ContentSize.cell_items(cell),
+        ])
+    }
+}
+
+impl FromStr for WeightedCriteria {
+    type Err = String;
+
+    /// Parses the weights in the format of 'objects=1,vertices=0.5', the missing criteria
+    /// have a weight of 0.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weighted = Self::default();
+        for pair in s.split(',') {
+            let (name, weight) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected 'criterion=weight', got {pair:?}"))?;
+            let weight: f64 = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid weight {weight:?}"))?;
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!("the weight must be positive, got {weight}"));
+            }
+            match name.trim() {
+                "objects" => weighted.objects = weight,
+                "vertices" => weighted.vertices = weight,
+                "triangles" => weighted.triangles = weight,
+                "bytes" => weighted.bytes = weight,
+                other => {
+                    return Err(format!(
+                    "unknown criterion {other:?}, expected objects, vertices, triangles or bytes"
+                ))
+                }
+            }
+        }
+        Ok(weighted)
+    }
+}
+
+/// The limits of the depth of the [QuadTree], which take precedence over the capacity.
+/// A node above the `min` level is never a leaf, and a node at the `max` level is always a
+/// leaf. The `min` cannot be deeper than the grid cells, because the cells are not split.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct QuadTreeDepth {
+    pub min: u16,
+    pub max: Option<u16>,
+}
+
+impl QuadTreeDepth {
+    /// Is the node at the `level` with `nr_items` a leaf.
+    fn is_leaf(self, level: u16, nr_items: usize, limit: usize) -> bool {
+        match self.max {
+            Some(max) if level >= max => true,
+            _ => nr_items <= limit && level >= self.min,
+        }
+    }
 }
 
 /// The type of items to count for the quadtree leaf capacity.
@@ -369,6 +1523,11 @@ pub enum QuadTreeCriteria {
     Objects,
     #[default]
     Vertices,
+    Triangles,
+    /// The estimated content size in bytes.
+    Bytes,
+    /// The weighted combination of the criteria that is set with --qtree-criteria-weights.
+    Weighted,
 }
 
 /// 64-bit mask
@@ -392,7 +1551,6 @@ pub fn interleave(x: &u64, y: &u64) -> u64 {
     part1by1_64(x) | (part1by1_64(y) << 1)
 }
 
-#[allow(dead_code)]
 fn unpart1by1_64(mortoncode: &u64) -> u64 {
     let mut n = *mortoncode;
     n &= 0x5555555555555555; // binary: 101010101010101010101010101010101010101010101010101010101010101, len: 63
@@ -407,12 +1565,62 @@ fn unpart1by1_64(mortoncode: &u64) -> u64 {
 /// Computing `[x, y]` from a Morton-code.
 ///
 /// Reference: https://github.com/trevorprater/pymorton
+pub fn deinterleave(mortoncode: &u64) -> [u64; 2] {
+    [
+        unpart1by1_64(mortoncode),
+        unpart1by1_64(&(*mortoncode >> 1)),
+    ]
+}
+
+/// Rotate and flip a quadrant of the Hilbert curve, so that the sub-curve in the quadrant
+/// connects to its neighbours.
+fn hilbert_rotate(side: u64, x: &mut u64, y: &mut u64, rx: u64, ry: u64) {
+    if ry == 0 {
+        if rx == 1 {
+            *x = side - 1 - *x;
+            *y = side - 1 - *y;
+        }
+        std::mem::swap(x, y);
+    }
+}
+
+/// The position of the point `[x, y]` along the Hilbert curve that fills a square of
+/// `2^order` by `2^order` cells. Unlike the Morton-code, consecutive positions on the Hilbert
+/// curve are always neighbouring cells.
+///
+/// Reference: https://en.wikipedia.org/wiki/Hilbert_curve
+pub fn hilbert_encode(order: u32, x: u64, y: u64) -> u64 {
+    let side = 1_u64 << order;
+    let (mut x, mut y) = (x, y);
+    let mut d: u64 = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        hilbert_rotate(side, &mut x, &mut y, rx, ry);
+        s /= 2;
+    }
+    d
+}
+
+/// Computing `[x, y]` from a position along the Hilbert curve, see [hilbert_encode].
 #[allow(dead_code)]
-pub fn deinterleave(mortoncode: &u64) -> [u64; 2] {
-    [
-        unpart1by1_64(mortoncode),
-        unpart1by1_64(&(*mortoncode >> 1)),
-    ]
+pub fn hilbert_decode(order: u32, d: u64) -> [u64; 2] {
+    let side = 1_u64 << order;
+    let (mut x, mut y) = (0_u64, 0_u64);
+    let mut t = d;
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        hilbert_rotate(s, &mut x, &mut y, rx, ry);
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    [x, y]
 }
 
 /// Represents a square grid with square cells.
@@ -420,6 +1628,14 @@ pub fn deinterleave(mortoncode: &u64) -> [u64; 2] {
 /// The `length` of the grid is the number of cells of one dimension, thus the total
 /// number of cells is obtained by `length * length`.
 ///
+/// A rectangular grid (see [SquareGrid::cropped]) only stores the `nr_columns * nr_rows`
+/// cells that cover the extent, but it keeps the `length` and the [CellId] addressing of the
+/// square, so that the quadtree is built the same way. The cells of the square that are
+/// outside of the rectangle are empty.
+///
+/// The cells are stored in a [CellStorage], which is either dense, as in the figure below, or
+/// sparse, see [SquareGrid::choose_storage].
+///
 /// Note the a 'column' in the grid is represented by the X-axis, and a 'row' by the Y-axis.
 /// See [CellId] for details.
 ///
@@ -464,17 +1680,49 @@ pub struct SquareGrid {
     origin: [f64; 3],
     pub bbox: Bbox,
     pub length: usize,
-    cellsize: u32,
-    pub data: Vec<Vec<Cell>>,
+    /// The number of the stored columns, which is `length`, unless the grid is rectangular.
+    pub nr_columns: usize,
+    /// The number of the stored rows, which is `length`, unless the grid is rectangular.
+    pub nr_rows: usize,
+    cellsize: f64,
+    data: CellStorage,
     pub epsg: u16,
 }
 
+/// The storage of the grid cells. The grid cells are accessed through [SquareGrid::cell] and
+/// [SquareGrid::cell_mut], so the storage is transparent for the users of the grid.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CellStorage {
+    /// A column-vector for each column, with a cell for each row. The vectors are allocated
+    /// at the first modification of a cell, so that the storage can be switched to sparse
+    /// before that.
+    Dense(Vec<Vec<Cell>>),
+    /// Only the cells that were modified. For nation-wide extents with a small cell size,
+    /// where most of the cells are empty.
+    Sparse(HashMap<CellId, Cell>),
+}
+
+impl SquareGrid {
+    /// Use the sparse storage if the features are expected to occupy less than one in this
+    /// many cells. An occupied cell takes about twice the memory in the sparse storage, so this
+    /// leaves a margin for the features that occupy several cells.
+    const SPARSE_CELLS_PER_FEATURE: usize = 16;
+}
+
+/// The content of the cells that are outside of a rectangular grid.
+static EMPTY_CELL: Cell = Cell {
+    feature_ids: Vec::new(),
+    nr_vertices: 0,
+    weight: 0,
+    nr_triangles: 0,
+};
+
 impl Display for SquareGrid {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "SquareGrid (origin: {:?}, bbox: {:?}, length: {}, cellsize: {}, data: not-displayed, epsg: {})",
-            self.origin, self.bbox, self.length, self.cellsize, self.epsg
+            "SquareGrid (origin: {:?}, bbox: {:?}, length: {}, columns: {}, rows: {}, cellsize: {}, data: not-displayed, sparse: {}, epsg: {})",
+            self.origin, self.bbox, self.length, self.nr_columns, self.nr_rows, self.cellsize, self.is_sparse(), self.epsg
         )
     }
 }
@@ -484,7 +1732,7 @@ impl SquareGrid {
     /// The grid and the cells are square.
     /// The grid center is the `extent` center.
     /// The grid is returned as an origin coordinate and the number of cells.
-    pub fn new(extent: &Bbox, cellsize: u32, epsg: u16) -> Self {
+    pub fn new(extent: &Bbox, cellsize: f64, epsg: u16) -> Self {
         // We only compute 2D. Z is constant for the grid.
         let extent_center = [
             extent[0] + (extent[3] - extent[0]) / 2.0,
@@ -494,6 +1742,10 @@ impl SquareGrid {
         let dy = extent[4] - extent[1];
         // The grid starting dimension is the longest edge of the rectangle
         let mut d = if dx > dy { dx } else { dy };
+        // A degenerate extent, eg. of a single point-like feature, is covered by a single cell
+        if d <= 0.0 {
+            d = cellsize;
+        }
         // The grid is constructed as a square, from the center of the extent.
         // We don't need to add any buffer to the extent, because we already round up when we
         // compute the number of cells.
@@ -501,69 +1753,172 @@ impl SquareGrid {
         // a 4^n cells quadtree.
         // Adjust the cellsize so that we can get a tightly fit square on the extent
         let mut cellsize_new = d;
-        let cellsize_f64 = cellsize as f64;
         loop {
             let cn = cellsize_new / 2.0;
-            if cn < cellsize_f64 {
+            if cn < cellsize {
                 break;
             } else {
                 cellsize_new /= 2.0;
             }
         }
         let d_cells = (d / cellsize_new).ceil() as usize;
-        let cellsize = cellsize_new.ceil() as u32;
+        // Round up the cell size to whole units, or to thousandths for the cells that are
+        // smaller than a unit (eg. indoor models).
+        let cellsize = if cellsize_new >= 1.0 {
+            cellsize_new.ceil()
+        } else {
+            // Without the tolerance, eg. 0.05 would be rounded up to 0.051
+            ((cellsize_new * 1000.0) - 1e-6).ceil() / 1000.0
+        };
         // Compute new dimension from the calculated length
-        d = d_cells as f64 * cellsize as f64;
+        d = d_cells as f64 * cellsize;
         let origin = [
             extent_center[0] - d / 2.0,
             extent_center[1] - d / 2.0,
             extent[2],
         ];
+        Self::allocate(origin, d_cells, d_cells, cellsize, extent[5], epsg)
+    }
+
+    /// Creates a grid with cells of exactly `cellsize`, that covers the `extent` and whose
+    /// cell corners are aligned to the `origin`.
+    /// Grids that are created with the same `origin` and `cellsize` have aligned cells, even if
+    /// their extent is different. Thus the tiles of separately generated tilesets align too.
+    pub fn new_snapped(extent: &Bbox, cellsize: f64, epsg: u16, origin: &[f64; 3]) -> Self {
+        let (origin_snapped, nx, ny) = Self::snap(extent, cellsize, origin);
+        // We need 2^n cells in one dimension for the quadtree
+        let d_cells = nx.max(ny).next_power_of_two();
+        Self::allocate(origin_snapped, d_cells, d_cells, cellsize, extent[5], epsg)
+    }
+
+    /// Creates a grid with cells of exactly `cellsize`, whose lower-left corner is the
+    /// lower-left corner of `grid_extent` and that covers `grid_extent`, as
+    /// `[min x, min y, max x, max y]`. Unlike [SquareGrid::new], the grid does not depend on the
+    /// extent of the data, thus the cells and the tiles stay the same when the data grows
+    /// within the `grid_extent`. The z-range of the grid is taken from the `extent`.
+    pub fn new_pinned(extent: &Bbox, grid_extent: &[f64; 4], cellsize: f64, epsg: u16) -> Self {
+        let origin = [grid_extent[0], grid_extent[1], extent[2]];
+        let nx = ((grid_extent[2] - grid_extent[0]) / cellsize).ceil() as usize;
+        let ny = ((grid_extent[3] - grid_extent[1]) / cellsize).ceil() as usize;
+        // We need 2^n cells in one dimension for the quadtree
+        let d_cells = nx.max(ny).max(1).next_power_of_two();
+        Self::allocate(origin, d_cells, d_cells, cellsize, extent[5], epsg)
+    }
+
+    /// A rectangular grid with the same cell size and cell corners as this grid, that only
+    /// stores the columns and rows of cells that cover the `extent`. For elongated extents,
+    /// eg. a rail corridor, it stores a fraction of the cells of the square grid. The quadtree
+    /// of the rectangular grid still covers a square of `length * length` cells from the
+    /// origin, but the cells outside of the rectangle are empty. The features need to be
+    /// indexed again.
+    pub fn cropped(&self, extent: &Bbox) -> Self {
+        let (origin, nx, ny) = Self::snap(extent, self.cellsize, &self.origin);
+        Self::allocate(origin, nx, ny, self.cellsize, extent[5], self.epsg)
+    }
+
+    /// Snap the lower-left corner of the `extent` to the cell corners that are aligned to the
+    /// `origin`. Returns the snapped corner and the number of columns and rows that cover the
+    /// extent from it.
+    fn snap(extent: &Bbox, cellsize: f64, origin: &[f64; 3]) -> ([f64; 3], usize, usize) {
+        let origin_snapped = [
+            origin[0] + ((extent[0] - origin[0]) / cellsize).floor() * cellsize,
+            origin[1] + ((extent[1] - origin[1]) / cellsize).floor() * cellsize,
+            extent[2],
+        ];
+        let nx = ((extent[3] - origin_snapped[0]) / cellsize).ceil() as usize;
+        let ny = ((extent[4] - origin_snapped[1]) / cellsize).ceil() as usize;
+        (origin_snapped, nx.max(1), ny.max(1))
+    }
+
+    /// Allocates the empty cells of a grid of `nr_columns` x `nr_rows` cells, starting at
+    /// `origin`. The `length` of the grid is the smallest power of two that fits both.
+    fn allocate(
+        origin: [f64; 3],
+        nr_columns: usize,
+        nr_rows: usize,
+        cellsize: f64,
+        maxz: f64,
+        epsg: u16,
+    ) -> Self {
         let bbox = [
             origin[0],
             origin[1],
             origin[2],
-            origin[0] + d,
-            origin[1] + d,
-            extent[5],
+            origin[0] + nr_columns as f64 * cellsize,
+            origin[1] + nr_rows as f64 * cellsize,
+            maxz,
         ];
-        // A row-vector (x-axis) to store the column-vectors (y-axis).
-        let mut row: Vec<Vec<Cell>> = Vec::with_capacity(d_cells);
-        // For each column create a column vector that stores the cells and for each row in the
-        // column create a cell to store the feature IDs.
-        row.resize_with(d_cells, || {
-            let mut column: Vec<Cell> = Vec::with_capacity(d_cells);
-            column.resize(
-                d_cells,
-                Cell {
-                    feature_ids: Vec::new(),
-                    nr_vertices: 0,
-                },
-            );
-            column
-        });
         Self {
             origin,
             bbox,
-            length: d_cells,
+            length: nr_columns.max(nr_rows).next_power_of_two(),
+            nr_columns,
+            nr_rows,
             cellsize,
-            data: row,
+            // Allocated at the first modification of a cell
+            data: CellStorage::Dense(Vec::new()),
             epsg,
         }
     }
 
+    /// Switch to the sparse storage if the `nr_features` are expected to occupy a small
+    /// fraction of the cells, see [CellStorage]. Nothing changes after a cell was modified,
+    /// thus it is called on an empty grid.
+    pub fn choose_storage(&mut self, nr_features: usize) {
+        let nr_cells = self.nr_columns * self.nr_rows;
+        match &self.data {
+            CellStorage::Dense(columns) if columns.is_empty() => {
+                if nr_features.saturating_mul(Self::SPARSE_CELLS_PER_FEATURE) < nr_cells {
+                    debug!(
+                        "Using a sparse storage for the {} grid cells, for {} features",
+                        nr_cells, nr_features
+                    );
+                    self.data = CellStorage::Sparse(HashMap::new());
+                }
+            }
+            _ => debug!("The grid cells are already stored, keeping their storage"),
+        }
+    }
+
+    /// Returns `true` if the cells are stored in a [CellStorage::Sparse].
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.data, CellStorage::Sparse(_))
+    }
+
+    /// The lower-left corner of the grid.
+    pub fn origin(&self) -> [f64; 3] {
+        self.origin
+    }
+
+    pub fn cellsize(&self) -> f64 {
+        self.cellsize
+    }
+
     /// Returns the cell index (x, y) where the point is located.
     pub fn locate_point(&self, point: &[f64; 2]) -> CellId {
         let dx = point[0] - self.origin[0];
         let dy = point[1] - self.origin[1];
-        let col_i = (dx / self.cellsize as f64).floor() as usize;
-        let row_i = (dy / self.cellsize as f64).floor() as usize;
+        let col_i = (dx / self.cellsize).floor() as usize;
+        let row_i = (dy / self.cellsize).floor() as usize;
         CellId {
             row: row_i,
             column: col_i,
         }
     }
 
+    /// Returns `true` if the point is within the grid.
+    pub fn contains_point(&self, point: &[f64; 2]) -> bool {
+        point[0] >= self.bbox[0]
+            && point[0] < self.bbox[3]
+            && point[1] >= self.bbox[1]
+            && point[1] < self.bbox[4]
+    }
+
+    /// Returns `true` if the cell is within the grid.
+    pub fn contains_cell(&self, cellid: &CellId) -> bool {
+        cellid.row < self.nr_rows && cellid.column < self.nr_columns
+    }
+
     /// Insert a point into the grid, by assigning it to the Cell where it is located.
     pub fn insert(&mut self, point: &[f64; 2], feature_id: usize) -> CellId {
         let cell_id = self.locate_point(point);
@@ -572,6 +1927,26 @@ impl SquareGrid {
         cell_id
     }
 
+    /// Insert a feature into every cell that its 2D bounding box intersects, instead of only
+    /// into the cell of a point, so that the tiles of all these cells contain it. The cells
+    /// outside of the grid are skipped, and a cell that already contains the feature does not
+    /// receive it again. The vertex count and the weight of the cells are not changed.
+    /// Returns the cells that contain the feature.
+    pub fn insert_bbox(&mut self, bbox: &Bbox, feature_id: usize) -> Vec<CellId> {
+        let cellids: Vec<CellId> = self
+            .intersect_bbox(bbox)
+            .into_iter()
+            .filter(|cellid| self.contains_cell(cellid))
+            .collect();
+        for cellid in &cellids {
+            let cell = self.cell_mut(cellid);
+            if !cell.feature_ids.contains(&feature_id) {
+                cell.feature_ids.push(feature_id);
+            }
+        }
+        cellids
+    }
+
     /// Return the Cells that intersect the Bounding Box.
     pub fn intersect_bbox(&self, bbox: &Bbox) -> Vec<CellId> {
         let mut cellids: Vec<CellId> = Vec::new();
@@ -593,6 +1968,8 @@ impl SquareGrid {
     /// If `feature_set` is provided, `transform` must be provided too (and vica-versa).
     /// If `output_dir` is provided, the files are written there. Else they are written to the
     /// working directory.
+    /// The cells with content are also written to `grid.geojson` as polygons, with their
+    /// counts as attributes, which is simpler to load into a GIS, see [write_geojson].
     pub fn export(
         &self,
         feature_set: Option<&FeatureSet>,
@@ -621,7 +1998,23 @@ impl SquareGrid {
         file_features
             .write_all("fid\tcell_id\twkt\n".as_bytes())
             .expect("cannot write features header");
+        // Only the cells with content, because the grid is mostly empty around the data
+        let mut cells_geojson: Vec<serde_json::Value> = Vec::new();
         for (cellid, cell) in self {
+            if !cell.feature_ids.is_empty() {
+                cells_geojson.push(serde_json::json!({
+                    "type": "Feature",
+                    "properties": {
+                        "cell_id": cellid.to_string(),
+                        "column": cellid.column,
+                        "row": cellid.row,
+                        "nr_features": cell.feature_ids.len(),
+                        "nr_vertices": cell.nr_vertices,
+                        "weight": cell.weight,
+                    },
+                    "geometry": bbox_to_geojson(&self.cell_bbox(&cellid)),
+                }));
+            }
             let wkt = self.cell_to_wkt(&cellid);
             file_grid
                 .write_all(format!("{}\t{}\t{}\n", &cellid, cell.nr_vertices, wkt).as_bytes())
@@ -648,87 +2041,227 @@ impl SquareGrid {
             // Remove empty file
             std::fs::remove_file(file_features_path)?;
         }
-        Ok(())
+        write_geojson(
+            &file_grid_path.with_extension("geojson"),
+            Some(self.epsg),
+            cells_geojson,
+        )
     }
 
     pub fn cell_to_wkt(&self, cellid: &CellId) -> String {
-        let minx = self.origin[0] + (cellid.column * self.cellsize as usize) as f64;
-        let miny = self.origin[1] + (cellid.row * self.cellsize as usize) as f64;
+        let minx = self.origin[0] + cellid.column as f64 * self.cellsize;
+        let miny = self.origin[1] + cellid.row as f64 * self.cellsize;
         bbox_to_wkt(&[
             minx,
             miny,
             self.bbox[2],
-            minx + self.cellsize as f64,
-            miny + self.cellsize as f64,
+            minx + self.cellsize,
+            miny + self.cellsize,
             self.bbox[5],
         ])
     }
 
     pub fn cell_bbox(&self, cellid: &CellId) -> Bbox {
-        let minx = self.origin[0] + (cellid.column * self.cellsize as usize) as f64;
-        let miny = self.origin[1] + (cellid.row * self.cellsize as usize) as f64;
+        let minx = self.origin[0] + cellid.column as f64 * self.cellsize;
+        let miny = self.origin[1] + cellid.row as f64 * self.cellsize;
         let minz = self.bbox[2];
-        let maxx = minx + self.cellsize as f64;
-        let maxy = miny + self.cellsize as f64;
+        let maxx = minx + self.cellsize;
+        let maxy = miny + self.cellsize;
         let maxz = self.bbox[5];
         [minx, miny, minz, maxx, maxy, maxz]
     }
 
-    pub fn cell(&self, cell_id: &CellId) -> &Cell {
-        &self.data[cell_id.column][cell_id.row]
+    /// The level of the quadtree nodes that consist of a single grid cell, thus the depth of
+    /// the quadtree that is split down to the cells, see [QuadTree::from_grid].
+    pub fn quadtree_max_level(&self) -> u16 {
+        self.length.next_power_of_two().trailing_zeros() as u16
     }
 
-    pub fn cell_mut(&mut self, cell_id: &CellId) -> &mut Cell {
-        &mut self.data[cell_id.column][cell_id.row]
+    /// The order of the Hilbert curve that covers the grid, thus the smallest `order` for
+    /// which `2^order >= length`.
+    pub fn hilbert_order(&self) -> u32 {
+        self.length.next_power_of_two().trailing_zeros()
     }
 
-    /// Compute the vertex distribution in the cells of the grid.
-    pub fn compute_statistics(&self) -> SquareGridStats {
-        // nr. of vertices in the cells that are not empty
-        let mut nr_vertices_not_empty: Vec<usize> = Vec::with_capacity(self.length * self.length);
-        let mut nr_cells_not_empty: usize = 0;
-        for (_, cell) in self {
-            if cell.nr_vertices > 0 {
-                nr_vertices_not_empty.push(cell.nr_vertices);
-                nr_cells_not_empty += 1;
-            }
+    /// The position of the cell that contains the point along the Hilbert curve of the grid,
+    /// see [hilbert_encode]. The points outside of the grid are moved to the nearest cell.
+    pub fn hilbert_index(&self, point: &[f64; 2]) -> u64 {
+        let max = self.length.saturating_sub(1);
+        let cellid = self.locate_point(point);
+        hilbert_encode(
+            self.hilbert_order(),
+            cellid.column.min(max) as u64,
+            cellid.row.min(max) as u64,
+        )
+    }
+
+    /// Iterate over the cells in Morton-order, see [SquareGridMortonIterator].
+    pub fn iter_morton(&self) -> SquareGridMortonIterator<'_> {
+        SquareGridMortonIterator {
+            mortoncode: 0,
+            nr_cells: self.length.pow(2) as u64,
+            grid: self,
         }
-        // Handle empty case
-        if nr_vertices_not_empty.is_empty() {
-            return SquareGridStats {
-                nr_vertices: 0,
-                nr_cells_with_content: 0,
-                nr_vertices_min: 0,
-                nr_vertices_max: 0,
-                nr_vertices_mean: 0.0,
-                nr_vertices_median: 0.0,
-            };
+    }
+
+    /// The cell, or an empty cell if it is outside of a rectangular grid, but within its
+    /// `length`.
+    pub fn cell(&self, cell_id: &CellId) -> &Cell {
+        match &self.data {
+            CellStorage::Dense(columns) => columns
+                .get(cell_id.column)
+                .and_then(|column| column.get(cell_id.row)),
+            CellStorage::Sparse(cells) => cells.get(cell_id),
         }
+        .unwrap_or(&EMPTY_CELL)
+    }
 
-        let sum = nr_vertices_not_empty.iter().sum();
-        let mean = sum as f64 / nr_cells_not_empty as f64;
+    /// Panics if the cell is not stored in the grid, see [SquareGrid::contains_cell].
+    pub fn cell_mut(&mut self, cell_id: &CellId) -> &mut Cell {
+        assert!(
+            self.contains_cell(cell_id),
+            "cell {cell_id} is outside of the grid of {} columns and {} rows",
+            self.nr_columns,
+            self.nr_rows
+        );
+        match &mut self.data {
+            CellStorage::Dense(columns) => {
+                if columns.is_empty() {
+                    // A row-vector (x-axis) to store the column-vectors (y-axis), and for each
+                    // row in the column a cell to store the feature IDs.
+                    columns.resize_with(self.nr_columns, || vec![EMPTY_CELL.clone(); self.nr_rows]);
+                }
+                &mut columns[cell_id.column][cell_id.row]
+            }
+            CellStorage::Sparse(cells) => cells.entry(*cell_id).or_default(),
+        }
+    }
 
-        // Sort for median calculation
-        nr_vertices_not_empty.sort();
+    /// An empty grid with the same origin and extent, and half of the cell size, thus each cell
+    /// is split into four. The features need to be indexed again.
+    pub fn refined(&self) -> Self {
+        Self::allocate(
+            self.origin,
+            self.nr_columns * 2,
+            self.nr_rows * 2,
+            self.cellsize / 2.0,
+            self.bbox[5],
+            self.epsg,
+        )
+    }
 
-        // Calculate median
-        let median = if nr_vertices_not_empty.len() % 2 == 0 {
-            // Even number of elements: average of the two middle elements
-            let mid = nr_vertices_not_empty.len() / 2;
-            (nr_vertices_not_empty[mid - 1] as f64 + nr_vertices_not_empty[mid] as f64) / 2.0
+    /// The cells whose content exceeds the capacity of a quadtree leaf, which the quadtree
+    /// cannot split, see [CapacityMismatch]. Only the cells with several features are counted,
+    /// because a smaller cell does not split a single feature. A capacity of 0 is ignored,
+    /// because it splits the quadtree down to the cells on purpose.
+    pub fn capacity_mismatch(&self, limit: QuadTreeCapacity) -> Option<CapacityMismatch> {
+        if limit.limit() == 0 {
+            return None;
+        }
+        let cells_items: Vec<usize> = self
+            .into_iter()
+            .filter(|(_, cell)| cell.feature_ids.len() > 1)
+            .map(|(_, cell)| limit.cell_items(cell))
+            .filter(|items| *items > limit.limit())
+            .collect();
+        let max_items = *cells_items.iter().max()?;
+        // Assuming that the content is evenly distributed in the cell, its items scale with
+        // the area of the cell
+        let cellsize = self.cellsize * (limit.limit() as f64 / max_items as f64).sqrt();
+        let suggested_cellsize = if cellsize >= 1.0 {
+            cellsize.floor()
         } else {
-            // Odd number of elements: the middle element
-            let mid = nr_vertices_not_empty.len() / 2;
-            nr_vertices_not_empty[mid] as f64
+            ((cellsize * 1000.0).floor() / 1000.0).max(0.001)
+        };
+        Some(CapacityMismatch {
+            nr_cells: cells_items.len(),
+            max_items,
+            suggested_cellsize,
+        })
+    }
+
+    /// The density raster of the grid, with a pixel for each cell, colored by the items of the
+    /// cell relative to the capacity of a quadtree leaf. The empty cells are transparent, the
+    /// colors go from purple to yellow until the capacity, and the cells that exceed the
+    /// capacity are red, because the quadtree cannot split them. With a capacity of 0, the
+    /// colors are relative to the fullest cell.
+    pub fn density_image(&self, limit: QuadTreeCapacity) -> crate::raster::RgbaImage {
+        const RAMP: [[u8; 3]; 5] = [
+            [68, 1, 84],
+            [59, 82, 139],
+            [33, 145, 140],
+            [94, 201, 98],
+            [253, 231, 37],
+        ];
+        const EXCEEDED: [u8; 4] = [215, 25, 28, 255];
+        let max_items = match limit.limit() {
+            0 => self
+                .into_iter()
+                .map(|(_, cell)| limit.cell_items(cell))
+                .max()
+                .unwrap_or(0),
+            l => l,
         };
+        let mut image = crate::raster::RgbaImage::new(self.nr_columns, self.nr_rows);
+        for (cellid, cell) in self {
+            let items = limit.cell_items(cell);
+            if cell.feature_ids.is_empty() {
+                continue;
+            }
+            // The image rows go from the top, the grid rows from the bottom
+            let row = self.nr_rows - 1 - cellid.row;
+            if items > max_items {
+                image.set(cellid.column, row, EXCEEDED);
+            } else {
+                let [r, g, b] =
+                    crate::raster::color_ramp(&RAMP, items as f64 / max_items.max(1) as f64);
+                image.set(cellid.column, row, [r, g, b, 255]);
+            }
+        }
+        image
+    }
+
+    /// Export the [SquareGrid::density_image] into `density.png` with its world file
+    /// `density.pgw` in the `output_dir`.
+    pub fn export_density(
+        &self,
+        limit: QuadTreeCapacity,
+        output_dir: &Path,
+    ) -> std::io::Result<()> {
+        self.density_image(limit)
+            .write_png(&output_dir.join("density.png"))?;
+        crate::raster::write_world_file(
+            &output_dir.join("density.pgw"),
+            [self.origin[0], self.bbox[4]],
+            self.cellsize,
+        )
+    }
 
+    /// Compute the distribution of the features and the vertices in the cells of the grid, see
+    /// [SquareGridStats].
+    pub fn stats(&self) -> SquareGridStats {
+        let nr_cells = self.nr_columns * self.nr_rows;
+        // The number of features and vertices in the cells that are not empty
+        let mut nr_features_not_empty: Vec<usize> = Vec::new();
+        let mut nr_vertices_not_empty: Vec<usize> = Vec::new();
+        for (_, cell) in self {
+            if !cell.feature_ids.is_empty() || cell.nr_vertices > 0 {
+                nr_features_not_empty.push(cell.feature_ids.len());
+                nr_vertices_not_empty.push(cell.nr_vertices);
+            }
+        }
+        let nr_cells_empty = nr_cells - nr_features_not_empty.len();
         SquareGridStats {
-            nr_vertices: sum,
-            nr_cells_with_content: nr_cells_not_empty,
-            nr_vertices_min: *nr_vertices_not_empty.iter().min().unwrap(),
-            nr_vertices_max: *nr_vertices_not_empty.iter().max().unwrap(),
-            nr_vertices_mean: mean,
-            nr_vertices_median: median,
+            nr_cells,
+            nr_cells_empty,
+            empty_ratio: if nr_cells > 0 {
+                nr_cells_empty as f64 / nr_cells as f64
+            } else {
+                0.0
+            },
+            nr_vertices: nr_vertices_not_empty.iter().sum(),
+            features: CellDistribution::new(nr_features_not_empty),
+            vertices: CellDistribution::new(nr_vertices_not_empty),
         }
     }
 }
@@ -742,67 +2275,170 @@ impl<'squaregrid> IntoIterator for &'squaregrid SquareGrid {
         SquareGridIterator {
             row_index: 0,
             col_index: 0,
-            items: &self.data,
+            grid: self,
         }
     }
 }
 
+/// Yields all the stored cells, also the empty cells of a sparse storage.
 pub struct SquareGridIterator<'squaregrid> {
     row_index: usize,
     col_index: usize,
-    items: &'squaregrid Vec<Vec<Cell>>,
+    grid: &'squaregrid SquareGrid,
 }
 
 impl<'squaregrid> Iterator for SquareGridIterator<'squaregrid> {
     type Item = (CellId, &'squaregrid Cell);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(column) = self.items.get(self.col_index) {
-            if let Some(cell) = column.get(self.row_index) {
-                let item = Some((
-                    CellId {
-                        row: self.row_index,
-                        column: self.col_index,
-                    },
-                    cell,
-                ));
-                self.row_index += 1;
-                item
-            } else {
-                // We are at the end of the current column, so jump to the next
-                self.col_index += 1;
-                self.row_index = 0;
-                self.next()
-            }
+        if self.col_index >= self.grid.nr_columns {
+            None
+        } else if self.row_index < self.grid.nr_rows {
+            let cellid = CellId {
+                row: self.row_index,
+                column: self.col_index,
+            };
+            self.row_index += 1;
+            Some((cellid, self.grid.cell(&cellid)))
+        } else {
+            // We are at the end of the current column, so jump to the next
+            self.col_index += 1;
+            self.row_index = 0;
+            self.next()
+        }
+    }
+}
+
+/// Returns a tuple of `(CellId, &Cell)` for each cell of the `length * length` square in
+/// Morton-order, which is the order of the implicit tiling subtrees and availability
+/// bitstreams of 3D Tiles. The column is the x and the row is the y of the Morton code, see
+/// [CellId::morton_code]. The cells outside of a rectangular grid are yielded as empty cells.
+///
+/// Note that the children of a [QuadTree] node are ordered by y first, see
+/// [QuadTree::from_grid].
+pub struct SquareGridMortonIterator<'squaregrid> {
+    mortoncode: u64,
+    nr_cells: u64,
+    grid: &'squaregrid SquareGrid,
+}
+
+impl<'squaregrid> Iterator for SquareGridMortonIterator<'squaregrid> {
+    type Item = (CellId, &'squaregrid Cell);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.mortoncode < self.nr_cells {
+            let cellid = CellId::from_morton_code(self.mortoncode);
+            self.mortoncode += 1;
+            Some((cellid, self.grid.cell(&cellid)))
         } else {
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.nr_cells - self.mortoncode) as usize;
+        (remaining, Some(remaining))
+    }
 }
 
-#[derive(Debug)]
+impl ExactSizeIterator for SquareGridMortonIterator<'_> {}
+
+/// The grid cells that exceed the capacity of a quadtree leaf, see
+/// [SquareGrid::capacity_mismatch]. These cells become oversized leaves.
+#[derive(Debug, PartialEq)]
+pub struct CapacityMismatch {
+    pub nr_cells: usize,
+    /// The items of the fullest cell.
+    pub max_items: usize,
+    /// The largest cell size whose cells would fit in the capacity, if the content was evenly
+    /// distributed.
+    pub suggested_cellsize: f64,
+}
+
+/// The statistics of the grid cells, see [SquareGrid::stats]. The distributions only count the
+/// cells that are not empty, because the grid is mostly empty around the data.
+#[derive(Debug, Serialize)]
 pub struct SquareGridStats {
-    nr_vertices: usize,
-    nr_cells_with_content: usize,
-    nr_vertices_min: usize,
-    nr_vertices_max: usize,
-    nr_vertices_mean: f64,
-    nr_vertices_median: f64,
+    /// The number of the stored cells.
+    pub nr_cells: usize,
+    pub nr_cells_empty: usize,
+    /// The fraction of the empty cells, between 0 and 1.
+    pub empty_ratio: f64,
+    pub nr_vertices: usize,
+    /// The number of features per cell.
+    pub features: CellDistribution,
+    /// The number of vertices per cell.
+    pub vertices: CellDistribution,
 }
 
 impl Display for SquareGridStats {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Nr. cells with vertices: {nr_cells}; Nr. vertices: {nr_vertices}, min.: {min}, max.: {max}, median: {median}, mean: {mean}",
-               nr_vertices = self.nr_vertices, nr_cells = self.nr_cells_with_content,
-               min = self.nr_vertices_min, max = self.nr_vertices_max,
-               median = self.nr_vertices_median, mean = self.nr_vertices_mean)
+        write!(f, "Nr. cells: {nr_cells}, empty: {nr_empty} ({empty_pct:.1}%); Nr. vertices: {nr_vertices}; Features per cell: {features}; Vertices per cell: {vertices}",
+               nr_cells = self.nr_cells, nr_empty = self.nr_cells_empty,
+               empty_pct = self.empty_ratio * 100.0, nr_vertices = self.nr_vertices,
+               features = self.features, vertices = self.vertices)
+    }
+}
+
+/// The distribution of a count over the grid cells.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct CellDistribution {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// The 90th percentile, with the nearest-rank method.
+    pub p90: usize,
+    /// The 99th percentile, with the nearest-rank method.
+    pub p99: usize,
+}
+
+impl CellDistribution {
+    fn new(mut values: Vec<usize>) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+        values.sort_unstable();
+        let n = values.len();
+        let median = if n.is_multiple_of(2) {
+            // Even number of elements: average of the two middle elements
+            (values[n / 2 - 1] as f64 + values[n / 2] as f64) / 2.0
+        } else {
+            values[n / 2] as f64
+        };
+        let percentile = |p: f64| values[((p * n as f64).ceil() as usize).clamp(1, n) - 1];
+        Self {
+            min: values[0],
+            max: values[n - 1],
+            mean: values.iter().sum::<usize>() as f64 / n as f64,
+            median,
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+impl Display for CellDistribution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "min.: {}, max.: {}, mean: {:.1}, median: {}, p90: {}, p99: {}",
+            self.min, self.max, self.mean, self.median, self.p90, self.p99
+        )
     }
 }
 
-#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Cell {
     pub feature_ids: Vec<usize>,
     pub nr_vertices: usize,
+    /// The sum of the weights of the features in the cell, see [crate::parser::FeatureWeights].
+    #[serde(default)]
+    pub weight: usize,
+    /// The sum of the triangles of the features in the cell, see
+    /// [crate::parser::Feature::nr_triangles].
+    #[serde(default)]
+    pub nr_triangles: usize,
 }
 
 /// Grid cell identifier.
@@ -833,21 +2469,311 @@ impl Display for CellId {
     }
 }
 
-/// 3D bounding box.
-///
-/// [min x, min y, min z, max x, max y, max z]
-/// TODO: this must become a struct and have a .to_wkt() method
-pub type Bbox = [f64; 6];
+impl CellId {
+    /// The Morton code of the cell, with the column as x and the row as y, so that the cells
+    /// sorted by the code follow the Z-order curve, see [interleave].
+    #[allow(dead_code)]
+    pub fn morton_code(self) -> u64 {
+        interleave(&(self.column as u64), &(self.row as u64))
+    }
+
+    /// The cell of a Morton code, the inverse of [CellId::morton_code].
+    pub fn from_morton_code(mortoncode: u64) -> Self {
+        let [column, row] = deinterleave(&mortoncode);
+        Self {
+            row: row as usize,
+            column: column as usize,
+        }
+    }
+}
+
+/// A voxel of the [VoxelGrid], which is a grid cell and a layer of it in z.
+#[derive(Copy, Clone, Hash, Debug, Ord, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoxelId {
+    pub row: usize,
+    pub column: usize,
+    // A layer is along the z-axis, from the bottom of the grid
+    pub layer: usize,
+}
+
+impl Display for VoxelId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}-{}", &self.column, &self.row, &self.layer)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Voxel {
+    pub feature_ids: Vec<usize>,
+    /// The z-range of the features of the voxel, `[min z, max z]`.
+    pub z_range: [f64; 2],
+}
+
+/// The cells of the [SquareGrid] sliced into layers of `z_cellsize` in z, so that the features
+/// that are stacked in a cell, eg. tunnels, utilities and towers, are in separate voxels.
+///
+/// A feature is in the layer of the middle of its height, in each cell that it is in. The
+/// z-range of a voxel is the z-range of its features instead of the layer, because the features
+/// can reach into the neighbouring layers. The empty voxels are not stored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VoxelGrid {
+    pub z_cellsize: f64,
+    pub voxels: BTreeMap<VoxelId, Voxel>,
+}
+
+impl VoxelGrid {
+    pub fn from_world(world: &crate::parser::World, z_cellsize: f64) -> Self {
+        let grid = &world.grid;
+        let mut voxels: BTreeMap<VoxelId, Voxel> = BTreeMap::new();
+        for (cellid, cell) in grid {
+            for fid in &cell.feature_ids {
+                let b = world.features[*fid]
+                    .bbox_qc
+                    .to_bbox(&world.transform, None, None);
+                let z_mid = (b[2] + b[5]) / 2.0;
+                let layer = ((z_mid - grid.bbox[2]) / z_cellsize).floor().max(0.0) as usize;
+                let voxel = voxels
+                    .entry(VoxelId {
+                        row: cellid.row,
+                        column: cellid.column,
+                        layer,
+                    })
+                    .or_insert_with(|| Voxel {
+                        feature_ids: Vec::new(),
+                        z_range: [f64::MAX, f64::MIN],
+                    });
+                voxel.feature_ids.push(*fid);
+                voxel.z_range = [voxel.z_range[0].min(b[2]), voxel.z_range[1].max(b[5])];
+            }
+        }
+        Self { z_cellsize, voxels }
+    }
+
+    /// The bounding box of the voxel, with the cell in the xy-plane and the z-range of the
+    /// features of the voxel.
+    pub fn voxel_bbox(&self, grid: &SquareGrid, voxelid: &VoxelId) -> Bbox {
+        let b = grid.cell_bbox(&CellId {
+            row: voxelid.row,
+            column: voxelid.column,
+        });
+        let z_range = self.voxels[voxelid].z_range;
+        [b[0], b[1], z_range[0], b[3], b[4], z_range[1]]
+    }
+}
+
+/// 3D bounding box.
+///
+/// [min x, min y, min z, max x, max y, max z]
+///
+/// The untyped array is used for the computations within a single CRS. Where a box
+/// crosses a CRS boundary, use the typed [`InputBbox`] and [`GeographicBbox`] instead.
+pub type Bbox = [f64; 6];
+
+/// The CRS of the input data, as an EPSG code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InputCrs(pub u16);
+
+impl Display for InputCrs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EPSG:{}", self.0)
+    }
+}
+
+/// The geographic 3D CRS EPSG:4979 (WGS84 longitude, latitude, ellipsoidal height).
+/// It is serialized as the EPSG code and deserializing any other code is an error.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub struct Epsg4979;
+
+impl TryFrom<u16> for Epsg4979 {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        if code == 4979 {
+            Ok(Epsg4979)
+        } else {
+            Err(format!("expected EPSG:4979, got EPSG:{code}"))
+        }
+    }
+}
+
+impl From<Epsg4979> for u16 {
+    fn from(_: Epsg4979) -> Self {
+        4979
+    }
+}
+
+impl Display for Epsg4979 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EPSG:4979")
+    }
+}
+
+/// 3D bounding box that is tagged with its CRS, so that boxes in different CRS cannot be
+/// mixed up.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TypedBbox<C> {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+    pub crs: C,
+}
+
+/// Bounding box in the CRS of the input data.
+pub type InputBbox = TypedBbox<InputCrs>;
+
+/// Bounding box in EPSG:4979, `min` and `max` are `[lon, lat, h]`.
+pub type GeographicBbox = TypedBbox<Epsg4979>;
+
+impl<C> TypedBbox<C> {
+    /// The box as `[min x, min y, min z, max x, max y, max z]`.
+    pub fn to_array(&self) -> Bbox {
+        [
+            self.min[0],
+            self.min[1],
+            self.min[2],
+            self.max[0],
+            self.max[1],
+            self.max[2],
+        ]
+    }
+}
+
+impl InputBbox {
+    pub fn new(bbox: &Bbox, epsg: u16) -> Self {
+        Self {
+            min: [bbox[0], bbox[1], bbox[2]],
+            max: [bbox[3], bbox[4], bbox[5]],
+            crs: InputCrs(epsg),
+        }
+    }
+
+    /// Convert the min and max corners of the box to EPSG:4979.
+    pub fn to_epsg4979(self) -> Result<GeographicBbox, Box<dyn std::error::Error>> {
+        let transformer =
+            crate::proj::Proj::new_known_crs(&self.crs.to_string(), "EPSG:4979", None)?;
+        let (west, south, minh) = transformer.convert((self.min[0], self.min[1], self.min[2]))?;
+        let (east, north, maxh) = transformer.convert((self.max[0], self.max[1], self.max[2]))?;
+        Ok(GeographicBbox {
+            min: [west, south, minh],
+            max: [east, north, maxh],
+            crs: Epsg4979,
+        })
+    }
+}
+
+impl GeographicBbox {
+    /// The 2D box as `[west, south, east, north]`.
+    pub fn to_lonlat(self) -> [f64; 4] {
+        [self.min[0], self.min[1], self.max[0], self.max[1]]
+    }
+}
+
+/// Mix the `key` with the `seed` into a well distributed hash (SplitMix64), for making the
+/// arbitrary choices reproducible, eg. breaking ties.
+pub fn mix_seed(seed: u64, key: u64) -> u64 {
+    let mut z = seed.wrapping_add(key).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Serialize a 3D bounding box as 2D WKT Polygon.
+pub fn bbox_to_wkt(bbox: &Bbox) -> String {
+    format!(
+        "POLYGON(({minx} {miny}, {maxx} {miny}, {maxx} {maxy}, {minx} {maxy}, {minx} {miny}))",
+        minx = bbox[0],
+        miny = bbox[1],
+        maxx = bbox[3],
+        maxy = bbox[4]
+    )
+}
+
+/// The 2D boundary of a 3D bounding box as a GeoJSON Polygon.
+pub fn bbox_to_geojson(bbox: &Bbox) -> serde_json::Value {
+    serde_json::json!({
+        "type": "Polygon",
+        "coordinates": [[
+            [bbox[0], bbox[1]],
+            [bbox[3], bbox[1]],
+            [bbox[3], bbox[4]],
+            [bbox[0], bbox[4]],
+            [bbox[0], bbox[1]],
+        ]],
+    })
+}
+
+/// Write the `features` into a GeoJSON FeatureCollection at `path`. If `epsg` is set, the
+/// coordinates are in the input CRS of `epsg`, which is declared with the `crs` member of the
+/// 2008 GeoJSON specification, because RFC 7946 only allows WGS84. QGIS and GDAL read the `crs`
+/// member. Without `epsg`, the coordinates are WGS84 longitude, latitude.
+pub fn write_geojson(
+    path: &Path,
+    epsg: Option<u16>,
+    features: Vec<serde_json::Value>,
+) -> std::io::Result<()> {
+    let mut collection = serde_json::json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    if let Some(epsg) = epsg {
+        collection["crs"] = serde_json::json!({
+            "type": "name",
+            "properties": {"name": format!("urn:ogc:def:crs:EPSG::{epsg}")},
+        });
+    }
+    let file = std::io::BufWriter::new(File::create(path)?);
+    serde_json::to_writer(file, &collection)?;
+    Ok(())
+}
+
+/// Multiply the z coordinates of the 3D bounding box with `z_scale`, for vertical exaggeration.
+pub fn bbox_scale_z(bbox: &Bbox, z_scale: f64) -> Bbox {
+    [
+        bbox[0],
+        bbox[1],
+        bbox[2] * z_scale,
+        bbox[3],
+        bbox[4],
+        bbox[5] * z_scale,
+    ]
+}
+
+/// The offset that is subtracted from the input coordinates of the tile content before they
+/// are stored in single precision, which is the center of the tile `bbox`, rounded to whole
+/// input units, so that the offset itself is exact. With the `origin` of `--origin`, the
+/// center is rounded to whole units from the origin, so that the tilesets that are generated
+/// with the same origin store their content relative to the same points.
+///
+/// The coordinates flow from the quantized integers of the CityJSONFeatures to double
+/// precision input coordinates, which are reprojected to ECEF and stored relative to the tile
+/// center as 32-bit floats in the glTF. The conversion subprocess also keeps the input
+/// coordinates in single precision, relative to an offset. With a single offset for the whole
+/// dataset, the tiles that are far from the offset lose precision before the reprojection,
+/// which shows as vertex jitter, thus each tile uses its own offset.
+pub fn content_offset(bbox: &Bbox, origin: Option<&[f64; 3]>) -> [f64; 3] {
+    let origin = origin.copied().unwrap_or_default();
+    [
+        origin[0] + ((bbox[0] + bbox[3]) / 2.0 - origin[0]).round(),
+        origin[1] + ((bbox[1] + bbox[4]) / 2.0 - origin[1]).round(),
+        origin[2] + ((bbox[2] + bbox[5]) / 2.0 - origin[2]).round(),
+    ]
+}
+
+/// The coordinate after storing it relative to the `offset` in single precision, see
+/// [content_offset].
+pub fn to_single_precision(coordinate: &[f64; 3], offset: &[f64; 3]) -> [f64; 3] {
+    [
+        offset[0] + (coordinate[0] - offset[0]) as f32 as f64,
+        offset[1] + (coordinate[1] - offset[1]) as f32 as f64,
+        offset[2] + (coordinate[2] - offset[2]) as f32 as f64,
+    ]
+}
 
-/// Serialize a 3D bounding box as 2D WKT Polygon.
-pub fn bbox_to_wkt(bbox: &Bbox) -> String {
-    format!(
-        "POLYGON(({minx} {miny}, {maxx} {miny}, {maxx} {maxy}, {minx} {maxy}, {minx} {miny}))",
-        minx = bbox[0],
-        miny = bbox[1],
-        maxx = bbox[3],
-        maxy = bbox[4]
-    )
+/// Returns true if the `inner` 3D bounding box is completely within the `outer` bounding box.
+/// The boundary is included.
+pub fn bbox_contains(outer: &Bbox, inner: &Bbox) -> bool {
+    (0..3).all(|i| inner[i] >= outer[i] && inner[i + 3] <= outer[i + 3])
 }
 
 /// 3D bounding box with quantized coordinates.
@@ -903,6 +2829,16 @@ impl BboxQc {
         extent_rw
     }
 
+    /// Compute the quantized coordinates from the real-world coordinates and the
+    /// [Transform](https://www.cityjson.org/specs/1.1.3/#transform-object).
+    pub fn from_bbox(bbox: &Bbox, transform: &crate::parser::Transform) -> Self {
+        let mut bbox_qc = [0_i64; 6];
+        for (i, c) in bbox.iter().enumerate() {
+            bbox_qc[i] = ((c - transform.translate[i % 3]) / transform.scale[i % 3]).round() as i64;
+        }
+        Self(bbox_qc)
+    }
+
     // Update with another bounding box, if the other is larger.
     pub fn update_with(&mut self, bbox_qc: &Self) {
         if bbox_qc.0[0] < self.0[0] {
@@ -934,7 +2870,7 @@ mod tests {
     #[test]
     fn test_intersect_bbox() {
         let extent = [195548.0, 538909.0, 0.0, 264268.0, 590410.0, 0.0];
-        let grid = SquareGrid::new(&extent, 400, 7415);
+        let grid = SquareGrid::new(&extent, 400.0, 7415);
         grid.export(None, None, None).unwrap();
 
         // Polygon ((248923.44474360189633444 601084.25658657902386039, 249381.04931766359368339 601093.95845033996738493, 249369.73047660905285738 601954.19037048425525427, 248923.44474360189633444 601084.25658657902386039))
@@ -976,13 +2912,13 @@ mod tests {
         let extent = [84372.91, 446316.814, -10.66, 171800.0, 472700.0, 52.882];
         let extent = [13603.33, 314127.708, -15.0, 268943.608, 612658.036, 400.0];
         println!("extent: {}", bbox_to_wkt(&extent));
-        let grid = SquareGrid::new(&extent, 500, 7415);
+        let grid = SquareGrid::new(&extent, 500.0, 7415);
         println!("grid: {}", bbox_to_wkt(&grid.bbox));
     }
 
     #[test]
     fn test_locate_point() {
-        let grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 4.0], 1, 0);
+        let grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 4.0], 1.0, 0);
         let cellid = grid.locate_point(&[2.5, 1.5]);
         println!("{}", cellid);
         assert_eq!(
@@ -994,6 +2930,135 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_cellid_morton_code() {
+        let mut cells: Vec<CellId> = (0..4)
+            .flat_map(|row| (0..4).map(move |column| CellId { row, column }))
+            .collect();
+        for cellid in &cells {
+            assert_eq!(CellId::from_morton_code(cellid.morton_code()), *cellid);
+        }
+        cells.sort_by_key(|cellid| cellid.morton_code());
+        let first_quadrant: Vec<String> = cells[..4].iter().map(|c| c.to_string()).collect();
+        assert_eq!(first_quadrant, vec!["0-0", "1-0", "0-1", "1-1"]);
+        assert_eq!(cells[4].to_string(), "2-0");
+        assert_eq!(CellId { row: 3, column: 3 }.morton_code(), 15);
+    }
+
+    #[test]
+    fn test_octree_build() {
+        let item = |fid: usize, z: f64| (fid, [10.0 + fid as f64, 10.0, z], 10);
+        // A tower, taller than wide, is split in z
+        let octree = Octree::build(
+            OctreeNodeId::default(),
+            [0.0, 0.0, 0.0, 100.0, 100.0, 200.0],
+            vec![item(0, 10.0), item(1, 150.0), item(2, 180.0)],
+            20,
+            10.0,
+            OctreeSplitZ::default(),
+        );
+        let ids: Vec<String> = (&octree as &dyn PartitionTree)
+            .collect_leaves()
+            .iter()
+            .map(|leaf| leaf.file_stem())
+            .collect();
+        assert_eq!(ids, vec!["1-0-0-0", "1-0-0-1"]);
+        assert_eq!(octree.children[1].bbox[2], 100.0);
+        assert_eq!(octree.children[1].feature_ids, vec![1, 2]);
+        // A flat area is split like a quadtree, and the empty quadrants are not stored
+        let octree = Octree::build(
+            OctreeNodeId::default(),
+            [0.0, 0.0, 0.0, 100.0, 100.0, 20.0],
+            vec![item(0, 10.0), item(60, 10.0), item(70, 10.0)],
+            20,
+            10.0,
+            OctreeSplitZ::default(),
+        );
+        assert_eq!(octree.children.len(), 2);
+        assert_eq!(octree.children[1].id.to_string(), "1/1/0/0");
+        assert_eq!(octree.children[1].bbox, [50.0, 0.0, 0.0, 100.0, 50.0, 20.0]);
+        // The leaves are not split below the minimum side length
+        let octree = Octree::build(
+            OctreeNodeId::default(),
+            [0.0, 0.0, 0.0, 10.0, 10.0, 20.0],
+            vec![item(0, 10.0), item(1, 10.0), item(2, 10.0)],
+            20,
+            10.0,
+            OctreeSplitZ::default(),
+        );
+        assert!(octree.children.is_empty());
+        assert_eq!(octree.nr_items, 30);
+    }
+
+    #[test]
+    fn test_octree_split_z() {
+        let bbox = [0.0, 0.0, 0.0, 100.0, 100.0, 60.0];
+        // By default, only the nodes that are at least as tall as wide
+        assert!(!OctreeSplitZ::default().is_split(0, &bbox));
+        assert!(OctreeSplitZ::default().is_split(0, &[0.0, 0.0, 0.0, 50.0, 50.0, 60.0]));
+        // Like a quadtree above the level
+        let below_level = OctreeSplitZ {
+            min_level: Some(2),
+            min_height: None,
+        };
+        assert!(!below_level.is_split(1, &[0.0, 0.0, 0.0, 50.0, 50.0, 60.0]));
+        assert!(below_level.is_split(2, &bbox));
+        let taller = OctreeSplitZ {
+            min_level: None,
+            min_height: Some(50.0),
+        };
+        assert!(taller.is_split(0, &bbox));
+        assert!(!taller.is_split(5, &[0.0, 0.0, 0.0, 10.0, 10.0, 40.0]));
+
+        // The tower is split in z, although the root is wider than tall
+        let item = |fid: usize, z: f64| (fid, [10.0 + fid as f64, 10.0, z], 10);
+        let octree = Octree::build(
+            OctreeNodeId::default(),
+            bbox,
+            vec![item(0, 10.0), item(1, 40.0), item(2, 50.0)],
+            20,
+            10.0,
+            taller,
+        );
+        assert_eq!(octree.children.len(), 2);
+        assert_eq!(octree.children[1].id.to_string(), "1/0/0/1");
+    }
+
+    #[test]
+    fn test_kdtree_build() {
+        // A dense cluster on the left and a few sparse features on the right
+        let mut items: Vec<PartitionItem> = (0..6)
+            .map(|i| (i, [1.0 + i as f64, 50.0, 0.0], 10))
+            .collect();
+        items.push((6, [80.0, 20.0, 0.0], 10));
+        items.push((7, [90.0, 80.0, 0.0], 10));
+        let kdtree = KdTree::build(
+            KdTreeNodeId::default(),
+            [0.0, 0.0, 0.0, 100.0, 100.0, 10.0],
+            items,
+            40,
+        );
+        // The median splits the cluster, instead of the middle of the node
+        assert_eq!(kdtree.children[0].bbox[3], 5.0);
+        assert_eq!(kdtree.children[0].feature_ids, vec![0, 1, 2, 3]);
+        assert_eq!(kdtree.children[1].nr_items, 40);
+        assert_eq!(kdtree.children[1].id.to_string(), "1/1");
+        // The centroids that cannot be separated stay in one leaf
+        let items: Vec<PartitionItem> = (0..3).map(|i| (i, [5.0, 5.0, 0.0], 10)).collect();
+        let kdtree = KdTree::build(
+            KdTreeNodeId::default(),
+            [0.0, 0.0, 0.0, 10.0, 10.0, 10.0],
+            items,
+            10,
+        );
+        assert!(kdtree.children.is_empty());
+        assert_eq!(kdtree.feature_ids.len(), 3);
+        // The median is the minimum coordinate
+        let mut items: Vec<PartitionItem> = (0..3).map(|i| (i, [1.0, 0.0, 0.0], 1)).collect();
+        items.push((3, [2.0, 0.0, 0.0], 1));
+        assert_eq!(KdTree::median_split(&mut items, 0), Some(3));
+    }
+
     #[test]
     fn test_morton_encode_rd() {
         let coords = vec![
@@ -1150,43 +3215,63 @@ mod tests {
     #[test]
     fn test_quadtree_construction() {
         let mut feature_set: FeatureSet = Vec::new();
-        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1, 0);
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1.0, 0);
         for x in 0..4_u64 {
             for y in 0..4u64 {
                 for f in 0..5 {
                     feature_set.push(crate::parser::Feature {
                         centroid_qc: [0, 0],
                         nr_vertices: 0,
+                        nr_triangles: 0,
+                        cityobject_types: Vec::new(),
                         path_jsonl: Default::default(),
                         bbox_qc: BboxQc([0, 0, 0, 0, 0, 0]),
+                        external_geometry: Vec::new(),
+                        weight: 0,
+                        priority: false,
+                        group: None,
                     });
                     let xc: f64 = format!("{}.{}", &x, &f).parse().unwrap();
                     grid.insert(&[xc, y as f64], f as usize);
                 }
             }
         }
-        let _ = QuadTree::from_grid(&grid, QuadTreeCapacity::Objects(20));
+        let _ = QuadTree::from_grid(
+            &grid,
+            QuadTreeCapacity::Objects(20),
+            QuadTreeDepth::default(),
+        );
     }
 
     #[test]
     fn test_quadtree_leaves() {
         let mut feature_set: FeatureSet = Vec::new();
-        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1, 0);
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1.0, 0);
         for x in 0..4_u64 {
             for y in 0..4u64 {
                 for f in 0..5 {
                     feature_set.push(crate::parser::Feature {
                         centroid_qc: [0, 0],
                         nr_vertices: 0,
+                        nr_triangles: 0,
+                        cityobject_types: Vec::new(),
                         path_jsonl: Default::default(),
                         bbox_qc: BboxQc([0, 0, 0, 0, 0, 0]),
+                        external_geometry: Vec::new(),
+                        weight: 0,
+                        priority: false,
+                        group: None,
                     });
                     let xc: f64 = format!("{}.{}", &x, &f).parse().unwrap();
                     grid.insert(&[xc, y as f64], f as usize);
                 }
             }
         }
-        let qtree = QuadTree::from_grid(&grid, QuadTreeCapacity::Objects(20));
+        let qtree = QuadTree::from_grid(
+            &grid,
+            QuadTreeCapacity::Objects(20),
+            QuadTreeDepth::default(),
+        );
         let leaves: Vec<&QuadTree> = QuadTree::collect_leaves(&qtree);
         for tile in leaves {
             println!("{}", tile.id);
@@ -1196,22 +3281,32 @@ mod tests {
     #[test]
     fn test_quadtree_node() {
         let mut feature_set: FeatureSet = Vec::new();
-        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 16.0, 16.0, 1.0], 1, 0);
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 16.0, 16.0, 1.0], 1.0, 0);
         for x in 0..16_u64 {
             for y in 0..16u64 {
                 for f in 0..5 {
                     feature_set.push(crate::parser::Feature {
                         centroid_qc: [0, 0],
                         nr_vertices: 0,
+                        nr_triangles: 0,
+                        cityobject_types: Vec::new(),
                         path_jsonl: Default::default(),
                         bbox_qc: BboxQc([0, 0, 0, 0, 0, 0]),
+                        external_geometry: Vec::new(),
+                        weight: 0,
+                        priority: false,
+                        group: None,
                     });
                     let xc: f64 = format!("{}.{}", &x, &f).parse().unwrap();
                     grid.insert(&[xc, y as f64], f as usize);
                 }
             }
         }
-        let qtree = QuadTree::from_grid(&grid, QuadTreeCapacity::Objects(20));
+        let qtree = QuadTree::from_grid(
+            &grid,
+            QuadTreeCapacity::Objects(20),
+            QuadTreeDepth::default(),
+        );
         let _leaves: Vec<&QuadTree> = QuadTree::collect_leaves(&qtree);
         let n = qtree.node(&QuadTreeNodeId::new(0, 0, 2));
         if n.is_some() {
@@ -1220,4 +3315,876 @@ mod tests {
             println!("did not find node");
         }
     }
+
+    #[test]
+    fn test_quadtree_split() {
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1.0, 0);
+        for x in 0..4_u64 {
+            for y in 0..4u64 {
+                grid.insert(&[x as f64 + 0.5, y as f64 + 0.5], (x * 4 + y) as usize);
+            }
+        }
+        let mut qtree = QuadTree::from_grid(
+            &grid,
+            QuadTreeCapacity::Objects(20),
+            QuadTreeDepth::default(),
+        );
+        assert!(qtree.children.is_empty());
+        assert!(qtree.split(&grid, QuadTreeCapacity::Objects(20)));
+        assert_eq!(qtree.children.len(), 4);
+        assert_eq!(qtree.children[1].id, QuadTreeNodeId::new(2, 0, 1));
+        assert_eq!(qtree.children[2].id, QuadTreeNodeId::new(0, 2, 1));
+        assert!(qtree.children.iter().all(|c| c.nr_items == 4));
+        assert_eq!(qtree.cells().len(), 16);
+        // Not a leaf anymore
+        assert!(!qtree.split(&grid, QuadTreeCapacity::Objects(20)));
+        let child = qtree.node_mut(&QuadTreeNodeId::new(2, 2, 1)).unwrap();
+        assert!(child.split(&grid, QuadTreeCapacity::Objects(20)));
+        // A single cell cannot be split
+        let grandchild = child.children.first_mut().unwrap();
+        assert!(!grandchild.split(&grid, QuadTreeCapacity::Objects(20)));
+    }
+
+    #[test]
+    fn test_quadtree_split_over_budget() {
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1.0, 0);
+        for x in 0..4_u64 {
+            for y in 0..4u64 {
+                let cellid = grid.insert(&[x as f64 + 0.5, y as f64 + 0.5], (x * 4 + y) as usize);
+                // About 1000 bytes in each cell, and 10000 bytes in the lower-left cell
+                let cell = grid.cell_mut(&cellid);
+                cell.nr_vertices = if x == 0 && y == 0 { 400 } else { 40 };
+                cell.nr_triangles = if x == 0 && y == 0 { 32 } else { 3 };
+            }
+        }
+        let capacity = QuadTreeCapacity::Objects(20);
+        let mut qtree = QuadTree::from_grid(&grid, capacity, QuadTreeDepth::default());
+        assert!(qtree.children.is_empty());
+        assert_eq!(qtree.split_over_budget(&grid, 30000, capacity), 0);
+        // The root and the lower-left child are split, the lower-left cell is over the budget,
+        // but it cannot be split
+        assert_eq!(qtree.split_over_budget(&grid, 4000, capacity), 2);
+        let leaves = qtree.collect_leaves();
+        assert_eq!(leaves.len(), 7);
+        assert!(leaves.iter().all(|leaf| leaf.nr_items == leaf.cells.len()));
+        assert_eq!(qtree.split_over_budget(&grid, 4000, capacity), 0);
+    }
+
+    #[test]
+    fn test_quadtree_leaves_are_cells() {
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1.0, 0);
+        for x in 0..4_u64 {
+            for y in 0..4u64 {
+                grid.insert(&[x as f64 + 0.5, y as f64 + 0.5], (x * 4 + y) as usize);
+            }
+        }
+        // All the cells fit into the root
+        assert!(!QuadTree::from_grid(
+            &grid,
+            QuadTreeCapacity::Objects(20),
+            QuadTreeDepth::default()
+        )
+        .leaves_are_cells());
+        // Each cell is full
+        assert!(QuadTree::from_grid(
+            &grid,
+            QuadTreeCapacity::Objects(1),
+            QuadTreeDepth::default()
+        )
+        .leaves_are_cells());
+    }
+
+    #[test]
+    fn test_squaregrid_small_cellsize() {
+        // An indoor model of 12 x 7 meters with 5 cm cells
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 12.0, 7.0, 3.0], 0.05, 0);
+        assert_eq!(grid.cellsize(), 0.094);
+        assert_eq!(grid.length, 128);
+        let cellid = grid.insert(&[0.1, 0.1], 0);
+        assert_eq!(cellid, CellId { row: 27, column: 1 });
+        let qtree = QuadTree::from_grid(
+            &grid,
+            QuadTreeCapacity::Objects(0),
+            QuadTreeDepth::default(),
+        );
+        let bbox = qtree.bbox(&grid);
+        assert!((bbox[3] - bbox[0] - 128.0 * 0.094).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_squaregrid_contains() {
+        let grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1.0, 0);
+        assert!(grid.contains_point(&[0.0, 3.9]));
+        assert!(!grid.contains_point(&[4.0, 1.0]));
+        assert!(!grid.contains_point(&[-0.1, 1.0]));
+        assert!(grid.contains_cell(&CellId { row: 3, column: 0 }));
+        assert!(!grid.contains_cell(&CellId { row: 4, column: 0 }));
+    }
+
+    #[test]
+    fn test_squaregrid_insert_bbox() {
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1.0, 0);
+        let cellids = grid.insert_bbox(&[1.5, 0.5, 0.0, 2.5, 1.5, 0.0], 0);
+        assert_eq!(cellids.len(), 4);
+        assert!(cellids.contains(&CellId { row: 1, column: 2 }));
+        // The cells outside of the grid are skipped
+        let cellids = grid.insert_bbox(&[3.5, 3.5, 0.0, 5.5, 5.5, 0.0], 1);
+        assert_eq!(cellids, vec![CellId { row: 3, column: 3 }]);
+        // A feature is only once in a cell
+        grid.insert_bbox(&[1.5, 0.5, 0.0, 1.9, 0.9, 0.0], 0);
+        assert_eq!(
+            grid.cell(&CellId { row: 0, column: 1 }).feature_ids,
+            vec![0]
+        );
+        assert_eq!(grid.cell(&CellId { row: 0, column: 1 }).nr_vertices, 0);
+        // The root aggregates the four cells of feature 0, and returns it once
+        let qtree = QuadTree::from_grid(
+            &grid,
+            QuadTreeCapacity::Objects(20),
+            QuadTreeDepth::default(),
+        );
+        assert_eq!(qtree.feature_ids(&grid), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_quadtree_single_cell() {
+        // The extent of a single feature can be degenerate
+        let mut grid = SquareGrid::new(&[10.0, 10.0, 0.0, 10.0, 10.0, 0.0], 100.0, 0);
+        assert_eq!(grid.length, 1);
+        assert_eq!(grid.cellsize(), 100.0);
+        grid.insert(&[10.0, 10.0], 0);
+        let qtree = QuadTree::from_grid(
+            &grid,
+            QuadTreeCapacity::Objects(0),
+            QuadTreeDepth::default(),
+        );
+        assert!(qtree.children.is_empty());
+        assert_eq!(qtree.id, QuadTreeNodeId::new(0, 0, 0));
+        assert_eq!(qtree.nr_items, 1);
+        assert_eq!(qtree.bbox(&grid)[3] - qtree.bbox(&grid)[0], 100.0);
+    }
+
+    #[test]
+    fn test_tiling_sample_grid() {
+        let fixture = crate::fixtures::TilingFixture::load("tiling_sample", 20.0);
+        fixture.assert_reference("grid", &fixture.grid_reference());
+    }
+
+    #[test]
+    fn test_tiling_sample_quadtree() {
+        let fixture = crate::fixtures::TilingFixture::load("tiling_sample", 20.0);
+        fixture.assert_reference(
+            "quadtree",
+            &fixture.quadtree_reference(QuadTreeCapacity::Objects(4)),
+        );
+    }
+
+    #[test]
+    fn test_capacity_mismatch() {
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 200.0, 200.0, 10.0], 100.0, 7415);
+        for fid in 0..3 {
+            grid.insert(&[10.0, 10.0], fid);
+        }
+        // A single feature cannot be split by a smaller cell
+        let cellid = grid.insert(&[150.0, 150.0], 3);
+        grid.cell_mut(&cellid).nr_vertices = 1000;
+        assert_eq!(
+            grid.capacity_mismatch(QuadTreeCapacity::Objects(2)),
+            Some(CapacityMismatch {
+                nr_cells: 1,
+                max_items: 3,
+                suggested_cellsize: 81.0
+            })
+        );
+        assert_eq!(grid.capacity_mismatch(QuadTreeCapacity::Objects(3)), None);
+        assert_eq!(grid.capacity_mismatch(QuadTreeCapacity::Vertices(10)), None);
+        assert_eq!(grid.capacity_mismatch(QuadTreeCapacity::Objects(0)), None);
+
+        let refined = grid.refined();
+        assert_eq!(refined.length, grid.length * 2);
+        assert_eq!(refined.cellsize, grid.cellsize / 2.0);
+        assert_eq!(refined.bbox, grid.bbox);
+        assert!(refined
+            .into_iter()
+            .all(|(_, cell)| cell.feature_ids.is_empty()));
+    }
+
+    #[test]
+    fn test_merge_sparse_leaves() {
+        let fixture = crate::fixtures::TilingFixture::load("tiling_sample", 20.0);
+        let world = &fixture.world;
+        let limit = QuadTreeCapacity::Objects(4);
+        let quadtree = QuadTree::from_world(world, limit, QuadTreeDepth::default());
+        let nr_leaves = quadtree.collect_leaves().len();
+
+        // Below the capacity, there is nothing to merge
+        let mut merged = quadtree.clone();
+        assert_eq!(merged.merge_sparse_leaves(&world.grid, 4, 0), 0);
+        assert_eq!(merged.collect_leaves().len(), nr_leaves);
+
+        let mut merged = quadtree.clone();
+        assert!(merged.merge_sparse_leaves(&world.grid, 8, 0) > 0);
+        let leaves = merged.collect_leaves();
+        assert!(leaves.len() < nr_leaves);
+        assert_eq!(merged.nr_items, quadtree.nr_items);
+        let nr_cells: usize = leaves.iter().map(|leaf| leaf.cells.len()).sum();
+        assert_eq!(nr_cells, world.grid.length.pow(2));
+
+        // Everything fits in the root, unless the root level is kept split
+        let mut merged = quadtree.clone();
+        merged.merge_sparse_leaves(&world.grid, usize::MAX, 0);
+        assert!(merged.children.is_empty());
+        let mut merged = quadtree.clone();
+        merged.merge_sparse_leaves(&world.grid, usize::MAX, 1);
+        assert!(merged
+            .collect_leaves()
+            .iter()
+            .all(|leaf| leaf.id.level == 1));
+    }
+
+    #[test]
+    fn test_density_image() {
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 200.0, 200.0, 10.0], 100.0, 7415);
+        grid.insert(&[10.0, 10.0], 0);
+        for fid in 1..4 {
+            grid.insert(&[150.0, 150.0], fid);
+        }
+        grid.insert(&[150.0, 10.0], 4);
+        grid.insert(&[160.0, 10.0], 5);
+        let image = grid.density_image(QuadTreeCapacity::Objects(2));
+        assert_eq!([image.width, image.height], [2, 2]);
+        // The bottom row of the grid is the last row of the image
+        assert_eq!(image.pixels[0], [0, 0, 0, 0]);
+        assert_eq!(image.pixels[2], [33, 145, 140, 255]);
+        assert_eq!(image.pixels[3], [253, 231, 37, 255]);
+        assert_eq!(image.pixels[1], [215, 25, 28, 255]);
+        // Relative to the fullest cell
+        let image = grid.density_image(QuadTreeCapacity::Objects(0));
+        assert_eq!(image.pixels[1], [253, 231, 37, 255]);
+    }
+
+    #[test]
+    fn test_quadtree_parallel() {
+        // Large enough to be merged in parallel
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 1280.0, 1280.0, 10.0], 10.0, 7415);
+        assert!(grid.length.pow(2) >= MERGE_PARALLEL_MIN_TILES);
+        for fid in 0..2000 {
+            // Denser towards the origin
+            let t = fid as f64 / 2000.0;
+            let x = (fid * 7919 % 1280) as f64 * t;
+            let y = (fid * 104729 % 1280) as f64 * t;
+            grid.insert(&[x, y], fid);
+        }
+        let build = || {
+            QuadTree::from_grid(
+                &grid,
+                QuadTreeCapacity::Objects(20),
+                QuadTreeDepth::default(),
+            )
+        };
+        let quadtree = build();
+        assert_eq!(quadtree.nr_items, 2000);
+        // The same node IDs in every run
+        for _ in 0..4 {
+            assert_eq!(
+                bincode::serialize(&build()).unwrap(),
+                bincode::serialize(&quadtree).unwrap()
+            );
+        }
+        // The children are in the quadrants of their parent, in Morton-order
+        fn check(node: &QuadTree, grid: &SquareGrid) {
+            let b = node.bbox(grid);
+            let mid = [(b[0] + b[3]) / 2.0, (b[1] + b[4]) / 2.0];
+            for (i, child) in node.children.iter().enumerate() {
+                assert_eq!(child.id.level, node.id.level + 1);
+                let c = child.bbox(grid);
+                assert_eq!(c[1] >= mid[1], i & 1 == 1);
+                assert_eq!(c[0] >= mid[0], i & 2 == 2);
+                check(child, grid);
+            }
+        }
+        check(&quadtree, &grid);
+    }
+
+    #[test]
+    fn test_quadtree_depth() {
+        let fixture = crate::fixtures::TilingFixture::load("tiling_sample", 20.0);
+        let world = &fixture.world;
+        let limit = QuadTreeCapacity::Objects(4);
+        let nr_items = QuadTree::from_world(world, limit, QuadTreeDepth::default()).nr_items;
+        let leaf_levels = |depth: QuadTreeDepth| -> Vec<u16> {
+            let quadtree = QuadTree::from_world(world, limit, depth);
+            assert_eq!(quadtree.nr_items, nr_items);
+            let leaves = quadtree.collect_leaves();
+            // Each cell is in exactly one leaf
+            let nr_cells: usize = leaves.iter().map(|leaf| leaf.cells.len()).sum();
+            assert_eq!(nr_cells, world.grid.length.pow(2));
+            leaves.iter().map(|leaf| leaf.id.level).collect()
+        };
+        let levels = leaf_levels(QuadTreeDepth::default());
+        assert!(levels.iter().any(|l| *l < 3));
+        assert!(levels.iter().any(|l| *l > 1));
+        assert!(leaf_levels(QuadTreeDepth {
+            min: 0,
+            max: Some(1)
+        })
+        .iter()
+        .all(|l| *l <= 1));
+        assert!(leaf_levels(QuadTreeDepth { min: 3, max: None })
+            .iter()
+            .all(|l| *l >= 3));
+        // The leaves are not deeper than the grid cells
+        assert!(leaf_levels(QuadTreeDepth { min: 9, max: None })
+            .iter()
+            .all(|l| *l == 4));
+    }
+
+    #[test]
+    fn test_promote_large_features() {
+        let fixture = crate::fixtures::TilingFixture::load("tiling_sample", 20.0);
+        let world = &fixture.world;
+        let quadtree = QuadTree::from_world(
+            world,
+            QuadTreeCapacity::Objects(4),
+            QuadTreeDepth::default(),
+        );
+        let promoted = quadtree.promote_large_features(world);
+        // Only the 300m long feature is larger than its leaf, and only the root is larger
+        // than the feature
+        let fid_long = world
+            .features
+            .iter()
+            .position(|f| f.path_jsonl.ends_with("b032.city.jsonl"))
+            .unwrap();
+        assert_eq!(promoted.feature_ids, HashSet::from([fid_long]));
+        assert_eq!(
+            promoted.nodes,
+            HashMap::from([(QuadTreeNodeId::new(0, 0, 0), vec![fid_long])])
+        );
+    }
+
+    #[test]
+    fn test_hilbert() {
+        // The first order curve goes up, right and down
+        let first: Vec<[u64; 2]> = (0..4).map(|d| hilbert_decode(1, d)).collect();
+        assert_eq!(first, vec![[0, 0], [0, 1], [1, 1], [1, 0]]);
+        let order = 4;
+        let side = 1_u64 << order;
+        let mut previous = hilbert_decode(order, 0);
+        for d in 0..side * side {
+            let [x, y] = hilbert_decode(order, d);
+            assert_eq!(hilbert_encode(order, x, y), d);
+            // Consecutive positions are neighbouring cells
+            assert_eq!(x.abs_diff(previous[0]) + y.abs_diff(previous[1]), d.min(1));
+            previous = [x, y];
+        }
+        let grid = SquareGrid::new(&[0.0, 0.0, 0.0, 4.0, 4.0, 1.0], 1.0, 0);
+        assert_eq!(grid.hilbert_order(), 2);
+        // The second order curve starts towards the right
+        assert_eq!(grid.hilbert_index(&[1.5, 0.5]), 1);
+        assert_eq!(grid.hilbert_index(&[0.5, 1.5]), 3);
+        assert_eq!(grid.hilbert_index(&[-10.0, -10.0]), 0);
+    }
+
+    #[test]
+    fn test_voxel_grid() {
+        let fixture = crate::fixtures::TilingFixture::load("tiling_sample", 20.0);
+        let world = &fixture.world;
+        let voxel_grid = VoxelGrid::from_world(world, 5.0);
+        let mut nr_assigned = 0;
+        for (cellid, cell) in &world.grid {
+            // The features of a cell are distributed over its layers
+            let mut fids: Vec<usize> = voxel_grid
+                .voxels
+                .iter()
+                .filter(|(id, _)| id.row == cellid.row && id.column == cellid.column)
+                .flat_map(|(_, voxel)| voxel.feature_ids.iter().copied())
+                .collect();
+            fids.sort();
+            let mut expected = cell.feature_ids.clone();
+            expected.sort();
+            assert_eq!(fids, expected);
+            nr_assigned += fids.len();
+        }
+        assert!(nr_assigned > 0);
+        assert!(voxel_grid.voxels.keys().any(|id| id.layer > 0));
+        for (voxelid, voxel) in &voxel_grid.voxels {
+            let bbox = voxel_grid.voxel_bbox(&world.grid, voxelid);
+            for fid in &voxel.feature_ids {
+                let b = world.features[*fid]
+                    .bbox_qc
+                    .to_bbox(&world.transform, None, None);
+                assert!(bbox[2] <= b[2] && b[5] <= bbox[5]);
+                let layer = ((b[2] + b[5]) / 2.0 - world.grid.bbox[2]) / voxel_grid.z_cellsize;
+                assert_eq!(layer.floor() as usize, voxelid.layer);
+            }
+        }
+        assert_eq!(
+            VoxelId {
+                row: 5,
+                column: 3,
+                layer: 1
+            }
+            .to_string(),
+            "3-5-1"
+        );
+    }
+
+    #[test]
+    fn test_content_offset() {
+        // A tile far from the origin, eg. in a UTM zone
+        let bbox = [500_000.0, 5_400_000.0, -10.0, 500_250.0, 5_400_250.0, 90.0];
+        let offset = content_offset(&bbox, None);
+        assert_eq!(offset, [500_125.0, 5_400_125.0, 40.0]);
+        // Snapped to a forced origin
+        let origin = [0.5, 0.25, 0.0];
+        assert_eq!(
+            content_offset(&bbox, Some(&origin)),
+            [500_125.5, 5_400_125.25, 40.0]
+        );
+        let p = [500_201.237, 5_400_013.891, 12.345];
+        let error = |q: [f64; 3]| (0..3).map(|i| (q[i] - p[i]).abs()).fold(0.0, f64::max);
+        // Relative to the tile center, the error is well below a millimeter...
+        assert!(error(to_single_precision(&p, &offset)) < 1e-5);
+        // ...but relative to a distant offset, it is in the order of decimeters
+        assert!(error(to_single_precision(&p, &[0.0, 0.0, 0.0])) > 0.1);
+    }
+
+    #[test]
+    fn test_rtree_search() {
+        // A 30x30 grid of 1x1 boxes, with some long ones across
+        let mut items: Vec<(usize, Bbox2D)> = Vec::new();
+        for x in 0..30 {
+            for y in 0..30 {
+                let (x, y) = (x as f64 * 2.0, y as f64 * 2.0);
+                items.push((items.len(), [x, y, x + 1.0, y + 1.0]));
+            }
+        }
+        items.push((items.len(), [0.5, 10.5, 59.0, 10.6]));
+        items.push((items.len(), [30.5, 0.0, 30.6, 59.0]));
+        let rtree = RTree::new(items.clone());
+        for query in [
+            [0.0, 0.0, 3.0, 3.0],
+            [10.2, 10.2, 14.8, 14.8],
+            [1.2, 1.2, 1.8, 1.8],
+            [30.0, 50.0, 31.0, 51.0],
+            [-5.0, -5.0, -1.0, -1.0],
+            [0.0, 0.0, 100.0, 100.0],
+        ] {
+            let mut found = rtree.search(&query);
+            found.sort();
+            let expected: Vec<usize> = items
+                .iter()
+                .filter(|(_, b)| bbox2d_intersects(b, &query))
+                .map(|(id, _)| *id)
+                .collect();
+            assert_eq!(found, expected, "query {:?}", query);
+        }
+        assert!(RTree::new(Vec::new())
+            .search(&[0.0, 0.0, 1.0, 1.0])
+            .is_empty());
+    }
+
+    #[test]
+    fn test_quadtreenodeid_from_str() {
+        let id: QuadTreeNodeId = "3/10/2".parse().unwrap();
+        assert_eq!(id, QuadTreeNodeId::new(10, 2, 3));
+        assert_eq!(id.to_string(), "3/10/2");
+        assert!("3/10".parse::<QuadTreeNodeId>().is_err());
+    }
+
+    #[test]
+    fn test_tile_naming() {
+        // The node of 2x2 cells at column 4 and row 2 of an 8x8 grid
+        let id = QuadTreeNodeId::new(4, 2, 2);
+        assert_eq!(TileNaming::LevelXY.file_stem(&id, 3), "2/4/2");
+        assert_eq!(TileNaming::Quadkey.file_stem(&id, 3), "12");
+        assert_eq!(TileNaming::Morton.file_stem(&id, 3), "2-6");
+        let root = QuadTreeNodeId::new(0, 0, 0);
+        assert_eq!(TileNaming::Quadkey.file_stem(&root, 3), "root");
+        assert_eq!(TileNaming::Morton.file_stem(&root, 3), "0-0");
+
+        // The names are unique in a quadtree
+        let world = crate::fixtures::TilingFixture::load("tiling_sample", 20.0).world;
+        let quadtree = QuadTree::from_world(
+            &world,
+            QuadTreeCapacity::Objects(2),
+            QuadTreeDepth::default(),
+        );
+        let max_level = world.grid.quadtree_max_level();
+        for naming in [TileNaming::Quadkey, TileNaming::Morton] {
+            let mut stems: Vec<String> = quadtree
+                .collect_leaves()
+                .iter()
+                .map(|leaf| naming.file_stem(&leaf.id, max_level))
+                .collect();
+            let nr_leaves = stems.len();
+            assert!(nr_leaves > 1);
+            stems.sort();
+            stems.dedup();
+            assert_eq!(stems.len(), nr_leaves);
+        }
+    }
+
+    #[test]
+    fn test_squaregrid_iter_morton() {
+        let extent = [0.0, 0.0, 0.0, 400.0, 400.0, 10.0];
+        let mut grid = SquareGrid::new(&extent, 100.0, 7415);
+        assert_eq!(grid.length, 4);
+        let cellid = grid.insert(&[250.0, 50.0], 1);
+        let cells: Vec<(CellId, &Cell)> = grid.iter_morton().collect();
+        assert_eq!(cells.len(), 16);
+        let first_quadrant: Vec<String> = cells[..4].iter().map(|(c, _)| c.to_string()).collect();
+        assert_eq!(first_quadrant, vec!["0-0", "1-0", "0-1", "1-1"]);
+        for (i, (c, cell)) in cells.iter().enumerate() {
+            assert_eq!(c.morton_code(), i as u64);
+            assert_eq!(!cell.feature_ids.is_empty(), *c == cellid);
+        }
+        assert_eq!(cells[4].0, cellid);
+
+        // The cells of the square outside of a rectangular grid are yielded too
+        let rect = grid.cropped(&[0.0, 0.0, 0.0, 400.0, 150.0, 10.0]);
+        assert_eq!(rect.into_iter().count(), 8);
+        assert_eq!(rect.iter_morton().len(), 16);
+    }
+
+    #[test]
+    fn test_export_geojson() {
+        let fixture = crate::fixtures::TilingFixture::load("tiling_sample", 20.0);
+        let world = &fixture.world;
+        let quadtree = QuadTree::from_world(
+            world,
+            QuadTreeCapacity::Vertices(1000),
+            QuadTreeDepth::default(),
+        );
+        let dir = std::env::temp_dir().join(format!("tyler-export-geojson-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        world.grid.export(None, None, Some(&dir)).unwrap();
+        quadtree.export(world, Some(&dir)).unwrap();
+        let read = |name: &str| -> serde_json::Value {
+            serde_json::from_reader(File::open(dir.join(name)).unwrap()).unwrap()
+        };
+
+        let grid = read("grid.geojson");
+        assert_eq!(
+            grid["crs"]["properties"]["name"],
+            format!("urn:ogc:def:crs:EPSG::{}", world.grid.epsg)
+        );
+        let cells = grid["features"].as_array().unwrap();
+        let nr_cells = world
+            .grid
+            .into_iter()
+            .filter(|(_, cell)| !cell.feature_ids.is_empty())
+            .count();
+        assert_eq!(cells.len(), nr_cells);
+        let ring = cells[0]["geometry"]["coordinates"][0].as_array().unwrap();
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring[0], ring[4]);
+
+        let nodes = read("quadtree.geojson");
+        let nodes = nodes["features"].as_array().unwrap();
+        assert_eq!(nodes[0]["properties"]["level"], 0);
+        assert_eq!(nodes[0]["properties"]["nr_items"], quadtree.nr_items);
+        let nr_leaves = nodes
+            .iter()
+            .filter(|node| node["properties"]["leaf"] == true)
+            .count();
+        assert_eq!(nr_leaves, quadtree.collect_leaves().len());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_criterion() {
+        let cell = Cell {
+            feature_ids: vec![1, 2],
+            nr_vertices: 100,
+            weight: 5,
+            nr_triangles: 150,
+        };
+        let items = |capacity: QuadTreeCapacity| capacity.cell_items(&cell);
+        assert_eq!(items(QuadTreeCapacity::Objects(1)), 2);
+        assert_eq!(items(QuadTreeCapacity::Vertices(1)), 100);
+        assert_eq!(items(QuadTreeCapacity::Weight(1)), 5);
+        assert_eq!(items(QuadTreeCapacity::Triangles(1)), 150);
+        assert_eq!(items(QuadTreeCapacity::Bytes(1)), 100 * 24 + 150 * 12);
+        let weighted: WeightedCriteria = "objects=1000, triangles=0.5".parse().unwrap();
+        assert_eq!(
+            weighted,
+            WeightedCriteria {
+                objects: 1000.0,
+                triangles: 0.5,
+                ..Default::default()
+            }
+        );
+        assert_eq!(items(QuadTreeCapacity::Weighted(1, weighted)), 2075);
+        assert!("faces=1".parse::<WeightedCriteria>().is_err());
+        assert!("vertices=-1".parse::<WeightedCriteria>().is_err());
+        assert!("vertices".parse::<WeightedCriteria>().is_err());
+
+        let feature = crate::parser::Feature {
+            nr_vertices: 8,
+            nr_triangles: 12,
+            ..Default::default()
+        };
+        assert_eq!(TriangleCount.feature_items(&feature), 12);
+        assert_eq!(
+            QuadTreeCapacity::Bytes(1).feature_items(&feature),
+            8 * 24 + 12 * 12
+        );
+    }
+
+    #[test]
+    fn test_export_geojson_wgs84() {
+        let fixture = crate::fixtures::TilingFixture::load("tiling_sample", 20.0);
+        let world = &fixture.world;
+        let quadtree = QuadTree::from_world(
+            world,
+            QuadTreeCapacity::Vertices(1000),
+            QuadTreeDepth::default(),
+        );
+        let path = std::env::temp_dir().join(format!(
+            "tyler-quadtree-wgs84-{}.geojson",
+            std::process::id()
+        ));
+        quadtree.export_geojson_wgs84(&world.grid, &path).unwrap();
+        let geojson: serde_json::Value =
+            serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(geojson.get("crs").is_none());
+        let nodes = geojson["features"].as_array().unwrap();
+        assert_eq!(nodes[0]["properties"]["node_id"], quadtree.id.to_string());
+        // The sample is in the Netherlands
+        let [lon, lat] = [
+            nodes[0]["geometry"]["coordinates"][0][0][0]
+                .as_f64()
+                .unwrap(),
+            nodes[0]["geometry"]["coordinates"][0][0][1]
+                .as_f64()
+                .unwrap(),
+        ];
+        assert!((3.0..8.0).contains(&lon) && (50.0..54.0).contains(&lat));
+    }
+
+    #[test]
+    fn test_squaregrid_sparse() {
+        let extent = [0.0, 0.0, 0.0, 640.0, 640.0, 10.0];
+        let points = [
+            [5.0, 5.0],
+            [15.0, 5.0],
+            [15.0, 5.0],
+            [635.0, 320.0],
+            [5.0, 635.0],
+        ];
+        let mut dense = SquareGrid::new(&extent, 10.0, 7415);
+        let mut sparse = SquareGrid::new(&extent, 10.0, 7415);
+        assert_eq!(dense.length, 64);
+        dense.choose_storage(1000);
+        sparse.choose_storage(points.len());
+        assert!(!dense.is_sparse());
+        assert!(sparse.is_sparse());
+        for grid in [&mut dense, &mut sparse] {
+            for (fid, point) in points.iter().enumerate() {
+                let cellid = grid.insert(point, fid);
+                grid.cell_mut(&cellid).nr_vertices += 10;
+            }
+        }
+        // The same API, regardless of the storage
+        let cellid = CellId { row: 0, column: 1 };
+        assert_eq!(sparse.cell(&cellid).feature_ids, vec![1, 2]);
+        assert_eq!(sparse.cell(&cellid), dense.cell(&cellid));
+        assert!(sparse
+            .cell(&CellId { row: 1, column: 1 })
+            .feature_ids
+            .is_empty());
+        assert_eq!(sparse.into_iter().count(), 64 * 64);
+        assert!(sparse.into_iter().eq(dense.into_iter()));
+        assert_eq!(sparse.stats().nr_cells_empty, dense.stats().nr_cells_empty);
+        let build = |grid: &SquareGrid| {
+            let quadtree = QuadTree::from_grid(
+                grid,
+                QuadTreeCapacity::Vertices(15),
+                QuadTreeDepth::default(),
+            );
+            bincode::serialize(&quadtree).unwrap()
+        };
+        assert_eq!(build(&sparse), build(&dense));
+        let sparse_bincode: SquareGrid =
+            bincode::deserialize(&bincode::serialize(&sparse).unwrap()).unwrap();
+        assert!(sparse_bincode.is_sparse());
+        assert_eq!(sparse_bincode.cell(&cellid), sparse.cell(&cellid));
+
+        // The storage does not change once the cells are stored
+        dense.choose_storage(0);
+        assert!(!dense.is_sparse());
+    }
+
+    #[test]
+    fn test_squaregrid_stats() {
+        let extent = [0.0, 0.0, 0.0, 400.0, 400.0, 10.0];
+        let mut grid = SquareGrid::new(&extent, 100.0, 7415);
+        for (fid, nr_vertices) in [(0, 10), (1, 20), (2, 30), (3, 40)] {
+            let cellid = grid.insert(&[50.0 + fid as f64 * 100.0, 50.0], fid);
+            grid.cell_mut(&cellid).nr_vertices = nr_vertices;
+        }
+        let cellid = grid.insert(&[350.0, 50.0], 4);
+        grid.cell_mut(&cellid).nr_vertices += 5;
+        let stats = grid.stats();
+        assert_eq!(stats.nr_cells, 16);
+        assert_eq!(stats.nr_cells_empty, 12);
+        assert_eq!(stats.empty_ratio, 0.75);
+        assert_eq!(stats.nr_vertices, 105);
+        assert_eq!(
+            stats.features,
+            CellDistribution {
+                min: 1,
+                max: 2,
+                mean: 1.25,
+                median: 1.0,
+                p90: 2,
+                p99: 2,
+            }
+        );
+        assert_eq!(stats.vertices.min, 10);
+        assert_eq!(stats.vertices.max, 45);
+        assert_eq!(stats.vertices.median, 25.0);
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["vertices"]["p90"], 45);
+
+        let empty = SquareGrid::new(&extent, 100.0, 7415).stats();
+        assert_eq!(empty.empty_ratio, 1.0);
+        assert_eq!(empty.vertices, CellDistribution::default());
+    }
+
+    #[test]
+    fn test_squaregrid_new_pinned() {
+        // Aligned to a kilometre grid, eg. of the Dutch RD
+        let grid_extent = [80000.0, 440000.0, 90000.0, 445000.0];
+        let extent = [84310.5, 443012.7, -5.0, 84920.1, 443890.3, 50.0];
+        let grid = SquareGrid::new_pinned(&extent, &grid_extent, 500.0, 7415);
+        assert_eq!(grid.origin(), [80000.0, 440000.0, -5.0]);
+        assert_eq!(grid.cellsize(), 500.0);
+        // 20 x 10 cells, rounded up to 32 x 32
+        assert_eq!(grid.length, 32);
+        assert_eq!(grid.bbox[5], 50.0);
+        // The grid does not depend on the extent of the data
+        let extent_grown = [80120.0, 440550.0, -5.0, 89400.0, 444980.0, 50.0];
+        let grid_grown = SquareGrid::new_pinned(&extent_grown, &grid_extent, 500.0, 7415);
+        assert_eq!(grid_grown.origin()[..2], grid.origin()[..2]);
+        assert_eq!(grid_grown.length, grid.length);
+        let point = [84500.0, 443500.0];
+        assert_eq!(grid.locate_point(&point), CellId { row: 7, column: 9 });
+        assert_eq!(grid_grown.locate_point(&point), grid.locate_point(&point));
+    }
+
+    #[test]
+    fn test_squaregrid_cropped() {
+        let extent = [0.0, 0.0, 0.0, 1000.0, 150.0, 10.0];
+        let grid = SquareGrid::new(&extent, 100.0, 7415);
+        let mut rect = grid.cropped(&extent);
+        assert_eq!(rect.length, grid.length);
+        assert_eq!((rect.nr_columns, rect.nr_rows), (grid.length, 2));
+        assert_eq!(rect.into_iter().count(), grid.length * 2);
+        assert_eq!(rect.origin()[0], grid.origin()[0]);
+        assert!(rect.bbox[1] <= extent[1] && rect.bbox[4] >= extent[4]);
+        assert!(rect.bbox[4] < grid.bbox[4]);
+        // The same cell corners as the square grid
+        let point = [510.0, 120.0];
+        assert_eq!(
+            rect.cell_bbox(&rect.locate_point(&point))[..2],
+            grid.cell_bbox(&grid.locate_point(&point))[..2]
+        );
+        // The cells of the square outside of the rectangle are empty
+        let outside = CellId {
+            row: grid.length - 1,
+            column: 0,
+        };
+        assert!(!rect.contains_cell(&outside));
+        assert!(rect.cell(&outside).feature_ids.is_empty());
+        let cellid = rect.insert(&point, 1);
+        rect.cell_mut(&cellid).nr_vertices = 10;
+        let quadtree = QuadTree::from_grid(
+            &rect,
+            QuadTreeCapacity::Vertices(5),
+            QuadTreeDepth::default(),
+        );
+        let nr_cells: usize = quadtree
+            .collect_leaves()
+            .iter()
+            .map(|l| l.cells.len())
+            .sum();
+        assert_eq!(nr_cells, rect.length.pow(2));
+        let refined = rect.refined();
+        assert_eq!((refined.nr_columns, refined.nr_rows), (grid.length * 2, 4));
+        assert_eq!(refined.length, grid.length * 2);
+        assert_eq!(rect.density_image(QuadTreeCapacity::Vertices(5)).height, 2);
+    }
+
+    #[test]
+    fn test_squaregrid_new_snapped() {
+        let extent: Bbox = [1050.0, 2020.0, 0.0, 1480.0, 2150.0, 10.0];
+        let grid = SquareGrid::new_snapped(&extent, 100.0, 7415, &[0.0, 0.0, 0.0]);
+        assert_eq!(grid.origin(), [1000.0, 2000.0, 0.0]);
+        assert_eq!(grid.cellsize(), 100.0);
+        // 5 cells are needed in x, rounded up to 8
+        assert_eq!(grid.length, 8);
+        assert_eq!(grid.bbox, [1000.0, 2000.0, 0.0, 1800.0, 2800.0, 10.0]);
+        let other = SquareGrid::new_snapped(&extent, 100.0, 7415, &[30.0, 20.0, 0.0]);
+        assert_eq!(other.origin(), [1030.0, 2020.0, 0.0]);
+    }
+
+    #[test]
+    fn test_mix_seed() {
+        assert_eq!(mix_seed(42, 7), mix_seed(42, 7));
+        assert_ne!(mix_seed(42, 7), mix_seed(43, 7));
+        assert_ne!(mix_seed(42, 7), mix_seed(42, 8));
+    }
+
+    #[test]
+    fn test_typed_bbox_serde() {
+        let bbox = InputBbox::new(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 7415);
+        let value = serde_json::to_value(bbox).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"min": [1.0, 2.0, 3.0], "max": [4.0, 5.0, 6.0], "crs": 7415})
+        );
+        let back: InputBbox = serde_json::from_value(value).unwrap();
+        assert_eq!(back, bbox);
+        assert_eq!(back.to_array(), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(back.crs.to_string(), "EPSG:7415");
+
+        let geographic =
+            serde_json::json!({"min": [4.0, 52.0, 0.0], "max": [5.0, 53.0, 10.0], "crs": 4979});
+        let geographic: GeographicBbox = serde_json::from_value(geographic).unwrap();
+        assert_eq!(geographic.to_lonlat(), [4.0, 52.0, 5.0, 53.0]);
+        let wrong_crs =
+            serde_json::json!({"min": [4.0, 52.0, 0.0], "max": [5.0, 53.0, 10.0], "crs": 7415});
+        assert!(serde_json::from_value::<GeographicBbox>(wrong_crs).is_err());
+    }
+
+    #[test]
+    fn test_bbox_scale_z() {
+        let bbox: Bbox = [1.0, 2.0, -3.0, 4.0, 5.0, 6.0];
+        assert_eq!(bbox_scale_z(&bbox, 2.0), [1.0, 2.0, -6.0, 4.0, 5.0, 12.0]);
+    }
+
+    #[test]
+    fn test_bbox_contains() {
+        let outer: Bbox = [0.0, 0.0, 0.0, 10.0, 10.0, 10.0];
+        assert!(bbox_contains(&outer, &[1.0, 1.0, 1.0, 2.0, 2.0, 2.0]));
+        assert!(bbox_contains(&outer, &outer));
+        assert!(!bbox_contains(&outer, &[9.0, 9.0, 0.0, 11.0, 10.0, 10.0]));
+        assert!(!bbox_contains(&outer, &[1.0, 1.0, -1.0, 2.0, 2.0, 2.0]));
+    }
+
+    #[test]
+    fn test_bboxqc_from_bbox() {
+        let transform = crate::parser::Transform {
+            scale: [0.001, 0.001, 0.001],
+            translate: [100.0, 200.0, 0.0],
+        };
+        let bbox: Bbox = [101.5, 202.25, -1.0, 103.0, 204.0, 10.0];
+        let bbox_qc = BboxQc::from_bbox(&bbox, &transform);
+        assert_eq!(bbox_qc, BboxQc([1500, 2250, -1000, 3000, 4000, 10000]));
+        assert_eq!(bbox_qc.to_bbox(&transform, None, None), bbox);
+    }
 }
@@ -0,0 +1,495 @@
+//! External tile index.
+//! A tile index is a user-supplied tile layout, a GeoJSON FeatureCollection of polygons with
+//! an ID, which replaces the quadtree. Each feature is assigned to the polygon that contains
+//! its centroid, and each polygon becomes one tile.
+//! The flat tileset of the grid cells is a tile index too, see [TileIndex::from_grid], and so
+//! are the leaves of the octree and the kd-tree, see [TileIndex::from_partition_tree].
+// Copyright 2023 Balázs Dukai, Ravi Peters
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::spatial_structs::{Bbox, PartitionTree, RTree, SquareGrid, VoxelGrid};
+
+/// A ring of a polygon, as a list of 2D coordinates.
+type Ring = Vec<[f64; 2]>;
+
+/// The tiles of a tile index.
+#[derive(Debug, Default)]
+pub struct TileIndex {
+    pub tiles: Vec<IndexTile>,
+}
+
+/// A tile of the tile index, with the polygons that make up its area.
+#[derive(Debug, Default)]
+pub struct IndexTile {
+    pub id: String,
+    /// The rings of each polygon, the first ring is the exterior.
+    polygons: Vec<Vec<Ring>>,
+    /// The 2D bounding box of the polygons, in the format of `[minx, miny, maxx, maxy]`.
+    pub bbox: [f64; 4],
+    /// The features that are assigned to the tile.
+    pub feature_ids: Vec<usize>,
+    /// The z-range of the tile, `[min z, max z]`, if it is tighter than the z-range of the grid.
+    pub z_range: Option<[f64; 2]>,
+}
+
+impl TileIndex {
+    /// Read the tile index from a GeoJSON file.
+    /// The ID of the tiles is read from the `id_property` of the GeoJSON Feature properties,
+    /// or from the Feature `id` member.
+    pub fn from_geojson<P: AsRef<Path>>(
+        path: P,
+        id_property: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(path.as_ref())?;
+        let geojson: Value = serde_json::from_reader(BufReader::new(file))?;
+        Self::from_value(&geojson, id_property)
+    }
+
+    fn from_value(geojson: &Value, id_property: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let features = geojson["features"]
+            .as_array()
+            .ok_or("the tile index must be a GeoJSON FeatureCollection")?;
+        let mut tiles: Vec<IndexTile> = Vec::with_capacity(features.len());
+        for (i, feature) in features.iter().enumerate() {
+            let id = match feature["properties"]
+                .get(id_property)
+                .or_else(|| feature.get("id"))
+            {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Number(n)) => n.to_string(),
+                _ => {
+                    return Err(format!("feature {i} of the tile index does not have an ID").into())
+                }
+            };
+            if tiles.iter().any(|t| t.id == id) {
+                return Err(format!("the tile index contains the ID {id} more than once").into());
+            }
+            let geometry = &feature["geometry"];
+            let polygons: Vec<Vec<Ring>> = match geometry["type"].as_str() {
+                Some("Polygon") => vec![serde_json::from_value(geometry["coordinates"].clone())?],
+                Some("MultiPolygon") => serde_json::from_value(geometry["coordinates"].clone())?,
+                _ => {
+                    return Err(format!(
+                    "the geometry of tile {id} in the tile index must be a Polygon or MultiPolygon"
+                )
+                    .into())
+                }
+            };
+            let mut bbox = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+            for [x, y] in polygons.iter().filter_map(|p| p.first()).flatten() {
+                bbox = [
+                    bbox[0].min(*x),
+                    bbox[1].min(*y),
+                    bbox[2].max(*x),
+                    bbox[3].max(*y),
+                ];
+            }
+            tiles.push(IndexTile {
+                id,
+                polygons,
+                bbox,
+                feature_ids: Vec::new(),
+                z_range: None,
+            });
+        }
+        Ok(Self { tiles })
+    }
+
+    /// Create a tile index with a tile for each non-empty cell of the grid, for a flat tileset
+    /// without the quadtree. The features are already assigned to the cells, and the tiles are
+    /// named by the cell ID, `<column>-<row>`.
+    pub fn from_grid(grid: &SquareGrid) -> Self {
+        let tiles = grid
+            .into_iter()
+            .filter(|(_, cell)| !cell.feature_ids.is_empty())
+            .map(|(cellid, cell)| {
+                let b = grid.cell_bbox(&cellid);
+                IndexTile {
+                    id: cellid.to_string(),
+                    polygons: vec![vec![vec![
+                        [b[0], b[1]],
+                        [b[3], b[1]],
+                        [b[3], b[4]],
+                        [b[0], b[4]],
+                        [b[0], b[1]],
+                    ]]],
+                    bbox: [b[0], b[1], b[3], b[4]],
+                    feature_ids: cell.feature_ids.clone(),
+                    z_range: None,
+                }
+            })
+            .collect();
+        Self { tiles }
+    }
+
+    /// Create a tile index with a tile for each leaf of the partition tree, in depth-first
+    /// order. The features are already assigned to the leaves, and the tiles are named by the
+    /// node ID, see [PartitionTree::file_stem].
+    pub fn from_partition_tree(tree: &dyn PartitionTree) -> Self {
+        let tiles = tree
+            .collect_leaves()
+            .into_iter()
+            .filter(|leaf| !leaf.feature_ids().is_empty())
+            .map(|leaf| {
+                let b = leaf.bbox();
+                IndexTile {
+                    id: leaf.file_stem(),
+                    polygons: vec![vec![vec![
+                        [b[0], b[1]],
+                        [b[3], b[1]],
+                        [b[3], b[4]],
+                        [b[0], b[4]],
+                        [b[0], b[1]],
+                    ]]],
+                    bbox: [b[0], b[1], b[3], b[4]],
+                    feature_ids: leaf.feature_ids().to_vec(),
+                    z_range: None,
+                }
+            })
+            .collect();
+        Self { tiles }
+    }
+
+    /// Create a tile index with a tile for each voxel of the voxel grid, for a flat tileset
+    /// that is sliced in z too, named by the voxel ID '<column>-<row>-<layer>'. The z-range of
+    /// the tiles is the z-range of the features of the voxel.
+    pub fn from_voxel_grid(grid: &SquareGrid, voxel_grid: &VoxelGrid) -> Self {
+        let tiles = voxel_grid
+            .voxels
+            .iter()
+            .map(|(voxelid, voxel)| {
+                let b = voxel_grid.voxel_bbox(grid, voxelid);
+                IndexTile {
+                    id: voxelid.to_string(),
+                    polygons: vec![vec![vec![
+                        [b[0], b[1]],
+                        [b[3], b[1]],
+                        [b[3], b[4]],
+                        [b[0], b[4]],
+                        [b[0], b[1]],
+                    ]]],
+                    bbox: [b[0], b[1], b[3], b[4]],
+                    feature_ids: voxel.feature_ids.clone(),
+                    z_range: Some(voxel.z_range),
+                }
+            })
+            .collect();
+        Self { tiles }
+    }
+
+    /// Assign the features of the world to the tiles, by the centroid of the features.
+    /// A feature is assigned to the first tile that contains its centroid.
+    /// Returns the IDs of the features that are not in any of the tiles.
+    pub fn assign(&mut self, world: &crate::parser::World) -> Vec<usize> {
+        let mut unassigned: Vec<usize> = Vec::new();
+        for (fid, feature) in world.features.iter().enumerate() {
+            let centroid = feature.centroid(&world.transform);
+            match self.tiles.iter_mut().find(|t| t.contains(&centroid)) {
+                Some(tile) => tile.feature_ids.push(fid),
+                None => unassigned.push(fid),
+            }
+        }
+        unassigned
+    }
+
+    /// Order the tiles along the Hilbert curve of the grid, by the center of their bounding box,
+    /// so that the neighbouring tiles are also close in the order. The ties are kept in their
+    /// original order.
+    pub fn sort_hilbert(&mut self, grid: &SquareGrid) {
+        self.tiles.sort_by_cached_key(|tile| {
+            let center = [
+                (tile.bbox[0] + tile.bbox[2]) / 2.0,
+                (tile.bbox[1] + tile.bbox[3]) / 2.0,
+            ];
+            grid.hilbert_index(&center)
+        });
+    }
+
+    /// Add the features that are at least `min_size` long to every tile that their bounding
+    /// box intersects, in addition to the tile of their centroid. The features are looked up
+    /// in an [RTree] by the bounding box of the tiles, thus the polygons of the tiles are
+    /// approximated by their bounding box.
+    /// Returns the number of large features.
+    pub fn assign_large_features(&mut self, world: &crate::parser::World, min_size: f64) -> usize {
+        let large_features = world.large_features(min_size);
+        let nr_large_features = large_features.len();
+        let rtree = RTree::new(large_features);
+        for tile in self.tiles.iter_mut() {
+            for fid in rtree.search(&tile.bbox) {
+                if !tile.feature_ids.contains(&fid) {
+                    tile.feature_ids.push(fid);
+                }
+            }
+        }
+        nr_large_features
+    }
+}
+
+impl IndexTile {
+    /// The file stem of the tile content, which is the ID with the characters that are not
+    /// safe in file names replaced by `_`.
+    pub fn file_stem(&self) -> String {
+        self.id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    /// The 3D bounding box of the tile, with the z-range of the tile, or else of the grid.
+    pub fn bbox_3d(&self, grid: &SquareGrid) -> Bbox {
+        let [min_z, max_z] = self.z_range.unwrap_or([grid.bbox[2], grid.bbox[5]]);
+        [
+            self.bbox[0],
+            self.bbox[1],
+            min_z,
+            self.bbox[2],
+            self.bbox[3],
+            max_z,
+        ]
+    }
+
+    /// Is the point inside one of the polygons of the tile.
+    /// Uses the even-odd rule over all the rings of a polygon, thus the holes are excluded.
+    fn contains(&self, point: &[f64; 2]) -> bool {
+        if point[0] < self.bbox[0]
+            || point[0] > self.bbox[2]
+            || point[1] < self.bbox[1]
+            || point[1] > self.bbox[3]
+        {
+            return false;
+        }
+        self.polygons.iter().any(|rings| {
+            rings
+                .iter()
+                .filter(|ring| ring_crossings(ring, point))
+                .count()
+                % 2
+                == 1
+        })
+    }
+}
+
+/// Does a ray from the point towards +x cross the ring an odd number of times.
+fn ring_crossings(ring: &Ring, point: &[f64; 2]) -> bool {
+    let [px, py] = *point;
+    let mut inside = false;
+    for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+        if (a[1] > py) != (b[1] > py) && px < (b[0] - a[0]) * (py - a[1]) / (b[1] - a[1]) + a[0] {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::TilingFixture;
+    use serde_json::json;
+
+    #[test]
+    fn test_assign_large_features() {
+        let mut fixture = TilingFixture::load("tiling_sample", 20.0);
+        let fid_long = fixture
+            .world
+            .features
+            .iter()
+            .position(|f| f.path_jsonl.ends_with("b032.city.jsonl"))
+            .unwrap();
+        let cells_with = |world: &crate::parser::World| {
+            world
+                .grid
+                .into_iter()
+                .filter(|(_, cell)| cell.feature_ids.contains(&fid_long))
+                .count()
+        };
+        assert_eq!(cells_with(&fixture.world), 1);
+
+        // The long feature crosses the border of the two tiles
+        let half = |x0: f64, x1: f64| {
+            json!({
+                "type": "Feature",
+                "properties": {"id": format!("{x0}")},
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[[x0, 446305.0], [x1, 446305.0], [x1, 446700.0], [x0, 446700.0], [x0, 446305.0]]]
+                }
+            })
+        };
+        let geojson = json!({
+            "type": "FeatureCollection",
+            "features": [half(85005.0, 85200.0), half(85200.0, 85400.0)]
+        });
+        let mut tile_index = TileIndex::from_value(&geojson, "id").unwrap();
+        let unassigned = tile_index.assign(&fixture.world);
+        // The extra, default feature of the world
+        assert_eq!(unassigned.len(), 1);
+        let nr_features = fixture.world.features.len() - 1;
+        let nr_assignments = |tile_index: &TileIndex| -> usize {
+            tile_index.tiles.iter().map(|t| t.feature_ids.len()).sum()
+        };
+        assert_eq!(nr_assignments(&tile_index), nr_features);
+        assert_eq!(tile_index.assign_large_features(&fixture.world, 100.0), 1);
+        // Only the long feature is in both tiles
+        assert_eq!(nr_assignments(&tile_index), nr_features + 1);
+        assert!(tile_index
+            .tiles
+            .iter()
+            .all(|t| t.feature_ids.contains(&fid_long)));
+
+        assert_eq!(fixture.world.assign_large_features(100.0), 1);
+        // The 300m long feature spans 15 columns of the 22m cells, because the grid is
+        // stretched to the extent
+        assert_eq!(cells_with(&fixture.world), 15);
+    }
+
+    #[test]
+    fn test_tile_index_from_value() {
+        let geojson = json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "properties": {"sheet": "25EZ1"},
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [
+                            [[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0], [0.0, 0.0]],
+                            [[4.0, 4.0], [6.0, 4.0], [6.0, 6.0], [4.0, 6.0], [4.0, 4.0]]
+                        ]
+                    }
+                },
+                {
+                    "type": "Feature",
+                    "id": 2,
+                    "properties": {},
+                    "geometry": {
+                        "type": "MultiPolygon",
+                        "coordinates": [
+                            [[[10.0, 0.0], [20.0, 0.0], [20.0, 10.0], [10.0, 0.0]]]
+                        ]
+                    }
+                }
+            ]
+        });
+        let index = TileIndex::from_value(&geojson, "sheet").unwrap();
+        assert_eq!(index.tiles[0].id, "25EZ1");
+        assert_eq!(index.tiles[1].id, "2");
+        assert_eq!(index.tiles[1].bbox, [10.0, 0.0, 20.0, 10.0]);
+        assert_eq!(
+            IndexTile {
+                id: "kaart 1/2".to_string(),
+                ..Default::default()
+            }
+            .file_stem(),
+            "kaart_1_2"
+        );
+        assert!(index.tiles[0].contains(&[1.0, 1.0]));
+        // In the hole
+        assert!(!index.tiles[0].contains(&[5.0, 5.0]));
+        assert!(index.tiles[1].contains(&[19.0, 5.0]));
+        assert!(!index.tiles[1].contains(&[11.0, 5.0]));
+    }
+
+    #[test]
+    fn test_tile_index_sort_hilbert() {
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 200.0, 200.0, 10.0], 100.0, 7415);
+        for (fid, point) in [[150.0, 50.0], [50.0, 50.0], [150.0, 150.0], [50.0, 150.0]]
+            .iter()
+            .enumerate()
+        {
+            grid.insert(point, fid);
+        }
+        let mut index = TileIndex::from_grid(&grid);
+        index.sort_hilbert(&grid);
+        // Up, right and down
+        let fids: Vec<usize> = index.tiles.iter().map(|t| t.feature_ids[0]).collect();
+        assert_eq!(fids, vec![1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn test_tile_index_from_grid() {
+        let mut grid = SquareGrid::new(&[0.0, 0.0, 0.0, 200.0, 200.0, 10.0], 100.0, 7415);
+        let cellid = grid.insert(&[50.0, 50.0], 0);
+        grid.insert(&[60.0, 40.0], 1);
+        let index = TileIndex::from_grid(&grid);
+        assert_eq!(index.tiles.len(), 1);
+        let tile = &index.tiles[0];
+        assert_eq!(tile.id, cellid.to_string());
+        assert_eq!(tile.feature_ids, vec![0, 1]);
+        assert!(tile.contains(&[55.0, 45.0]));
+        let cell_bbox = grid.cell_bbox(&cellid);
+        assert_eq!(
+            tile.bbox,
+            [cell_bbox[0], cell_bbox[1], cell_bbox[3], cell_bbox[4]]
+        );
+    }
+
+    #[test]
+    fn test_tile_index_from_voxel_grid() {
+        use crate::spatial_structs::{Voxel, VoxelId};
+        let mut grid = SquareGrid::new(&[0.0, 0.0, -20.0, 200.0, 200.0, 100.0], 100.0, 7415);
+        let cellid = grid.insert(&[50.0, 50.0], 0);
+        grid.insert(&[60.0, 40.0], 1);
+        // A tunnel below a building in the same cell
+        let voxel_grid = VoxelGrid {
+            z_cellsize: 10.0,
+            voxels: [(0, [-15.0, -5.0], 0), (2, [0.0, 12.0], 1)]
+                .into_iter()
+                .map(|(layer, z_range, fid)| {
+                    (
+                        VoxelId {
+                            row: cellid.row,
+                            column: cellid.column,
+                            layer,
+                        },
+                        Voxel {
+                            feature_ids: vec![fid],
+                            z_range,
+                        },
+                    )
+                })
+                .collect(),
+        };
+        let index = TileIndex::from_voxel_grid(&grid, &voxel_grid);
+        assert_eq!(index.tiles.len(), 2);
+        assert_eq!(index.tiles[0].id, format!("{}-0", cellid));
+        assert_eq!(index.tiles[1].feature_ids, vec![1]);
+        let b = index.tiles[1].bbox_3d(&grid);
+        assert_eq!([b[2], b[5]], [0.0, 12.0]);
+        // Without a z-range, the tile spans the height of the grid
+        let b = TileIndex::from_grid(&grid).tiles[0].bbox_3d(&grid);
+        assert_eq!([b[2], b[5]], [-20.0, 100.0]);
+    }
+
+    #[test]
+    fn test_tile_index_invalid() {
+        let point = json!({"features": [{"id": "a", "geometry": {"type": "Point", "coordinates": [0.0, 0.0]}}]});
+        assert!(TileIndex::from_value(&point, "id").is_err());
+        let no_id = json!({"features": [{"properties": {}, "geometry": {"type": "Polygon", "coordinates": []}}]});
+        assert!(TileIndex::from_value(&no_id, "id").is_err());
+    }
+}
@@ -0,0 +1,677 @@
+//! Commands that work on the output of a previous run.
+//! The tileset is handled as plain JSON, so that the members that *tyler* does not model
+//! (eg. extensions) are retained.
+// Copyright 2023 Balázs Dukai, Ravi Peters
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde_json::Value;
+
+use crate::cli::Command;
+use crate::proj::Proj;
+
+/// A 2D bounding box in WGS84 longitude, latitude degrees: [min_lon, min_lat, max_lon, max_lat].
+pub type BboxLonLat = [f64; 4];
+
+/// Run a command on the output of a previous run.
+pub fn run(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Extract {
+            bbox,
+            input,
+            output,
+        } => extract(&input, &output, &bbox),
+        Command::Thin {
+            max_level,
+            input,
+            output,
+        } => thin(&input, &output, max_level),
+        Command::Inspect { tile, tileset } => inspect(&tile, tileset.as_deref()),
+        Command::Daemon { config } => crate::daemon::run(&config),
+    }
+}
+
+fn read_tileset(dir: &Path) -> Result<Value, Box<dyn std::error::Error>> {
+    let tileset_path = dir.join("tileset.json");
+    let tileset: Value = serde_json::from_reader(BufReader::new(File::open(&tileset_path)?))?;
+    if tileset["root"].get("implicitTiling").is_some() {
+        return Err(format!("implicit tilesets are not supported: {:?}", tileset_path).into());
+    }
+    Ok(tileset)
+}
+
+fn write_tileset(dir: &Path, tileset: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+    let file_out = BufWriter::new(File::create(dir.join("tileset.json"))?);
+    serde_json::to_writer(file_out, tileset)?;
+    Ok(())
+}
+
+/// Copy the tiles that intersect `bbox` from the tileset in `input` into a new tileset in
+/// `output`. The content files are copied with their relative path, so that their URIs
+/// remain valid.
+pub fn extract(
+    input: &Path,
+    output: &Path,
+    bbox: &BboxLonLat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tileset = read_tileset(input)?;
+    let to_lonlat = Proj::new_known_crs("EPSG:4978", "EPSG:4979", None)?;
+    let intersects = |bounding_volume: &Value| match lonlat_bbox(bounding_volume, &to_lonlat) {
+        Some(bv) => bbox_intersects(&bv, bbox),
+        // Keep the tile if we cannot tell
+        None => true,
+    };
+    let root = filter_tile(&tileset["root"], &intersects)
+        .ok_or_else(|| format!("there are no tiles that intersect {:?}", bbox))?;
+    tileset["root"] = collapse_root(root);
+    let nr_contents = copy_contents(input, output, &tileset)?;
+    write_tileset(output, &tileset)?;
+    info!("Extracted {} contents into {:?}", nr_contents, output);
+    Ok(())
+}
+
+/// Write a tileset with only the tiles up to and including `max_level` from the tileset in
+/// `input` into `output`. The root is at level 0.
+/// The tiles at `max_level` become leaves. If such a tile does not have content, it receives
+/// the content of its descendants.
+pub fn thin(input: &Path, output: &Path, max_level: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tileset = read_tileset(input)?;
+    let multiple_contents = thin_tile(&mut tileset["root"], 0, max_level, "REPLACE");
+    if multiple_contents {
+        // Multiple contents per tile is a 3D Tiles 1.1 feature
+        tileset["asset"]["version"] = Value::from("1.1");
+    }
+    let nr_contents = copy_contents(input, output, &tileset)?;
+    write_tileset(output, &tileset)?;
+    info!(
+        "Wrote the tileset up to level {} with {} contents into {:?}",
+        max_level, nr_contents, output
+    );
+    Ok(())
+}
+
+/// Remove the descendants of the `tile` below `max_level`. `refine` is the refinement of the
+/// parent, which is inherited if the tile does not declare it.
+/// Returns `true` if any of the tiles received multiple contents.
+fn thin_tile(tile: &mut Value, level: u16, max_level: u16, refine: &str) -> bool {
+    let refine = tile["refine"].as_str().unwrap_or(refine).to_string();
+    let Some(object) = tile.as_object_mut() else {
+        return false;
+    };
+    let Some(Value::Array(mut children)) = object.remove("children") else {
+        return false;
+    };
+    if level < max_level {
+        let mut multiple_contents = false;
+        for child in children.iter_mut() {
+            multiple_contents |= thin_tile(child, level + 1, max_level, &refine);
+        }
+        object.insert("children".to_string(), Value::Array(children));
+        return multiple_contents;
+    }
+    object.insert("geometricError".to_string(), Value::from(0.0));
+    if has_content(tile) {
+        return false;
+    }
+    let mut contents = descendant_contents(&children, &refine);
+    let Some(object) = tile.as_object_mut() else {
+        return false;
+    };
+    match contents.len() {
+        0 => false,
+        1 => {
+            object.insert("content".to_string(), contents.remove(0));
+            false
+        }
+        _ => {
+            object.insert("contents".to_string(), Value::Array(contents));
+            true
+        }
+    }
+}
+
+fn has_content(tile: &Value) -> bool {
+    tile.get("content").is_some() || tile.get("contents").is_some()
+}
+
+/// Collect the contents that replace the descendants of a tile. With REPLACE refinement, the
+/// first content on each branch is enough, with ADD refinement all contents are needed.
+fn descendant_contents(tiles: &[Value], refine: &str) -> Vec<Value> {
+    let mut contents: Vec<Value> = Vec::new();
+    for tile in tiles {
+        let refine = tile["refine"].as_str().unwrap_or(refine);
+        if let Some(content) = tile.get("content") {
+            contents.push(content.clone());
+        }
+        if let Some(tile_contents) = tile["contents"].as_array() {
+            contents.extend(tile_contents.iter().cloned());
+        }
+        if has_content(tile) && refine == "REPLACE" {
+            continue;
+        }
+        if let Some(children) = tile["children"].as_array() {
+            contents.extend(descendant_contents(children, refine));
+        }
+    }
+    contents
+}
+
+/// Copy the content files of the tileset from `input` to `output`, with their relative path,
+/// so that their URIs remain valid. Returns the number of contents.
+fn copy_contents(
+    input: &Path,
+    output: &Path,
+    tileset: &Value,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let uris = content_uris(&tileset["root"]);
+    for uri in &uris {
+        let from = input.join(uri);
+        let to = output.join(uri);
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Err(e) = fs::copy(&from, &to) {
+            warn!("Failed to copy the content {:?}: {}", &from, e);
+        }
+    }
+    Ok(uris.len())
+}
+
+/// Print the content of a GLB tile, and the tile from the tileset that references the content.
+/// If `tileset` is not provided, the tileset.json is searched in the parent directories of the
+/// `tile`.
+pub fn inspect(tile: &Path, tileset: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let (gltf, bin) = read_glb(tile)?;
+    println!("{}", tile.display());
+    for key in ["extensionsUsed", "extensionsRequired"] {
+        let extensions: Vec<&str> = gltf[key]
+            .as_array()
+            .map(|e| e.iter().filter_map(|v| v.as_str()).collect())
+            .unwrap_or_default();
+        println!("{key}: {}", extensions.join(", "));
+    }
+
+    let mut nr_vertices_total: u64 = 0;
+    let mut nr_triangles_total: u64 = 0;
+    for (mi, mesh) in gltf["meshes"].as_array().into_iter().flatten().enumerate() {
+        for (pi, primitive) in mesh["primitives"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let (nr_vertices, nr_triangles) = primitive_counts(&gltf, primitive);
+            nr_vertices_total += nr_vertices;
+            nr_triangles_total += nr_triangles;
+            print!("mesh {mi} primitive {pi}: {nr_vertices} vertices, {nr_triangles} triangles");
+            for feature_ids in primitive["extensions"]["EXT_mesh_features"]["featureIds"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                print!(", {} features", feature_ids["featureCount"]);
+            }
+            println!();
+        }
+    }
+    println!("total: {nr_vertices_total} vertices, {nr_triangles_total} triangles");
+
+    let metadata = &gltf["extensions"]["EXT_structural_metadata"];
+    for (ti, table) in metadata["propertyTables"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        println!(
+            "property table {ti} (class {}, {} features):",
+            table["class"], table["count"]
+        );
+        let (header, rows) = property_table_rows(&gltf, bin.as_deref(), table);
+        println!("{}", header.join("\t"));
+        for row in rows {
+            println!("{}", row.join("\t"));
+        }
+    }
+
+    let tileset_path = match tileset {
+        Some(path) => Some(path.to_path_buf()),
+        None => find_tileset(tile),
+    };
+    if let Some(tileset_path) = tileset_path {
+        let tileset: Value = serde_json::from_reader(BufReader::new(File::open(&tileset_path)?))?;
+        let tileset_dir = tileset_path.parent().unwrap_or(Path::new("."));
+        let tile_canonical = tile.canonicalize()?;
+        match find_tile(&tileset["root"], &|uri| {
+            tileset_dir
+                .join(uri)
+                .canonicalize()
+                .is_ok_and(|p| p == tile_canonical)
+        }) {
+            Some(tile_json) => {
+                println!("tile in {}:", tileset_path.display());
+                println!("geometricError: {}", tile_json["geometricError"]);
+                println!("boundingVolume: {}", tile_json["boundingVolume"]);
+                if let Some(bv) = tile_json["content"].get("boundingVolume") {
+                    println!("content boundingVolume: {bv}");
+                }
+            }
+            None => warn!("The tile is not referenced in {:?}", tileset_path),
+        }
+    }
+    Ok(())
+}
+
+/// The JSON and the binary chunk of a GLB file.
+type Glb = (Value, Option<Vec<u8>>);
+
+/// Read the JSON and the binary chunk of a GLB file.
+fn read_glb(path: &Path) -> Result<Glb, Box<dyn std::error::Error>> {
+    let glb = fs::read(path)?;
+    let u32_at = |offset: usize| -> Option<usize> {
+        glb.get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+    if glb.get(0..4) != Some(b"glTF".as_slice()) {
+        return Err(format!("not a GLB file: {:?}", path).into());
+    }
+    let json_length = u32_at(12).ok_or("the GLB file is truncated")?;
+    let json_chunk = glb
+        .get(20..20 + json_length)
+        .ok_or("the GLB file is truncated")?;
+    let gltf: Value = serde_json::from_slice(json_chunk)?;
+    let bin_start = 20 + json_length;
+    let bin = u32_at(bin_start).and_then(|bin_length| {
+        glb.get(bin_start + 8..bin_start + 8 + bin_length)
+            .map(|b| b.to_vec())
+    });
+    Ok((gltf, bin))
+}
+
+/// The number of vertices and triangles of a glTF mesh primitive.
+fn primitive_counts(gltf: &Value, primitive: &Value) -> (u64, u64) {
+    let accessor_count = |index: &Value| -> Option<u64> {
+        gltf["accessors"][index.as_u64()? as usize]["count"].as_u64()
+    };
+    let nr_vertices = accessor_count(&primitive["attributes"]["POSITION"]).unwrap_or(0);
+    // The default mode is TRIANGLES
+    let nr_triangles = match primitive["mode"].as_u64().unwrap_or(4) {
+        4 => accessor_count(&primitive["indices"]).unwrap_or(nr_vertices) / 3,
+        _ => 0,
+    };
+    (nr_vertices, nr_triangles)
+}
+
+/// The bytes of a buffer view in the GLB binary chunk. Returns `None` if the buffer view is not
+/// in the binary chunk or it is compressed.
+fn buffer_view_bytes<'a>(gltf: &Value, bin: Option<&'a [u8]>, index: &Value) -> Option<&'a [u8]> {
+    let buffer_view = &gltf["bufferViews"][index.as_u64()? as usize];
+    if buffer_view["buffer"].as_u64()? != 0
+        || buffer_view["extensions"]
+            .get("EXT_meshopt_compression")
+            .is_some()
+    {
+        return None;
+    }
+    let offset = buffer_view["byteOffset"].as_u64().unwrap_or(0) as usize;
+    let length = buffer_view["byteLength"].as_u64()? as usize;
+    bin?.get(offset..offset + length)
+}
+
+/// Read the `i`-th little-endian value of `component_type` from `bytes`.
+fn read_component(bytes: &[u8], component_type: &str, i: usize) -> Option<String> {
+    let size = match component_type {
+        "INT8" | "UINT8" => 1,
+        "INT16" | "UINT16" => 2,
+        "INT32" | "UINT32" | "FLOAT32" => 4,
+        "INT64" | "UINT64" | "FLOAT64" => 8,
+        _ => return None,
+    };
+    let b = bytes.get(i * size..(i + 1) * size)?;
+    let value = match component_type {
+        "INT8" => (b[0] as i8).to_string(),
+        "UINT8" => b[0].to_string(),
+        "INT16" => i16::from_le_bytes(b.try_into().ok()?).to_string(),
+        "UINT16" => u16::from_le_bytes(b.try_into().ok()?).to_string(),
+        "INT32" => i32::from_le_bytes(b.try_into().ok()?).to_string(),
+        "UINT32" => u32::from_le_bytes(b.try_into().ok()?).to_string(),
+        "FLOAT32" => f32::from_le_bytes(b.try_into().ok()?).to_string(),
+        "INT64" => i64::from_le_bytes(b.try_into().ok()?).to_string(),
+        "UINT64" => u64::from_le_bytes(b.try_into().ok()?).to_string(),
+        _ => f64::from_le_bytes(b.try_into().ok()?).to_string(),
+    };
+    Some(value)
+}
+
+/// Decode an `EXT_structural_metadata` property table into its header and rows. The values
+/// that cannot be decoded (eg. arrays or compressed buffers) are printed as `?`.
+fn property_table_rows(
+    gltf: &Value,
+    bin: Option<&[u8]>,
+    table: &Value,
+) -> (Vec<String>, Vec<Vec<String>>) {
+    let count = table["count"].as_u64().unwrap_or(0) as usize;
+    let class = table["class"].as_str().unwrap_or_default();
+    let class_properties =
+        &gltf["extensions"]["EXT_structural_metadata"]["schema"]["classes"][class]["properties"];
+    let mut header: Vec<String> = Vec::new();
+    let mut columns: Vec<Vec<String>> = Vec::new();
+    for (name, property) in table["properties"].as_object().into_iter().flatten() {
+        let definition = &class_properties[name];
+        let values = buffer_view_bytes(gltf, bin, &property["values"]);
+        let column: Vec<String> = (0..count)
+            .map(|i| {
+                if definition["array"].as_bool().unwrap_or(false) {
+                    return None;
+                }
+                let values = values?;
+                match definition["type"].as_str()? {
+                    "STRING" => {
+                        let offsets = buffer_view_bytes(gltf, bin, &property["stringOffsets"])?;
+                        let offset_type = property["stringOffsetType"].as_str().unwrap_or("UINT32");
+                        let start: usize = read_component(offsets, offset_type, i)?.parse().ok()?;
+                        let end: usize =
+                            read_component(offsets, offset_type, i + 1)?.parse().ok()?;
+                        String::from_utf8(values.get(start..end)?.to_vec()).ok()
+                    }
+                    "BOOLEAN" => Some((values.get(i / 8)? >> (i % 8) & 1 == 1).to_string()),
+                    "SCALAR" => read_component(values, definition["componentType"].as_str()?, i),
+                    _ => None,
+                }
+            })
+            .map(|value| value.unwrap_or_else(|| "?".to_string()))
+            .collect();
+        header.push(name.clone());
+        columns.push(column);
+    }
+    let rows = (0..count)
+        .map(|i| columns.iter().map(|column| column[i].clone()).collect())
+        .collect();
+    (header, rows)
+}
+
+/// Find the tileset.json in the parent directories of `path`.
+fn find_tileset(path: &Path) -> Option<PathBuf> {
+    path.canonicalize()
+        .ok()?
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join("tileset.json"))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Find the tile of which a content URI satisfies `is_match`.
+fn find_tile<'a, F: Fn(&str) -> bool>(tile: &'a Value, is_match: &F) -> Option<&'a Value> {
+    let contents = tile["contents"].as_array().into_iter().flatten();
+    for content in std::iter::once(&tile["content"]).chain(contents) {
+        if content["uri"].as_str().is_some_and(is_match) {
+            return Some(tile);
+        }
+    }
+    tile["children"]
+        .as_array()?
+        .iter()
+        .find_map(|child| find_tile(child, is_match))
+}
+
+/// Returns a copy of the `tile` with only those children that intersect, or `None` if neither
+/// the tile nor any of its descendants intersect.
+fn filter_tile<F: Fn(&Value) -> bool>(tile: &Value, intersects: &F) -> Option<Value> {
+    let children: Vec<Value> = tile["children"]
+        .as_array()
+        .map(|children| {
+            children
+                .iter()
+                .filter_map(|child| filter_tile(child, intersects))
+                .collect()
+        })
+        .unwrap_or_default();
+    if children.is_empty() && !intersects(&tile["boundingVolume"]) {
+        return None;
+    }
+    let mut tile = tile.clone();
+    if let Some(object) = tile.as_object_mut() {
+        if children.is_empty() {
+            object.remove("children");
+        } else {
+            object.insert("children".to_string(), Value::Array(children));
+        }
+    }
+    Some(tile)
+}
+
+/// Replace the root with its only child, as long as the root has no content, so that the root
+/// fits the extracted area.
+fn collapse_root(mut root: Value) -> Value {
+    loop {
+        let has_content = root.get("content").is_some() || root.get("contents").is_some();
+        let single_child = root["children"]
+            .as_array()
+            .filter(|c| c.len() == 1)
+            .is_some();
+        if has_content || !single_child || root.get("transform").is_some() {
+            return root;
+        }
+        let refine = root.get("refine").cloned();
+        let mut child = root["children"][0].take();
+        if let (Some(refine), Some(object)) = (refine, child.as_object_mut()) {
+            // The root must declare the refinement, but the children may inherit it
+            object.entry("refine").or_insert(refine);
+        }
+        root = child;
+    }
+}
+
+/// Collect the relative URIs of the content of the tile and its descendants.
+fn content_uris(tile: &Value) -> Vec<String> {
+    let mut uris: Vec<String> = Vec::new();
+    let mut stack: Vec<&Value> = vec![tile];
+    while let Some(tile) = stack.pop() {
+        let contents = tile["contents"].as_array().into_iter().flatten();
+        for content in std::iter::once(&tile["content"]).chain(contents) {
+            if let Some(uri) = content["uri"].as_str() {
+                if !uri.contains("://") {
+                    uris.push(uri.to_string());
+                }
+            }
+        }
+        if let Some(children) = tile["children"].as_array() {
+            stack.extend(children.iter());
+        }
+    }
+    uris
+}
+
+/// Compute the longitude, latitude bounding box of a 3D Tiles bounding volume.
+/// Returns `None` if the bounding volume is not recognized.
+fn lonlat_bbox(bounding_volume: &Value, to_lonlat: &Proj) -> Option<BboxLonLat> {
+    let numbers = |key: &str| -> Option<Vec<f64>> {
+        bounding_volume[key]
+            .as_array()?
+            .iter()
+            .map(|v| v.as_f64())
+            .collect()
+    };
+    if let Some(region) = numbers("region").filter(|r| r.len() == 6) {
+        return Some([
+            region[0].to_degrees(),
+            region[1].to_degrees(),
+            region[2].to_degrees(),
+            region[3].to_degrees(),
+        ]);
+    }
+    // The corners of the box or the sphere, in EPSG:4978
+    let mut corners: Vec<[f64; 3]> = Vec::with_capacity(8);
+    if let Some(b) = numbers("box").filter(|b| b.len() == 12) {
+        for sx in [-1.0, 1.0] {
+            for sy in [-1.0, 1.0] {
+                for sz in [-1.0, 1.0] {
+                    corners.push([
+                        b[0] + sx * b[3] + sy * b[6] + sz * b[9],
+                        b[1] + sx * b[4] + sy * b[7] + sz * b[10],
+                        b[2] + sx * b[5] + sy * b[8] + sz * b[11],
+                    ]);
+                }
+            }
+        }
+    } else if let Some(s) = numbers("sphere").filter(|s| s.len() == 4) {
+        for sx in [-1.0, 1.0] {
+            for sy in [-1.0, 1.0] {
+                for sz in [-1.0, 1.0] {
+                    corners.push([s[0] + sx * s[3], s[1] + sy * s[3], s[2] + sz * s[3]]);
+                }
+            }
+        }
+    } else {
+        return None;
+    }
+    let mut bbox = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+    for c in corners {
+        let (lon, lat, _) = to_lonlat.convert((c[0], c[1], c[2])).ok()?;
+        bbox[0] = bbox[0].min(lon);
+        bbox[1] = bbox[1].min(lat);
+        bbox[2] = bbox[2].max(lon);
+        bbox[3] = bbox[3].max(lat);
+    }
+    Some(bbox)
+}
+
+fn bbox_intersects(a: &BboxLonLat, b: &BboxLonLat) -> bool {
+    a[0] <= b[2] && a[2] >= b[0] && a[1] <= b[3] && a[3] >= b[1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn region_tile(west: f64, east: f64, uri: &str, children: Vec<Value>) -> Value {
+        let mut tile = json!({
+            "boundingVolume": {"region": [west.to_radians(), 0.0, east.to_radians(), 0.1, 0.0, 10.0]},
+            "geometricError": 1.0,
+            "content": {"uri": uri}
+        });
+        if !children.is_empty() {
+            tile["children"] = Value::Array(children);
+        }
+        tile
+    }
+
+    #[test]
+    fn test_filter_tile() {
+        let root = json!({
+            "boundingVolume": {"region": [0.0, 0.0, 0.04, 0.1, 0.0, 10.0]},
+            "geometricError": 10.0,
+            "refine": "REPLACE",
+            "children": [
+                region_tile(0.0, 1.0, "t/1/0/0.glb", vec![region_tile(0.0, 0.5, "t/2/0/0.glb", vec![])]),
+                region_tile(1.0, 2.0, "t/1/1/0.glb", vec![]),
+            ]
+        });
+        let bbox: BboxLonLat = [0.1, 0.0, 0.2, 1.0];
+        let intersects = |bv: &Value| {
+            let to_lonlat = |v: &Value| v.as_f64().unwrap().to_degrees();
+            let region = &bv["region"];
+            let bv_lonlat = [
+                to_lonlat(&region[0]),
+                to_lonlat(&region[1]),
+                to_lonlat(&region[2]),
+                to_lonlat(&region[3]),
+            ];
+            bbox_intersects(&bv_lonlat, &bbox)
+        };
+        let filtered = filter_tile(&root, &intersects).unwrap();
+        let root = collapse_root(filtered);
+        assert_eq!(root["content"]["uri"], "t/1/0/0.glb");
+        assert_eq!(root["refine"], "REPLACE");
+        let mut uris = content_uris(&root);
+        uris.sort();
+        assert_eq!(uris, vec!["t/1/0/0.glb", "t/2/0/0.glb"]);
+        assert!(filter_tile(&root, &|_: &Value| false).is_none());
+    }
+
+    #[test]
+    fn test_property_table_rows() {
+        let mut bin: Vec<u8> = Vec::new();
+        // "ab", "c" as strings with UINT32 offsets
+        bin.extend_from_slice(b"abc\0");
+        for offset in [0_u32, 2, 3] {
+            bin.extend_from_slice(&offset.to_le_bytes());
+        }
+        for height in [1.5_f64, 2.0] {
+            bin.extend_from_slice(&height.to_le_bytes());
+        }
+        let gltf = json!({
+            "bufferViews": [
+                {"buffer": 0, "byteOffset": 0, "byteLength": 3},
+                {"buffer": 0, "byteOffset": 4, "byteLength": 12},
+                {"buffer": 0, "byteOffset": 16, "byteLength": 16}
+            ],
+            "extensions": {"EXT_structural_metadata": {"schema": {"classes": {"building": {"properties": {
+                "identificatie": {"type": "STRING"},
+                "height": {"type": "SCALAR", "componentType": "FLOAT64"}
+            }}}}}}
+        });
+        let table = json!({
+            "class": "building",
+            "count": 2,
+            "properties": {
+                "identificatie": {"values": 0, "stringOffsets": 1},
+                "height": {"values": 2}
+            }
+        });
+        let (header, rows) = property_table_rows(&gltf, Some(&bin), &table);
+        assert_eq!(header, vec!["height", "identificatie"]);
+        assert_eq!(rows, vec![vec!["1.5", "ab"], vec!["2", "c"]]);
+        let (_, rows) = property_table_rows(&gltf, None, &table);
+        assert_eq!(rows[0], vec!["?", "?"]);
+    }
+
+    #[test]
+    fn test_primitive_counts() {
+        let gltf = json!({"accessors": [{"count": 8}, {"count": 36}]});
+        let primitive = json!({"attributes": {"POSITION": 0}, "indices": 1});
+        assert_eq!(primitive_counts(&gltf, &primitive), (8, 12));
+        let lines = json!({"attributes": {"POSITION": 0}, "indices": 1, "mode": 1});
+        assert_eq!(primitive_counts(&gltf, &lines), (8, 0));
+    }
+
+    #[test]
+    fn test_thin_tile() {
+        let leaf = |uri: &str| json!({"geometricError": 0.0, "content": {"uri": uri}});
+        let mut root = json!({
+            "geometricError": 100.0,
+            "refine": "REPLACE",
+            "children": [
+                {"geometricError": 10.0, "children": [leaf("t/2/0/0.glb"), leaf("t/2/0/1.glb")]},
+                {"geometricError": 10.0, "content": {"uri": "t/1/1/0.glb"}, "children": [leaf("t/2/2/0.glb")]},
+                {"geometricError": 10.0, "children": [{"geometricError": 1.0, "children": [leaf("t/3/0/0.glb")]}]}
+            ]
+        });
+        assert!(thin_tile(&mut root, 0, 1, "REPLACE"));
+        let children = root["children"].as_array().unwrap();
+        assert!(children.iter().all(|c| c.get("children").is_none()));
+        assert!(children.iter().all(|c| c["geometricError"] == 0.0));
+        assert_eq!(children[0]["contents"].as_array().unwrap().len(), 2);
+        assert_eq!(children[1]["content"]["uri"], "t/1/1/0.glb");
+        assert_eq!(children[2]["content"]["uri"], "t/3/0/0.glb");
+        assert_eq!(root["geometricError"], 100.0);
+    }
+}